@@ -56,6 +56,16 @@ pub struct Usage {
     pub total_tokens: u32,
 }
 
+impl Usage {
+    fn new(prompt_tokens: usize, completion_tokens: usize) -> Self {
+        Self {
+            prompt_tokens: prompt_tokens as u32,
+            completion_tokens: completion_tokens as u32,
+            total_tokens: (prompt_tokens + completion_tokens) as u32,
+        }
+    }
+}
+
 /// OpenAI-compatible models response
 #[derive(Debug, Serialize)]
 pub struct ModelsResponse {
@@ -89,6 +99,9 @@ struct StreamEvent {
     pub created: u64,
     pub model: String,
     pub choices: Vec<StreamChoice>,
+    /// Only on the final chunk
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
 }
 
 #[derive(Debug, Serialize)]
@@ -202,6 +215,7 @@ async fn chat_completions(
                         },
                         finish_reason: None,
                     }],
+                    usage: None,
                 };
 
                 if let Ok(json) = serde_json::to_string(&event) {
@@ -221,8 +235,9 @@ async fn chat_completions(
                         content: None,
                         role: None,
                     },
-                    finish_reason: Some("stop".to_string()),
+                    finish_reason: Some(response.finish_reason),
                 }],
+                usage: Some(Usage::new(response.prompt_tokens, response.tokens_used)),
             };
 
             if let Ok(json) = serde_json::to_string(&final_event) {
@@ -259,11 +274,7 @@ async fn chat_completions(
                         },
                         finish_reason: response.finish_reason,
                     }],
-                    usage: Usage {
-                        prompt_tokens: 0, // TODO: Implement tokenization
-                        completion_tokens: response.tokens_used as u32,
-                        total_tokens: response.tokens_used as u32,
-                    },
+                    usage: Usage::new(response.prompt_tokens, response.tokens_used),
                 };
 
                 Json(chat_response).into_response()
@@ -1,6 +1,6 @@
 use anyhow::Result;
 use std::sync::Arc;
-use crate::model::{ModelLoader, GenerateRequest, GenerateResponse};
+use crate::model::{estimate_tokens, ModelLoader, GenerateRequest, GenerateResponse};
 use tokio::sync::Mutex;
 
 /// Text generation engine using llama-gguf
@@ -69,6 +69,7 @@ impl InferenceEngine {
 
         // Get the prompt
         let prompt = request.prompt;
+        let prompt_tokens = estimate_tokens(&prompt);
 
         // Lock and use the engine for generation
         // We need to hold the lock for the entire generation to prevent concurrent access
@@ -90,10 +91,12 @@ impl InferenceEngine {
         // Put the engine back
         *engine_guard = Some(result.1);
 
+        let tokens_used = estimate_tokens(&result.0).min(max_tokens);
         Ok(GenerateResponse {
             text: result.0,
-            finish_reason: "length".to_string(),
-            tokens_used: max_tokens,
+            finish_reason: if tokens_used >= max_tokens { "length" } else { "stop" }.to_string(),
+            prompt_tokens,
+            tokens_used,
         })
     }
 }
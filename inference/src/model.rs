@@ -142,5 +142,14 @@ pub struct GenerateRequest {
 pub struct GenerateResponse {
     pub text: String,
     pub finish_reason: String,
+    /// Estimated prompt tokens (see `estimate_tokens`)
+    pub prompt_tokens: usize,
+    /// Estimated generated tokens
     pub tokens_used: usize,
 }
+
+/// Rough token count for usage reporting: about four characters per token
+/// for English text with the common BPE vocabularies
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
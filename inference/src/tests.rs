@@ -1,6 +1,6 @@
 #[cfg(test)]
 mod tests {
-    use crate::model::{ModelLoader, SamplingParams, GenerateRequest};
+    use crate::model::{estimate_tokens, ModelLoader, SamplingParams, GenerateRequest};
     use crate::inference::InferenceEngine;
     use crate::api::{ChatCompletionRequest, ModelsResponse, ModelInfo};
     use std::sync::Arc;
@@ -75,4 +75,11 @@ mod tests {
         assert_eq!(params.top_p, Some(0.8));
         assert_eq!(params.top_k, Some(30));
     }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abc"), 1);
+        assert_eq!(estimate_tokens("Hello, world"), 3);
+    }
 }
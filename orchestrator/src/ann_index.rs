@@ -0,0 +1,851 @@
+//! Approximate nearest-neighbour index for memory embeddings
+//!
+//! Without sqlite-vss, `SharedMemory` answers vector searches from this index
+//! instead of scanning every stored embedding. Each org and embedding space
+//! (model and dimension) gets its own HNSW graph (Malkov & Yashunin, 2016).
+//! Vectors are normalized on insert, so inner product is cosine similarity.
+//! Spaces too small for a graph to pay off are scanned exactly.
+//!
+//! Deletes leave a tombstone: the node keeps routing searches but is never
+//! returned. A space is rebuilt once too many of its nodes are tombstones.
+//!
+//! The graphs are saved next to the database so a restart doesn't rebuild
+//! them. The file holds no embeddings, only the IDs and links, and
+//! tombstones are left out along with their links, so a deleted memory
+//! leaves disk with the next save. The file is only a cache: on open it is
+//! reconciled with the `memories` table, and a space that no longer matches
+//! is rebuilt from scratch.
+//!
+//! Recall and latency against the brute-force scan are measured by the
+//! ignored `ann_benchmark` test in `shared_memory`:
+//!
+//! ```bash
+//! cargo test --release ann_benchmark -- --ignored --nocapture
+//! ```
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Links a node keeps on the upper layers
+const M: usize = 16;
+/// Links a node keeps on layer 0
+const M0: usize = 2 * M;
+/// Candidate list size while inserting
+const EF_CONSTRUCTION: usize = 100;
+/// Candidate list size while searching (at least the number of results)
+const EF_SEARCH: usize = 64;
+const MAX_LEVEL: usize = 16;
+/// Spaces with at most this many live vectors are scanned exactly
+const EXACT_SCAN_MAX: usize = 256;
+/// A space is rebuilt once more than this fraction of its nodes are deleted
+const MAX_DELETED_FRACTION: f64 = 0.25;
+/// How often a changed index is written back to disk
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+const FILE_MAGIC: &[u8; 8] = b"CPANN\0\0\x02";
+/// Longest org or model name read back, so a corrupt length can't force a
+/// huge allocation
+const MAX_STR_LEN: usize = 4096;
+
+/// The set of vectors a search compares with: one org's vectors from one
+/// embedding model
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SpaceKey {
+    pub org: String,
+    pub model: Option<String>,
+    pub dim: usize,
+}
+
+/// A stored embedding to index
+#[derive(Debug, Clone)]
+pub struct IndexedVector {
+    pub id: i64,
+    pub space: SpaceKey,
+    pub vector: Vec<f32>,
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn normalized(vector: &[f32]) -> Vec<f32> {
+    let norm = dot(vector, vector).sqrt();
+    if norm == 0.0 {
+        return vector.to_vec();
+    }
+    vector.iter().map(|x| x / norm).collect()
+}
+
+/// A node's top layer, drawn from the usual exponential distribution. It is
+/// derived from the memory ID rather than an RNG so rebuilds are reproducible.
+fn level_for(id: i64) -> usize {
+    // splitmix64
+    let mut z = (id as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    // Uniform in (0, 1]
+    let uniform = ((z >> 11) + 1) as f64 / (1u64 << 53) as f64;
+    let level = -uniform.ln() / (M as f64).ln();
+    (level as usize).min(MAX_LEVEL)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    distance: f32,
+    node: u32,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.node.cmp(&other.node))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug)]
+struct Node {
+    id: i64,
+    deleted: bool,
+    /// Links per layer, from layer 0 up to the node's level
+    links: Vec<Vec<u32>>,
+}
+
+/// One HNSW graph
+#[derive(Debug)]
+struct Hnsw {
+    dim: usize,
+    nodes: Vec<Node>,
+    /// Normalized vectors, `dim` floats per node
+    vectors: Vec<f32>,
+    /// Live node for each memory ID
+    live: HashMap<i64, u32>,
+    entry: Option<u32>,
+    deleted: usize,
+}
+
+impl Hnsw {
+    fn new(dim: usize) -> Self {
+        Self {
+            dim,
+            nodes: Vec::new(),
+            vectors: Vec::new(),
+            live: HashMap::new(),
+            entry: None,
+            deleted: 0,
+        }
+    }
+
+    fn build(dim: usize, vectors: impl IntoIterator<Item = (i64, Vec<f32>)>) -> Self {
+        let mut hnsw = Self::new(dim);
+        for (id, vector) in vectors {
+            hnsw.insert(id, &vector);
+        }
+        hnsw
+    }
+
+    fn live_count(&self) -> usize {
+        self.live.len()
+    }
+
+    fn vector(&self, node: u32) -> &[f32] {
+        let start = node as usize * self.dim;
+        &self.vectors[start..start + self.dim]
+    }
+
+    fn distance(&self, query: &[f32], node: u32) -> f32 {
+        1.0 - dot(query, self.vector(node))
+    }
+
+    fn top_layer(&self, node: u32) -> usize {
+        self.nodes[node as usize].links.len() - 1
+    }
+
+    /// Insert a normalized vector, replacing any live one with the same ID
+    fn insert(&mut self, id: i64, vector: &[f32]) {
+        self.remove(id);
+        let node = self.nodes.len() as u32;
+        let level = level_for(id);
+        self.vectors.extend_from_slice(vector);
+        self.nodes.push(Node {
+            id,
+            deleted: false,
+            links: vec![Vec::new(); level + 1],
+        });
+        self.live.insert(id, node);
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(node);
+            return;
+        };
+        let top = self.top_layer(entry);
+        let mut nearest = entry;
+        for layer in (level + 1..=top).rev() {
+            nearest = self.search_layer(vector, nearest, 1, layer)[0].node;
+        }
+        for layer in (0..=level.min(top)).rev() {
+            let candidates = self.search_layer(vector, nearest, EF_CONSTRUCTION, layer);
+            let max_links = if layer == 0 { M0 } else { M };
+            let neighbours = self.select_neighbours(&candidates, M);
+            for &neighbour in &neighbours {
+                self.link(neighbour, node, layer, max_links);
+            }
+            self.nodes[node as usize].links[layer] = neighbours;
+            nearest = candidates[0].node;
+        }
+        if level > top {
+            self.entry = Some(node);
+        }
+    }
+
+    /// Tombstone a memory's node; it keeps routing searches
+    fn remove(&mut self, id: i64) -> bool {
+        let Some(node) = self.live.remove(&id) else {
+            return false;
+        };
+        self.nodes[node as usize].deleted = true;
+        self.deleted += 1;
+        true
+    }
+
+    fn needs_rebuild(&self) -> bool {
+        self.nodes.len() > EXACT_SCAN_MAX
+            && self.deleted as f64 > self.nodes.len() as f64 * MAX_DELETED_FRACTION
+    }
+
+    /// A new graph of just the live nodes
+    fn rebuilt(&self) -> Self {
+        let mut live: Vec<(i64, u32)> = self.live.iter().map(|(&id, &node)| (id, node)).collect();
+        live.sort_unstable();
+        Self::build(
+            self.dim,
+            live.into_iter().map(|(id, node)| (id, self.vector(node).to_vec())),
+        )
+    }
+
+    /// Add a link from `node` to `neighbour`, pruning `node`'s links back to
+    /// `max_links` if it now has too many
+    fn link(&mut self, node: u32, neighbour: u32, layer: usize, max_links: usize) {
+        self.nodes[node as usize].links[layer].push(neighbour);
+        if self.nodes[node as usize].links[layer].len() <= max_links {
+            return;
+        }
+        let base = self.vector(node);
+        let mut candidates: Vec<Candidate> = self.nodes[node as usize].links[layer]
+            .iter()
+            .map(|&n| Candidate {
+                distance: 1.0 - dot(base, self.vector(n)),
+                node: n,
+            })
+            .collect();
+        candidates.sort_unstable();
+        let kept = self.select_neighbours(&candidates, max_links);
+        self.nodes[node as usize].links[layer] = kept;
+    }
+
+    /// Pick up to `m` of `candidates` (nearest first), preferring ones closer
+    /// to the base than to any neighbour already picked, so links spread in
+    /// different directions
+    fn select_neighbours(&self, candidates: &[Candidate], m: usize) -> Vec<u32> {
+        let mut selected: Vec<u32> = Vec::with_capacity(m);
+        let mut skipped = Vec::new();
+        for candidate in candidates {
+            if selected.len() >= m {
+                break;
+            }
+            let vector = self.vector(candidate.node);
+            let diverse = selected
+                .iter()
+                .all(|&s| 1.0 - dot(vector, self.vector(s)) > candidate.distance);
+            if diverse {
+                selected.push(candidate.node);
+            } else {
+                skipped.push(candidate.node);
+            }
+        }
+        let room = m.saturating_sub(selected.len());
+        selected.extend(skipped.into_iter().take(room));
+        selected
+    }
+
+    /// The `ef` nodes nearest to `query` found on `layer` starting from
+    /// `entry`, nearest first. Tombstoned nodes are included.
+    fn search_layer(&self, query: &[f32], entry: u32, ef: usize, layer: usize) -> Vec<Candidate> {
+        let start = Candidate {
+            distance: self.distance(query, entry),
+            node: entry,
+        };
+        let mut visited = HashSet::from([entry]);
+        let mut frontier = BinaryHeap::from([Reverse(start)]);
+        let mut found = BinaryHeap::from([start]);
+
+        while let Some(Reverse(current)) = frontier.pop() {
+            let furthest = found.peek().map_or(f32::INFINITY, |c| c.distance);
+            if found.len() >= ef && current.distance > furthest {
+                break;
+            }
+            for &neighbour in &self.nodes[current.node as usize].links[layer] {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let distance = self.distance(query, neighbour);
+                let furthest = found.peek().map_or(f32::INFINITY, |c| c.distance);
+                if found.len() < ef || distance < furthest {
+                    let candidate = Candidate {
+                        distance,
+                        node: neighbour,
+                    };
+                    frontier.push(Reverse(candidate));
+                    found.push(candidate);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        found.into_sorted_vec()
+    }
+
+    /// The `k` live vectors most similar to a normalized query, as
+    /// (memory ID, cosine similarity), most similar first
+    fn search(&self, query: &[f32], k: usize) -> Vec<(i64, f32)> {
+        if self.live_count() <= EXACT_SCAN_MAX {
+            return self.scan(query, k);
+        }
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        let mut nearest = entry;
+        for layer in (1..=self.top_layer(entry)).rev() {
+            nearest = self.search_layer(query, nearest, 1, layer)[0].node;
+        }
+        // Widen the search by the share of tombstones so they don't crowd
+        // live results out
+        let ef = EF_SEARCH.max(k) * self.nodes.len() / self.live_count();
+        self.search_layer(query, nearest, ef, 0)
+            .into_iter()
+            .filter(|c| !self.nodes[c.node as usize].deleted)
+            .take(k)
+            .map(|c| (self.nodes[c.node as usize].id, 1.0 - c.distance))
+            .collect()
+    }
+
+    fn scan(&self, query: &[f32], k: usize) -> Vec<(i64, f32)> {
+        let mut hits: Vec<Candidate> = self
+            .live
+            .values()
+            .map(|&node| Candidate {
+                distance: self.distance(query, node),
+                node,
+            })
+            .collect();
+        hits.sort_unstable();
+        hits.into_iter()
+            .take(k)
+            .map(|c| (self.nodes[c.node as usize].id, 1.0 - c.distance))
+            .collect()
+    }
+}
+
+/// HNSW graphs for every org and embedding space
+#[derive(Debug)]
+pub struct AnnIndex {
+    spaces: HashMap<SpaceKey, Hnsw>,
+    /// Space of each indexed memory ID
+    locations: HashMap<i64, SpaceKey>,
+    path: Option<PathBuf>,
+    dirty: bool,
+    last_saved: Instant,
+}
+
+impl AnnIndex {
+    /// Index `vectors`, reusing the graphs saved at `path` where they still
+    /// match. Changes are saved back to `path` as they happen.
+    pub fn open(path: Option<PathBuf>, vectors: Vec<IndexedVector>) -> Self {
+        let mut saved = match path.as_deref().map(load) {
+            Some(Ok(saved)) => saved,
+            Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => {
+                tracing::warn!("Ignoring unreadable ANN index {:?}: {}", path, e);
+                HashMap::new()
+            }
+            _ => HashMap::new(),
+        };
+
+        let mut grouped: HashMap<SpaceKey, Vec<(i64, Vec<f32>)>> = HashMap::new();
+        let mut locations = HashMap::new();
+        for v in vectors {
+            if v.vector.len() != v.space.dim {
+                continue;
+            }
+            locations.insert(v.id, v.space.clone());
+            grouped
+                .entry(v.space)
+                .or_default()
+                .push((v.id, normalized(&v.vector)));
+        }
+
+        let (mut rebuilt, mut added) = (0, 0);
+        let spaces: HashMap<SpaceKey, Hnsw> = grouped
+            .into_iter()
+            .map(|(key, rows)| {
+                let hnsw = match saved.remove(&key).and_then(|s| s.restore(key.dim, &rows)) {
+                    Some((hnsw, new_rows)) => {
+                        added += new_rows;
+                        hnsw
+                    }
+                    None => {
+                        rebuilt += 1;
+                        Hnsw::build(key.dim, rows)
+                    }
+                };
+                (key, hnsw)
+            })
+            .collect();
+
+        let mut index = Self {
+            spaces,
+            locations,
+            path,
+            dirty: rebuilt > 0 || added > 0 || !saved.is_empty(),
+            last_saved: Instant::now(),
+        };
+        tracing::info!(
+            "ANN index: {} vectors in {} spaces ({} rebuilt)",
+            index.len(),
+            index.spaces.len(),
+            rebuilt
+        );
+        index.flush();
+        index
+    }
+
+    /// Number of live vectors
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    /// Add a memory's embedding, replacing the one indexed for it before
+    pub fn insert(&mut self, id: i64, space: SpaceKey, vector: &[f32]) {
+        self.remove_quietly(id);
+        if vector.len() != space.dim {
+            return;
+        }
+        let dim = space.dim;
+        self.spaces
+            .entry(space.clone())
+            .or_insert_with(|| Hnsw::new(dim))
+            .insert(id, &normalized(vector));
+        self.locations.insert(id, space);
+        self.changed();
+    }
+
+    /// Drop a memory from the index
+    pub fn remove(&mut self, id: i64) {
+        if self.remove_quietly(id) {
+            self.changed();
+        }
+    }
+
+    fn remove_quietly(&mut self, id: i64) -> bool {
+        let Some(space) = self.locations.remove(&id) else {
+            return false;
+        };
+        let Some(hnsw) = self.spaces.get_mut(&space) else {
+            return false;
+        };
+        hnsw.remove(id);
+        if hnsw.live_count() == 0 {
+            self.spaces.remove(&space);
+        } else if hnsw.needs_rebuild() {
+            *hnsw = hnsw.rebuilt();
+        }
+        true
+    }
+
+    /// The `limit` memories most similar to `query` among those embedded by
+    /// `model`, in `org` or every org if None, as (memory ID, cosine
+    /// similarity), most similar first
+    pub fn search(
+        &self,
+        org: Option<&str>,
+        model: Option<&str>,
+        query: &[f32],
+        limit: usize,
+    ) -> Vec<(i64, f32)> {
+        let query = normalized(query);
+        let mut hits: Vec<(i64, f32)> = self
+            .spaces
+            .iter()
+            .filter(|(key, _)| {
+                key.dim == query.len()
+                    && key.model.as_deref() == model
+                    && org.is_none_or(|org| key.org == org)
+            })
+            .flat_map(|(_, hnsw)| hnsw.search(&query, limit))
+            .collect();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        hits.truncate(limit);
+        hits
+    }
+
+    fn changed(&mut self) {
+        self.dirty = true;
+        if self.last_saved.elapsed() >= SAVE_INTERVAL {
+            self.flush();
+        }
+    }
+
+    /// Write the index to disk if it changed since it was last saved
+    pub fn flush(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        let Some(path) = &self.path else {
+            return;
+        };
+        match save(path, &self.spaces) {
+            Ok(()) => self.dirty = false,
+            Err(e) => tracing::warn!("Failed to save ANN index to {:?}: {}", path, e),
+        }
+        self.last_saved = Instant::now();
+    }
+}
+
+impl Drop for AnnIndex {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+// ============================================================================
+// Persistence
+// ============================================================================
+
+/// A graph as read back from disk, without its vectors
+struct SavedSpace {
+    entry: Option<u32>,
+    nodes: Vec<Node>,
+}
+
+impl SavedSpace {
+    /// Rebuild the graph with the vectors in `rows`, then add the rows it
+    /// doesn't have, returning how many that was. None if a live node's
+    /// memory is gone, in which case the graph can't be trusted.
+    fn restore(self, dim: usize, rows: &[(i64, Vec<f32>)]) -> Option<(Hnsw, usize)> {
+        let by_id: HashMap<i64, &[f32]> = rows.iter().map(|(id, v)| (*id, v.as_slice())).collect();
+        let mut hnsw = Hnsw::new(dim);
+        hnsw.entry = self.entry;
+        for (i, node) in self.nodes.into_iter().enumerate() {
+            let vector = by_id.get(&node.id).copied()?;
+            if vector.len() != dim || hnsw.live.insert(node.id, i as u32).is_some() {
+                return None;
+            }
+            hnsw.vectors.extend_from_slice(vector);
+            hnsw.nodes.push(node);
+        }
+        let count = hnsw.nodes.len() as u32;
+        let links_valid = hnsw
+            .nodes
+            .iter()
+            .flat_map(|n| n.links.iter().flatten())
+            .all(|&n| n < count);
+        let entry_valid = match hnsw.entry {
+            Some(entry) => entry < count,
+            None => count == 0,
+        };
+        if !links_valid || !entry_valid {
+            return None;
+        }
+        let mut added = 0;
+        for (id, vector) in rows {
+            if !hnsw.live.contains_key(id) {
+                hnsw.insert(*id, vector);
+                added += 1;
+            }
+        }
+        Some((hnsw, added))
+    }
+}
+
+fn save(path: &Path, spaces: &HashMap<SpaceKey, Hnsw>) -> io::Result<()> {
+    let tmp = path.with_extension("ann.tmp");
+    let mut out = BufWriter::new(fs::File::create(&tmp)?);
+    out.write_all(FILE_MAGIC)?;
+    write_u32(&mut out, spaces.len() as u32)?;
+    for (key, hnsw) in spaces {
+        write_str(&mut out, &key.org)?;
+        match &key.model {
+            Some(model) => {
+                out.write_all(&[1])?;
+                write_str(&mut out, model)?;
+            }
+            None => out.write_all(&[0])?,
+        }
+        write_u32(&mut out, key.dim as u32)?;
+
+        // Tombstones are dropped, so live nodes are renumbered and their
+        // links to tombstones cut
+        let mut renumbered = vec![u32::MAX; hnsw.nodes.len()];
+        let mut live = 0;
+        for (i, node) in hnsw.nodes.iter().enumerate() {
+            if !node.deleted {
+                renumbered[i] = live;
+                live += 1;
+            }
+        }
+        let entry = match hnsw.entry {
+            Some(entry) if !hnsw.nodes[entry as usize].deleted => Some(entry),
+            // Any live node on the top remaining layer can take over
+            _ => (0..hnsw.nodes.len() as u32)
+                .filter(|&n| !hnsw.nodes[n as usize].deleted)
+                .max_by_key(|&n| (hnsw.nodes[n as usize].links.len(), Reverse(n))),
+        };
+        write_u32(&mut out, entry.map_or(u32::MAX, |e| renumbered[e as usize]))?;
+        write_u32(&mut out, live)?;
+        for node in hnsw.nodes.iter().filter(|n| !n.deleted) {
+            out.write_all(&node.id.to_le_bytes())?;
+            out.write_all(&[node.links.len() as u8])?;
+            for links in &node.links {
+                let links: Vec<u32> = links
+                    .iter()
+                    .map(|&link| renumbered[link as usize])
+                    .filter(|&link| link != u32::MAX)
+                    .collect();
+                write_u32(&mut out, links.len() as u32)?;
+                for link in links {
+                    write_u32(&mut out, link)?;
+                }
+            }
+        }
+    }
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(tmp, path)
+}
+
+fn load(path: &Path) -> io::Result<HashMap<SpaceKey, SavedSpace>> {
+    let mut input = BufReader::new(fs::File::open(path)?);
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != FILE_MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an ANN index file"));
+    }
+    let mut spaces = HashMap::new();
+    for _ in 0..read_u32(&mut input)? {
+        let org = read_str(&mut input)?;
+        let model = match read_u8(&mut input)? {
+            0 => None,
+            _ => Some(read_str(&mut input)?),
+        };
+        let dim = read_u32(&mut input)? as usize;
+        let entry = Some(read_u32(&mut input)?).filter(|&e| e != u32::MAX);
+        let count = read_u32(&mut input)?;
+        let mut nodes = Vec::new();
+        for _ in 0..count {
+            let mut id = [0u8; 8];
+            input.read_exact(&mut id)?;
+            let layers = read_u8(&mut input)?;
+            let mut links = Vec::with_capacity(layers as usize);
+            for _ in 0..layers {
+                let len = read_u32(&mut input)?;
+                if len as usize > M0 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "too many links"));
+                }
+                links.push((0..len).map(|_| read_u32(&mut input)).collect::<io::Result<_>>()?);
+            }
+            if links.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "node without layers"));
+            }
+            nodes.push(Node {
+                id: i64::from_le_bytes(id),
+                deleted: false,
+                links,
+            });
+        }
+        spaces.insert(SpaceKey { org, model, dim }, SavedSpace { entry, nodes });
+    }
+    Ok(spaces)
+}
+
+fn write_u32(out: &mut impl Write, value: u32) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+fn write_str(out: &mut impl Write, value: &str) -> io::Result<()> {
+    write_u32(out, value.len() as u32)?;
+    out.write_all(value.as_bytes())
+}
+
+fn read_u8(input: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    input.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_str(input: &mut impl Read) -> io::Result<String> {
+    let len = read_u32(input)? as usize;
+    if len > MAX_STR_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "string too long"));
+    }
+    let mut buf = vec![0u8; len];
+    input.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Clustered random vectors, which is closer to real embeddings than
+    /// uniform noise
+    pub(crate) fn clustered_vectors(count: usize, dim: usize, seed: u64) -> Vec<Vec<f32>> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let centres: Vec<Vec<f32>> = (0..32)
+            .map(|_| (0..dim).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect();
+        (0..count)
+            .map(|_| {
+                let centre = &centres[rng.gen_range(0..centres.len())];
+                centre.iter().map(|c| c + rng.gen_range(-0.5..0.5)).collect()
+            })
+            .collect()
+    }
+
+    fn space(org: &str) -> SpaceKey {
+        SpaceKey {
+            org: org.to_string(),
+            model: Some("test".to_string()),
+            dim: 16,
+        }
+    }
+
+    fn exact(vectors: &[(i64, Vec<f32>)], query: &[f32], k: usize) -> Vec<i64> {
+        let query = normalized(query);
+        let mut scored: Vec<(i64, f32)> = vectors
+            .iter()
+            .map(|(id, v)| (*id, dot(&query, &normalized(v))))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored.into_iter().take(k).map(|(id, _)| id).collect()
+    }
+
+    fn recall(index: &AnnIndex, vectors: &[(i64, Vec<f32>)], queries: &[Vec<f32>]) -> f64 {
+        let mut found = 0;
+        for query in queries {
+            let truth = exact(vectors, query, 10);
+            let hits = index.search(Some("default"), Some("test"), query, 10);
+            found += hits.iter().filter(|(id, _)| truth.contains(id)).count();
+        }
+        found as f64 / (queries.len() * 10) as f64
+    }
+
+    fn indexed(vectors: &[(i64, Vec<f32>)]) -> Vec<IndexedVector> {
+        vectors
+            .iter()
+            .map(|(id, vector)| IndexedVector {
+                id: *id,
+                space: space("default"),
+                vector: vector.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_recall_against_exact_search() {
+        // Queries come from the same distribution as the data
+        let mut data = clustered_vectors(2050, 16, 1);
+        let queries = data.split_off(2000);
+        let mut vectors: Vec<(i64, Vec<f32>)> = data
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as i64 + 1, v))
+            .collect();
+        let mut index = AnnIndex::open(None, indexed(&vectors));
+        assert!(recall(&index, &vectors, &queries) >= 0.9);
+
+        // Deletes stop being returned, and enough of them rebuild the graph
+        for (id, _) in vectors.drain(..1000) {
+            index.remove(id);
+        }
+        assert_eq!(index.len(), 1000);
+        assert!(recall(&index, &vectors, &queries) >= 0.9);
+
+        // Other orgs and models are separate spaces
+        index.insert(5000, space("other"), &queries[0]);
+        let hits = index.search(Some("default"), Some("test"), &queries[0], 10);
+        assert!(hits.iter().all(|(id, _)| *id != 5000));
+        assert_eq!(index.search(None, Some("test"), &queries[0], 1)[0].0, 5000);
+        assert!(index.search(None, Some("other"), &queries[0], 1).is_empty());
+    }
+
+    #[test]
+    fn test_saved_index_is_reconciled_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("memory.ann");
+        let mut vectors: Vec<(i64, Vec<f32>)> = clustered_vectors(600, 16, 3)
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (i as i64 + 1, v))
+            .collect();
+        {
+            let mut index = AnnIndex::open(Some(path.clone()), indexed(&vectors));
+            index.remove(1);
+            vectors.remove(0);
+            // Saving leaves the live graph alone
+            index.flush();
+            assert_eq!(index.spaces[&space("default")].deleted, 1);
+        }
+        assert!(path.exists());
+
+        // Memories stored while the index wasn't running are added
+        let extra = clustered_vectors(1, 16, 4).remove(0);
+        vectors.push((1000, extra.clone()));
+        // The deleted memory is gone from the file, vector and all
+        let saved = load(&path).unwrap();
+        assert_eq!(saved[&space("default")].nodes.len(), 599);
+        assert!(saved[&space("default")].nodes.iter().all(|n| n.id != 1));
+        let index = AnnIndex::open(Some(path.clone()), indexed(&vectors));
+        assert_eq!(index.len(), 600);
+        assert_eq!(index.search(Some("default"), Some("test"), &extra, 1)[0].0, 1000);
+        assert!(index.search(None, Some("test"), &vectors[0].1, 600).iter().all(|(id, _)| *id != 1));
+        drop(index);
+
+        // A live node whose memory is gone forces a rebuild
+        vectors.remove(0);
+        let index = AnnIndex::open(Some(path.clone()), indexed(&vectors));
+        assert_eq!(index.len(), 599);
+        assert_eq!(load(&path).unwrap()[&space("default")].nodes.len(), 599);
+
+        // A corrupt length is refused rather than allocated
+        let mut corrupt = FILE_MAGIC.to_vec();
+        corrupt.extend_from_slice(&1u32.to_le_bytes());
+        corrupt.extend_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, &corrupt).unwrap();
+        assert_eq!(load(&path).err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+        // A corrupt file is ignored
+        fs::write(&path, b"garbage").unwrap();
+        drop(index);
+        let index = AnnIndex::open(Some(path), indexed(&vectors));
+        assert_eq!(index.len(), 599);
+    }
+}
@@ -467,6 +467,7 @@ pub async fn create_agent(
     });
 
     // Get the appropriate runtime client based on agent's runtime preference
+    let container_config = container_config(&state, &req.name, &config).await;
     let id = if agent_runtime.as_deref() == Some("direct") {
         // Direct backend has no container; the agent ID is just the name.
         req.name.clone()
@@ -475,21 +476,21 @@ pub async fn create_agent(
             // Use exo-specific runtime if available
             state
                 .exo_runtime
-                .create_container(&req.name, &config)
+                .create_container(&req.name, &container_config)
                 .await
                 .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
         } else {
             // Use default runtime (docker or containment)
             state
                 .runtime
-                .create_container(&req.name, &config)
+                .create_container(&req.name, &container_config)
                 .await
                 .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
         }
     } else {
         state
             .runtime
-            .create_container(&req.name, &config)
+            .create_container(&req.name, &container_config)
             .await
             .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
     };
@@ -505,6 +506,7 @@ pub async fn create_agent(
         tags: req.tags,
         restart_policy: AgentConfig::default().restart_policy,
         health_status: None,
+        restarts: Default::default(),
         runtime: agent_runtime,
         gateway_port,
     };
@@ -644,6 +646,9 @@ pub async fn delete_agent(
     if let Err(e) = crate::storage::remove_agent(&id) {
         tracing::warn!("Failed to remove agent from storage: {}", e);
     }
+    if let Err(e) = state.metrics_history.delete_agent(&id) {
+        tracing::warn!("Failed to remove agent metrics history: {}", e);
+    }
 
     Ok(StatusCode::NO_CONTENT)
 }
@@ -697,6 +702,29 @@ pub async fn start_agent(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
 ) -> Result<Json<AgentContainer>, (StatusCode, String)> {
+    // A manual start clears any crash-loop hold
+    if let Some(agent) = state.containers.write().await.iter_mut().find(|a| a.id == id) {
+        state.supervisor.record_manual_start(agent);
+    }
+
+    launch_agent(&state, &id).await.map(Json)
+}
+
+/// The config an agent's container is created with: the agent's own, plus
+/// the name and token it uses for the shared-memory endpoints
+async fn container_config(state: &AppState, name: &str, config: &AgentConfig) -> AgentConfig {
+    let mut config = config.clone();
+    let token = state.auth.read().await.agent_token(name);
+    config
+        .env_vars
+        .insert(crate::auth::AGENT_NAME_ENV.to_string(), name.to_string());
+    config.env_vars.insert(crate::auth::AGENT_TOKEN_ENV.to_string(), token);
+    config
+}
+
+/// Create (if needed) and start an agent's container, waiting until it is
+/// healthy. Shared by `start_agent` and the restart supervisor.
+pub async fn launch_agent(state: &AppState, id: &str) -> Result<AgentContainer, (StatusCode, String)> {
     let mut containers = state.containers.write().await;
 
     let agent = containers
@@ -710,7 +738,7 @@ pub async fn start_agent(
         if let Err(e) = crate::storage::upsert_agent(&crate::storage::to_stored_agent(agent)) {
             tracing::warn!("Failed to persist direct agent status: {}", e);
         }
-        return Ok(Json(agent.clone()));
+        return Ok(agent.clone());
     }
 
     // Choose the right runtime based on agent's runtime setting
@@ -766,8 +794,9 @@ pub async fn start_agent(
             .env_vars
             .insert("PORT".to_string(), agent.gateway_port.to_string());
 
+        let config = container_config(state, &agent.name, &agent.config).await;
         let docker_container_id = runtime
-            .create_container(&agent.name, &agent.config)
+            .create_container(&agent.name, &config)
             .await
             .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
@@ -830,7 +859,7 @@ pub async fn start_agent(
         tracing::warn!("Failed to persist agent status: {}", e);
     }
 
-    Ok(Json(agent.clone()))
+    Ok(agent.clone())
 }
 
 pub async fn stop_agent(
@@ -873,6 +902,7 @@ pub async fn stop_agent(
         .ok_or((StatusCode::NOT_FOUND, "Agent not found".to_string()))?;
 
     agent.status = AgentStatus::Stopped;
+    state.supervisor.record_user_stop(agent);
 
     // Persist status change
     if let Err(e) = crate::storage::upsert_agent(&crate::storage::to_stored_agent(agent)) {
@@ -1183,30 +1213,18 @@ async fn handle_terminal_stream(mut socket: WebSocket, container_id: String, she
 
 // === Metrics ===
 
+// Container stats are refreshed by the sampler in `metrics`; these read its
+// latest sample rather than asking the runtime.
+
 pub async fn get_metrics(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
 ) -> Result<Json<ResourceUsage>, (StatusCode, String)> {
-    // Get agent to find its runtime
-    let agent_runtime = {
-        let containers = state.containers.read().await;
-        containers
-            .iter()
-            .find(|a| a.id == id)
-            .and_then(|a| a.runtime.clone())
-    };
-
-    // Choose the right runtime
-    let runtime: &dyn ContainerRuntime = if agent_runtime.as_deref() == Some("exo") {
-        &state.exo_runtime
-    } else {
-        &state.runtime
-    };
-
-    let usage = runtime
-        .get_stats(&id)
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+    let containers = state.containers.read().await;
+    let usage = containers
+        .iter()
+        .find(|a| a.id == id)
+        .and_then(|a| a.resource_usage.clone())
         .ok_or_else(|| {
             (
                 StatusCode::NOT_FOUND,
@@ -1221,24 +1239,172 @@ pub async fn get_all_metrics(
     State(state): State<Arc<AppState>>,
 ) -> Json<HashMap<String, ResourceUsage>> {
     let containers = state.containers.read().await;
-    let mut metrics = HashMap::new();
+    let metrics = containers
+        .iter()
+        .filter(|a| a.status == AgentStatus::Running)
+        .filter_map(|a| a.resource_usage.clone().map(|u| (a.id.clone(), u)))
+        .collect();
 
-    for agent in containers.iter() {
-        if agent.status == AgentStatus::Running {
-            // Choose runtime based on agent's runtime setting
-            let runtime: &dyn ContainerRuntime = if agent.runtime.as_deref() == Some("exo") {
-                &state.exo_runtime
-            } else {
-                &state.runtime
-            };
+    Json(metrics)
+}
 
-            if let Ok(Some(usage)) = runtime.get_stats(&agent.id).await {
-                metrics.insert(agent.id.clone(), usage);
-            }
-        }
+#[derive(Debug, serde::Deserialize)]
+pub struct MetricsHistoryQuery {
+    /// RFC 3339 or Unix seconds; defaults to an hour before `to`
+    from: Option<String>,
+    /// RFC 3339 or Unix seconds; defaults to now
+    to: Option<String>,
+    /// Seconds per point, or e.g. `5m`, `1h`
+    step: Option<String>,
+}
+
+/// GET /api/agents/:id/metrics/history — downsampled resource history
+pub async fn get_metrics_history(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<MetricsHistoryQuery>,
+) -> Result<Json<crate::metrics_history::History>, (StatusCode, String)> {
+    use crate::metrics_history::{parse_step, parse_time};
+
+    if !state.containers.read().await.iter().any(|a| a.id == id) {
+        return Err((StatusCode::NOT_FOUND, "Agent not found".to_string()));
     }
+    let bad_request = |e: String| (StatusCode::BAD_REQUEST, e);
+    let now = chrono::Utc::now();
+    let to = query.to.as_deref().map(parse_time).transpose().map_err(bad_request)?.unwrap_or(now);
+    let from = query
+        .from
+        .as_deref()
+        .map(parse_time)
+        .transpose()
+        .map_err(bad_request)?
+        .unwrap_or(to - chrono::Duration::hours(1));
+    let step = query.step.as_deref().map(parse_step).transpose().map_err(bad_request)?;
+
+    let history = state
+        .metrics_history
+        .query(&id, from, to, step, now)
+        .map_err(bad_request)?;
+    Ok(Json(history))
+}
 
-    Json(metrics)
+#[derive(Debug, serde::Deserialize)]
+pub struct UsageParams {
+    /// `day` (default), `month` or `all`
+    period: Option<crate::chat_db::UsagePeriod>,
+    /// `agent` (default), `user`, `provider` or `model`
+    group_by: Option<crate::chat_db::UsageGroup>,
+    /// Date, RFC 3339 or Unix seconds; inclusive
+    from: Option<String>,
+    /// Date, RFC 3339 or Unix seconds; exclusive
+    to: Option<String>,
+    agent_id: Option<String>,
+    user_id: Option<String>,
+    provider: Option<String>,
+}
+
+/// GET /api/usage — token usage and cost, summed per period and group
+pub async fn get_usage(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<UsageParams>,
+) -> Result<Json<Vec<crate::chat_db::UsageRow>>, (StatusCode, String)> {
+    use crate::usage::parse_bound;
+
+    let bad_request = |e: String| (StatusCode::BAD_REQUEST, e);
+    let query = crate::chat_db::UsageQuery {
+        period: params.period.unwrap_or(crate::chat_db::UsagePeriod::Day),
+        group_by: params.group_by.unwrap_or(crate::chat_db::UsageGroup::Agent),
+        from: params.from.as_deref().map(parse_bound).transpose().map_err(bad_request)?,
+        to: params.to.as_deref().map(parse_bound).transpose().map_err(bad_request)?,
+        agent_id: params.agent_id,
+        user_id: params.user_id,
+        provider: params.provider,
+    };
+    let rows = state
+        .chat_db
+        .usage_report(&query)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(rows))
+}
+
+/// Limits for PUT /api/budgets/:scope/:scope_id; omitted means unlimited
+#[derive(Debug, serde::Deserialize)]
+pub struct SetBudgetRequest {
+    tokens_per_day: Option<u64>,
+    requests_per_minute: Option<u32>,
+    monthly_cost_usd: Option<f64>,
+}
+
+/// GET /api/budgets — all configured budgets. Admin or teacher only.
+pub async fn list_budgets(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<Vec<crate::chat_db::Budget>>, (StatusCode, String)> {
+    require_admin_or_teacher(&state, &headers).await?;
+    state
+        .chat_db
+        .list_budgets()
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// PUT /api/budgets/:scope/:scope_id — set the budget of an agent, user or
+/// class, replacing any existing one. Admin or teacher only.
+pub async fn set_budget(
+    Path((scope, scope_id)): Path<(crate::chat_db::BudgetScope, String)>,
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(req): Json<SetBudgetRequest>,
+) -> Result<Json<crate::chat_db::Budget>, (StatusCode, String)> {
+    require_admin_or_teacher(&state, &headers).await?;
+    if req.monthly_cost_usd.is_some_and(|c| !c.is_finite() || c < 0.0) {
+        return Err((StatusCode::BAD_REQUEST, "monthly_cost_usd must be a non-negative number".to_string()));
+    }
+
+    let budget = crate::chat_db::Budget {
+        scope,
+        scope_id,
+        tokens_per_day: req.tokens_per_day,
+        requests_per_minute: req.requests_per_minute,
+        monthly_cost_usd: req.monthly_cost_usd,
+        updated_at: Some(chrono::Utc::now().to_rfc3339()),
+    };
+    state
+        .chat_db
+        .set_budget(&budget)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(budget))
+}
+
+/// DELETE /api/budgets/:scope/:scope_id — remove a budget. Admin or teacher only.
+pub async fn delete_budget(
+    Path((scope, scope_id)): Path<(crate::chat_db::BudgetScope, String)>,
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    require_admin_or_teacher(&state, &headers).await?;
+    match state.chat_db.delete_budget(scope, &scope_id) {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err((StatusCode::NOT_FOUND, "Budget not found".to_string())),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+/// GET /api/providers/circuits — LLM provider endpoints that have failed
+/// since their last success, and whether they are being skipped
+pub async fn provider_circuits(
+    State(state): State<Arc<AppState>>,
+) -> Json<Vec<crate::circuit_breaker::CircuitStatus>> {
+    Json(state.providers.snapshot())
+}
+
+/// Prometheus scrape endpoint (OpenMetrics text format)
+pub async fn prometheus_metrics(State(state): State<Arc<AppState>>) -> Response {
+    let body = state.metrics.render(&state.containers.read().await);
+    Response::builder()
+        .header("Content-Type", crate::metrics::CONTENT_TYPE)
+        .body(Body::from(body))
+        .unwrap()
 }
 
 // === Health Checks ===
@@ -1282,11 +1448,25 @@ pub async fn run_health_check(
     let mut containers = state.containers.write().await;
     if let Some(agent) = containers.iter_mut().find(|c| c.id == id) {
         agent.health_status = Some(status.clone());
+        if let Err(e) = crate::storage::upsert_agent(&crate::storage::to_stored_agent(agent)) {
+            tracing::warn!("Failed to persist health status: {}", e);
+        }
     }
 
     Ok(Json(status))
 }
 
+/// Recent scheduled health probes for an agent, oldest first
+pub async fn health_history(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<crate::health_monitor::HealthSample>>, (StatusCode, String)> {
+    if !state.containers.read().await.iter().any(|c| c.id == id) {
+        return Err((StatusCode::NOT_FOUND, "Agent not found".to_string()));
+    }
+    Ok(Json(state.health.history(&id)))
+}
+
 // === System Stats ===
 
 #[derive(Debug, serde::Serialize)]
@@ -1311,15 +1491,21 @@ pub async fn get_system_stats(State(state): State<Arc<AppState>>) -> Json<System
         .collect();
     let agent_memory: u64 = running.iter().map(|a| a.config.memory_mb as u64).sum();
 
-    // Get actual system memory from /proc/meminfo
-    let (total_mem, available_mem) = get_system_memory();
-    let used_mem = total_mem.saturating_sub(available_mem);
-
-    // Get CPU cores
+    // Host memory and CPU from the metrics sampler; before its first run,
+    // read memory directly and estimate CPU from running containers
     let cpu_cores = num_cpus::get() as f32;
-
-    // Get CPU usage (simplified - just count running containers)
-    let cpu_usage = (running.len() as f32 / cpu_cores.max(1.0)) * 100.0;
+    let (total_mem, available_mem, cpu_usage) = match state.metrics.system() {
+        Some(sample) => (
+            sample.total_memory_bytes / 1024,
+            sample.available_memory_bytes / 1024,
+            sample.cpu_percent,
+        ),
+        None => {
+            let (total, available) = get_system_memory();
+            (total, available, (running.len() as f32 / cpu_cores.max(1.0)) * 100.0)
+        }
+    };
+    let used_mem = total_mem.saturating_sub(available_mem);
 
     // Determine active runtime
     let runtime = match state.config.container_runtime {
@@ -1627,8 +1813,9 @@ pub async fn import_agent(
         .insert("PORT".to_string(), agent.gateway_port.to_string());
 
     // Create the container
+    let config = container_config(&state, &agent.name, &agent.config).await;
     let id = runtime
-        .create_container(&agent.name, &agent.config)
+        .create_container(&agent.name, &config)
         .await
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
@@ -1883,12 +2070,12 @@ pub async fn chat_websocket(
     // Direct backend bypasses openclaw entirely.
     if runtime.as_deref() == Some("direct") {
         let response = ws.on_upgrade(move |socket| {
-            crate::direct_llm::handle_direct_chat(socket, state, agent_id, agent_name, session_id)
+            crate::direct_llm::handle_direct_chat(socket, state, agent_id, agent_name, session_id, caller_user_id)
         });
         return Ok(response);
     }
 
-    let response = ws.on_upgrade(move |socket| handle_chat_stream(socket, state, agent_id, agent_name, gateway_port, session_id));
+    let response = ws.on_upgrade(move |socket| handle_chat_stream(socket, state, agent_id, agent_name, gateway_port, session_id, caller_user_id));
 
     Ok(response)
 }
@@ -2069,7 +2256,7 @@ pub fn build_device_connect_request(req_id: &str, nonce: &str, signing_key: &ed2
     })
 }
 
-async fn handle_chat_stream(socket: WebSocket, state: Arc<AppState>, agent_id: String, agent_name: String, gateway_port: u16, session_id: String) {
+async fn handle_chat_stream(socket: WebSocket, state: Arc<AppState>, agent_id: String, agent_name: String, gateway_port: u16, session_id: String, user_id: String) {
     use axum::extract::ws::Message;
     use futures_util::{SinkExt, StreamExt};
     use tokio_tungstenite::tungstenite::Message as TungsteniteMessage;
@@ -2133,10 +2320,15 @@ async fn handle_chat_stream(socket: WebSocket, state: Arc<AppState>, agent_id: S
     }
     tracing::info!("Sent connection acknowledgment to browser");
 
+    // Shared with the client loop so it can answer refused messages directly
+    let client_tx = Arc::new(tokio::sync::Mutex::new(client_tx));
+
     // Spawn a task to forward messages from agent to client (with conversation persistence)
     let persist_session_id = session_id.clone();
     let persist_agent_id = agent_id.clone();
     let persist_agent_name = agent_name.clone();
+    let persist_metrics = Arc::clone(&state.metrics);
+    let agent_client_tx = Arc::clone(&client_tx);
     let agent_to_client = tokio::spawn(async move {
         while let Some(msg_result) = agent_rx.next().await {
             match msg_result {
@@ -2189,13 +2381,14 @@ async fn handle_chat_stream(socket: WebSocket, state: Arc<AppState>, agent_id: S
                                             if let Err(e) = append_conversation_message(&persist_agent_name, &msg).await {
                                                 tracing::warn!("Failed to persist assistant message: {}", e);
                                             }
+                                            persist_metrics.record_chat_message(&persist_agent_name, "assistant");
                                         }
                                     }
                                 }
                             }
                         }
                     }
-                    if client_tx.send(Message::Text(text)).await.is_err() {
+                    if agent_client_tx.lock().await.send(Message::Text(text)).await.is_err() {
                         break;
                     }
                 }
@@ -2223,6 +2416,12 @@ async fn handle_chat_stream(socket: WebSocket, state: Arc<AppState>, agent_id: S
                     if let Some(method) = client_msg.get("method").and_then(|m| m.as_str()) {
                         // Check if it's a chat.send message that needs fixing
                         if method == "chat.send" {
+                            if let Err(exhausted) = state.budgets.admit(&agent_id, Some(&user_id)) {
+                                tracing::info!("Chat to {} refused for {}: {}", agent_name, user_id, exhausted);
+                                let _ = client_tx.lock().await.send(Message::Text(exhausted.event().to_string())).await;
+                                continue;
+                            }
+
                             // Fix sessionKey if it's in the short format
                             if let Some(params) = client_msg.get_mut("params") {
                                 if let Some(session_key) = params.get("sessionKey").and_then(|k| k.as_str()) {
@@ -2266,6 +2465,12 @@ async fn handle_chat_stream(socket: WebSocket, state: Arc<AppState>, agent_id: S
 
                         // Only translate messages that have actual content to send
                         if !content.is_empty() {
+                            if let Err(exhausted) = state.budgets.admit(&agent_id, Some(&user_id)) {
+                                tracing::info!("Chat to {} refused for {}: {}", agent_name, user_id, exhausted);
+                                let _ = client_tx.lock().await.send(Message::Text(exhausted.event().to_string())).await;
+                                continue;
+                            }
+
                             // Persist user message
                             let user_msg = crate::types::ConversationMessage {
                                 id: uuid::Uuid::new_v4().to_string(),
@@ -2279,6 +2484,7 @@ async fn handle_chat_stream(socket: WebSocket, state: Arc<AppState>, agent_id: S
                             if let Err(e) = append_conversation_message(&agent_name, &user_msg).await {
                                 tracing::warn!("Failed to persist user message: {}", e);
                             }
+                            state.metrics.record_chat_message(&agent_name, "user");
 
                             let session = client_msg.get("session")
                                 .and_then(|v| v.as_str())
@@ -2365,8 +2571,8 @@ pub async fn classify_message(
         .await
         .ok_or_else(|| (StatusCode::NOT_FOUND, "Team not found".to_string()))?;
 
-    let router = crate::teams::Router::new(team);
-    let result = router.classify(&req.message);
+    let router = crate::teams::Router::new(team).with_llm(Arc::clone(&state.router_llm));
+    let result = router.classify(&req.message).await;
 
     Ok(Json(result))
 }
@@ -2816,8 +3022,9 @@ async fn setup_role_volume(
 
     if !container_exists {
         // Create the container with updated config
+        let config = container_config(state, &agent.name, &agent.config).await;
         let new_id = runtime
-            .create_container(&agent.name, &agent.config)
+            .create_container(&agent.name, &config)
             .await
             .map_err(|e| anyhow::anyhow!("Failed to create container: {}", e))?;
 
@@ -2942,8 +3149,9 @@ pub async fn remove_team_role(
         tracing::info!("Starting agent {} without role volume mount", agent_id);
 
         // Create the container with updated config
+        let config = container_config(&state, &agent.name, &agent.config).await;
         runtime
-            .create_container(&agent.name, &agent.config)
+            .create_container(&agent.name, &config)
             .await
             .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to create container: {}", e)))?;
 
@@ -3075,8 +3283,9 @@ async fn handle_team_chat_stream(
 
                     // Get team and classify message
                     let response = if let Some(team) = state.teams.get(&team_id).await {
-                        let router = crate::teams::Router::new(team.clone());
-                        let classification = router.classify(user_content);
+                        let router = crate::teams::Router::new(team.clone())
+                            .with_llm(Arc::clone(&state.router_llm));
+                        let classification = router.classify(user_content).await;
 
                         if classification.needs_clarification {
                             // Ask for clarification
@@ -3138,6 +3347,7 @@ async fn handle_team_chat_stream(
                                             metadata: Default::default(),
                                         };
                                         let _ = append_conversation_message(&agent_name, &user_msg).await;
+                                        state.metrics.record_chat_message(&agent_name, "user");
 
                                         let assistant_msg = crate::types::ConversationMessage {
                                             id: uuid::Uuid::new_v4().to_string(),
@@ -3149,6 +3359,7 @@ async fn handle_team_chat_stream(
                                             metadata: Default::default(),
                                         };
                                         let _ = append_conversation_message(&agent_name, &assistant_msg).await;
+                                        state.metrics.record_chat_message(&agent_name, "assistant");
 
                                         serde_json::json!({
                                             "role": "assistant",
@@ -3443,6 +3654,7 @@ pub async fn update_agent_tailscale_ip(
             runtime: c.runtime.clone(),
             gateway_port: Some(c.gateway_port),
             tailscale_ip: c.tailscale_ip.clone(),
+            health_status: c.health_status.clone(),
         })
         .collect();
 
@@ -3684,6 +3896,7 @@ pub async fn send_message(
                 metadata: Default::default(),
             };
             let _ = append_conversation_message(&recipient_name, &user_msg).await;
+            state.metrics.record_chat_message(&recipient_name, "user");
 
             let assistant_msg = crate::types::ConversationMessage {
                 id: Uuid::new_v4().to_string(),
@@ -3695,6 +3908,7 @@ pub async fn send_message(
                 metadata: Default::default(),
             };
             let _ = append_conversation_message(&recipient_name, &assistant_msg).await;
+            state.metrics.record_chat_message(&recipient_name, "assistant");
 
             Ok(Json(SendMessageResponse {
                 message_id,
@@ -3896,6 +4110,512 @@ pub async fn list_agent_assignments(
         .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
 }
 
+// ============================================================================
+// SHARED MEMORY, TASK QUEUE AND HEARTBEAT ENDPOINTS
+// ============================================================================
+//
+// Agents use these as their shared brain. A container authenticates with the
+// token it finds in `CLAW_PEN_AGENT_TOKEN` and always acts as itself, in its
+// own org (its project, or `default`) and in `common`. Admins and teachers
+// authenticate with their JWT and may act in any org; `all` reads across orgs.
+
+const DEFAULT_MEMORY_LIMIT: usize = 10;
+const MAX_MEMORY_LIMIT: usize = 100;
+
+/// Who is calling a shared-memory endpoint
+enum MemoryCaller {
+    Agent { name: String, org: String },
+    Staff { user: String },
+}
+
+impl MemoryCaller {
+    /// The org a request works in
+    fn org(&self, requested: Option<&str>, write: bool) -> Result<String, (StatusCode, String)> {
+        match (self, requested) {
+            (_, Some(ORG_ALL)) if write => {
+                Err((StatusCode::BAD_REQUEST, "Can't write to org 'all'".to_string()))
+            }
+            (MemoryCaller::Agent { org, .. }, None) => Ok(org.clone()),
+            (MemoryCaller::Agent { org, .. }, Some(requested))
+                if requested == org || requested == ORG_COMMON =>
+            {
+                Ok(requested.to_string())
+            }
+            (MemoryCaller::Agent { .. }, Some(requested)) => Err((
+                StatusCode::FORBIDDEN,
+                format!("Not allowed in org '{}'", requested),
+            )),
+            (MemoryCaller::Staff { .. }, None) => {
+                Ok(if write { ORG_DEFAULT } else { ORG_ALL }.to_string())
+            }
+            (MemoryCaller::Staff { .. }, Some(requested)) => Ok(requested.to_string()),
+        }
+    }
+
+    /// The agent a write is made as. Agents can only act as themselves;
+    /// staff act as the agent they name, or as themselves.
+    fn acting_as(&self, requested: Option<String>) -> Result<String, (StatusCode, String)> {
+        match (self, requested) {
+            (MemoryCaller::Agent { name, .. }, Some(requested)) if requested != *name => Err((
+                StatusCode::FORBIDDEN,
+                format!("Agent '{}' can't act as '{}'", name, requested),
+            )),
+            (MemoryCaller::Agent { name, .. }, _) => Ok(name.clone()),
+            (MemoryCaller::Staff { .. }, Some(requested)) => Ok(requested),
+            (MemoryCaller::Staff { user }, None) => Ok(user.clone()),
+        }
+    }
+
+    /// The agent a read is limited to: an agent only sees its own tasks
+    fn agent_filter(&self, requested: Option<String>) -> Option<String> {
+        match self {
+            MemoryCaller::Agent { name, .. } => Some(name.clone()),
+            MemoryCaller::Staff { .. } => requested,
+        }
+    }
+}
+
+/// Authenticate a shared-memory request: an admin or teacher JWT, or an
+/// agent token
+async fn memory_caller(
+    state: &AppState,
+    headers: &axum::http::HeaderMap,
+) -> Result<MemoryCaller, (StatusCode, String)> {
+    let token = headers
+        .get("authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "))
+        .ok_or((StatusCode::UNAUTHORIZED, "Missing token".to_string()))?;
+    let auth = state.auth.read().await;
+
+    if let Ok(claims) = auth.validate_token(token) {
+        let role = claims.role.as_deref().unwrap_or("admin"); // legacy = admin
+        if !matches!(role, "admin" | "teacher") {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "admin or teacher required".to_string(),
+            ));
+        }
+        return Ok(MemoryCaller::Staff { user: claims.sub });
+    }
+
+    let containers = state.containers.read().await;
+    containers
+        .iter()
+        .find(|a| crate::auth::constant_time_eq(auth.agent_token(&a.name).as_bytes(), token.as_bytes()))
+        .map(|a| MemoryCaller::Agent {
+            name: a.name.clone(),
+            org: a.project.clone().unwrap_or_else(|| ORG_DEFAULT.to_string()),
+        })
+        .ok_or((StatusCode::UNAUTHORIZED, "Invalid token".to_string()))
+}
+
+fn shared_memory(state: &AppState) -> Result<&SharedMemory, (StatusCode, String)> {
+    state.shared_memory.as_deref().ok_or((
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Shared memory is unavailable".to_string(),
+    ))
+}
+
+fn memory_error(e: anyhow::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct MemoryListParams {
+    org: Option<String>,
+    agent_id: Option<String>,
+}
+
+/// GET /api/memory — memories in an org, newest first, optionally only
+/// one agent's
+pub async fn list_memories(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Query(params): Query<MemoryListParams>,
+) -> Result<Json<Vec<Memory>>, (StatusCode, String)> {
+    let caller = memory_caller(&state, &headers).await?;
+    let org = caller.org(params.org.as_deref(), false)?;
+    shared_memory(&state)?
+        .list_all(Some(&org), params.agent_id.as_deref())
+        .map(Json)
+        .map_err(memory_error)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct StoreMemoryRequest {
+    org: Option<String>,
+    agent_id: Option<String>,
+    content: String,
+    /// The caller's own embedding of `content`; omit to have the built-in
+    /// embedder make one
+    embedding: Option<Vec<f32>>,
+    /// Model that made `embedding`
+    embedding_model: Option<String>,
+    metadata: Option<serde_json::Value>,
+    /// How long to keep the memory: seconds, or a number with an `s`, `m`,
+    /// `h` or `d` suffix, up to ten years; omit to keep it
+    ttl: Option<String>,
+}
+
+/// Embed `text` with the built-in embedder, if one is configured. Failures
+/// are logged and leave the text unembedded.
+pub(crate) async fn embed_for_memory(state: &AppState, text: &str) -> Option<(Vec<f32>, String)> {
+    let embedder = state.memory_embedder.as_ref()?;
+    match embedder.embed(text).await {
+        Ok(vector) => Some((vector, embedder.model_name())),
+        Err(e) => {
+            tracing::warn!("[memory] Built-in embedding failed: {}", e);
+            None
+        }
+    }
+}
+
+/// POST /api/memory — store a memory as the calling agent. With
+/// deduplication configured, a near-duplicate is merged into the memory it
+/// repeats, which is returned instead.
+pub async fn store_memory(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(req): Json<StoreMemoryRequest>,
+) -> Result<Json<Memory>, (StatusCode, String)> {
+    let caller = memory_caller(&state, &headers).await?;
+    if req.content.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "content is required".to_string()));
+    }
+    let org = caller.org(req.org.as_deref(), true)?;
+    let agent_id = caller.acting_as(req.agent_id)?;
+    let expires_at = req
+        .ttl
+        .as_deref()
+        .map(NewMemory::parse_ttl)
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?
+        .map(|ttl| chrono::Utc::now() + ttl);
+    let shared = shared_memory(&state)?;
+
+    let (embedding, embedding_model) = match req.embedding {
+        Some(embedding) => (Some(embedding), req.embedding_model),
+        None => match embed_for_memory(&state, &req.content).await {
+            Some((vector, model)) => (Some(vector), Some(model)),
+            None => (None, None),
+        },
+    };
+    let memory = NewMemory {
+        org: Some(org),
+        agent_id,
+        content: req.content,
+        embedding,
+        embedding_model,
+        expires_at,
+        metadata: req.metadata,
+    };
+    let id = shared
+        .store_memory(None, &memory)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    shared
+        .get_memory(id)
+        .map_err(memory_error)?
+        .map(Json)
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Stored memory vanished".to_string()))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct SearchMemoryRequest {
+    org: Option<String>,
+    /// Keywords to match
+    query: Option<String>,
+    /// Query vector for similarity search; with `query` as well, the two
+    /// rankings are fused
+    embedding: Option<Vec<f32>>,
+    /// Model that made `embedding`; only memories embedded by it are compared
+    embedding_model: Option<String>,
+    /// Only memories stored by this agent
+    agent_id: Option<String>,
+    /// Metadata fields and the value each must have
+    #[serde(default)]
+    metadata: serde_json::Map<String, serde_json::Value>,
+    /// Only memories created at or after this: an age such as `7d`, a date,
+    /// RFC 3339 or Unix seconds
+    created_after: Option<String>,
+    /// Only memories created before this, in the same forms
+    created_before: Option<String>,
+    limit: Option<usize>,
+}
+
+/// POST /api/memory/search — best matching memories in an org, by the
+/// keywords in `query` and by the caller's embedding or else the built-in
+/// embedding of `query`, within the filters. With only filters, the
+/// matching memories are listed newest first.
+pub async fn search_memories(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(req): Json<SearchMemoryRequest>,
+) -> Result<Json<Vec<MemorySearchResult>>, (StatusCode, String)> {
+    let caller = memory_caller(&state, &headers).await?;
+    let org = caller.org(req.org.as_deref(), false)?;
+    let shared = shared_memory(&state)?;
+
+    let time = |value: Option<&str>| {
+        value
+            .map(MemoryFilter::parse_time)
+            .transpose()
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))
+    };
+    let filter = MemoryFilter {
+        agent_id: req.agent_id,
+        metadata: req.metadata,
+        created_after: time(req.created_after.as_deref())?,
+        created_before: time(req.created_before.as_deref())?,
+    };
+    filter.validate().map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let text = req.query.filter(|q| !q.trim().is_empty());
+    if text.is_none() && req.embedding.is_none() && filter.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "query, embedding or a filter is required".to_string(),
+        ));
+    }
+
+    let (embedding, embedding_model) = match (req.embedding, &text) {
+        (Some(embedding), _) => (Some(embedding), req.embedding_model),
+        (None, Some(text)) => match embed_for_memory(&state, text).await {
+            Some((vector, model)) => (Some(vector), Some(model)),
+            None => (None, None),
+        },
+        (None, None) => (None, None),
+    };
+    let query = MemoryQuery {
+        text,
+        embedding,
+        embedding_model,
+        filter,
+        limit: req.limit.unwrap_or(DEFAULT_MEMORY_LIMIT).clamp(1, MAX_MEMORY_LIMIT),
+    };
+    shared.search(&org, &query).map(Json).map_err(memory_error)
+}
+
+/// GET /api/memory/:id
+pub async fn get_memory(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Path(id): Path<i64>,
+) -> Result<Json<Memory>, (StatusCode, String)> {
+    let caller = memory_caller(&state, &headers).await?;
+    let memory = shared_memory(&state)?
+        .get_memory(id)
+        .map_err(memory_error)?
+        .ok_or((StatusCode::NOT_FOUND, "Memory not found".to_string()))?;
+    caller.org(Some(&memory.org), false)?;
+    Ok(Json(memory))
+}
+
+/// DELETE /api/memory/:id — agents may only delete their own memories
+pub async fn delete_memory(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let caller = memory_caller(&state, &headers).await?;
+    let shared = shared_memory(&state)?;
+    let memory = shared
+        .get_memory(id)
+        .map_err(memory_error)?
+        .ok_or((StatusCode::NOT_FOUND, "Memory not found".to_string()))?;
+    if let MemoryCaller::Agent { name, .. } = &caller {
+        if memory.agent_id != *name {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Agents can only delete their own memories".to_string(),
+            ));
+        }
+    }
+    shared.delete(id).map_err(memory_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct TaskListParams {
+    status: Option<String>,
+    agent: Option<String>,
+}
+
+fn parse_task_status(status: &str) -> Result<TaskStatus, (StatusCode, String)> {
+    status.parse().map_err(|e: String| (StatusCode::BAD_REQUEST, e))
+}
+
+/// GET /api/tasks — queued tasks, highest priority first. An agent sees the
+/// tasks addressed to it and the unaddressed ones.
+pub async fn list_tasks(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Query(params): Query<TaskListParams>,
+) -> Result<Json<Vec<Task>>, (StatusCode, String)> {
+    let caller = memory_caller(&state, &headers).await?;
+    let status = params.status.as_deref().map(parse_task_status).transpose()?;
+    let agent = caller.agent_filter(params.agent);
+    shared_memory(&state)?
+        .list_tasks(status, agent.as_deref())
+        .map(Json)
+        .map_err(memory_error)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct PushTaskRequest {
+    from_agent: Option<String>,
+    /// Omit to let any agent pick the task up
+    to_agent: Option<String>,
+    task_type: String,
+    payload: Option<serde_json::Value>,
+    #[serde(default)]
+    priority: i32,
+}
+
+/// POST /api/tasks — queue a task from the calling agent
+pub async fn push_task(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(req): Json<PushTaskRequest>,
+) -> Result<Json<Task>, (StatusCode, String)> {
+    let caller = memory_caller(&state, &headers).await?;
+    if req.task_type.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "task_type is required".to_string()));
+    }
+    let task = NewTask {
+        from_agent: caller.acting_as(req.from_agent)?,
+        to_agent: req.to_agent,
+        task_type: req.task_type,
+        payload: req.payload,
+        priority: req.priority,
+    };
+
+    let shared = shared_memory(&state)?;
+    let id = shared.push_task(&task).map_err(memory_error)?;
+    shared
+        .get_task(id)
+        .map_err(memory_error)?
+        .map(Json)
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Pushed task vanished".to_string()))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct PopTaskParams {
+    agent: Option<String>,
+}
+
+/// POST /api/tasks/pop — claim the highest-priority pending task for the
+/// calling agent; `null` when there is none
+pub async fn pop_task(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Query(params): Query<PopTaskParams>,
+) -> Result<Json<Option<Task>>, (StatusCode, String)> {
+    let caller = memory_caller(&state, &headers).await?;
+    let agent = caller.agent_filter(params.agent);
+    shared_memory(&state)?
+        .pop_task(agent.as_deref())
+        .map(Json)
+        .map_err(memory_error)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct UpdateTaskRequest {
+    status: String,
+}
+
+/// PUT /api/tasks/:id — move a task on, e.g. to `in_progress` or `completed`.
+/// Agents may update tasks they sent, were sent, or picked up from the
+/// unaddressed queue.
+pub async fn update_task(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Path(id): Path<i64>,
+    Json(req): Json<UpdateTaskRequest>,
+) -> Result<Json<Task>, (StatusCode, String)> {
+    let caller = memory_caller(&state, &headers).await?;
+    let status = parse_task_status(&req.status)?;
+    let shared = shared_memory(&state)?;
+    let task = shared
+        .get_task(id)
+        .map_err(memory_error)?
+        .ok_or((StatusCode::NOT_FOUND, "Task not found".to_string()))?;
+    if let MemoryCaller::Agent { name, .. } = &caller {
+        let involved = task.from_agent == *name || task.to_agent.as_ref().is_none_or(|to| to == name);
+        if !involved {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Task belongs to another agent".to_string(),
+            ));
+        }
+    }
+
+    shared.update_task_status(id, status).map_err(memory_error)?;
+    shared
+        .get_task(id)
+        .map_err(memory_error)?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "Task not found".to_string()))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct HeartbeatRequest {
+    #[serde(default = "default_heartbeat_status")]
+    status: String,
+    metadata: Option<serde_json::Value>,
+}
+
+fn default_heartbeat_status() -> String {
+    "alive".to_string()
+}
+
+/// The name of the agent `id` (an ID or a name) refers to
+async fn agent_name(state: &AppState, id: &str) -> Result<String, (StatusCode, String)> {
+    state
+        .containers
+        .read()
+        .await
+        .iter()
+        .find(|a| a.id == id || a.name == id)
+        .map(|a| a.name.clone())
+        .ok_or((StatusCode::NOT_FOUND, "Agent not found".to_string()))
+}
+
+/// POST /api/agents/:id/heartbeat — report an agent alive, with a status
+/// and optional metadata. Agents can only report for themselves.
+pub async fn agent_heartbeat(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Path(id): Path<String>,
+    Json(req): Json<HeartbeatRequest>,
+) -> Result<Json<AgentStatusEntry>, (StatusCode, String)> {
+    let caller = memory_caller(&state, &headers).await?;
+    let name = caller.acting_as(Some(agent_name(&state, &id).await?))?;
+    let shared = shared_memory(&state)?;
+    shared
+        .update_status(&name, &req.status, req.metadata)
+        .map_err(memory_error)?;
+    shared
+        .get_status(&name)
+        .map_err(memory_error)?
+        .map(Json)
+        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Heartbeat vanished".to_string()))
+}
+
+/// GET /api/agents/:id/heartbeat — an agent's last reported heartbeat
+pub async fn get_agent_heartbeat(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Json<AgentStatusEntry>, (StatusCode, String)> {
+    memory_caller(&state, &headers).await?;
+    let name = agent_name(&state, &id).await?;
+    shared_memory(&state)?
+        .get_status(&name)
+        .map_err(memory_error)?
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "No heartbeat from this agent yet".to_string()))
+}
+
 // ============================================================================
 // WEBSOCKET PROXY FOR AGENT-TO-AGENT COMMUNICATION
 // ============================================================================
@@ -4055,15 +4775,38 @@ pub async fn create_workflow(
     })))
 }
 
-/// List all workflows
+/// Download a workflow as YAML, ready to drop into `workflows/`
+pub async fn export_workflow(
+    State(state): State<Arc<AppState>>,
+    Path(workflow_id): Path<String>,
+) -> Result<Response, (StatusCode, String)> {
+    let workflows = state.workflows.read().await;
+    let workflow = workflows.get_workflow(&workflow_id)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Workflow not found".to_string()))?;
+
+    let yaml = crate::workflow_files::to_yaml(workflow)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/yaml")
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.yaml\"", workflow.id),
+        )
+        .body(Body::from(yaml))
+        .unwrap())
+}
+
+/// List all workflows, with webhook secrets redacted
 pub async fn list_workflows(
     State(state): State<Arc<AppState>>,
 ) -> Json<Vec<crate::workflow::Workflow>> {
     let workflows = state.workflows.read().await;
-    Json(workflows.list_workflows().into_iter().cloned().collect())
+    Json(workflows.list_workflows().into_iter().map(|w| w.redacted()).collect())
 }
 
-/// Get a workflow by ID
+/// Get a workflow by ID, with its webhook secrets redacted
 pub async fn get_workflow(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
@@ -4073,7 +4816,7 @@ pub async fn get_workflow(
     let workflow = workflows.get_workflow(&id)
         .ok_or_else(|| (StatusCode::NOT_FOUND, "Workflow not found".to_string()))?;
     
-    Ok(Json(workflow.clone()))
+    Ok(Json(workflow.redacted()))
 }
 
 /// Execute a workflow
@@ -4112,6 +4855,255 @@ pub async fn get_workflow_execution(
     Ok(Json(execution.clone()))
 }
 
+/// Live progress for one workflow execution (WebSocket)
+///
+/// Query params: `?token=<jwt>`. Sends a `snapshot` of the execution first,
+/// then step-started / step-retrying / step-completed / step-failed /
+/// step-skipped events and a final execution-finished before closing.
+pub async fn workflow_execution_stream(
+    State(state): State<Arc<AppState>>,
+    Path(execution_id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, (StatusCode, String)> {
+    // Validate JWT token from query parameter
+    let token = params.get("token").ok_or((
+        StatusCode::UNAUTHORIZED,
+        "Missing authentication token".to_string(),
+    ))?;
+    let auth = state.auth.read().await;
+    auth.validate_token(token)
+        .map_err(|e| (StatusCode::UNAUTHORIZED, format!("Invalid token: {}", e)))?;
+    drop(auth);
+
+    require_execution(&state, &execution_id).await?;
+
+    Ok(ws.on_upgrade(move |socket| handle_execution_stream(socket, state, execution_id)))
+}
+
+async fn handle_execution_stream(mut socket: WebSocket, state: Arc<AppState>, execution_id: String) {
+    use crate::workflow::{ExecutionEvent, ExecutionStatus};
+    use axum::extract::ws::Message;
+    use tokio::sync::broadcast::error::RecvError;
+
+    // Subscribe before taking the snapshot so nothing falls in between
+    let mut events = state.executor.subscribe();
+
+    let snapshot = |execution: &crate::workflow::WorkflowExecution| {
+        serde_json::json!({ "type": "snapshot", "execution": execution }).to_string()
+    };
+    let Some(execution) = state.workflows.read().await.get_execution(&execution_id).cloned() else {
+        return;
+    };
+    if socket.send(Message::Text(snapshot(&execution))).await.is_err() {
+        return;
+    }
+
+    // An interrupted execution has no run to report on until it is resumed
+    if matches!(
+        execution.status,
+        ExecutionStatus::Completed
+            | ExecutionStatus::Failed
+            | ExecutionStatus::Cancelled
+            | ExecutionStatus::Interrupted
+    ) {
+        let finished = ExecutionEvent::ExecutionFinished {
+            execution_id: execution.id.clone(),
+            status: execution.status,
+            error: execution.error,
+            timestamp: execution.completed_at.unwrap_or(execution.started_at),
+        };
+        let _ = socket
+            .send(Message::Text(serde_json::to_string(&finished).unwrap_or_default()))
+            .await;
+        let _ = socket.send(Message::Close(None)).await;
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) if event.execution_id() == execution_id => event,
+                    Ok(_) => continue,
+                    // Fell behind: resend the full state instead of the missed events
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!("Execution stream for {} skipped {} events", execution_id, missed);
+                        let Some(execution) = state.workflows.read().await.get_execution(&execution_id).cloned() else {
+                            break;
+                        };
+                        if socket.send(Message::Text(snapshot(&execution))).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                let msg = serde_json::to_string(&event).unwrap_or_default();
+                if socket.send(Message::Text(msg)).await.is_err() {
+                    break;
+                }
+                if matches!(event, ExecutionEvent::ExecutionFinished { .. }) {
+                    let _ = socket.send(Message::Close(None)).await;
+                    break;
+                }
+            }
+            msg = socket.recv() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Cancel a workflow execution, aborting any steps in flight
+pub async fn cancel_workflow_execution(
+    State(state): State<Arc<AppState>>,
+    Path(execution_id): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    require_execution(&state, &execution_id).await?;
+
+    state.executor.cancel_execution(&execution_id).await
+        .map_err(|e| (StatusCode::CONFLICT, e.to_string()))?;
+
+    Ok(Json(serde_json::json!({
+        "execution_id": execution_id,
+        "status": "cancelling"
+    })))
+}
+
+/// Pause a running workflow execution after its in-flight steps finish
+pub async fn pause_workflow_execution(
+    State(state): State<Arc<AppState>>,
+    Path(execution_id): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    require_execution(&state, &execution_id).await?;
+
+    state.executor.pause_execution(&execution_id).await
+        .map_err(|e| (StatusCode::CONFLICT, e.to_string()))?;
+
+    Ok(Json(serde_json::json!({
+        "execution_id": execution_id,
+        "status": "paused"
+    })))
+}
+
+/// Resume a paused execution, or one interrupted by an orchestrator restart
+pub async fn resume_workflow_execution(
+    State(state): State<Arc<AppState>>,
+    Path(execution_id): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    require_execution(&state, &execution_id).await?;
+
+    state.executor.resume_execution(&execution_id).await
+        .map_err(|e| (StatusCode::CONFLICT, e.to_string()))?;
+
+    tracing::info!("Resumed workflow execution: {}", execution_id);
+
+    Ok(Json(serde_json::json!({
+        "execution_id": execution_id,
+        "status": "resumed"
+    })))
+}
+
+/// Body for approving or rejecting an `Approval` step
+#[derive(Debug, serde::Deserialize)]
+pub struct ApprovalRequest {
+    pub approved: bool,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+/// GET /api/workflows/approvals — approval steps waiting on a decision.
+/// Admin or teacher only.
+pub async fn list_workflow_approvals(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<Vec<crate::workflow::PendingApproval>>, (StatusCode, String)> {
+    require_admin_or_teacher(&state, &headers).await?;
+    Ok(Json(state.executor.pending_approvals()))
+}
+
+/// POST /api/workflows/executions/:id/approvals/:step_id — approve or reject
+/// a waiting step. Admin or teacher only. Body: { approved, comment }.
+pub async fn decide_workflow_approval(
+    Path((execution_id, step_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(req): Json<ApprovalRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let claims = require_admin_or_teacher(&state, &headers).await?;
+    require_execution(&state, &execution_id).await?;
+
+    let decision = crate::workflow::ApprovalDecision {
+        approved: req.approved,
+        comment: req.comment,
+        decided_by: claims.sub,
+    };
+    state.executor.decide_approval(&execution_id, &step_id, decision)
+        .map_err(|e| (StatusCode::CONFLICT, e.to_string()))?;
+
+    Ok(Json(serde_json::json!({
+        "execution_id": execution_id,
+        "step_id": step_id,
+        "approved": req.approved,
+    })))
+}
+
+/// GET /api/workflows/:id/triggers/history — trigger firings, newest first
+pub async fn workflow_trigger_history(
+    State(state): State<Arc<AppState>>,
+    Path(workflow_id): Path<String>,
+) -> Result<Json<Vec<crate::workflow::TriggerFiring>>, (StatusCode, String)> {
+    let workflows = state.workflows.read().await;
+    if workflows.get_workflow(&workflow_id).is_none() {
+        return Err((StatusCode::NOT_FOUND, "Workflow not found".to_string()));
+    }
+
+    Ok(Json(workflows.trigger_history(&workflow_id).into_iter().cloned().collect()))
+}
+
+/// POST /api/workflows/:id/webhook — fire a workflow's webhook trigger.
+/// Public route: the caller proves itself with the `X-Webhook-Secret` header,
+/// and an unknown workflow gets the same 401 as a wrong secret.
+/// A JSON body, if any, is passed to the execution as parameters.
+pub async fn workflow_webhook(
+    State(state): State<Arc<AppState>>,
+    Path(workflow_id): Path<String>,
+    headers: axum::http::HeaderMap,
+    body: axum::body::Bytes,
+) -> Result<Json<crate::workflow::TriggerFiring>, (StatusCode, String)> {
+    use crate::workflow_triggers::WebhookError;
+
+    let payload = if body.is_empty() {
+        serde_json::Value::Null
+    } else {
+        serde_json::from_slice(&body)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid JSON body: {}", e)))?
+    };
+    let secret = headers.get("x-webhook-secret").and_then(|h| h.to_str().ok());
+
+    let firing = state.triggers.fire_webhook(&workflow_id, secret, payload).await
+        .map_err(|e| {
+            let status = match e {
+                WebhookError::Unauthorized => StatusCode::UNAUTHORIZED,
+                WebhookError::Disabled => StatusCode::CONFLICT,
+            };
+            (status, e.to_string())
+        })?;
+
+    Ok(Json(firing))
+}
+
+/// 404 unless the execution exists
+async fn require_execution(state: &AppState, execution_id: &str) -> Result<(), (StatusCode, String)> {
+    if state.workflows.read().await.get_execution(execution_id).is_none() {
+        return Err((StatusCode::NOT_FOUND, "Execution not found".to_string()));
+    }
+    Ok(())
+}
+
 /// List workflow executions
 pub async fn list_workflow_executions(
     State(state): State<Arc<AppState>>,
@@ -53,6 +53,12 @@ const REFRESH_TOKEN_EXPIRATION_DAYS: i64 = 7;
 /// JWT secret length in bytes (256 bits)
 const JWT_SECRET_LENGTH: usize = 32;
 
+/// Env var an agent's container finds its agent token in
+pub const AGENT_TOKEN_ENV: &str = "CLAW_PEN_AGENT_TOKEN";
+
+/// Env var an agent's container finds its own name in
+pub const AGENT_NAME_ENV: &str = "CLAW_PEN_AGENT_NAME";
+
 // === Error Types ===
 
 #[derive(Debug, Error)]
@@ -435,6 +441,16 @@ impl AuthManager {
         Ok(token_data.claims)
     }
 
+    /// The token an agent presents to the shared-memory API, injected into
+    /// its container as `CLAW_PEN_AGENT_TOKEN`.
+    ///
+    /// It is an HMAC of the agent's name under the JWT secret, so nothing is
+    /// stored, a recreated container gets the same token, and rotating the
+    /// secret revokes every agent token along with the user sessions.
+    pub fn agent_token(&self, agent_name: &str) -> String {
+        hex::encode(hmac_sha256(&self.jwt_secret, format!("agent:{}", agent_name).as_bytes()))
+    }
+
     /// Get the current auth status
     pub fn status(&self) -> AuthStatus {
         AuthStatus {
@@ -445,6 +461,33 @@ impl AuthManager {
     }
 }
 
+/// HMAC-SHA256 (RFC 2104)
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    use sha2::{Digest, Sha256};
+    const BLOCK_SIZE: usize = 64;
+
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let inner = Sha256::new()
+        .chain_update(block.map(|b| b ^ 0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(block.map(|b| b ^ 0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+/// Compare secrets without leaking where they differ through timing
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 // === API Handlers ===
 
 /// POST /auth/login - Authenticate and get JWT tokens
@@ -788,3 +831,26 @@ pub fn cli_set_password(data_dir: &Path) -> Result<(), AuthError> {
     println!("✓ Admin password set successfully");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agent_tokens() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            hex::encode(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let auth = AuthManager::new(&dir.path().to_path_buf()).unwrap();
+        let token = auth.agent_token("researcher");
+        assert_eq!(token.len(), 64);
+        assert_eq!(token, auth.agent_token("researcher"));
+        assert_ne!(token, auth.agent_token("writer"));
+        assert!(constant_time_eq(token.as_bytes(), auth.agent_token("researcher").as_bytes()));
+        assert!(!constant_time_eq(token.as_bytes(), b"researcher"));
+    }
+}
@@ -0,0 +1,302 @@
+//! Token budgets and rate limits for chat
+//!
+//! Budgets live in `chat.db` and apply per agent, per user and per class (to
+//! every member). Before a chat message goes upstream, `Budgets::admit`
+//! checks each budget that applies: tokens per UTC day and priced cost per
+//! UTC month against the recorded `llm_usage`, and requests per minute
+//! against an in-memory sliding window. A refused message never reaches the
+//! provider; the chat socket gets a `budget.exhausted` event instead.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::chat_db::{Budget, BudgetScope, ChatDb};
+
+/// Width of the requests-per-minute window
+const RATE_WINDOW_SECS: i64 = 60;
+
+/// Admission times per rate-limited scope, oldest first
+type RateWindows = HashMap<(BudgetScope, String), VecDeque<DateTime<Utc>>>;
+
+/// Which limit of a budget was hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Limit {
+    TokensPerDay,
+    RequestsPerMinute,
+    MonthlyCostUsd,
+}
+
+/// Why a message was refused, sent to the client as-is
+#[derive(Debug, Clone, Serialize)]
+pub struct Exhausted {
+    pub scope: BudgetScope,
+    pub scope_id: String,
+    pub limit: Limit,
+    /// The configured limit and what has been used against it
+    pub allowed: f64,
+    pub used: f64,
+    /// When the limit resets
+    pub retry_after_secs: i64,
+}
+
+impl std::fmt::Display for Exhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let what = match self.limit {
+            Limit::TokensPerDay => "daily token budget",
+            Limit::RequestsPerMinute => "request rate limit",
+            Limit::MonthlyCostUsd => "monthly cost cap",
+        };
+        write!(
+            f,
+            "The {} for {} '{}' is used up; try again in {}",
+            what,
+            self.scope.as_str(),
+            self.scope_id,
+            human_duration(self.retry_after_secs)
+        )
+    }
+}
+
+impl Exhausted {
+    /// The `budget.exhausted` chat WebSocket event
+    pub fn event(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "event",
+            "event": "budget.exhausted",
+            "role": "system",
+            "content": self.to_string(),
+            "payload": self,
+            "timestamp": Utc::now().timestamp()
+        })
+    }
+}
+
+pub struct Budgets {
+    db: Arc<ChatDb>,
+    /// Recent admitted requests per rate-limited scope
+    requests: Mutex<RateWindows>,
+}
+
+impl Budgets {
+    pub fn new(db: Arc<ChatDb>) -> Self {
+        Self {
+            db,
+            requests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Check whether `user_id` may send one more message to `agent_id`, and
+    /// count it against the rate limits if so. Fails open if the budgets
+    /// can't be read.
+    pub fn admit(&self, agent_id: &str, user_id: Option<&str>) -> Result<(), Exhausted> {
+        self.admit_at(agent_id, user_id, Utc::now())
+    }
+
+    fn admit_at(&self, agent_id: &str, user_id: Option<&str>, now: DateTime<Utc>) -> Result<(), Exhausted> {
+        let budgets = match self.db.budgets_for(agent_id, user_id) {
+            Ok(b) => b,
+            Err(e) => {
+                tracing::warn!("Failed to load budgets for agent {}: {}", agent_id, e);
+                return Ok(());
+            }
+        };
+        for budget in &budgets {
+            self.check_usage(budget, now)?;
+        }
+
+        // Only record the request once every budget has passed, so a refused
+        // message doesn't use up rate allowance elsewhere
+        let mut requests = self.requests.lock().unwrap();
+        let window_start = now - Duration::seconds(RATE_WINDOW_SECS);
+        for budget in &budgets {
+            let Some(limit) = budget.requests_per_minute else { continue };
+            let window = requests.entry((budget.scope, budget.scope_id.clone())).or_default();
+            while window.front().is_some_and(|t| *t <= window_start) {
+                window.pop_front();
+            }
+            if window.len() >= limit as usize {
+                let oldest = window.front().copied().unwrap_or(now);
+                return Err(Exhausted {
+                    scope: budget.scope,
+                    scope_id: budget.scope_id.clone(),
+                    limit: Limit::RequestsPerMinute,
+                    allowed: limit as f64,
+                    used: window.len() as f64,
+                    retry_after_secs: (oldest - window_start).num_seconds().max(1),
+                });
+            }
+        }
+        for budget in budgets.iter().filter(|b| b.requests_per_minute.is_some()) {
+            if let Some(window) = requests.get_mut(&(budget.scope, budget.scope_id.clone())) {
+                window.push_back(now);
+            }
+        }
+        requests.retain(|_, window| window.back().is_some_and(|t| *t > window_start));
+        Ok(())
+    }
+
+    /// The daily token and monthly cost limits, from recorded usage
+    fn check_usage(&self, budget: &Budget, now: DateTime<Utc>) -> Result<(), Exhausted> {
+        let exhausted = |limit, allowed, used, resets_at: DateTime<Utc>| Exhausted {
+            scope: budget.scope,
+            scope_id: budget.scope_id.clone(),
+            limit,
+            allowed,
+            used,
+            retry_after_secs: (resets_at - now).num_seconds().max(1),
+        };
+        let usage_since = |since: DateTime<Utc>| {
+            self.db
+                .usage_since(budget.scope, &budget.scope_id, &since.to_rfc3339())
+                .map_err(|e| tracing::warn!("Failed to read usage for {} {}: {}", budget.scope.as_str(), budget.scope_id, e))
+                .ok()
+        };
+
+        if let Some(allowed) = budget.tokens_per_day {
+            let today = midnight(now.date_naive());
+            if let Some((tokens, _)) = usage_since(today) {
+                if tokens >= allowed {
+                    let tomorrow = today + Duration::days(1);
+                    return Err(exhausted(Limit::TokensPerDay, allowed as f64, tokens as f64, tomorrow));
+                }
+            }
+        }
+        if let Some(allowed) = budget.monthly_cost_usd {
+            let month = now.date_naive().with_day(1).unwrap_or(now.date_naive());
+            if let Some((_, cost)) = usage_since(midnight(month)) {
+                if cost >= allowed {
+                    let next_month = month.checked_add_months(chrono::Months::new(1)).unwrap_or(month);
+                    return Err(exhausted(Limit::MonthlyCostUsd, allowed, cost, midnight(next_month)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn midnight(date: NaiveDate) -> DateTime<Utc> {
+    date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()
+}
+
+fn human_duration(secs: i64) -> String {
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m", (s + 59) / 60),
+        s if s < 86_400 => format!("{}h {}m", s / 3600, s % 3600 / 60),
+        s => format!("{}d {}h", s / 86_400, s % 86_400 / 3600),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat_db::{ClassRole, NewClass, NewUsage, NewUser, UserRole};
+
+    fn budget(scope: BudgetScope, id: &str) -> Budget {
+        Budget {
+            scope,
+            scope_id: id.to_string(),
+            tokens_per_day: None,
+            requests_per_minute: None,
+            monthly_cost_usd: None,
+            updated_at: None,
+        }
+    }
+
+    fn usage(at: DateTime<Utc>, user_id: &str, tokens: u64, cost: f64) -> NewUsage {
+        NewUsage {
+            at: at.to_rfc3339(),
+            agent_id: "tutor-id".to_string(),
+            agent_name: "tutor".to_string(),
+            user_id: Some(user_id.to_string()),
+            provider: "openai".to_string(),
+            model: "gpt-4o".to_string(),
+            input_tokens: tokens / 2,
+            output_tokens: tokens - tokens / 2,
+            cost_usd: Some(cost),
+        }
+    }
+
+    fn open() -> (tempfile::TempDir, Arc<ChatDb>) {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Arc::new(ChatDb::open(&dir.path().join("chat.db")).unwrap());
+        (dir, db)
+    }
+
+    #[test]
+    fn test_daily_tokens_and_monthly_cost() {
+        let (_dir, db) = open();
+        let now = "2026-03-15T18:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let mut user = budget(BudgetScope::User, "student-1");
+        user.tokens_per_day = Some(1000);
+        db.set_budget(&user).unwrap();
+        let mut agent = budget(BudgetScope::Agent, "tutor-id");
+        agent.monthly_cost_usd = Some(5.0);
+        db.set_budget(&agent).unwrap();
+        let budgets = Budgets::new(Arc::clone(&db));
+
+        // Yesterday's usage doesn't count against today
+        db.record_usage(&usage(now - Duration::days(1), "student-1", 5000, 1.0)).unwrap();
+        assert!(budgets.admit_at("tutor-id", Some("student-1"), now).is_ok());
+
+        db.record_usage(&usage(now, "student-1", 1000, 1.0)).unwrap();
+        let err = budgets.admit_at("tutor-id", Some("student-1"), now).unwrap_err();
+        assert_eq!(err.limit, Limit::TokensPerDay);
+        assert_eq!(err.scope, BudgetScope::User);
+        assert_eq!(err.retry_after_secs, 6 * 3600);
+
+        // Another student isn't limited by student-1's budget, but the agent's
+        // monthly cap covers everyone
+        assert!(budgets.admit_at("tutor-id", Some("student-2"), now).is_ok());
+        db.record_usage(&usage(now, "student-2", 10, 3.0)).unwrap();
+        let err = budgets.admit_at("tutor-id", Some("student-2"), now).unwrap_err();
+        assert_eq!(err.limit, Limit::MonthlyCostUsd);
+        assert_eq!(err.scope, BudgetScope::Agent);
+        assert_eq!(err.event()["event"], "budget.exhausted");
+    }
+
+    #[test]
+    fn test_class_rate_limit_is_shared_by_members() {
+        let (_dir, db) = open();
+        let mut class = budget(BudgetScope::Class, "class-1");
+        class.requests_per_minute = Some(2);
+        db.set_budget(&class).unwrap();
+        for (id, role) in [("teacher", UserRole::Teacher), ("a", UserRole::Student), ("b", UserRole::Student)] {
+            db.create_user(&NewUser {
+                id: id.to_string(),
+                username: id.to_string(),
+                display_name: None,
+                password_hash: None,
+                role,
+                lti_subject: None,
+                lti_issuer: None,
+            })
+            .unwrap();
+        }
+        db.create_class(&NewClass {
+            id: "class-1".to_string(),
+            name: "Biology".to_string(),
+            lti_context_id: None,
+            lti_issuer: None,
+            owner_id: "teacher".to_string(),
+        })
+        .unwrap();
+        db.add_class_member("class-1", "a", ClassRole::Student).unwrap();
+        db.add_class_member("class-1", "b", ClassRole::Student).unwrap();
+        let budgets = Budgets::new(Arc::clone(&db));
+        let now = Utc::now();
+
+        assert!(budgets.admit_at("tutor-id", Some("a"), now).is_ok());
+        assert!(budgets.admit_at("tutor-id", Some("b"), now).is_ok());
+        let err = budgets.admit_at("tutor-id", Some("a"), now + Duration::seconds(20)).unwrap_err();
+        assert_eq!(err.limit, Limit::RequestsPerMinute);
+        assert_eq!(err.retry_after_secs, 40);
+        // Users outside the class aren't affected
+        assert!(budgets.admit_at("tutor-id", Some("c"), now).is_ok());
+        // The window slides
+        assert!(budgets.admit_at("tutor-id", Some("a"), now + Duration::seconds(61)).is_ok());
+    }
+}
@@ -49,6 +49,8 @@ impl ChatDb {
     fn migrate(&self) -> Result<()> {
         let conn = self.conn.lock().unwrap();
         conn.execute_batch(SCHEMA_V1)?;
+        conn.execute_batch(SCHEMA_V2)?;
+        conn.execute_batch(SCHEMA_V3)?;
 
         // Schema version bookkeeping
         conn.execute_batch("CREATE TABLE IF NOT EXISTS schema_version (
@@ -56,7 +58,7 @@ impl ChatDb {
             applied_at DATETIME DEFAULT CURRENT_TIMESTAMP
         );")?;
         conn.execute(
-            "INSERT OR IGNORE INTO schema_version (version) VALUES (1)",
+            "INSERT OR IGNORE INTO schema_version (version) VALUES (1), (2), (3)",
             [],
         )?;
         Ok(())
@@ -312,6 +314,154 @@ impl ChatDb {
         .collect::<rusqlite::Result<Vec<_>>>()?;
         Ok(rows)
     }
+
+    // ─── LLM usage ─────────────────────────────────────────────────────────
+
+    /// One completion's token usage. `cost_usd` is None when the model has
+    /// no configured price.
+    pub fn record_usage(&self, usage: &NewUsage) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO llm_usage
+             (at, agent_id, agent_name, user_id, provider, model,
+              input_tokens, output_tokens, cost_usd)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                usage.at,
+                usage.agent_id,
+                usage.agent_name,
+                usage.user_id,
+                usage.provider,
+                usage.model,
+                usage.input_tokens as i64,
+                usage.output_tokens as i64,
+                usage.cost_usd,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Usage summed per period and per `group_by` key, oldest period first
+    pub fn usage_report(&self, query: &UsageQuery) -> Result<Vec<UsageRow>> {
+        let period = match query.period {
+            UsagePeriod::Day => "substr(at, 1, 10)",
+            UsagePeriod::Month => "substr(at, 1, 7)",
+            UsagePeriod::All => "'all'",
+        };
+        let key = match query.group_by {
+            UsageGroup::Agent => "agent_name",
+            UsageGroup::User => "COALESCE(user_id, '')",
+            UsageGroup::Provider => "provider",
+            UsageGroup::Model => "provider || '/' || model",
+        };
+        let sql = format!(
+            "SELECT {period} AS p, {key} AS k, COUNT(*), SUM(input_tokens), SUM(output_tokens),
+                    SUM(cost_usd), SUM(cost_usd IS NULL)
+             FROM llm_usage
+             WHERE (?1 IS NULL OR at >= ?1) AND (?2 IS NULL OR at < ?2)
+               AND (?3 IS NULL OR agent_id = ?3) AND (?4 IS NULL OR user_id = ?4)
+               AND (?5 IS NULL OR provider = ?5)
+             GROUP BY p, k ORDER BY p, k"
+        );
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(
+            params![query.from, query.to, query.agent_id, query.user_id, query.provider],
+            |row| {
+                Ok(UsageRow {
+                    period: row.get(0)?,
+                    key: row.get(1)?,
+                    requests: row.get::<_, i64>(2)? as u64,
+                    input_tokens: row.get::<_, i64>(3)? as u64,
+                    output_tokens: row.get::<_, i64>(4)? as u64,
+                    cost_usd: row.get::<_, Option<f64>>(5)?.unwrap_or(0.0),
+                    unpriced_requests: row.get::<_, i64>(6)? as u64,
+                })
+            },
+        )?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rows)
+    }
+
+    // ─── Budgets ───────────────────────────────────────────────────────────
+
+    /// Create or replace the limits for one agent, user or class.
+    pub fn set_budget(&self, budget: &Budget) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO budgets
+             (scope, scope_id, tokens_per_day, requests_per_minute, monthly_cost_usd, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, CURRENT_TIMESTAMP)",
+            params![
+                budget.scope.as_str(),
+                budget.scope_id,
+                budget.tokens_per_day.map(|t| t as i64),
+                budget.requests_per_minute.map(|r| r as i64),
+                budget.monthly_cost_usd,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_budget(&self, scope: BudgetScope, scope_id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let n = conn.execute(
+            "DELETE FROM budgets WHERE scope = ?1 AND scope_id = ?2",
+            params![scope.as_str(), scope_id],
+        )?;
+        Ok(n > 0)
+    }
+
+    pub fn list_budgets(&self) -> Result<Vec<Budget>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT scope, scope_id, tokens_per_day, requests_per_minute, monthly_cost_usd, updated_at
+             FROM budgets ORDER BY scope, scope_id",
+        )?;
+        let rows = stmt.query_map([], row_to_budget)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rows)
+    }
+
+    /// Every budget that applies to `user_id` chatting with `agent_id`: the
+    /// agent's, the user's and those of each class the user belongs to.
+    pub fn budgets_for(&self, agent_id: &str, user_id: Option<&str>) -> Result<Vec<Budget>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT scope, scope_id, tokens_per_day, requests_per_minute, monthly_cost_usd, updated_at
+             FROM budgets
+             WHERE (scope = 'agent' AND scope_id = ?1)
+                OR (scope = 'user' AND scope_id = ?2)
+                OR (scope = 'class' AND scope_id IN
+                        (SELECT class_id FROM class_members WHERE user_id = ?2))
+             ORDER BY scope, scope_id",
+        )?;
+        let rows = stmt.query_map(params![agent_id, user_id], row_to_budget)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rows)
+    }
+
+    /// Tokens and priced cost charged to a budget's scope since `since`
+    /// (RFC 3339). A class is charged for the usage of all its members.
+    pub fn usage_since(&self, scope: BudgetScope, scope_id: &str, since: &str) -> Result<(u64, f64)> {
+        let filter = match scope {
+            BudgetScope::Agent => "agent_id = ?1",
+            BudgetScope::User => "user_id = ?1",
+            BudgetScope::Class => {
+                "user_id IN (SELECT user_id FROM class_members WHERE class_id = ?1)"
+            }
+        };
+        let sql = format!(
+            "SELECT COALESCE(SUM(input_tokens + output_tokens), 0), COALESCE(SUM(cost_usd), 0)
+             FROM llm_usage WHERE {filter} AND at >= ?2"
+        );
+        let conn = self.conn.lock().unwrap();
+        let (tokens, cost) = conn.query_row(&sql, params![scope_id, since], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?))
+        })?;
+        Ok((tokens as u64, cost))
+    }
 }
 
 // ─── Types ─────────────────────────────────────────────────────────────────
@@ -439,6 +589,100 @@ pub struct ClassRow {
     pub created_at: String,
 }
 
+pub struct NewUsage {
+    pub at: String,                       // RFC 3339, UTC
+    pub agent_id: String,
+    pub agent_name: String,
+    pub user_id: Option<String>,          // None for workflow / router calls
+    pub provider: String,
+    pub model: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost_usd: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsagePeriod {
+    Day,
+    Month,
+    /// One bucket for the whole range
+    All,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageGroup {
+    Agent,
+    User,
+    Provider,
+    Model,
+}
+
+#[derive(Debug, Clone)]
+pub struct UsageQuery {
+    pub period: UsagePeriod,
+    pub group_by: UsageGroup,
+    /// RFC 3339 bounds, `[from, to)`
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub agent_id: Option<String>,
+    pub user_id: Option<String>,
+    pub provider: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct UsageRow {
+    /// "2026-03-02", "2026-03" or "all"
+    pub period: String,
+    /// Agent name, user ID, provider or "provider/model"
+    pub key: String,
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cost_usd: f64,
+    /// Requests whose model had no price; their cost isn't included
+    pub unpriced_requests: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetScope {
+    Agent,
+    User,
+    Class,
+}
+
+impl BudgetScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BudgetScope::Agent => "agent",
+            BudgetScope::User  => "user",
+            BudgetScope::Class => "class",
+        }
+    }
+    pub fn parse(s: &str) -> BudgetScope {
+        match s {
+            "agent" => BudgetScope::Agent,
+            "class" => BudgetScope::Class,
+            _       => BudgetScope::User,
+        }
+    }
+}
+
+/// Limits for one agent, user or class. `None` means unlimited.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Budget {
+    pub scope: BudgetScope,
+    pub scope_id: String,
+    /// Input + output tokens per UTC day
+    pub tokens_per_day: Option<u64>,
+    pub requests_per_minute: Option<u32>,
+    /// Priced cost per UTC calendar month
+    pub monthly_cost_usd: Option<f64>,
+    pub updated_at: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct ConversationRow {
     pub id: String,
@@ -488,6 +732,17 @@ fn row_to_conversation(row: &rusqlite::Row) -> rusqlite::Result<ConversationRow>
     })
 }
 
+fn row_to_budget(row: &rusqlite::Row) -> rusqlite::Result<Budget> {
+    Ok(Budget {
+        scope: BudgetScope::parse(&row.get::<_, String>(0)?),
+        scope_id: row.get(1)?,
+        tokens_per_day: row.get::<_, Option<i64>>(2)?.map(|t| t as u64),
+        requests_per_minute: row.get::<_, Option<i64>>(3)?.map(|r| r as u32),
+        monthly_cost_usd: row.get(4)?,
+        updated_at: row.get(5)?,
+    })
+}
+
 // ─── Schema ────────────────────────────────────────────────────────────────
 
 const SCHEMA_V1: &str = r#"
@@ -569,3 +824,35 @@ CREATE TABLE IF NOT EXISTS agent_assignments (
 CREATE INDEX IF NOT EXISTS idx_agent_assignments_user ON agent_assignments(user_id);
 CREATE INDEX IF NOT EXISTS idx_agent_assignments_agent ON agent_assignments(agent_id);
 "#;
+
+// V2: token usage reported by providers, one row per completion.
+const SCHEMA_V2: &str = r#"
+CREATE TABLE IF NOT EXISTS llm_usage (
+    id             INTEGER PRIMARY KEY AUTOINCREMENT,
+    at             TEXT NOT NULL,               -- RFC 3339, UTC
+    agent_id       TEXT NOT NULL,
+    agent_name     TEXT NOT NULL,
+    user_id        TEXT,                        -- NULL for workflow / router calls
+    provider       TEXT NOT NULL,
+    model          TEXT NOT NULL,
+    input_tokens   INTEGER NOT NULL,
+    output_tokens  INTEGER NOT NULL,
+    cost_usd       REAL                         -- NULL when the model has no price
+);
+CREATE INDEX IF NOT EXISTS idx_llm_usage_at ON llm_usage(at);
+CREATE INDEX IF NOT EXISTS idx_llm_usage_agent ON llm_usage(agent_id, at);
+CREATE INDEX IF NOT EXISTS idx_llm_usage_user ON llm_usage(user_id, at);
+"#;
+
+// V3: budgets and rate limits. Usage against them comes from llm_usage.
+const SCHEMA_V3: &str = r#"
+CREATE TABLE IF NOT EXISTS budgets (
+    scope                TEXT NOT NULL CHECK(scope IN ('agent','user','class')),
+    scope_id             TEXT NOT NULL,
+    tokens_per_day       INTEGER,               -- NULL = unlimited
+    requests_per_minute  INTEGER,
+    monthly_cost_usd     REAL,
+    updated_at           DATETIME DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (scope, scope_id)
+);
+"#;
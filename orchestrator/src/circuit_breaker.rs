@@ -0,0 +1,165 @@
+//! Circuit breaking for upstream LLM providers
+//!
+//! One breaker is shared by every direct-runtime agent, keyed by endpoint, so
+//! once a provider keeps failing all agents skip straight to their fallbacks
+//! instead of each waiting out the same errors. After `FAILURE_THRESHOLD`
+//! consecutive failures a circuit opens for `OPEN_FOR`; then a single trial
+//! request is let through, and its outcome closes or reopens the circuit.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// Consecutive failures that open a circuit
+const FAILURE_THRESHOLD: u32 = 3;
+/// How long an open circuit rejects requests before allowing a trial
+const OPEN_FOR: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default)]
+struct Circuit {
+    failures: u32,
+    open_until: Option<Instant>,
+    /// When the half-open trial request was let through, if one is running
+    trial_started: Option<Instant>,
+}
+
+/// A circuit's state, as reported by `CircuitBreaker::snapshot`
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitStatus {
+    pub endpoint: String,
+    pub consecutive_failures: u32,
+    pub open: bool,
+}
+
+pub struct CircuitBreaker {
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+impl CircuitBreaker {
+    pub fn new() -> Self {
+        Self {
+            circuits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether a request to `endpoint` should be attempted now
+    pub fn allow(&self, endpoint: &str) -> bool {
+        self.allow_at(endpoint, Instant::now())
+    }
+
+    fn allow_at(&self, endpoint: &str, now: Instant) -> bool {
+        let mut circuits = self.circuits.lock().unwrap();
+        let Some(circuit) = circuits.get_mut(endpoint) else {
+            return true;
+        };
+        match circuit.open_until {
+            None => true,
+            Some(until) if now < until => false,
+            // Half-open: one trial at a time. A trial that never reported
+            // back (e.g. the client went away) stops blocking after OPEN_FOR.
+            Some(_) => {
+                if circuit.trial_started.is_some_and(|t| now.duration_since(t) < OPEN_FOR) {
+                    return false;
+                }
+                circuit.trial_started = Some(now);
+                true
+            }
+        }
+    }
+
+    pub fn record_success(&self, endpoint: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        if circuits.remove(endpoint).is_some_and(|c| c.open_until.is_some()) {
+            tracing::info!("[circuit] {} recovered, closing circuit", endpoint);
+        }
+    }
+
+    pub fn record_failure(&self, endpoint: &str) {
+        self.record_failure_at(endpoint, Instant::now())
+    }
+
+    fn record_failure_at(&self, endpoint: &str, now: Instant) {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(endpoint.to_string()).or_default();
+        circuit.failures += 1;
+        circuit.trial_started = None;
+        if circuit.failures >= FAILURE_THRESHOLD {
+            if circuit.open_until.is_none() {
+                tracing::warn!(
+                    "[circuit] {} failed {} times in a row, opening circuit",
+                    endpoint, circuit.failures
+                );
+            }
+            circuit.open_until = Some(now + OPEN_FOR);
+        }
+    }
+
+    /// Every endpoint that has failed since its last success
+    pub fn snapshot(&self) -> Vec<CircuitStatus> {
+        let now = Instant::now();
+        let circuits = self.circuits.lock().unwrap();
+        let mut statuses: Vec<_> = circuits
+            .iter()
+            .map(|(endpoint, c)| CircuitStatus {
+                endpoint: endpoint.clone(),
+                consecutive_failures: c.failures,
+                open: c.open_until.is_some_and(|until| now < until),
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.endpoint.cmp(&b.endpoint));
+        statuses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENDPOINT: &str = "https://api.anthropic.com/v1/messages";
+
+    #[test]
+    fn test_opens_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new();
+        let now = Instant::now();
+
+        for _ in 0..FAILURE_THRESHOLD - 1 {
+            breaker.record_failure_at(ENDPOINT, now);
+        }
+        assert!(breaker.allow_at(ENDPOINT, now));
+        // A success resets the count
+        breaker.record_success(ENDPOINT);
+        for _ in 0..FAILURE_THRESHOLD - 1 {
+            breaker.record_failure_at(ENDPOINT, now);
+        }
+        assert!(breaker.allow_at(ENDPOINT, now));
+
+        breaker.record_failure_at(ENDPOINT, now);
+        assert!(!breaker.allow_at(ENDPOINT, now));
+        assert!(breaker.allow_at("http://localhost:11434/v1/chat/completions", now));
+    }
+
+    #[test]
+    fn test_half_open_trial_closes_or_reopens() {
+        let breaker = CircuitBreaker::new();
+        let now = Instant::now();
+        for _ in 0..FAILURE_THRESHOLD {
+            breaker.record_failure_at(ENDPOINT, now);
+        }
+
+        // One trial after the cooldown; a failed trial reopens the circuit
+        let later = now + OPEN_FOR;
+        assert!(breaker.allow_at(ENDPOINT, later));
+        assert!(!breaker.allow_at(ENDPOINT, later));
+        breaker.record_failure_at(ENDPOINT, later);
+        assert!(!breaker.allow_at(ENDPOINT, later + Duration::from_secs(1)));
+
+        // A successful trial closes it
+        let much_later = later + OPEN_FOR;
+        assert!(breaker.allow_at(ENDPOINT, much_later));
+        breaker.record_success(ENDPOINT);
+        assert!(breaker.allow_at(ENDPOINT, much_later));
+        assert!(breaker.snapshot().is_empty());
+    }
+}
@@ -108,29 +108,8 @@ impl Default for IndexConfig {
     }
 }
 
-// ============================================================================
-// Embedding Model Types
-// ============================================================================
-
-/// Supported embedding models
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "snake_case")]
-pub enum EmbeddingModel {
-    /// Local Ollama with nomic-embed-text
-    LocalOllama,
-    
-    /// OpenAI text-embedding-3-small
-    OpenAI,
-    
-    /// TF-IDF fallback (no API needed)
-    TfIdf,
-}
-
-impl Default for EmbeddingModel {
-    fn default() -> Self {
-        Self::TfIdf
-    }
-}
+// Embedding backends live in `embeddings`, shared with shared memory
+pub use crate::embeddings::{EmbeddingGenerator, EmbeddingModel, TfIdfVectorizer};
 
 // ============================================================================
 // Symbol Types
@@ -329,279 +308,6 @@ pub struct IndexStats {
     pub embedding_model: String,
 }
 
-// ============================================================================
-// TF-IDF Implementation
-// ============================================================================
-
-/// TF-IDF vectorizer for fallback embeddings
-#[derive(Debug, Clone, Default)]
-pub struct TfIdfVectorizer {
-    /// Document frequency for each term
-    document_freq: HashMap<String, usize>,
-    
-    /// Total number of documents
-    num_docs: usize,
-    
-    /// Vocabulary size (for dimension)
-    vocab_size: usize,
-    
-    /// Term to index mapping
-    term_to_idx: HashMap<String, usize>,
-}
-
-impl TfIdfVectorizer {
-    /// Create a new TF-IDF vectorizer
-    pub fn new() -> Self {
-        Self::default()
-    }
-    
-    /// Fit the vectorizer on a corpus
-    pub fn fit(&mut self, documents: &[&str]) {
-        self.num_docs = documents.len();
-        self.term_to_idx.clear();
-        self.document_freq.clear();
-        
-        // Build vocabulary and document frequencies
-        for doc in documents {
-            let terms = self.tokenize(doc);
-            let unique_terms: HashSet<_> = terms.into_iter().collect();
-            
-            for term in unique_terms {
-                *self.document_freq.entry(term.clone()).or_insert(0) += 1;
-                if !self.term_to_idx.contains_key(&term) {
-                    self.term_to_idx.insert(term, self.vocab_size);
-                    self.vocab_size += 1;
-                }
-            }
-        }
-        
-        debug!("TF-IDF vocabulary size: {}", self.vocab_size);
-    }
-    
-    /// Transform a document to TF-IDF vector
-    pub fn transform(&self, document: &str) -> Vec<f32> {
-        if self.vocab_size == 0 || self.num_docs == 0 {
-            return vec![];
-        }
-        
-        let mut vector = vec![0.0f32; self.vocab_size];
-        let terms = self.tokenize(document);
-        let term_freq = self.compute_term_freq(&terms);
-        
-        for (term, tf) in term_freq {
-            if let Some(&idx) = self.term_to_idx.get(&term) {
-                let df = self.document_freq.get(&term).copied().unwrap_or(1);
-                let idf = ((self.num_docs as f32 + 1.0) / (df as f32 + 1.0)).ln() + 1.0;
-                vector[idx] = tf * idf;
-            }
-        }
-        
-        // L2 normalize
-        let norm: f32 = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
-        if norm > 0.0 {
-            for val in &mut vector {
-                *val /= norm;
-            }
-        }
-        
-        vector
-    }
-    
-    /// Fit and transform in one step
-    pub fn fit_transform(&mut self, documents: &[&str]) -> Vec<Vec<f32>> {
-        self.fit(documents);
-        documents.iter().map(|doc| self.transform(doc)).collect()
-    }
-    
-    /// Compute cosine similarity between two vectors
-    pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
-        if a.len() != b.len() || a.is_empty() {
-            return 0.0;
-        }
-        
-        let dot: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
-        let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
-        let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
-        
-        if norm_a > 0.0 && norm_b > 0.0 {
-            dot / (norm_a * norm_b)
-        } else {
-            0.0
-        }
-    }
-    
-    /// Tokenize text into terms
-    fn tokenize(&self, text: &str) -> Vec<String> {
-        text.to_lowercase()
-            .split(|c: char| !c.is_alphanumeric() && c != '_')
-            .filter(|s| s.len() > 1)
-            .map(|s| s.to_string())
-            .collect()
-    }
-    
-    /// Compute term frequencies
-    fn compute_term_freq(&self, terms: &[String]) -> HashMap<String, f32> {
-        let mut freq = HashMap::new();
-        let total = terms.len() as f32;
-        
-        for term in terms {
-            *freq.entry(term.clone()).or_insert(0.0) += 1.0;
-        }
-        
-        if total > 0.0 {
-            for count in freq.values_mut() {
-                *count /= total;
-            }
-        }
-        
-        freq
-    }
-}
-
-// ============================================================================
-// Embedding Generator
-// ============================================================================
-
-/// Embedding generator that supports multiple backends
-pub struct EmbeddingGenerator {
-    model: EmbeddingModel,
-    tfidf: Arc<RwLock<TfIdfVectorizer>>,
-    ollama_url: Option<String>,
-    openai_key: Option<String>,
-}
-
-impl EmbeddingGenerator {
-    /// Create a new embedding generator
-    pub fn new(model: EmbeddingModel) -> Self {
-        Self {
-            model,
-            tfidf: Arc::new(RwLock::new(TfIdfVectorizer::new())),
-            ollama_url: None,
-            openai_key: None,
-        }
-    }
-    
-    /// Set Ollama URL
-    pub fn with_ollama_url(mut self, url: String) -> Self {
-        self.ollama_url = Some(url);
-        self
-    }
-    
-    /// Set OpenAI API key
-    pub fn with_openai_key(mut self, key: String) -> Self {
-        self.openai_key = Some(key);
-        self
-    }
-    
-    /// Fit the TF-IDF model on a corpus (only needed for TfIdf backend)
-    pub fn fit_tfidf(&self, documents: &[&str]) {
-        if matches!(self.model, EmbeddingModel::TfIdf) {
-            let mut tfidf = self.tfidf.write().unwrap();
-            tfidf.fit(documents);
-        }
-    }
-    
-    /// Generate embedding for text
-    pub async fn embed(&self, text: &str) -> Result<Vec<f32>> {
-        match &self.model {
-            EmbeddingModel::TfIdf => {
-                let tfidf = self.tfidf.read().unwrap();
-                Ok(tfidf.transform(text))
-            }
-            EmbeddingModel::LocalOllama => {
-                self.embed_ollama(text).await
-            }
-            EmbeddingModel::OpenAI => {
-                self.embed_openai(text).await
-            }
-        }
-    }
-    
-    /// Generate embeddings for multiple texts
-    pub async fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
-        // For now, process sequentially. Could be parallelized later.
-        let mut embeddings = Vec::with_capacity(texts.len());
-        for text in texts {
-            embeddings.push(self.embed(text).await?);
-        }
-        Ok(embeddings)
-    }
-    
-    /// Embed using Ollama
-    async fn embed_ollama(&self, text: &str) -> Result<Vec<f32>> {
-        let url = self.ollama_url.as_deref()
-            .unwrap_or("http://localhost:11434");
-        
-        let client = reqwest::Client::new();
-        let response = client
-            .post(format!("{}/api/embeddings", url))
-            .json(&serde_json::json!({
-                "model": "nomic-embed-text",
-                "prompt": text
-            }))
-            .send()
-            .await
-            .context("Failed to call Ollama API")?;
-        
-        if !response.status().is_success() {
-            anyhow::bail!("Ollama API error: {}", response.status());
-        }
-        
-        let json: serde_json::Value = response.json().await?;
-        let embedding = json["embedding"]
-            .as_array()
-            .context("Invalid Ollama response")?
-            .iter()
-            .map(|v| v.as_f64().unwrap_or(0.0) as f32)
-            .collect();
-        
-        Ok(embedding)
-    }
-    
-    /// Embed using OpenAI
-    async fn embed_openai(&self, text: &str) -> Result<Vec<f32>> {
-        let api_key = self.openai_key.as_ref()
-            .context("OpenAI API key not set")?;
-        
-        let client = reqwest::Client::new();
-        let response = client
-            .post("https://api.openai.com/v1/embeddings")
-            .header("Authorization", format!("Bearer {}", api_key))
-            .json(&serde_json::json!({
-                "model": "text-embedding-3-small",
-                "input": text
-            }))
-            .send()
-            .await
-            .context("Failed to call OpenAI API")?;
-        
-        if !response.status().is_success() {
-            anyhow::bail!("OpenAI API error: {}", response.status());
-        }
-        
-        let json: serde_json::Value = response.json().await?;
-        let embedding = json["data"][0]["embedding"]
-            .as_array()
-            .context("Invalid OpenAI response")?
-            .iter()
-            .map(|v| v.as_f64().unwrap_or(0.0) as f32)
-            .collect();
-        
-        Ok(embedding)
-    }
-    
-    /// Get the embedding dimension
-    pub fn dimension(&self) -> usize {
-        match &self.model {
-            EmbeddingModel::TfIdf => {
-                self.tfidf.read().unwrap().vocab_size
-            }
-            EmbeddingModel::LocalOllama => 768,  // nomic-embed-text
-            EmbeddingModel::OpenAI => 1536,      // text-embedding-3-small
-        }
-    }
-}
-
 // ============================================================================
 // Language Detection
 // ============================================================================
@@ -1,6 +1,10 @@
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fmt;
 
+use crate::embeddings::EmbeddingModel;
+use crate::types::LlmProvider;
+
 #[derive(Debug, Deserialize, Clone, PartialEq, Default)]
 #[serde(rename_all = "kebab-case")]
 pub enum DeploymentMode {
@@ -71,6 +75,19 @@ pub struct Config {
     /// Native inference service configuration
     #[serde(default)]
     pub native_inference: Option<NativeInferenceConfig>,
+    /// Per-model prices used to cost LLM token usage
+    #[serde(default)]
+    pub prices: Vec<ModelPrice>,
+    /// Per-model context windows for direct-runtime chat
+    #[serde(default)]
+    pub context_windows: Vec<ModelContextWindow>,
+    /// Built-in embedding of shared memories; unset leaves embedding to
+    /// the agents
+    #[serde(default)]
+    pub memory_embeddings: Option<MemoryEmbeddingsConfig>,
+    /// Expiry, retention, deduplication and consolidation of shared memories
+    #[serde(default)]
+    pub memory_lifecycle: MemoryLifecycleConfig,
 }
 
 impl fmt::Debug for Config {
@@ -90,6 +107,10 @@ impl fmt::Debug for Config {
             .field("model_servers", &self.model_servers)
             .field("andor_bridge", &self.andor_bridge)
             .field("native_inference", &self.native_inference)
+            .field("prices", &self.prices)
+            .field("context_windows", &self.context_windows)
+            .field("memory_embeddings", &self.memory_embeddings)
+            .field("memory_lifecycle", &self.memory_lifecycle)
             .finish()
     }
 }
@@ -119,6 +140,160 @@ pub struct NativeInferenceConfig {
     pub top_p: f32,
 }
 
+/// Price of a provider's model, in USD per million tokens
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct ModelPrice {
+    /// Provider name as in agent configs, e.g. "anthropic"
+    pub provider: String,
+    /// Model id; a trailing `*` matches any suffix. Unset matches every
+    /// model of the provider not priced more specifically.
+    #[serde(default)]
+    pub model: Option<String>,
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+/// Context window of a provider's model, in tokens
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct ModelContextWindow {
+    /// Provider name as in agent configs, e.g. "ollama"
+    pub provider: String,
+    /// Model id, matched like `ModelPrice::model`
+    #[serde(default)]
+    pub model: Option<String>,
+    pub tokens: u32,
+}
+
+/// How the orchestrator embeds shared memories and search queries
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct MemoryEmbeddingsConfig {
+    /// `local_ollama`, `open_ai` or `tf_idf`
+    pub model: EmbeddingModel,
+    /// Ollama server for `local_ollama`; defaults to the `ollama` model
+    /// server, then localhost
+    #[serde(default)]
+    pub ollama_url: Option<String>,
+}
+
+/// When shared memories are deleted, merged or consolidated
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct MemoryLifecycleConfig {
+    /// Seconds between sweeps for expired memories and retention
+    #[serde(default = "default_sweep_interval_secs")]
+    pub sweep_interval_secs: u64,
+    /// Days memories are kept, per org
+    #[serde(default)]
+    pub retention_days: HashMap<String, u32>,
+    /// Days memories are kept in orgs `retention-days` doesn't list; unset
+    /// keeps them
+    #[serde(default)]
+    pub default_retention_days: Option<u32>,
+    /// Cosine similarity at or above which a new memory is merged into an
+    /// existing one instead of being stored; unset stores every memory
+    #[serde(default)]
+    pub dedup_threshold: Option<f32>,
+    /// Summarizing clusters of old memories with an LLM; off when unset
+    #[serde(default)]
+    pub consolidation: Option<MemoryConsolidationConfig>,
+}
+
+impl Default for MemoryLifecycleConfig {
+    fn default() -> Self {
+        Self {
+            sweep_interval_secs: default_sweep_interval_secs(),
+            retention_days: HashMap::new(),
+            default_retention_days: None,
+            dedup_threshold: None,
+            consolidation: None,
+        }
+    }
+}
+
+impl MemoryLifecycleConfig {
+    /// Days an org's memories are kept, if they expire
+    pub fn retention_for(&self, org: &str) -> Option<u32> {
+        self.retention_days.get(org).copied().or(self.default_retention_days)
+    }
+}
+
+/// Consolidation of old memories: similar ones are summarized by the model
+/// into a single entry that replaces them
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct MemoryConsolidationConfig {
+    /// Provider the summaries are written by; its API key comes from the
+    /// stored keys, like a direct-runtime agent's
+    pub provider: LlmProvider,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Overrides the provider's default endpoint
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// `openai` or `anthropic`, for `base-url`s of another kind
+    #[serde(default)]
+    pub api_format: Option<String>,
+    /// Only memories older than this are consolidated
+    #[serde(default = "default_consolidate_after_days")]
+    pub after_days: u32,
+    /// Cosine similarity to a cluster's first memory that puts another in it
+    #[serde(default = "default_consolidation_similarity")]
+    pub similarity: f32,
+    /// Smallest cluster worth summarizing
+    #[serde(default = "default_min_cluster")]
+    pub min_cluster: usize,
+    /// Most memories summarized into one
+    #[serde(default = "default_max_cluster")]
+    pub max_cluster: usize,
+    /// Hours between consolidation runs
+    #[serde(default = "default_consolidation_interval_hours")]
+    pub interval_hours: u64,
+    /// Orgs to consolidate; empty means all
+    #[serde(default)]
+    pub orgs: Vec<String>,
+}
+
+/// How specifically a `model` pattern from a `[[prices]]`-style table
+/// matches `model`: exact beats the longest `*` prefix beats a
+/// provider-wide (unset) pattern. `None` if it doesn't match.
+pub fn model_match_rank(pattern: Option<&str>, model: &str) -> Option<usize> {
+    match pattern {
+        None => Some(0),
+        Some(p) if p == model => Some(usize::MAX),
+        Some(p) => match p.strip_suffix('*') {
+            Some(prefix) if model.starts_with(prefix) => Some(prefix.len() + 1),
+            _ => None,
+        },
+    }
+}
+
+fn default_sweep_interval_secs() -> u64 {
+    300
+}
+
+fn default_consolidate_after_days() -> u32 {
+    30
+}
+
+fn default_consolidation_similarity() -> f32 {
+    0.8
+}
+
+fn default_min_cluster() -> usize {
+    3
+}
+
+fn default_max_cluster() -> usize {
+    20
+}
+
+fn default_consolidation_interval_hours() -> u64 {
+    24
+}
+
 fn default_inference_port() -> u16 {
     8765
 }
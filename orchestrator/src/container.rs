@@ -157,6 +157,19 @@ impl RuntimeClient {
     pub fn clone_runtime_client(&self) -> Self {
         self.clone()
     }
+
+    /// A client that never connects to anything, for tests of code paths
+    /// that don't reach a runtime
+    #[cfg(test)]
+    pub fn unconnected() -> Self {
+        Self {
+            inner: RuntimeClientInner::Containment(ContainmentClient::new().unwrap()),
+            network_backend: NetworkBackend::default(),
+            headscale_url: None,
+            headscale_auth_key: None,
+            headscale_namespace: None,
+        }
+    }
 }
 
 #[async_trait]
@@ -638,6 +651,7 @@ impl ContainerRuntime for DockerClient {
                     tags: vec![],
                     restart_policy: Default::default(),
                     health_status: None,
+                    restarts: Default::default(),
                     runtime: Some("docker".to_string()),
                     gateway_port: crate::types::default_gateway_port(),
                 });
@@ -1010,14 +1024,14 @@ impl ContainerRuntime for DockerClient {
     }
 
     async fn exec_container(&self, id: &str, cmd: Vec<String>) -> Result<String> {
-        use bollard::exec::{CreateExecOptions, StartExecOptions};
+        use bollard::exec::{CreateExecOptions, StartExecOptions, StartExecResults};
+        use futures_util::StreamExt;
 
-        // Create exec instance
+        // Create exec instance (no TTY so stdout/stderr arrive demultiplexed)
         let create_config = CreateExecOptions {
             cmd: Some(cmd),
             attach_stdout: Some(true),
             attach_stderr: Some(true),
-            tty: Some(true),
             ..Default::default()
         };
 
@@ -1034,16 +1048,31 @@ impl ContainerRuntime for DockerClient {
             ..Default::default()
         });
 
-        let output_stream = self.docker
+        let results = self.docker
             .start_exec(&exec_id, start_config)
             .await
             .map_err(|e| anyhow::anyhow!("Failed to start exec: {}", e))?;
 
-        // For now, just return success - the output is a stream
-        // In a real implementation, we'd need to handle the multiplexed stream
-        drop(output_stream);
+        // Collect output until the command exits
+        let mut output = String::new();
+        if let StartExecResults::Attached { output: mut stream, .. } = results {
+            while let Some(chunk) = stream.next().await {
+                match chunk {
+                    Ok(log_output) => output.push_str(&log_output.to_string()),
+                    Err(e) => return Err(anyhow::anyhow!("Error reading exec output: {}", e)),
+                }
+            }
+        }
+
+        let inspect = self.docker
+            .inspect_exec(&exec_id)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to inspect exec: {}", e))?;
+        if let Some(code) = inspect.exit_code.filter(|c| *c != 0) {
+            return Err(anyhow::anyhow!("Command exited with code {}: {}", code, output.trim()));
+        }
 
-        Ok(format!("Shell access initiated for container {}", id))
+        Ok(output)
     }
 
     async fn container_logs(&self, container_name: &str, tail_lines: usize) -> Result<String> {
@@ -1332,6 +1361,7 @@ impl ContainerRuntime for ExoClient {
                     tags: vec![],
                     restart_policy: Default::default(),
                     health_status: None,
+                    restarts: Default::default(),
                     runtime: Some("exo".to_string()),
                     gateway_port: crate::types::default_gateway_port(),
                 });
@@ -97,6 +97,7 @@ impl ContainmentClient {
                     tags: vec![],
                     restart_policy: Default::default(),
                     health_status: None,
+                    restarts: Default::default(),
                     runtime: Some("containment".to_string()),
                     gateway_port: crate::types::default_gateway_port(),
                 });
@@ -0,0 +1,311 @@
+//! Context window management for direct-runtime chat
+//!
+//! Before each request, `fit` estimates the tokens of the session history
+//! and, if they don't fit in what the model's window leaves after the
+//! system prompt and room for the reply, leaves the oldest turns out of the
+//! request. With the `summarize` strategy those turns are first condensed by
+//! the model into a summary, stored in the transcript as a `system` message
+//! marked `context_summary`; later requests send the latest summary in place
+//! of the turns it covers. The transcript on disk is only ever appended to.
+
+use std::collections::HashMap;
+
+use serde_json::json;
+
+use crate::config::{model_match_rank, ModelContextWindow};
+use crate::types::{AgentContainer, ConversationMessage};
+
+/// Metadata flag on summary messages
+pub const SUMMARY_MARKER: &str = "context_summary";
+/// Metadata key on summary messages: ID of the last turn the summary covers
+const SUMMARIZED_THROUGH: &str = "summarized_through";
+
+/// Tokens per message for the role and framing
+const MESSAGE_OVERHEAD: usize = 4;
+/// Window assumed for models nothing else gives a size for
+const DEFAULT_WINDOW: u32 = 8192;
+/// Most of the window kept free for the reply
+const MAX_REPLY_RESERVE: usize = 4096;
+
+/// Windows of common models, used when `[[context-windows]]` has no match
+const BUILT_IN_WINDOWS: &[(&str, Option<&str>, u32)] = &[
+    ("anthropic", None, 200_000),
+    ("openai", None, 128_000),
+    ("openai", Some("gpt-4.1*"), 1_047_576),
+    ("openai", Some("gpt-3.5*"), 16_385),
+    ("gemini", None, 1_048_576),
+    ("kimi", None, 128_000),
+    ("kimicode", None, 128_000),
+    // Ollama's default num_ctx, whatever the model supports
+    ("ollama", None, 4096),
+];
+
+/// Rough token count: about four characters per token for English text
+/// with the common BPE vocabularies
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+fn message_tokens(message: &ConversationMessage) -> usize {
+    estimate_tokens(&message.content) + MESSAGE_OVERHEAD
+}
+
+/// The context window for `model` served by `provider` on behalf of
+/// `agent`: the agent's own setting, then Ollama's `num_ctx` from
+/// `LLM_OPTIONS`, then the most specific configured or built-in entry.
+pub fn window_tokens(configured: &[ModelContextWindow], agent: &AgentContainer, provider: &str, model: &str) -> u32 {
+    if let Some(tokens) = agent.config.context.as_ref().and_then(|c| c.window_tokens) {
+        return tokens;
+    }
+    let num_ctx = agent
+        .config
+        .env_vars
+        .get("LLM_OPTIONS")
+        .and_then(|raw| serde_json::from_str::<serde_json::Value>(raw).ok())
+        .and_then(|options| options.get("num_ctx").and_then(serde_json::Value::as_u64));
+    if let Some(num_ctx) = num_ctx.filter(|_| provider == "ollama") {
+        return num_ctx as u32;
+    }
+
+    let mut best: Option<(usize, u32)> = None;
+    let entries = configured
+        .iter()
+        .map(|w| (w.provider.as_str(), w.model.as_deref(), w.tokens));
+    for (entry_provider, pattern, tokens) in entries {
+        if !entry_provider.eq_ignore_ascii_case(provider) {
+            continue;
+        }
+        let Some(rank) = model_match_rank(pattern, model) else { continue };
+        if best.is_none_or(|(r, _)| rank > r) {
+            best = Some((rank, tokens));
+        }
+    }
+    if let Some((_, tokens)) = best {
+        return tokens;
+    }
+    BUILT_IN_WINDOWS
+        .iter()
+        .filter(|(p, _, _)| *p == provider)
+        .filter_map(|(_, pattern, tokens)| model_match_rank(*pattern, model).map(|rank| (rank, *tokens)))
+        .max_by_key(|(rank, _)| *rank)
+        .map_or(DEFAULT_WINDOW, |(_, tokens)| tokens)
+}
+
+/// Tokens of history that fit in `window` next to `fixed_tokens` of system
+/// prompt and tool definitions, keeping room for the reply
+pub fn history_budget(window: u32, fixed_tokens: usize) -> usize {
+    let window = window as usize;
+    let reserve = (window / 4).min(MAX_REPLY_RESERVE);
+    window.saturating_sub(reserve).saturating_sub(fixed_tokens)
+}
+
+/// The part of a session to send
+#[derive(Debug, Default)]
+pub struct Fitted {
+    /// Latest summary of earlier turns
+    pub summary: Option<String>,
+    /// Turns to send, oldest first
+    pub turns: Vec<ConversationMessage>,
+    /// Turns left out that the summary doesn't cover
+    pub dropped: Vec<ConversationMessage>,
+}
+
+impl Fitted {
+    /// The system prompt with the summary appended
+    pub fn system_prompt(&self, base: Option<&str>) -> Option<String> {
+        let Some(summary) = &self.summary else {
+            return base.map(str::to_string);
+        };
+        let summary = format!("Summary of the earlier conversation:\n{}", summary);
+        Some(match base {
+            Some(base) => format!("{}\n\n{}", base, summary),
+            None => summary,
+        })
+    }
+}
+
+fn is_summary(message: &ConversationMessage) -> bool {
+    message.metadata.get(SUMMARY_MARKER).and_then(|v| v.as_bool()) == Some(true)
+}
+
+/// Choose the newest turns of `history` that fit in `budget` tokens, with
+/// the latest summary standing in for the turns it covers. The last turn
+/// (the user's new message) is always kept, and the kept turns start with a
+/// user turn, as some providers require.
+pub fn fit(history: &[ConversationMessage], budget: usize) -> Fitted {
+    // Everything up to the last turn the latest summary covers is replaced
+    // by it
+    let latest_summary = history.iter().rposition(is_summary);
+    let (summary, start) = match latest_summary {
+        Some(i) => {
+            let through = history[i]
+                .metadata
+                .get(SUMMARIZED_THROUGH)
+                .and_then(|v| v.as_str())
+                .and_then(|id| history.iter().position(|m| m.id == id))
+                .unwrap_or(i);
+            (Some(history[i].content.clone()), through + 1)
+        }
+        None => (None, 0),
+    };
+    let candidates: Vec<&ConversationMessage> = history[start..]
+        .iter()
+        .filter(|m| !is_summary(m) && m.role != "tool" && !m.content.is_empty())
+        .collect();
+
+    let mut used = summary.as_deref().map_or(0, estimate_tokens);
+    let mut keep_from = candidates.len();
+    for (i, message) in candidates.iter().enumerate().rev() {
+        let tokens = message_tokens(message);
+        if used + tokens > budget && keep_from < candidates.len() {
+            break;
+        }
+        used += tokens;
+        keep_from = i;
+    }
+    while keep_from + 1 < candidates.len() && candidates[keep_from].role != "user" {
+        keep_from += 1;
+    }
+
+    Fitted {
+        summary,
+        turns: candidates[keep_from..].iter().map(|m| (*m).clone()).collect(),
+        dropped: candidates[..keep_from].iter().map(|m| (*m).clone()).collect(),
+    }
+}
+
+/// System prompt for summarizing turns that no longer fit
+pub const SUMMARY_INSTRUCTIONS: &str = "You maintain a running summary of a conversation between a user and an \
+     assistant, so it can continue after the older messages are gone. Merge the previous summary, if any, with \
+     the new messages. Keep facts about the user, decisions, open questions and anything the assistant promised; \
+     drop pleasantries. Write in the conversation's language, in under 300 words, as plain prose.";
+
+/// The summarization prompt for `dropped` turns
+pub fn summary_prompt(previous: Option<&str>, dropped: &[ConversationMessage]) -> String {
+    let mut prompt = String::new();
+    if let Some(previous) = previous {
+        prompt.push_str("Previous summary:\n");
+        prompt.push_str(previous);
+        prompt.push_str("\n\n");
+    }
+    prompt.push_str("New messages:\n");
+    for message in dropped {
+        let speaker = if message.role == "assistant" { "Assistant" } else { "User" };
+        prompt.push_str(&format!("{}: {}\n", speaker, message.content));
+    }
+    prompt
+}
+
+/// A summary of everything through `through`, to append to the transcript
+pub fn summary_message(session_id: &str, agent_id: &str, summary: &str, through: &ConversationMessage) -> ConversationMessage {
+    ConversationMessage {
+        id: uuid::Uuid::new_v4().to_string(),
+        session_id: session_id.to_string(),
+        role: "system".to_string(),
+        content: summary.to_string(),
+        agent_id: agent_id.to_string(),
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        metadata: HashMap::from([
+            (SUMMARY_MARKER.to_string(), json!(true)),
+            (SUMMARIZED_THROUGH.to_string(), json!(through.id)),
+        ]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AgentConfig, AgentStatus, ContextConfig, LlmProvider};
+
+    fn turn(id: usize, role: &str, words: usize) -> ConversationMessage {
+        ConversationMessage {
+            id: format!("m{}", id),
+            session_id: "s".to_string(),
+            role: role.to_string(),
+            // "word " is 5 chars; 4 words = 5 tokens
+            content: "word ".repeat(words),
+            agent_id: "tutor-id".to_string(),
+            timestamp: String::new(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    fn conversation(turns: usize) -> Vec<ConversationMessage> {
+        (0..turns)
+            .map(|i| turn(i, if i % 2 == 0 { "user" } else { "assistant" }, 16))
+            .collect()
+    }
+
+    #[test]
+    fn test_sliding_window_keeps_newest_turns_from_a_user_turn() {
+        // 16 words = 20 tokens + 4 overhead = 24 per turn
+        let history = conversation(9);
+        let fitted = fit(&history, 24 * 4);
+        let ids: Vec<_> = fitted.turns.iter().map(|m| m.id.as_str()).collect();
+        // m5..m8 fit, but m5 is an assistant turn
+        assert_eq!(ids, ["m6", "m7", "m8"]);
+        assert_eq!(fitted.dropped.len(), 6);
+        assert!(fitted.summary.is_none());
+
+        // The new message is sent even if it alone is too long
+        assert_eq!(fit(&history, 1).turns.len(), 1);
+        assert!(fit(&history, 10_000).dropped.is_empty());
+    }
+
+    #[test]
+    fn test_summary_replaces_the_turns_it_covers() {
+        let mut history = conversation(6);
+        history.push(summary_message("s", "tutor-id", "They study photosynthesis.", &history[3]));
+        history.extend(conversation(10).into_iter().skip(6));
+
+        let fitted = fit(&history, 10_000);
+        assert_eq!(fitted.summary.as_deref(), Some("They study photosynthesis."));
+        let ids: Vec<_> = fitted.turns.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["m4", "m5", "m6", "m7", "m8", "m9"]);
+        assert_eq!(
+            fitted.system_prompt(Some("You are a tutor.")).unwrap(),
+            "You are a tutor.\n\nSummary of the earlier conversation:\nThey study photosynthesis."
+        );
+
+        let prompt = summary_prompt(fitted.summary.as_deref(), &fitted.turns[..2]);
+        assert!(prompt.starts_with("Previous summary:\nThey study photosynthesis."));
+        assert!(prompt.contains("\nUser: word") && prompt.contains("\nAssistant: word"));
+    }
+
+    #[test]
+    fn test_window_lookup_order() {
+        let mut agent = AgentContainer {
+            id: "tutor-id".to_string(),
+            name: "tutor".to_string(),
+            status: AgentStatus::Running,
+            config: AgentConfig {
+                llm_provider: LlmProvider::Ollama,
+                ..Default::default()
+            },
+            runtime: Some("direct".to_string()),
+            ..Default::default()
+        };
+        let configured = vec![ModelContextWindow {
+            provider: "ollama".to_string(),
+            model: Some("llama3.1*".to_string()),
+            tokens: 32_768,
+        }];
+
+        assert_eq!(window_tokens(&configured, &agent, "ollama", "llama3.1:8b"), 32_768);
+        assert_eq!(window_tokens(&configured, &agent, "ollama", "qwen2.5"), 4096);
+        assert_eq!(window_tokens(&configured, &agent, "openai", "gpt-4.1-mini"), 1_047_576);
+        assert_eq!(window_tokens(&configured, &agent, "custom", "x"), DEFAULT_WINDOW);
+
+        agent.config.env_vars.insert("LLM_OPTIONS".to_string(), r#"{"num_ctx": 16384}"#.to_string());
+        assert_eq!(window_tokens(&configured, &agent, "ollama", "llama3.1:8b"), 16_384);
+        agent.config.context = Some(ContextConfig {
+            window_tokens: Some(2048),
+            ..Default::default()
+        });
+        assert_eq!(window_tokens(&configured, &agent, "ollama", "llama3.1:8b"), 2048);
+
+        assert_eq!(history_budget(8192, 1000), 8192 - 2048 - 1000);
+        assert_eq!(history_budget(200_000, 1000), 200_000 - 4096 - 1000);
+        assert_eq!(history_budget(1000, 5000), 0);
+    }
+}
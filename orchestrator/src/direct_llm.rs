@@ -7,19 +7,40 @@
 //!   - `config.llm_model` is the model id sent to the provider.
 //!   - `config.env_vars["LLM_BASE_URL"]` overrides the default endpoint
 //!     (used for Ollama / LM Studio / self-hosted vLLM / sovereign registries).
+//!   - `config.env_vars["LLM_API_FORMAT"]` overrides the wire format:
+//!     `openai`, `anthropic`, `ollama` (native `/api/chat`) or `gemini`
+//!     (native `streamGenerateContent`). The native formats take
+//!     `LLM_KEEP_ALIVE` / `LLM_OPTIONS` (Ollama) and `LLM_SAFETY_SETTINGS`
+//!     (Gemini) from `env_vars` too.
 //!   - System prompt comes from `data/agents/<name>/identity/system_prompt.md`
 //!     if present, else the template default.
 //!   - Streams tokens to the browser as `chat`/`delta` events (already handled
 //!     by the Tauri client).
+//!   - `config.llm_fallbacks` lists providers to fail over to, in order, on
+//!     429/5xx/timeouts before the first token. The provider that answered
+//!     is recorded in the assistant message's `metadata`.
+//!   - History is cut to the model's context window (`context_window`),
+//!     optionally summarizing what's left out (`config.context`).
+//!   - `config.tools` are offered to the model. Calls are run by `tools`,
+//!     shown to the client as `tool` events, persisted as `role: "tool"`
+//!     turns and answered on the same provider, up to `MAX_TOOL_ROUNDS`.
 
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::extract::ws::{Message, WebSocket};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tokio::sync::RwLock;
 
 use crate::AppState;
-use crate::types::{AgentContainer, ConversationMessage, LlmProvider};
+use crate::config::{ModelServerConfig, ModelServers};
+use crate::context_window::{self, Fitted};
+use crate::tools::{ToolCall, ToolOutput, ToolSpec};
+use crate::types::{
+    AgentContainer, AgentTool, ContextStrategy, ConversationMessage, LlmFallback, LlmProvider, TokenUsage,
+};
 
 /// Wire format spoken by an upstream provider.
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -28,6 +49,11 @@ enum ApiFormat {
     OpenAi,
     /// Anthropic messages: POST /messages, SSE events with content_block_delta.
     AnthropicMessages,
+    /// Ollama native chat: POST /api/chat, NDJSON lines of {message:{content}, done}.
+    OllamaChat,
+    /// Gemini native: POST /models/<model>:streamGenerateContent?alt=sse,
+    /// SSE events of {candidates:[{content:{parts:[{text}]}}]}.
+    GeminiGenerate,
 }
 
 /// Default base URL + wire format per provider.
@@ -69,158 +95,1659 @@ struct OpenAiRequest<'a> {
     model: &'a str,
     messages: Vec<OpenAiMessage<'a>>,
     stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<OpenAiStreamOptions>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<OpenAiTool<'a>>,
+}
+
+/// Asks for a final chunk carrying `usage` (empty `choices`).
+#[derive(Debug, Serialize)]
+struct OpenAiStreamOptions {
+    include_usage: bool,
 }
 
 #[derive(Debug, Serialize)]
 struct OpenAiMessage<'a> {
     role: &'a str,
-    content: &'a str,
+    /// Null on an assistant turn that only calls tools
+    content: Option<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OpenAiToolCall<'a>>,
+    /// Set on `role: "tool"` results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<&'a str>,
+}
+
+impl<'a> OpenAiMessage<'a> {
+    fn text(role: &'a str, content: &'a str) -> Self {
+        Self { role, content: Some(content), tool_calls: Vec::new(), tool_call_id: None }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAiTool<'a> {
+    #[serde(rename = "type")]
+    tool_type: &'static str,
+    function: OpenAiFunction<'a>,
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAiFunction<'a> {
+    name: &'a str,
+    description: &'a str,
+    parameters: &'a serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAiToolCall<'a> {
+    id: &'a str,
+    #[serde(rename = "type")]
+    call_type: &'static str,
+    function: OpenAiFunctionCall<'a>,
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAiFunctionCall<'a> {
+    name: &'a str,
+    /// JSON-encoded, as the API expects
+    arguments: String,
 }
 
 #[derive(Debug, Deserialize)]
 struct OpenAiChunk {
+    #[serde(default)]
     choices: Vec<OpenAiChoice>,
+    #[serde(default)]
+    usage: Option<OpenAiUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiUsage {
+    #[serde(default)]
+    prompt_tokens: u64,
+    #[serde(default)]
+    completion_tokens: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiChoice {
+    #[serde(default)]
+    delta: OpenAiDelta,
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct OpenAiDelta {
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<OpenAiToolCallDelta>,
+}
+
+/// A fragment of a streamed tool call. The id and name come in the first
+/// fragment for an index; the arguments JSON arrives in pieces.
+#[derive(Debug, Deserialize)]
+struct OpenAiToolCallDelta {
+    #[serde(default)]
+    index: usize,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    function: Option<OpenAiFunctionDelta>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiFunctionDelta {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    arguments: Option<String>,
+}
+
+/// Non-streaming chat/completions response body.
+#[derive(Debug, Deserialize)]
+struct OpenAiResponse {
+    choices: Vec<OpenAiResponseChoice>,
+    #[serde(default)]
+    usage: Option<OpenAiUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiResponseChoice {
+    message: OpenAiResponseMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiResponseMessage {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+// ─── Anthropic /v1/messages wire types ──────────────────────────────────────
+#[derive(Debug, Serialize)]
+struct AnthropicRequest<'a> {
+    model: &'a str,
+    max_tokens: u32,
+    messages: Vec<AnthropicMessage<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<&'a str>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<AnthropicTool<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct AnthropicMessage<'a> {
+    role: &'a str,
+    content: AnthropicContent<'a>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum AnthropicContent<'a> {
+    Text(&'a str),
+    Blocks(Vec<AnthropicBlock<'a>>),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicBlock<'a> {
+    Text { text: &'a str },
+    ToolUse { id: &'a str, name: &'a str, input: &'a serde_json::Value },
+    ToolResult { tool_use_id: &'a str, content: &'a str, is_error: bool },
+}
+
+#[derive(Debug, Serialize)]
+struct AnthropicTool<'a> {
+    name: &'a str,
+    description: &'a str,
+    input_schema: &'a serde_json::Value,
+}
+
+/// SSE event body when type=content_block_delta.
+#[derive(Debug, Deserialize)]
+struct AnthropicSseEvent {
+    #[serde(rename = "type")]
+    event_type: Option<String>,
+    #[serde(default)]
+    delta: Option<AnthropicEventDelta>,
+    /// Content block the event belongs to
+    #[serde(default)]
+    index: Option<usize>,
+    /// Set on content_block_start; names the tool for tool_use blocks.
+    #[serde(default)]
+    content_block: Option<AnthropicSseBlock>,
+    /// Set on message_start; carries the input token count.
+    #[serde(default)]
+    message: Option<AnthropicSseMessage>,
+    /// Set on message_delta; carries the final output token count.
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicSseBlock {
+    #[serde(rename = "type")]
+    block_type: String,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicSseMessage {
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicUsage {
+    #[serde(default)]
+    input_tokens: Option<u64>,
+    #[serde(default)]
+    output_tokens: Option<u64>,
 }
 
-#[derive(Debug, Deserialize)]
-struct OpenAiChoice {
-    #[serde(default)]
-    delta: OpenAiDelta,
-    #[serde(default)]
-    finish_reason: Option<String>,
-}
+#[derive(Debug, Deserialize)]
+struct AnthropicEventDelta {
+    #[serde(rename = "type")]
+    delta_type: Option<String>,
+    text: Option<String>,
+    /// Set on input_json_delta; a piece of a tool_use block's input
+    #[serde(default)]
+    partial_json: Option<String>,
+    stop_reason: Option<String>,
+}
+
+/// Non-streaming /v1/messages response body.
+#[derive(Debug, Deserialize)]
+struct AnthropicResponse {
+    content: Vec<AnthropicContentBlock>,
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicContentBlock {
+    #[serde(rename = "type")]
+    block_type: String,
+    #[serde(default)]
+    text: Option<String>,
+}
+
+// ─── Ollama /api/chat wire types ────────────────────────────────────────────
+#[derive(Debug, Serialize)]
+struct OllamaRequest<'a> {
+    model: &'a str,
+    messages: Vec<OllamaMessage<'a>>,
+    stream: bool,
+    /// Same shape as OpenAI's
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<OpenAiTool<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<&'a serde_json::Value>,
+    /// Sampling options (temperature, num_predict, num_ctx, ...)
+    #[serde(skip_serializing_if = "serde_json::Map::is_empty")]
+    options: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct OllamaMessage<'a> {
+    role: &'a str,
+    content: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OllamaToolCall<'a>>,
+    /// Set on `role: "tool"` results; Ollama matches them by name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_name: Option<&'a str>,
+}
+
+impl<'a> OllamaMessage<'a> {
+    fn text(role: &'a str, content: &'a str) -> Self {
+        Self { role, content, tool_calls: Vec::new(), tool_name: None }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct OllamaToolCall<'a> {
+    function: OllamaFunctionCall<'a>,
+}
+
+#[derive(Debug, Serialize)]
+struct OllamaFunctionCall<'a> {
+    name: &'a str,
+    arguments: &'a serde_json::Value,
+}
+
+/// One NDJSON line of a streamed /api/chat response, or the whole
+/// non-streaming response.
+#[derive(Debug, Deserialize)]
+struct OllamaChunk {
+    #[serde(default)]
+    message: Option<OllamaResponseMessage>,
+    #[serde(default)]
+    done: bool,
+    /// Set on the final line
+    #[serde(default)]
+    prompt_eval_count: Option<u64>,
+    #[serde(default)]
+    eval_count: Option<u64>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaResponseMessage {
+    #[serde(default)]
+    content: String,
+    /// Tool calls arrive whole, with arguments as an object
+    #[serde(default)]
+    tool_calls: Vec<OllamaResponseToolCall>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaResponseToolCall {
+    function: FunctionCallIn,
+}
+
+/// A complete function call in a native Ollama or Gemini response
+#[derive(Debug, Deserialize)]
+struct FunctionCallIn {
+    name: String,
+    #[serde(default, alias = "args")]
+    arguments: serde_json::Value,
+}
+
+// ─── Gemini generateContent wire types ──────────────────────────────────────
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiRequest<'a> {
+    contents: Vec<GeminiContent<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<GeminiContent<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation_config: Option<GeminiGenerationConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    safety_settings: Option<&'a serde_json::Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<GeminiTools<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct GeminiContent<'a> {
+    /// "user" or "model"; unset on the system instruction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<&'a str>,
+    parts: Vec<GeminiPart<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum GeminiPart<'a> {
+    Text(&'a str),
+    FunctionCall { name: &'a str, args: &'a serde_json::Value },
+    FunctionResponse { name: &'a str, response: serde_json::Value },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiGenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_output_tokens: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiTools<'a> {
+    function_declarations: Vec<GeminiFunction<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct GeminiFunction<'a> {
+    name: &'a str,
+    description: &'a str,
+    parameters: &'a serde_json::Value,
+}
+
+/// One SSE event of a streamed response, or the whole non-streaming one.
+/// `usageMetadata` is cumulative, so the last one seen is the total.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiResponse {
+    #[serde(default)]
+    candidates: Vec<GeminiCandidate>,
+    #[serde(default)]
+    usage_metadata: Option<GeminiUsage>,
+    #[serde(default)]
+    prompt_feedback: Option<GeminiPromptFeedback>,
+    #[serde(default)]
+    error: Option<GeminiError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GeminiCandidate {
+    #[serde(default)]
+    content: Option<GeminiResponseContent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GeminiResponseContent {
+    #[serde(default)]
+    parts: Vec<GeminiResponsePart>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiResponsePart {
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    function_call: Option<FunctionCallIn>,
+    /// Thought summaries from thinking models aren't part of the answer
+    #[serde(default)]
+    thought: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiUsage {
+    #[serde(default)]
+    prompt_token_count: u64,
+    #[serde(default)]
+    candidates_token_count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiPromptFeedback {
+    #[serde(default)]
+    block_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GeminiError {
+    #[serde(default)]
+    message: String,
+}
+
+/// Settings only the native Ollama and Gemini formats accept, read from the
+/// agent's `env_vars`
+#[derive(Debug, Clone, Default)]
+struct NativeOptions {
+    /// `LLM_KEEP_ALIVE`: how long Ollama keeps the model loaded ("10m", -1)
+    keep_alive: Option<serde_json::Value>,
+    /// `LLM_OPTIONS`: Ollama `options` object
+    ollama_options: serde_json::Map<String, serde_json::Value>,
+    /// `LLM_SAFETY_SETTINGS`: Gemini `safetySettings` array
+    safety_settings: Option<serde_json::Value>,
+}
+
+impl NativeOptions {
+    fn from_env(agent_name: &str, env_vars: &HashMap<String, String>) -> Self {
+        let json = |key: &str| {
+            env_vars.get(key).and_then(|raw| match serde_json::from_str::<serde_json::Value>(raw) {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    tracing::warn!("[direct] {}: ignoring {}, not valid JSON: {}", agent_name, key, e);
+                    None
+                }
+            })
+        };
+        Self {
+            // A number ("-1", "300") or a duration string ("10m")
+            keep_alive: env_vars.get("LLM_KEEP_ALIVE").map(|raw| {
+                serde_json::from_str::<serde_json::Number>(raw)
+                    .map(serde_json::Value::Number)
+                    .unwrap_or_else(|_| json!(raw))
+            }),
+            ollama_options: match json("LLM_OPTIONS") {
+                Some(serde_json::Value::Object(map)) => map,
+                _ => Default::default(),
+            },
+            safety_settings: json("LLM_SAFETY_SETTINGS").filter(serde_json::Value::is_array),
+        }
+    }
+}
+
+impl NativeOptions {
+    /// `LLM_OPTIONS` with the request's own sampling settings on top
+    fn ollama_options(
+        &self,
+        temperature: Option<f32>,
+        max_tokens: Option<u32>,
+    ) -> serde_json::Map<String, serde_json::Value> {
+        let mut options = self.ollama_options.clone();
+        if let Some(t) = temperature {
+            options.insert("temperature".to_string(), json!(t));
+        }
+        if let Some(n) = max_tokens {
+            options.insert("num_predict".to_string(), json!(n));
+        }
+        options
+    }
+}
+
+impl OllamaChunk {
+    fn usage(&self) -> Option<TokenUsage> {
+        if self.prompt_eval_count.is_none() && self.eval_count.is_none() {
+            return None;
+        }
+        Some(TokenUsage {
+            input_tokens: self.prompt_eval_count.unwrap_or(0),
+            output_tokens: self.eval_count.unwrap_or(0),
+        })
+    }
+}
+
+impl GeminiResponse {
+    /// Errors reported in the body: an API error, or a prompt refused by
+    /// the safety filters
+    fn check(&self) -> Result<(), String> {
+        if let Some(e) = &self.error {
+            return Err(format!("provider error: {}", e.message));
+        }
+        match self.prompt_feedback.as_ref().and_then(|f| f.block_reason.as_deref()) {
+            Some(reason) if self.candidates.is_empty() => Err(format!("prompt blocked: {}", reason)),
+            _ => Ok(()),
+        }
+    }
+
+    fn parts(&self) -> impl Iterator<Item = &GeminiResponsePart> {
+        self.candidates
+            .iter()
+            .take(1)
+            .filter_map(|c| c.content.as_ref())
+            .flat_map(|c| c.parts.iter())
+            .filter(|p| !p.thought)
+    }
+
+    fn text_parts(&self) -> impl Iterator<Item = &str> {
+        self.parts().filter_map(|p| p.text.as_deref())
+    }
+
+    fn usage(&self) -> Option<TokenUsage> {
+        self.usage_metadata.as_ref().map(|u| TokenUsage {
+            input_tokens: u.prompt_token_count,
+            output_tokens: u.candidates_token_count,
+        })
+    }
+}
+
+/// Where and how to reach an agent's upstream provider.
+struct Upstream {
+    provider: LlmProvider,
+    endpoint: String,
+    format: ApiFormat,
+    model: String,
+    /// Key configured for this upstream; otherwise the stored key for
+    /// `provider` is used
+    api_key: Option<String>,
+    native: NativeOptions,
+}
+
+/// Resolve endpoint, wire format and model id for a provider, with optional
+/// base URL and format overrides.
+fn build_upstream(
+    provider: &LlmProvider,
+    model: Option<&str>,
+    base_url: Option<&str>,
+    api_format: Option<&str>,
+    api_key: Option<String>,
+) -> Result<Upstream, &'static str> {
+    let (default_url, default_format) = default_endpoint(provider);
+    let base_url = base_url.unwrap_or(default_url);
+    if base_url.is_empty() {
+        return Err("no LLM_BASE_URL configured for this provider");
+    }
+    let format = match api_format.map(|s| s.to_lowercase()) {
+        Some(s) if s == "openai" => ApiFormat::OpenAi,
+        Some(s) if s == "anthropic" || s == "anthropic-messages" => ApiFormat::AnthropicMessages,
+        Some(s) if s == "ollama" || s == "ollama-chat" => ApiFormat::OllamaChat,
+        Some(s) if s == "gemini" || s == "gemini-generate" => ApiFormat::GeminiGenerate,
+        _ => default_format,
+    };
+
+    let model = wire_model_id(provider, model.unwrap_or("default"));
+
+    let base_url = base_url.trim_end_matches('/');
+    // The default URLs point at OpenAI-compatible layers; the native APIs
+    // sit beside them
+    let endpoint = match format {
+        ApiFormat::OpenAi => format!("{}/chat/completions", base_url),
+        ApiFormat::AnthropicMessages => format!("{}/v1/messages", base_url),
+        ApiFormat::OllamaChat => format!("{}/api/chat", base_url.strip_suffix("/v1").unwrap_or(base_url)),
+        ApiFormat::GeminiGenerate => format!(
+            "{}/models/{}",
+            base_url.strip_suffix("/openai").unwrap_or(base_url),
+            model
+        ),
+    };
+    Ok(Upstream {
+        provider: provider.clone(),
+        endpoint,
+        format,
+        model,
+        api_key: api_key.filter(|k| !k.is_empty()),
+        native: NativeOptions::default(),
+    })
+}
+
+/// Resolve the agent's own upstream, honouring the `LLM_BASE_URL` /
+/// `LLM_API_FORMAT` overrides.
+fn resolve_upstream(agent: &AgentContainer) -> Result<Upstream, &'static str> {
+    let mut upstream = build_upstream(
+        &agent.config.llm_provider,
+        agent.config.llm_model.as_deref(),
+        agent.config.env_vars.get("LLM_BASE_URL").map(String::as_str),
+        agent.config.env_vars.get("LLM_API_FORMAT").map(String::as_str),
+        agent.config.api_key.clone(),
+    )?;
+    upstream.native = NativeOptions::from_env(&agent.name, &agent.config.env_vars);
+    Ok(upstream)
+}
+
+/// The `[model-servers]` entry for a local provider
+fn model_server<'a>(provider: &LlmProvider, servers: &'a ModelServers) -> Option<&'a ModelServerConfig> {
+    match provider {
+        LlmProvider::Ollama => servers.ollama.as_ref(),
+        LlmProvider::LlamaCpp => servers.llama_cpp.as_ref(),
+        LlmProvider::Vllm => servers.vllm.as_ref(),
+        LlmProvider::Lmstudio => servers.lm_studio.as_ref(),
+        _ => None,
+    }
+}
+
+/// Resolve one fallback. Local providers without a `base_url` use their
+/// `[model-servers]` endpoint, default model and token.
+fn fallback_upstream(fallback: &LlmFallback, servers: &ModelServers) -> Result<Upstream, &'static str> {
+    let server = model_server(&fallback.provider, servers);
+    // Model servers are configured by root URL; the OpenAI API is under /v1
+    let server_url = server.filter(|_| fallback.base_url.is_none()).map(|s| {
+        let root = s.endpoint.trim_end_matches('/');
+        if root.ends_with("/v1") { root.to_string() } else { format!("{}/v1", root) }
+    });
+    build_upstream(
+        &fallback.provider,
+        fallback.model.as_deref().or_else(|| server.and_then(|s| s.default_model.as_deref())),
+        fallback.base_url.as_deref().or(server_url.as_deref()),
+        fallback.api_format.as_deref(),
+        fallback.api_key.clone().or_else(|| server.and_then(|s| s.api_token.clone())),
+    )
+}
+
+/// The agent's provider followed by its fallbacks, skipping any that can't
+/// be resolved. Fails only if none can.
+fn upstream_chain(agent: &AgentContainer, servers: &ModelServers) -> Result<Vec<Upstream>, &'static str> {
+    let primary = resolve_upstream(agent);
+    let mut chain = Vec::new();
+    let mut first_error = None;
+    let fallbacks = agent.config.llm_fallbacks.iter().map(|f| fallback_upstream(f, servers));
+    for upstream in std::iter::once(primary).chain(fallbacks) {
+        match upstream {
+            Ok(u) => chain.push(u),
+            Err(e) => {
+                tracing::warn!("[direct] {}: skipping unusable provider: {}", agent.name, e);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    match first_error {
+        Some(e) if chain.is_empty() => Err(e),
+        _ => Ok(chain),
+    }
+}
+
+/// Local model servers usually run without authentication
+fn is_local(provider: &LlmProvider) -> bool {
+    matches!(
+        provider,
+        LlmProvider::Ollama | LlmProvider::LlamaCpp | LlmProvider::Vllm | LlmProvider::Lmstudio
+    )
+}
+
+/// Errors worth retrying on another provider: rate limits, timeouts and
+/// server-side failures. Anything else would fail the same way elsewhere.
+fn should_fail_over(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        || status == reqwest::StatusCode::REQUEST_TIMEOUT
+        || status.is_server_error()
+}
+
+/// Read the teacher-authored system prompt from the agent's identity volume.
+async fn load_system_prompt(agent_name: &str) -> Option<String> {
+    let path = std::path::PathBuf::from("./data/agents")
+        .join(agent_name)
+        .join("identity")
+        .join("system_prompt.md");
+    tokio::fs::read_to_string(path).await.ok()
+}
+
+/// Resolve the API key for an upstream: its own key → global stored key for
+/// the provider. `None` is fine for local model servers.
+async fn resolve_api_key(
+    api_keys: &RwLock<HashMap<String, String>>,
+    upstream: &Upstream,
+) -> Option<String> {
+    if let Some(k) = &upstream.api_key {
+        return Some(k.clone());
+    }
+    let key_lookup = match upstream.provider {
+        LlmProvider::OpenAI => "openai",
+        LlmProvider::Anthropic => "anthropic",
+        LlmProvider::Kimi => "kimi",
+        LlmProvider::KimiCode => "kimi-code",
+        LlmProvider::Zai => "zai",
+        LlmProvider::Gemini => "google",
+        LlmProvider::Huggingface => "huggingface",
+        _ => return None,
+    };
+    let keys = api_keys.read().await;
+    keys.get(key_lookup).cloned()
+}
+
+/// Start a POST to an upstream with its auth and version headers.
+fn post(http: &reqwest::Client, upstream: &Upstream, api_key: Option<&str>, stream: bool) -> reqwest::RequestBuilder {
+    // Gemini picks streaming by method rather than by a body field
+    let mut req = match upstream.format {
+        ApiFormat::GeminiGenerate if stream => {
+            http.post(format!("{}:streamGenerateContent?alt=sse", upstream.endpoint))
+        }
+        ApiFormat::GeminiGenerate => http.post(format!("{}:generateContent", upstream.endpoint)),
+        _ => http.post(&upstream.endpoint),
+    };
+    if let Some(key) = api_key {
+        req = match upstream.format {
+            ApiFormat::GeminiGenerate => req.header("x-goog-api-key", key),
+            _ => req.bearer_auth(key),
+        };
+    }
+    if upstream.format == ApiFormat::AnthropicMessages {
+        req = req.header("anthropic-version", "2023-06-01");
+    }
+    req
+}
+
+/// Run a single non-streaming completion against a direct-runtime agent's
+/// provider and return the assistant text, plus the token counts when the
+/// provider reports them.
+///
+/// Used by the workflow executor, which wants the whole answer rather than a
+/// token stream. Falls back to the agent's identity system prompt when the
+/// caller doesn't supply one.
+pub async fn complete(
+    http: &reqwest::Client,
+    api_keys: &RwLock<HashMap<String, String>>,
+    agent: &AgentContainer,
+    system: Option<&str>,
+    prompt: &str,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+) -> anyhow::Result<(String, Option<TokenUsage>)> {
+    let upstream = resolve_upstream(agent).map_err(|e| anyhow::anyhow!(e))?;
+    let api_key = resolve_api_key(api_keys, &upstream).await;
+    if api_key.is_none() && !is_local(&upstream.provider) {
+        return Err(anyhow::anyhow!("no API key configured for this provider"));
+    }
+
+    let identity_prompt = match system {
+        Some(_) => None,
+        None => load_system_prompt(&agent.name).await,
+    };
+    let system = system.or(identity_prompt.as_deref());
+    complete_on(http, &upstream, api_key.as_deref(), system, prompt, temperature, max_tokens).await
+}
+
+/// One non-streaming completion on a resolved upstream.
+async fn complete_on(
+    http: &reqwest::Client,
+    upstream: &Upstream,
+    api_key: Option<&str>,
+    system: Option<&str>,
+    prompt: &str,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+) -> anyhow::Result<(String, Option<TokenUsage>)> {
+    tracing::info!(
+        "[direct] POST {} model={} format={:?} (one-shot)",
+        upstream.endpoint, upstream.model, upstream.format
+    );
+
+    let resp = match upstream.format {
+        ApiFormat::OpenAi => {
+            let mut messages = Vec::new();
+            if let Some(sp) = system {
+                messages.push(OpenAiMessage::text("system", sp));
+            }
+            messages.push(OpenAiMessage::text("user", prompt));
+            let body = OpenAiRequest {
+                model: &upstream.model,
+                messages,
+                stream: false,
+                temperature,
+                max_tokens,
+                stream_options: None,
+                tools: Vec::new(),
+            };
+            post(http, upstream, api_key, false)
+                .json(&body)
+                .send()
+                .await?
+        }
+        ApiFormat::AnthropicMessages => {
+            let body = AnthropicRequest {
+                model: &upstream.model,
+                max_tokens: max_tokens.unwrap_or(4096),
+                messages: vec![AnthropicMessage { role: "user", content: AnthropicContent::Text(prompt) }],
+                system,
+                stream: false,
+                temperature,
+                tools: Vec::new(),
+            };
+            post(http, upstream, api_key, false)
+                .json(&body)
+                .send()
+                .await?
+        }
+        ApiFormat::OllamaChat => {
+            let mut messages = Vec::new();
+            if let Some(sp) = system {
+                messages.push(OllamaMessage::text("system", sp));
+            }
+            messages.push(OllamaMessage::text("user", prompt));
+            let body = OllamaRequest {
+                model: &upstream.model,
+                messages,
+                stream: false,
+                tools: Vec::new(),
+                keep_alive: upstream.native.keep_alive.as_ref(),
+                options: upstream.native.ollama_options(temperature, max_tokens),
+            };
+            post(http, upstream, api_key, false)
+                .json(&body)
+                .send()
+                .await?
+        }
+        ApiFormat::GeminiGenerate => {
+            let body = GeminiRequest {
+                contents: vec![GeminiContent { role: Some("user"), parts: vec![GeminiPart::Text(prompt)] }],
+                system_instruction: system.map(|sp| GeminiContent { role: None, parts: vec![GeminiPart::Text(sp)] }),
+                generation_config: Some(GeminiGenerationConfig { temperature, max_output_tokens: max_tokens }),
+                safety_settings: upstream.native.safety_settings.as_ref(),
+                tools: Vec::new(),
+            };
+            post(http, upstream, api_key, false)
+                .json(&body)
+                .send()
+                .await?
+        }
+    };
+
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        return Err(anyhow::anyhow!(provider_error(status, &body)));
+    }
+
+    let completion = match upstream.format {
+        ApiFormat::OpenAi => {
+            let parsed: OpenAiResponse = resp.json().await?;
+            let usage = parsed.usage.map(|u| TokenUsage {
+                input_tokens: u.prompt_tokens,
+                output_tokens: u.completion_tokens,
+            });
+            let text = parsed.choices
+                .into_iter()
+                .filter_map(|c| c.message.content)
+                .collect::<String>();
+            (text, usage)
+        }
+        ApiFormat::AnthropicMessages => {
+            let parsed: AnthropicResponse = resp.json().await?;
+            let usage = parsed.usage.map(|u| TokenUsage {
+                input_tokens: u.input_tokens.unwrap_or(0),
+                output_tokens: u.output_tokens.unwrap_or(0),
+            });
+            let text = parsed.content
+                .into_iter()
+                .filter(|b| b.block_type == "text")
+                .filter_map(|b| b.text)
+                .collect::<String>();
+            (text, usage)
+        }
+        ApiFormat::OllamaChat => {
+            let parsed: OllamaChunk = resp.json().await?;
+            if let Some(e) = parsed.error {
+                return Err(anyhow::anyhow!("provider error: {}", e));
+            }
+            let usage = parsed.usage();
+            (parsed.message.map(|m| m.content).unwrap_or_default(), usage)
+        }
+        ApiFormat::GeminiGenerate => {
+            let parsed: GeminiResponse = resp.json().await?;
+            parsed.check().map_err(|e| anyhow::anyhow!(e))?;
+            let text = parsed.text_parts().collect::<String>();
+            (text, parsed.usage())
+        }
+    };
+    Ok(completion)
+}
+
+/// Send `chat`/`final` event with full text and persist the assistant turn.
+async fn finalize(
+    client_tx: &mut futures::stream::SplitSink<WebSocket, Message>,
+    full_text: &str,
+    agent_id: &str,
+    agent_name: &str,
+    session_id: &str,
+    upstream: &Upstream,
+) {
+    use futures::SinkExt;
+    let final_event = json!({
+        "type": "event",
+        "event": "chat",
+        "payload": {
+            "state": "final",
+            "message": {
+                "role": "assistant",
+                "content": [{ "type": "text", "text": full_text }]
+            }
+        }
+    });
+    let _ = client_tx.send(Message::Text(final_event.to_string())).await;
+
+    // Which provider actually answered, since it may be a fallback
+    let metadata = HashMap::from([
+        ("provider".to_string(), json!(upstream.provider.as_str())),
+        ("model".to_string(), json!(upstream.model)),
+    ]);
+    let msg = ConversationMessage {
+        id: uuid::Uuid::new_v4().to_string(),
+        session_id: session_id.to_string(),
+        role: "assistant".to_string(),
+        content: full_text.to_string(),
+        agent_id: agent_id.to_string(),
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        metadata,
+    };
+    if let Err(e) = crate::api::append_conversation_message(agent_name, &msg).await {
+        tracing::warn!("Direct: failed to persist assistant message: {}", e);
+    }
+}
+
+async fn send_error(
+    client_tx: &mut futures::stream::SplitSink<WebSocket, Message>,
+    msg: &str,
+) {
+    use futures::SinkExt;
+    let err = json!({
+        "role": "system",
+        "error": "direct backend error",
+        "content": msg,
+        "timestamp": chrono::Utc::now().timestamp()
+    });
+    let _ = client_tx.send(Message::Text(err.to_string())).await;
+}
+
+/// How long a provider that has a fallback behind it gets to start
+/// answering (response headers, then the first token)
+const FIRST_TOKEN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Model/tool round trips allowed per user message
+const MAX_TOOL_ROUNDS: usize = 8;
+
+/// A streamed completion
+struct Reply {
+    text: String,
+    usage: Option<TokenUsage>,
+    tool_calls: Vec<ToolCall>,
+}
+
+/// A model turn that called tools, and what the tools returned
+struct ToolRound {
+    text: String,
+    calls: Vec<ToolCall>,
+    outputs: Vec<ToolOutput>,
+}
+
+/// Tool calls assembled from streamed fragments, keyed by the call's index
+/// (OpenAI) or content block index (Anthropic). Ollama and Gemini send
+/// calls whole, without ids.
+#[derive(Debug, Default)]
+struct ToolCallBuilder {
+    /// (id, name, arguments JSON so far)
+    calls: BTreeMap<usize, (String, String, String)>,
+}
+
+impl ToolCallBuilder {
+    fn start(&mut self, index: usize, id: Option<String>, name: Option<String>) {
+        let call = self.calls.entry(index).or_default();
+        if let Some(id) = id {
+            call.0 = id;
+        }
+        if let Some(name) = name {
+            call.1 = name;
+        }
+    }
+
+    fn push_arguments(&mut self, index: usize, fragment: &str) {
+        self.calls.entry(index).or_default().2.push_str(fragment);
+    }
+
+    fn push_whole(&mut self, call: FunctionCallIn) {
+        let index = self.calls.len();
+        let arguments = match call.arguments {
+            serde_json::Value::String(raw) => raw,
+            value => value.to_string(),
+        };
+        self.calls.insert(index, (String::new(), call.name, arguments));
+    }
+
+    /// Arguments that don't parse become `{}`, which the tool then rejects
+    /// as missing arguments.
+    fn finish(self) -> Vec<ToolCall> {
+        self.calls
+            .into_iter()
+            .filter(|(_, (_, name, _))| !name.is_empty())
+            .map(|(index, (id, name, arguments))| ToolCall {
+                // Some OpenAI-compatible servers leave the id out
+                id: if id.is_empty() { format!("call_{}", index) } else { id },
+                name,
+                arguments: serde_json::from_str(&arguments)
+                    .ok()
+                    .filter(serde_json::Value::is_object)
+                    .unwrap_or_else(|| json!({})),
+            })
+            .collect()
+    }
+}
+
+/// Send a streaming chat request: system prompt + prior session turns (which
+/// already end with this user turn) + this message's tool rounds so far, in
+/// the upstream's wire format.
+///
+/// Tool turns persisted for earlier messages are left out; the model sees
+/// only the answers it gave from them.
+#[allow(clippy::too_many_arguments)]
+async fn send_chat(
+    http: &reqwest::Client,
+    upstream: &Upstream,
+    api_key: Option<&str>,
+    system_prompt: Option<&str>,
+    history: &[ConversationMessage],
+    user_content: &str,
+    tools: &[ToolSpec],
+    rounds: &[ToolRound],
+) -> reqwest::Result<reqwest::Response> {
+    let turns: Vec<&ConversationMessage> = history
+        .iter()
+        .filter(|h| h.role != "tool" && !h.content.is_empty())
+        .collect();
+    match upstream.format {
+        ApiFormat::OpenAi => {
+            let mut messages = Vec::new();
+            if let Some(sp) = system_prompt {
+                messages.push(OpenAiMessage::text("system", sp));
+            }
+            for h in &turns {
+                messages.push(OpenAiMessage::text(h.role.as_str(), h.content.as_str()));
+            }
+            if turns.is_empty() {
+                messages.push(OpenAiMessage::text("user", user_content));
+            }
+            for round in rounds {
+                messages.push(OpenAiMessage {
+                    role: "assistant",
+                    content: Some(round.text.as_str()).filter(|t| !t.is_empty()),
+                    tool_calls: round.calls.iter().map(|c| OpenAiToolCall {
+                        id: &c.id,
+                        call_type: "function",
+                        function: OpenAiFunctionCall { name: &c.name, arguments: c.arguments.to_string() },
+                    }).collect(),
+                    tool_call_id: None,
+                });
+                for (call, output) in round.calls.iter().zip(&round.outputs) {
+                    messages.push(OpenAiMessage {
+                        role: "tool",
+                        content: Some(&output.content),
+                        tool_calls: Vec::new(),
+                        tool_call_id: Some(&call.id),
+                    });
+                }
+            }
+            let body = OpenAiRequest {
+                model: &upstream.model,
+                messages,
+                stream: true,
+                temperature: None,
+                max_tokens: None,
+                stream_options: Some(OpenAiStreamOptions { include_usage: true }),
+                tools: tools.iter().map(|t| OpenAiTool {
+                    tool_type: "function",
+                    function: OpenAiFunction { name: t.name, description: t.description, parameters: &t.parameters },
+                }).collect(),
+            };
+            post(http, upstream, api_key, true).json(&body).send().await
+        }
+        ApiFormat::AnthropicMessages => {
+            // Anthropic format: system goes outside `messages`, only user/assistant
+            // turns inside. No `system` role inside messages array.
+            let mut messages = Vec::new();
+            for h in turns.iter().filter(|h| h.role != "system") {
+                messages.push(AnthropicMessage {
+                    role: h.role.as_str(),
+                    content: AnthropicContent::Text(h.content.as_str()),
+                });
+            }
+            if messages.is_empty() {
+                messages.push(AnthropicMessage { role: "user", content: AnthropicContent::Text(user_content) });
+            }
+            // Each round is an assistant turn with tool_use blocks, answered
+            // by a user turn of tool_result blocks
+            for round in rounds {
+                let mut blocks = Vec::new();
+                if !round.text.is_empty() {
+                    blocks.push(AnthropicBlock::Text { text: &round.text });
+                }
+                blocks.extend(round.calls.iter().map(|c| AnthropicBlock::ToolUse {
+                    id: &c.id,
+                    name: &c.name,
+                    input: &c.arguments,
+                }));
+                messages.push(AnthropicMessage { role: "assistant", content: AnthropicContent::Blocks(blocks) });
+                let results = round.calls.iter().zip(&round.outputs).map(|(c, o)| AnthropicBlock::ToolResult {
+                    tool_use_id: &c.id,
+                    content: &o.content,
+                    is_error: o.is_error,
+                }).collect();
+                messages.push(AnthropicMessage { role: "user", content: AnthropicContent::Blocks(results) });
+            }
+            let body = AnthropicRequest {
+                model: &upstream.model,
+                max_tokens: 4096,
+                messages,
+                system: system_prompt,
+                stream: true,
+                temperature: None,
+                tools: tools.iter().map(|t| AnthropicTool {
+                    name: t.name,
+                    description: t.description,
+                    input_schema: &t.parameters,
+                }).collect(),
+            };
+            post(http, upstream, api_key, true).json(&body).send().await
+        }
+        ApiFormat::OllamaChat => {
+            let mut messages = Vec::new();
+            if let Some(sp) = system_prompt {
+                messages.push(OllamaMessage::text("system", sp));
+            }
+            for h in &turns {
+                messages.push(OllamaMessage::text(h.role.as_str(), h.content.as_str()));
+            }
+            if turns.is_empty() {
+                messages.push(OllamaMessage::text("user", user_content));
+            }
+            for round in rounds {
+                messages.push(OllamaMessage {
+                    role: "assistant",
+                    content: &round.text,
+                    tool_calls: round.calls.iter().map(|c| OllamaToolCall {
+                        function: OllamaFunctionCall { name: &c.name, arguments: &c.arguments },
+                    }).collect(),
+                    tool_name: None,
+                });
+                for (call, output) in round.calls.iter().zip(&round.outputs) {
+                    messages.push(OllamaMessage {
+                        role: "tool",
+                        content: &output.content,
+                        tool_calls: Vec::new(),
+                        tool_name: Some(&call.name),
+                    });
+                }
+            }
+            let body = OllamaRequest {
+                model: &upstream.model,
+                messages,
+                stream: true,
+                tools: tools.iter().map(|t| OpenAiTool {
+                    tool_type: "function",
+                    function: OpenAiFunction { name: t.name, description: t.description, parameters: &t.parameters },
+                }).collect(),
+                keep_alive: upstream.native.keep_alive.as_ref(),
+                options: upstream.native.ollama_options(None, None),
+            };
+            post(http, upstream, api_key, true).json(&body).send().await
+        }
+        ApiFormat::GeminiGenerate => {
+            // Gemini calls the assistant "model" and takes the system prompt
+            // as a separate instruction
+            let mut contents = Vec::new();
+            for h in turns.iter().filter(|h| h.role != "system") {
+                let role = if h.role == "assistant" { "model" } else { "user" };
+                contents.push(GeminiContent { role: Some(role), parts: vec![GeminiPart::Text(&h.content)] });
+            }
+            if contents.is_empty() {
+                contents.push(GeminiContent { role: Some("user"), parts: vec![GeminiPart::Text(user_content)] });
+            }
+            for round in rounds {
+                let mut parts = Vec::new();
+                if !round.text.is_empty() {
+                    parts.push(GeminiPart::Text(&round.text));
+                }
+                parts.extend(round.calls.iter().map(|c| GeminiPart::FunctionCall { name: &c.name, args: &c.arguments }));
+                contents.push(GeminiContent { role: Some("model"), parts });
+                let responses = round.calls.iter().zip(&round.outputs).map(|(c, o)| GeminiPart::FunctionResponse {
+                    name: &c.name,
+                    response: if o.is_error { json!({ "error": o.content }) } else { json!({ "content": o.content }) },
+                }).collect();
+                contents.push(GeminiContent { role: Some("user"), parts: responses });
+            }
+            let body = GeminiRequest {
+                contents,
+                system_instruction: system_prompt.map(|sp| GeminiContent { role: None, parts: vec![GeminiPart::Text(sp)] }),
+                generation_config: None,
+                safety_settings: upstream.native.safety_settings.as_ref(),
+                tools: if tools.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![GeminiTools {
+                        function_declarations: tools.iter().map(|t| GeminiFunction {
+                            name: t.name,
+                            description: t.description,
+                            parameters: &t.parameters,
+                        }).collect(),
+                    }]
+                },
+            };
+            post(http, upstream, api_key, true).json(&body).send().await
+        }
+    }
+}
+
+/// Send one `chat`/`delta` event. False if the client has gone away.
+async fn send_delta(
+    client_tx: &mut futures::stream::SplitSink<WebSocket, Message>,
+    text: &str,
+) -> bool {
+    use futures::SinkExt;
+    let delta_event = json!({
+        "type": "event",
+        "event": "chat",
+        "payload": {
+            "state": "delta",
+            "message": {
+                "role": "assistant",
+                "content": text
+            }
+        }
+    });
+    client_tx.send(Message::Text(delta_event.to_string())).await.is_ok()
+}
+
+/// Incremental parser for one streamed completion, fed one SSE `data:`
+/// payload (one NDJSON line for Ollama) at a time.
+struct StreamParser {
+    format: ApiFormat,
+    text: String,
+    usage: Option<TokenUsage>,
+    tool_calls: ToolCallBuilder,
+    /// Whether any text or tool call has arrived
+    started: bool,
+}
+
+impl StreamParser {
+    fn new(format: ApiFormat) -> Self {
+        Self {
+            format,
+            text: String::new(),
+            usage: None,
+            tool_calls: ToolCallBuilder::default(),
+            started: false,
+        }
+    }
+
+    /// The payload a stream line carries, if any
+    fn payload(format: ApiFormat, line: &str) -> Option<&str> {
+        let payload = match format {
+            ApiFormat::OllamaChat => line,
+            _ => line.strip_prefix("data: ").or_else(|| line.strip_prefix("data:"))?,
+        };
+        Some(payload.trim()).filter(|p| !p.is_empty())
+    }
+
+    /// Handle one payload: returns the text it adds and whether the
+    /// completion is finished. Payloads that don't parse are skipped;
+    /// errors the provider reports in-stream are returned.
+    fn feed(&mut self, payload: &str) -> Result<(Option<String>, bool), String> {
+        if payload == "[DONE]" {
+            return Ok((None, true));
+        }
+        let (text, stop) = match self.format {
+            ApiFormat::OpenAi => self.feed_openai(payload),
+            ApiFormat::AnthropicMessages => self.feed_anthropic(payload),
+            ApiFormat::OllamaChat => self.feed_ollama(payload)?,
+            ApiFormat::GeminiGenerate => self.feed_gemini(payload)?,
+        };
+        if text.is_empty() {
+            return Ok((None, stop));
+        }
+        self.started = true;
+        self.text.push_str(&text);
+        Ok((Some(text), stop))
+    }
+
+    fn feed_openai(&mut self, payload: &str) -> (String, bool) {
+        let Ok(parsed) = serde_json::from_str::<OpenAiChunk>(payload) else {
+            return (String::new(), false);
+        };
+        let mut text = String::new();
+        for choice in parsed.choices {
+            if let Some(d) = choice.delta.content {
+                text.push_str(&d);
+            }
+            for call in choice.delta.tool_calls {
+                self.started = true;
+                let (name, arguments) = call.function.map(|f| (f.name, f.arguments)).unwrap_or_default();
+                self.tool_calls.start(call.index, call.id, name);
+                if let Some(arguments) = arguments {
+                    self.tool_calls.push_arguments(call.index, &arguments);
+                }
+            }
+            if choice.finish_reason.is_some() {
+                tracing::debug!("[direct] finish_reason={:?}", choice.finish_reason);
+            }
+        }
+        // The usage chunk follows the finish_reason one, so keep reading
+        // until it (or [DONE]) arrives.
+        let stop = match parsed.usage {
+            Some(u) => {
+                self.usage = Some(TokenUsage {
+                    input_tokens: u.prompt_tokens,
+                    output_tokens: u.completion_tokens,
+                });
+                true
+            }
+            None => false,
+        };
+        (text, stop)
+    }
+
+    fn feed_anthropic(&mut self, payload: &str) -> (String, bool) {
+        let Ok(parsed) = serde_json::from_str::<AnthropicSseEvent>(payload) else {
+            return (String::new(), false);
+        };
+        match parsed.event_type.as_deref() {
+            Some("message_start") => {
+                let input = parsed.message.as_ref()
+                    .and_then(|m| m.usage.as_ref())
+                    .and_then(|u| u.input_tokens)
+                    .unwrap_or(0);
+                self.usage.get_or_insert_with(TokenUsage::default).input_tokens = input;
+                (String::new(), false)
+            }
+            Some("content_block_start") => {
+                if let Some(block) = parsed.content_block.filter(|b| b.block_type == "tool_use") {
+                    self.started = true;
+                    self.tool_calls.start(parsed.index.unwrap_or(0), block.id, block.name);
+                }
+                (String::new(), false)
+            }
+            Some("content_block_delta") => {
+                let delta = parsed.delta;
+                if let Some(json) = delta.as_ref().and_then(|d| d.partial_json.as_deref()) {
+                    self.tool_calls.push_arguments(parsed.index.unwrap_or(0), json);
+                }
+                (delta.and_then(|d| d.text).unwrap_or_default(), false)
+            }
+            Some("message_stop") => (String::new(), true),
+            Some("message_delta") => {
+                if let Some(output) = parsed.usage.as_ref().and_then(|u| u.output_tokens) {
+                    self.usage.get_or_insert_with(TokenUsage::default).output_tokens = output;
+                }
+                let stop = parsed.delta.as_ref().and_then(|d| d.stop_reason.as_ref()).is_some();
+                (String::new(), stop)
+            }
+            _ => (String::new(), false),
+        }
+    }
 
-#[derive(Debug, Default, Deserialize)]
-struct OpenAiDelta {
-    #[serde(default)]
-    content: Option<String>,
-}
+    fn feed_ollama(&mut self, payload: &str) -> Result<(String, bool), String> {
+        let Ok(parsed) = serde_json::from_str::<OllamaChunk>(payload) else {
+            return Ok((String::new(), false));
+        };
+        if let Some(e) = parsed.error {
+            return Err(format!("provider error: {}", e));
+        }
+        if parsed.done {
+            self.usage = parsed.usage();
+        }
+        let mut text = String::new();
+        if let Some(message) = parsed.message {
+            text = message.content;
+            for call in message.tool_calls {
+                self.started = true;
+                self.tool_calls.push_whole(call.function);
+            }
+        }
+        Ok((text, parsed.done))
+    }
 
-// ─── Anthropic /v1/messages wire types ──────────────────────────────────────
-#[derive(Debug, Serialize)]
-struct AnthropicRequest<'a> {
-    model: &'a str,
-    max_tokens: u32,
-    messages: Vec<AnthropicMessage<'a>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    system: Option<&'a str>,
-    stream: bool,
-}
+    /// Gemini's stream just ends; there's no final event.
+    fn feed_gemini(&mut self, payload: &str) -> Result<(String, bool), String> {
+        let Ok(parsed) = serde_json::from_str::<GeminiResponse>(payload) else {
+            return Ok((String::new(), false));
+        };
+        parsed.check()?;
+        if let Some(usage) = parsed.usage() {
+            self.usage = Some(usage);
+        }
+        let text = parsed.text_parts().collect::<String>();
+        let calls: Vec<_> = parsed
+            .candidates
+            .into_iter()
+            .take(1)
+            .filter_map(|c| c.content)
+            .flat_map(|c| c.parts)
+            .filter(|p| !p.thought)
+            .filter_map(|p| p.function_call)
+            .collect();
+        for call in calls {
+            self.started = true;
+            self.tool_calls.push_whole(call);
+        }
+        Ok((text, false))
+    }
 
-#[derive(Debug, Serialize)]
-struct AnthropicMessage<'a> {
-    role: &'a str,
-    content: &'a str,
+    fn finish(self) -> Reply {
+        Reply {
+            text: self.text,
+            usage: self.usage,
+            tool_calls: self.tool_calls.finish(),
+        }
+    }
 }
 
-/// SSE event body when type=content_block_delta.
-#[derive(Debug, Deserialize)]
-struct AnthropicSseEvent {
-    #[serde(rename = "type")]
-    event_type: Option<String>,
-    #[serde(default)]
-    delta: Option<AnthropicEventDelta>,
-}
+/// Relay a streamed completion to the client as `chat`/`delta` events and
+/// return the full text, reported usage and any tool calls.
+///
+/// Fails, without having sent anything, if the stream breaks or
+/// `first_token_timeout` passes before the first token or tool call; the
+/// caller can then try the next provider. Errors after that are reported to
+/// the client and the partial reply is returned.
+async fn relay_stream(
+    resp: reqwest::Response,
+    api_format: ApiFormat,
+    client_tx: &mut futures::stream::SplitSink<WebSocket, Message>,
+    first_token_timeout: Option<Duration>,
+) -> Result<Reply, String> {
+    use futures::stream::StreamExt as _;
 
-#[derive(Debug, Deserialize)]
-struct AnthropicEventDelta {
-    #[serde(rename = "type")]
-    delta_type: Option<String>,
-    text: Option<String>,
-    stop_reason: Option<String>,
-}
+    // Split into lines as bytes, so multi-byte characters cut across chunks
+    // decode whole.
+    let mut stream = resp.bytes_stream();
+    let mut buf: Vec<u8> = Vec::new();
+    let mut parser = StreamParser::new(api_format);
+    let first_token_deadline = first_token_timeout.map(|t| tokio::time::Instant::now() + t);
+    let mut ended = false;
 
-/// Read the teacher-authored system prompt from the agent's identity volume.
-async fn load_system_prompt(agent_name: &str) -> Option<String> {
-    let path = std::path::PathBuf::from("./data/agents")
-        .join(agent_name)
-        .join("identity")
-        .join("system_prompt.md");
-    tokio::fs::read_to_string(path).await.ok()
-}
+    'outer: while !ended {
+        let next = match first_token_deadline.filter(|_| !parser.started) {
+            Some(deadline) => match tokio::time::timeout_at(deadline, stream.next()).await {
+                Ok(next) => next,
+                Err(_) => return Err("timed out waiting for the first token".to_string()),
+            },
+            None => stream.next().await,
+        };
+        match next {
+            Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+            // Flush a last line that has no newline
+            None => {
+                ended = true;
+                buf.push(b'\n');
+            }
+            Some(Err(e)) if !parser.started => return Err(format!("stream error: {}", e)),
+            Some(Err(e)) => {
+                send_error(client_tx, &format!("stream error: {}", e)).await;
+                break;
+            }
+        }
 
-/// Resolve the API key for an agent: per-agent config → global stored key → env.
-async fn resolve_api_key(state: &Arc<AppState>, agent: &AgentContainer) -> Option<String> {
-    if let Some(k) = &agent.config.api_key {
-        if !k.is_empty() {
-            return Some(k.clone());
+        while let Some(nl_idx) = buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buf.drain(..=nl_idx).collect();
+            let line = String::from_utf8_lossy(&line);
+            let Some(payload) = StreamParser::payload(api_format, &line) else { continue };
+            let (delta, stop) = match parser.feed(payload) {
+                Ok(fed) => fed,
+                Err(e) if !parser.started => return Err(e),
+                Err(e) => {
+                    send_error(client_tx, &e).await;
+                    break 'outer;
+                }
+            };
+            if let Some(d) = delta {
+                if !send_delta(client_tx, &d).await {
+                    break 'outer;
+                }
+            }
+            if stop { break 'outer; }
         }
     }
-    let key_lookup = match agent.config.llm_provider {
-        LlmProvider::OpenAI => "openai",
-        LlmProvider::Anthropic => "anthropic",
-        LlmProvider::Kimi => "kimi",
-        LlmProvider::KimiCode => "kimi-code",
-        LlmProvider::Zai => "zai",
-        LlmProvider::Gemini => "google",
-        LlmProvider::Huggingface => "huggingface",
-        _ => return None,
-    };
-    let keys = state.api_keys.read().await;
-    keys.get(key_lookup).cloned()
+    Ok(parser.finish())
 }
 
-/// Send `chat`/`final` event with full text and persist the assistant turn.
-async fn finalize(
+async fn send_tool_event(
     client_tx: &mut futures::stream::SplitSink<WebSocket, Message>,
-    full_text: &str,
-    agent_id: &str,
-    agent_name: &str,
-    session_id: &str,
+    payload: serde_json::Value,
 ) {
     use futures::SinkExt;
-    let final_event = json!({
+    let event = json!({
         "type": "event",
-        "event": "chat",
-        "payload": {
-            "state": "final",
-            "message": {
-                "role": "assistant",
-                "content": [{ "type": "text", "text": full_text }]
-            }
-        }
+        "event": "tool",
+        "payload": payload
     });
-    let _ = client_tx.send(Message::Text(final_event.to_string())).await;
+    let _ = client_tx.send(Message::Text(event.to_string())).await;
+}
 
+/// Persist a tool invocation as a `role: "tool"` turn, so transcripts show
+/// what the agent looked up and did.
+async fn persist_tool_turn(agent_id: &str, agent_name: &str, session_id: &str, call: &ToolCall, output: &ToolOutput) {
+    let metadata = HashMap::from([
+        ("tool_call_id".to_string(), json!(call.id)),
+        ("name".to_string(), json!(call.name)),
+        ("arguments".to_string(), call.arguments.clone()),
+        ("is_error".to_string(), json!(output.is_error)),
+    ]);
     let msg = ConversationMessage {
         id: uuid::Uuid::new_v4().to_string(),
         session_id: session_id.to_string(),
-        role: "assistant".to_string(),
-        content: full_text.to_string(),
+        role: "tool".to_string(),
+        content: output.content.clone(),
         agent_id: agent_id.to_string(),
         timestamp: chrono::Utc::now().to_rfc3339(),
-        metadata: Default::default(),
+        metadata,
     };
     if let Err(e) = crate::api::append_conversation_message(agent_name, &msg).await {
-        tracing::warn!("Direct: failed to persist assistant message: {}", e);
+        tracing::warn!("Direct: failed to persist tool call: {}", e);
     }
 }
 
-async fn send_error(
-    client_tx: &mut futures::stream::SplitSink<WebSocket, Message>,
-    msg: &str,
-) {
-    use futures::SinkExt;
-    let err = json!({
-        "role": "system",
-        "error": "direct backend error",
-        "content": msg,
-        "timestamp": chrono::Utc::now().timestamp()
-    });
-    let _ = client_tx.send(Message::Text(err.to_string())).await;
+/// Longest summary asked for when condensing old turns
+const SUMMARY_MAX_TOKENS: u32 = 1024;
+
+/// Fit the session into `budget` tokens. With the `summarize` strategy,
+/// turns that don't fit are first summarized by the first available
+/// provider in the chain; if that fails they're just left out.
+#[allow(clippy::too_many_arguments)]
+async fn fit_context(
+    http: &reqwest::Client,
+    state: &AppState,
+    agent: &AgentContainer,
+    chain: &[Upstream],
+    session_id: &str,
+    user_id: &str,
+    history: &[ConversationMessage],
+    budget: usize,
+) -> Fitted {
+    let fitted = context_window::fit(history, budget);
+    if fitted.dropped.is_empty() {
+        return fitted;
+    }
+    let strategy = agent.config.context.as_ref().map(|c| c.strategy).unwrap_or_default();
+    if strategy != ContextStrategy::Summarize {
+        tracing::debug!("[direct] {}: {} turns outside the context window", agent.name, fitted.dropped.len());
+        return fitted;
+    }
+
+    // Summarize down to half the budget, so the next turns fit without
+    // summarizing again right away
+    let target = context_window::fit(history, budget / 2);
+    let Some(through) = target.dropped.last() else {
+        return fitted;
+    };
+    // A backlog too long to summarize in one request loses its oldest turns
+    let backlog = context_window::fit(&target.dropped, budget);
+    let prompt = context_window::summary_prompt(target.summary.as_deref(), &backlog.turns);
+
+    for upstream in chain {
+        if !state.providers.allow(&upstream.endpoint) {
+            continue;
+        }
+        let api_key = resolve_api_key(&state.api_keys, upstream).await;
+        if api_key.is_none() && !is_local(&upstream.provider) {
+            continue;
+        }
+        let summary = complete_on(
+            http,
+            upstream,
+            api_key.as_deref(),
+            Some(context_window::SUMMARY_INSTRUCTIONS),
+            &prompt,
+            None,
+            Some(SUMMARY_MAX_TOKENS),
+        )
+        .await;
+        match summary {
+            Ok((summary, usage)) if !summary.trim().is_empty() => {
+                if let Some(usage) = &usage {
+                    state.usage.record_served(agent, upstream.provider.as_str(), &upstream.model, Some(user_id), usage);
+                }
+                let summary = summary.trim();
+                let message = context_window::summary_message(session_id, &agent.id, summary, through);
+                if let Err(e) = crate::api::append_conversation_message(&agent.name, &message).await {
+                    tracing::warn!("Direct: failed to persist context summary: {}", e);
+                }
+                tracing::info!("[direct] {}: summarized {} older turns", agent.name, target.dropped.len());
+                return Fitted {
+                    summary: Some(summary.to_string()),
+                    turns: target.turns,
+                    dropped: Vec::new(),
+                };
+            }
+            Ok(_) => tracing::warn!("[direct] {}: {} returned an empty summary", agent.name, upstream.provider.as_str()),
+            Err(e) => tracing::warn!("[direct] {}: summarizing on {} failed: {}", agent.name, upstream.provider.as_str(), e),
+        }
+    }
+    fitted
+}
+
+/// Describe an upstream error response, keeping only the start of its body
+/// since it reaches clients and may be arbitrarily large
+fn provider_error(status: reqwest::StatusCode, body: &str) -> String {
+    format!("provider returned {}: {}", status, body.chars().take(500).collect::<String>())
 }
 
 /// Handle a chat WebSocket for a direct-runtime agent.
 ///
 /// Connection model: each user message starts a fresh streaming HTTP
 /// completion to the upstream provider. Conversation history is loaded from
-/// the JSONL persistence layer so the LLM has context across turns.
+/// the JSONL persistence layer so the LLM has context across turns. Budgets
+/// are checked and token usage is charged against `user_id`, the
+/// authenticated caller.
+///
+/// If the agent has `llm_fallbacks`, a provider that is rate limited,
+/// erroring, unreachable or slow to start answering is skipped for the next
+/// one, as long as nothing has been streamed yet. Providers that keep
+/// failing are circuit-broken for all agents (`AppState::providers`).
+///
+/// Tool calls are answered on whichever provider took the message.
 pub async fn handle_direct_chat(
     socket: WebSocket,
     state: Arc<AppState>,
     agent_id: String,
     agent_name: String,
     session_id: String,
+    user_id: String,
 ) {
     use futures::SinkExt;
     use futures::stream::StreamExt as _;
@@ -239,34 +1766,38 @@ pub async fn handle_direct_chat(
         }
     };
 
-    let api_key = match resolve_api_key(&state, &agent).await {
-        Some(k) => k,
-        None => {
-            send_error(&mut client_tx, "no API key configured for this provider").await;
+    let chain = match upstream_chain(&agent, &state.config.model_servers) {
+        Ok(chain) => chain,
+        Err(e) => {
+            send_error(&mut client_tx, e).await;
             return;
         }
     };
-
-    let (default_url, default_format) = default_endpoint(&agent.config.llm_provider);
-    let base_url = agent.config.env_vars.get("LLM_BASE_URL")
-        .cloned()
-        .unwrap_or_else(|| default_url.to_string());
-    if base_url.is_empty() {
-        send_error(&mut client_tx, "no LLM_BASE_URL configured for this provider").await;
+    let mut usable = false;
+    for upstream in &chain {
+        usable |= is_local(&upstream.provider) || resolve_api_key(&state.api_keys, upstream).await.is_some();
+    }
+    if !usable {
+        send_error(&mut client_tx, "no API key configured for this provider").await;
         return;
     }
-    let api_format = match agent.config.env_vars.get("LLM_API_FORMAT").map(|s| s.to_lowercase()) {
-        Some(s) if s == "openai" => ApiFormat::OpenAi,
-        Some(s) if s == "anthropic" || s == "anthropic-messages" => ApiFormat::AnthropicMessages,
-        _ => default_format,
-    };
-
-    let configured_model = agent.config.llm_model
-        .clone()
-        .unwrap_or_else(|| "default".to_string());
-    let model = wire_model_id(&agent.config.llm_provider, &configured_model);
 
     let system_prompt = load_system_prompt(&agent_name).await;
+    let tool_specs: Vec<ToolSpec> = agent.config.tools.iter().map(AgentTool::spec).collect();
+
+    // Size history for the smallest window in the chain, so failing over
+    // never overflows
+    let window = chain
+        .iter()
+        .map(|u| context_window::window_tokens(&state.config.context_windows, &agent, u.provider.as_str(), &u.model))
+        .min()
+        .unwrap_or_default();
+    let fixed_tokens = system_prompt.as_deref().map_or(0, context_window::estimate_tokens)
+        + tool_specs
+            .iter()
+            .map(|t| context_window::estimate_tokens(t.description) + context_window::estimate_tokens(&t.parameters.to_string()))
+            .sum::<usize>();
+    let history_budget = context_window::history_budget(window, fixed_tokens);
 
     // Send the initial connection ack the Tauri client expects.
     let ack = json!({
@@ -280,10 +1811,6 @@ pub async fn handle_direct_chat(
         return;
     }
 
-    let endpoint = match api_format {
-        ApiFormat::OpenAi => format!("{}/chat/completions", base_url.trim_end_matches('/')),
-        ApiFormat::AnthropicMessages => format!("{}/v1/messages", base_url.trim_end_matches('/')),
-    };
     let http = reqwest::Client::new();
 
     while let Some(msg_result) = client_rx.next().await {
@@ -308,6 +1835,13 @@ pub async fn handle_direct_chat(
             continue;
         }
 
+        // Refused messages aren't persisted or sent upstream.
+        if let Err(exhausted) = state.budgets.admit(&agent_id, Some(&user_id)) {
+            tracing::info!("[direct] {} refused for {}: {}", agent_name, user_id, exhausted);
+            let _ = client_tx.send(Message::Text(exhausted.event().to_string())).await;
+            continue;
+        }
+
         // Persist user turn.
         let user_msg = ConversationMessage {
             id: uuid::Uuid::new_v4().to_string(),
@@ -321,10 +1855,15 @@ pub async fn handle_direct_chat(
         if let Err(e) = crate::api::append_conversation_message(&agent_name, &user_msg).await {
             tracing::warn!("Direct: failed to persist user message: {}", e);
         }
+        state.metrics.record_chat_message(&agent_name, "user");
 
-        // Build messages: system + prior session turns + this user turn.
+        // Build messages: system + prior session turns + this user turn,
+        // cut to the context window.
         let history = crate::api::load_conversation_messages(&agent_name, &session_id)
             .unwrap_or_default();
+        let fitted = fit_context(&http, &state, &agent, &chain, &session_id, &user_id, &history, history_budget).await;
+        let request_system = fitted.system_prompt(system_prompt.as_deref());
+        let history = fitted.turns;
 
         // Send streaming start event.
         let start_event = json!({
@@ -338,185 +1877,169 @@ pub async fn handle_direct_chat(
         let _ = client_tx.send(Message::Text(start_event.to_string())).await;
 
         let total_chars: usize = history.iter().map(|h| h.content.len()).sum::<usize>()
-            + system_prompt.as_deref().map(|s| s.len()).unwrap_or(0)
-            + user_content.len();
-        tracing::info!(
-            "[direct] POST {} model={} format={:?} chars={}",
-            endpoint, model, api_format, total_chars
-        );
+            + request_system.as_deref().map(|s| s.len()).unwrap_or(0);
 
-        // Build and send request based on format.
-        let resp = match api_format {
-            ApiFormat::OpenAi => {
-                let mut messages = Vec::new();
-                if let Some(sp) = system_prompt.as_deref() {
-                    messages.push(OpenAiMessage { role: "system", content: sp });
-                }
-                for h in &history {
-                    messages.push(OpenAiMessage {
-                        role: h.role.as_str(),
-                        content: h.content.as_str(),
-                    });
+        // Walk the failover chain until a provider starts answering.
+        let mut served = None;
+        let mut failures: Vec<String> = Vec::new();
+        for (i, upstream) in chain.iter().enumerate() {
+            let provider = upstream.provider.as_str();
+            let has_next = i + 1 < chain.len();
+            // The last provider is always tried; failing fast helps no one
+            if has_next && !state.providers.allow(&upstream.endpoint) {
+                failures.push(format!("{}: temporarily disabled after repeated errors", provider));
+                continue;
+            }
+            let api_key = resolve_api_key(&state.api_keys, upstream).await;
+            if api_key.is_none() && !is_local(&upstream.provider) {
+                failures.push(format!("{}: no API key configured", provider));
+                continue;
+            }
+            // Only give up on a slow provider when there's another to try
+            let patience = has_next.then_some(FIRST_TOKEN_TIMEOUT);
+
+            tracing::info!(
+                "[direct] POST {} model={} format={:?} chars={}",
+                upstream.endpoint, upstream.model, upstream.format, total_chars
+            );
+            let request = send_chat(
+                &http,
+                upstream,
+                api_key.as_deref(),
+                request_system.as_deref(),
+                &history,
+                &user_content,
+                &tool_specs,
+                &[],
+            );
+            let resp = match patience {
+                Some(t) => match tokio::time::timeout(t, request).await {
+                    Ok(r) => r.map_err(|e| format!("HTTP error: {}", e)),
+                    Err(_) => Err("timed out waiting for a response".to_string()),
+                },
+                None => request.await.map_err(|e| format!("HTTP error: {}", e)),
+            };
+            let resp = match resp {
+                Ok(r) => r,
+                Err(e) => {
+                    tracing::warn!("[direct] {} failed: {}", provider, e);
+                    state.providers.record_failure(&upstream.endpoint);
+                    failures.push(format!("{}: {}", provider, e));
+                    continue;
                 }
-                if history.is_empty() {
-                    messages.push(OpenAiMessage { role: "user", content: &user_content });
+            };
+
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                let error = provider_error(status, &body);
+                tracing::warn!("[direct] {}", error);
+                failures.push(error);
+                if should_fail_over(status) {
+                    state.providers.record_failure(&upstream.endpoint);
+                    continue;
                 }
-                let body = OpenAiRequest { model: &model, messages, stream: true };
-                http.post(&endpoint)
-                    .bearer_auth(&api_key)
-                    .json(&body)
-                    .send()
-                    .await
-            }
-            ApiFormat::AnthropicMessages => {
-                // Anthropic format: system goes outside `messages`, only user/assistant
-                // turns inside. No `system` role inside messages array.
-                let mut messages = Vec::new();
-                for h in &history {
-                    if h.role == "system" {
-                        continue;
+                break;
+            }
+            tracing::info!("[direct] provider OK, streaming response...");
+
+            match relay_stream(resp, upstream.format, &mut client_tx, patience).await {
+                Ok(reply) => {
+                    state.providers.record_success(&upstream.endpoint);
+                    if i > 0 {
+                        tracing::info!("[direct] {} answered by fallback {}", agent_name, provider);
                     }
-                    messages.push(AnthropicMessage {
-                        role: h.role.as_str(),
-                        content: h.content.as_str(),
-                    });
+                    served = Some((upstream, api_key, reply));
+                    break;
                 }
-                if messages.is_empty() {
-                    messages.push(AnthropicMessage { role: "user", content: &user_content });
+                Err(e) => {
+                    tracing::warn!("[direct] {} failed before the first token: {}", provider, e);
+                    state.providers.record_failure(&upstream.endpoint);
+                    failures.push(format!("{}: {}", provider, e));
                 }
-                let body = AnthropicRequest {
-                    model: &model,
-                    max_tokens: 4096,
-                    messages,
-                    system: system_prompt.as_deref(),
-                    stream: true,
-                };
-                http.post(&endpoint)
-                    .bearer_auth(&api_key)
-                    .header("anthropic-version", "2023-06-01")
-                    .json(&body)
-                    .send()
-                    .await
-            }
-        };
-
-        let resp = match resp {
-            Ok(r) => r,
-            Err(e) => {
-                send_error(&mut client_tx, &format!("HTTP error: {}", e)).await;
-                continue;
             }
-        };
+        }
 
-        if !resp.status().is_success() {
-            let status = resp.status();
-            let body = resp.text().await.unwrap_or_default();
-            tracing::warn!("[direct] provider error {}: {}", status, &body[..body.len().min(500)]);
-            send_error(
-                &mut client_tx,
-                &format!("provider returned {}: {}", status, body),
-            ).await;
+        let Some((upstream, api_key, mut reply)) = served else {
+            send_error(&mut client_tx, &failures.join("; ")).await;
             continue;
-        }
-        tracing::info!("[direct] provider OK, streaming response...");
+        };
 
-        // SSE stream: parse lines, dispatch payloads by api_format.
-        let mut stream = resp.bytes_stream();
-        let mut buf = String::new();
+        // Run the tools the model called and send back the results until it
+        // answers without calling any.
         let mut full_text = String::new();
+        let mut usage: Option<TokenUsage> = None;
+        let mut rounds: Vec<ToolRound> = Vec::new();
+        loop {
+            let Reply { text, usage: round_usage, tool_calls } = reply;
+            if !text.is_empty() && !full_text.is_empty() {
+                full_text.push_str("\n\n");
+            }
+            full_text.push_str(&text);
+            if let Some(u) = round_usage {
+                let total = usage.get_or_insert_with(TokenUsage::default);
+                total.input_tokens += u.input_tokens;
+                total.output_tokens += u.output_tokens;
+            }
+            if tool_calls.is_empty() {
+                break;
+            }
+            if rounds.len() == MAX_TOOL_ROUNDS {
+                send_error(&mut client_tx, &format!("stopped after {} rounds of tool calls", MAX_TOOL_ROUNDS)).await;
+                break;
+            }
 
-        'outer: while let Some(chunk) = stream.next().await {
-            let chunk = match chunk {
-                Ok(b) => b,
+            let mut outputs = Vec::with_capacity(tool_calls.len());
+            for call in &tool_calls {
+                send_tool_event(&mut client_tx, json!({
+                    "state": "call",
+                    "id": call.id,
+                    "name": call.name,
+                    "arguments": call.arguments
+                })).await;
+                let output = crate::tools::execute(&state, &agent, call).await;
+                send_tool_event(&mut client_tx, json!({
+                    "state": "result",
+                    "id": call.id,
+                    "name": call.name,
+                    "content": output.content,
+                    "is_error": output.is_error
+                })).await;
+                persist_tool_turn(&agent_id, &agent_name, &session_id, call, &output).await;
+                outputs.push(output);
+            }
+            rounds.push(ToolRound { text, calls: tool_calls, outputs });
+
+            let request = send_chat(
+                &http,
+                upstream,
+                api_key.as_deref(),
+                request_system.as_deref(),
+                &history,
+                &user_content,
+                &tool_specs,
+                &rounds,
+            );
+            let resp = match request.await {
+                Ok(r) if r.status().is_success() => r,
+                Ok(r) => {
+                    let status = r.status();
+                    let body = r.text().await.unwrap_or_default();
+                    send_error(&mut client_tx, &format!("provider returned {}: {}", status, body)).await;
+                    break;
+                }
                 Err(e) => {
-                    send_error(&mut client_tx, &format!("stream error: {}", e)).await;
+                    send_error(&mut client_tx, &format!("HTTP error: {}", e)).await;
                     break;
                 }
             };
-            buf.push_str(&String::from_utf8_lossy(&chunk));
-
-            while let Some(nl_idx) = buf.find('\n') {
-                let line = buf[..nl_idx].trim_end_matches('\r').to_string();
-                buf.drain(..=nl_idx);
-                let payload = match line.strip_prefix("data: ").or_else(|| line.strip_prefix("data:")) {
-                    Some(p) => p.trim(),
-                    None => continue,
-                };
-                if payload.is_empty() || payload == "[DONE]" {
-                    if payload == "[DONE]" { break 'outer; }
-                    continue;
-                }
-
-                let delta_text: Option<String>;
-                let stop: bool;
-
-                match api_format {
-                    ApiFormat::OpenAi => {
-                        let parsed: OpenAiChunk = match serde_json::from_str(payload) {
-                            Ok(c) => c,
-                            Err(_) => continue,
-                        };
-                        let mut text = String::new();
-                        let mut finished = false;
-                        for choice in parsed.choices {
-                            if let Some(d) = choice.delta.content {
-                                text.push_str(&d);
-                            }
-                            if choice.finish_reason.is_some() {
-                                finished = true;
-                            }
-                        }
-                        delta_text = if text.is_empty() { None } else { Some(text) };
-                        stop = finished;
-                    }
-                    ApiFormat::AnthropicMessages => {
-                        let parsed: AnthropicSseEvent = match serde_json::from_str(payload) {
-                            Ok(c) => c,
-                            Err(_) => continue,
-                        };
-                        match parsed.event_type.as_deref() {
-                            Some("content_block_delta") => {
-                                let t = parsed.delta.as_ref()
-                                    .and_then(|d| d.text.clone());
-                                delta_text = t;
-                                stop = false;
-                            }
-                            Some("message_stop") => {
-                                delta_text = None;
-                                stop = true;
-                            }
-                            Some("message_delta") => {
-                                let s = parsed.delta.as_ref()
-                                    .and_then(|d| d.stop_reason.clone());
-                                delta_text = None;
-                                stop = s.is_some();
-                            }
-                            _ => continue,
-                        }
-                    }
-                }
-
-                if let Some(d) = delta_text {
-                    if !d.is_empty() {
-                        full_text.push_str(&d);
-                        let delta_event = json!({
-                            "type": "event",
-                            "event": "chat",
-                            "payload": {
-                                "state": "delta",
-                                "message": {
-                                    "role": "assistant",
-                                    "content": d
-                                }
-                            }
-                        });
-                        if client_tx.send(Message::Text(delta_event.to_string())).await.is_err() {
-                            break 'outer;
-                        }
-                    }
+            reply = match relay_stream(resp, upstream.format, &mut client_tx, None).await {
+                Ok(next) => next,
+                Err(e) => {
+                    send_error(&mut client_tx, &e).await;
+                    break;
                 }
-                if stop { break 'outer; }
-            }
+            };
         }
 
         // Send lifecycle end + final message + persist.
@@ -530,6 +2053,231 @@ pub async fn handle_direct_chat(
         });
         let _ = client_tx.send(Message::Text(end_event.to_string())).await;
 
-        finalize(&mut client_tx, &full_text, &agent_id, &agent_name, &session_id).await;
+        if let Some(usage) = &usage {
+            state.usage.record_served(&agent, upstream.provider.as_str(), &upstream.model, Some(&user_id), usage);
+        }
+
+        finalize(&mut client_tx, &full_text, &agent_id, &agent_name, &session_id, upstream).await;
+        state.metrics.record_chat_message(&agent_name, "assistant");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AgentConfig, AgentStatus};
+
+    fn fallback(provider: LlmProvider) -> LlmFallback {
+        LlmFallback {
+            provider,
+            model: None,
+            base_url: None,
+            api_format: None,
+            api_key: None,
+        }
+    }
+
+    #[test]
+    fn test_chain_resolves_fallbacks() {
+        let servers = ModelServers {
+            ollama: Some(ModelServerConfig {
+                endpoint: "http://localhost:11434/".to_string(),
+                default_model: Some("llama3".to_string()),
+                api_token: None,
+            }),
+            ..Default::default()
+        };
+        let agent = AgentContainer {
+            id: "tutor-id".to_string(),
+            name: "tutor".to_string(),
+            status: AgentStatus::Running,
+            config: AgentConfig {
+                llm_provider: LlmProvider::Anthropic,
+                llm_model: Some("claude-sonnet-4".to_string()),
+                llm_fallbacks: vec![
+                    LlmFallback {
+                        model: Some("gpt-4o".to_string()),
+                        ..fallback(LlmProvider::OpenAI)
+                    },
+                    fallback(LlmProvider::Ollama),
+                    // Skipped: no base URL
+                    fallback(LlmProvider::Custom { endpoint: String::new() }),
+                ],
+                ..Default::default()
+            },
+            runtime: Some("direct".to_string()),
+            ..Default::default()
+        };
+
+        let chain = upstream_chain(&agent, &servers).unwrap();
+        let endpoints: Vec<_> = chain.iter().map(|u| (u.endpoint.as_str(), u.model.as_str())).collect();
+        assert_eq!(
+            endpoints,
+            [
+                ("https://api.anthropic.com/v1/messages", "claude-sonnet-4"),
+                ("https://api.openai.com/v1/chat/completions", "gpt-4o"),
+                ("http://localhost:11434/v1/chat/completions", "llama3"),
+            ]
+        );
+        assert_eq!(chain[0].format, ApiFormat::AnthropicMessages);
+        assert!(is_local(&chain[2].provider));
+    }
+
+    #[test]
+    fn test_tool_calls_assemble_from_fragments() {
+        // OpenAI streams the arguments JSON in pieces under the call's index
+        let chunks = [
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_a","type":"function","function":{"name":"memory_search","arguments":""}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"query\":"}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":1,"function":{"name":"http_fetch","arguments":"not json"}}]}}]}"#,
+            r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":" \"photosynthesis\"}"}}]}}]}"#,
+        ];
+        let mut parser = StreamParser::new(ApiFormat::OpenAi);
+        for chunk in chunks {
+            assert_eq!(parser.feed(chunk), Ok((None, false)));
+        }
+        assert!(parser.started);
+        let calls = parser.finish().tool_calls;
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].id, "call_a");
+        assert_eq!(calls[0].arguments, json!({ "query": "photosynthesis" }));
+        assert_eq!(calls[1].id, "call_1");
+        assert_eq!(calls[1].arguments, json!({}));
+
+        // Anthropic replays a round as tool_use blocks answered by tool_result
+        let round = AnthropicMessage {
+            role: "user",
+            content: AnthropicContent::Blocks(vec![AnthropicBlock::ToolResult {
+                tool_use_id: &calls[0].id,
+                content: "[]",
+                is_error: false,
+            }]),
+        };
+        assert_eq!(
+            serde_json::to_value(&round).unwrap(),
+            json!({
+                "role": "user",
+                "content": [{ "type": "tool_result", "tool_use_id": "call_a", "content": "[]", "is_error": false }]
+            })
+        );
+        let text = AnthropicMessage { role: "user", content: AnthropicContent::Text("hi") };
+        assert_eq!(serde_json::to_value(&text).unwrap()["content"], "hi");
+    }
+
+    /// Feed a recorded stream through the parser line by line, as
+    /// `relay_stream` does
+    fn parse_fixture(format: ApiFormat, fixture: &str) -> (Vec<String>, Reply) {
+        let mut parser = StreamParser::new(format);
+        let mut deltas = Vec::new();
+        for line in fixture.lines() {
+            let Some(payload) = StreamParser::payload(format, line) else { continue };
+            let (delta, stop) = parser.feed(payload).unwrap();
+            deltas.extend(delta);
+            if stop {
+                break;
+            }
+        }
+        (deltas, parser.finish())
+    }
+
+    #[test]
+    fn test_native_stream_fixtures() {
+        let (deltas, reply) =
+            parse_fixture(ApiFormat::OllamaChat, include_str!("../tests/fixtures/direct_llm/ollama_chat.ndjson"));
+        assert_eq!(deltas.len(), 3);
+        assert_eq!(reply.text, "La photosynthèse transforme la lumière en énergie.");
+        assert_eq!(reply.usage, Some(TokenUsage { input_tokens: 31, output_tokens: 14 }));
+        assert!(reply.tool_calls.is_empty());
+
+        let (_, reply) =
+            parse_fixture(ApiFormat::OllamaChat, include_str!("../tests/fixtures/direct_llm/ollama_tool_call.ndjson"));
+        assert_eq!(reply.text, "");
+        assert_eq!(reply.tool_calls.len(), 1);
+        assert_eq!(reply.tool_calls[0].name, "memory_search");
+        assert_eq!(reply.tool_calls[0].arguments, json!({ "query": "photosynthesis notes", "limit": 3 }));
+        assert_eq!(reply.usage, Some(TokenUsage { input_tokens: 212, output_tokens: 27 }));
+
+        let (deltas, reply) =
+            parse_fixture(ApiFormat::GeminiGenerate, include_str!("../tests/fixtures/direct_llm/gemini_stream.sse"));
+        assert_eq!(deltas, ["Photosynthesis turns", " light into chemical energy."]);
+        assert_eq!(reply.usage, Some(TokenUsage { input_tokens: 9, output_tokens: 8 }));
+
+        let (_, reply) = parse_fixture(
+            ApiFormat::GeminiGenerate,
+            include_str!("../tests/fixtures/direct_llm/gemini_function_call.sse"),
+        );
+        assert_eq!(reply.tool_calls[0].id, "call_0");
+        assert_eq!(reply.tool_calls[0].arguments["url"], "https://en.wikipedia.org/wiki/Photosynthesis");
+
+        // In-stream errors are surfaced, not skipped
+        let mut parser = StreamParser::new(ApiFormat::OllamaChat);
+        assert!(parser.feed(r#"{"error":"model 'llama9' not found"}"#).is_err());
+        let mut parser = StreamParser::new(ApiFormat::GeminiGenerate);
+        assert_eq!(
+            parser.feed(r#"{"promptFeedback":{"blockReason":"SAFETY"}}"#),
+            Err("prompt blocked: SAFETY".to_string())
+        );
+    }
+
+    #[test]
+    fn test_native_formats_are_selected_by_env() {
+        let mut agent = AgentContainer {
+            id: "tutor-id".to_string(),
+            name: "tutor".to_string(),
+            status: AgentStatus::Running,
+            config: AgentConfig {
+                llm_provider: LlmProvider::Ollama,
+                llm_model: Some("llama3.1:8b".to_string()),
+                env_vars: HashMap::from([
+                    ("LLM_API_FORMAT".to_string(), "ollama".to_string()),
+                    ("LLM_KEEP_ALIVE".to_string(), "-1".to_string()),
+                    ("LLM_OPTIONS".to_string(), r#"{"num_ctx": 8192, "temperature": 0.2}"#.to_string()),
+                ]),
+                ..Default::default()
+            },
+            runtime: Some("direct".to_string()),
+            ..Default::default()
+        };
+        let upstream = resolve_upstream(&agent).unwrap();
+        assert_eq!(upstream.format, ApiFormat::OllamaChat);
+        assert_eq!(upstream.endpoint, "http://host.docker.internal:11434/api/chat");
+        assert_eq!(upstream.native.keep_alive, Some(json!(-1)));
+        let options = upstream.native.ollama_options(Some(0.7), Some(256));
+        assert_eq!(options["num_ctx"], 8192);
+        assert_eq!(options["num_predict"], 256);
+        assert_eq!(options["temperature"].as_f64().map(|t| (t * 10.0).round()), Some(7.0));
+
+        agent.config.llm_provider = LlmProvider::Gemini;
+        agent.config.llm_model = Some("gemini-2.0-flash".to_string());
+        agent.config.env_vars = HashMap::from([
+            ("LLM_API_FORMAT".to_string(), "gemini".to_string()),
+            (
+                "LLM_SAFETY_SETTINGS".to_string(),
+                r#"[{"category": "HARM_CATEGORY_HARASSMENT", "threshold": "BLOCK_LOW_AND_ABOVE"}]"#.to_string(),
+            ),
+        ]);
+        let upstream = resolve_upstream(&agent).unwrap();
+        assert_eq!(
+            upstream.endpoint,
+            "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.0-flash"
+        );
+        let body = GeminiRequest {
+            contents: vec![GeminiContent { role: Some("user"), parts: vec![GeminiPart::Text("hi")] }],
+            system_instruction: None,
+            generation_config: None,
+            safety_settings: upstream.native.safety_settings.as_ref(),
+            tools: Vec::new(),
+        };
+        let body = serde_json::to_value(&body).unwrap();
+        assert_eq!(body["contents"][0]["parts"][0], json!({ "text": "hi" }));
+        assert_eq!(body["safetySettings"][0]["threshold"], "BLOCK_LOW_AND_ABOVE");
+    }
+
+    #[test]
+    fn test_provider_error_truncates_body() {
+        let body = format!("{{\"error\": \"{}\"}}", "é".repeat(10_000));
+        let error = provider_error(reqwest::StatusCode::BAD_GATEWAY, &body);
+        assert!(error.starts_with("provider returned 502 Bad Gateway: {\"error\": \"é"));
+        assert_eq!(error.chars().count(), "provider returned 502 Bad Gateway: ".len() + 500);
     }
 }
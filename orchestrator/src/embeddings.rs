@@ -0,0 +1,491 @@
+//! Text embeddings
+//!
+//! `EmbeddingGenerator` turns text into vectors with one of three backends:
+//! Ollama's `nomic-embed-text`, OpenAI's `text-embedding-3-small`, or a local
+//! TF-IDF vectorizer that needs no model at all. The code index and shared
+//! memory both embed through it. Vectors from different models (or TF-IDF
+//! fits) aren't comparable, so callers record `model_name` alongside them.
+
+// Parts of the API are only used by code_index, which isn't built yet
+#![allow(dead_code)]
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use tracing::debug;
+
+use crate::config::MemoryEmbeddingsConfig;
+use crate::shared_memory::SharedMemory;
+
+/// Ollama model used by `EmbeddingModel::LocalOllama`
+const OLLAMA_MODEL: &str = "nomic-embed-text";
+/// OpenAI model used by `EmbeddingModel::OpenAI`
+const OPENAI_MODEL: &str = "text-embedding-3-small";
+/// TF-IDF model names are this plus the vocabulary fingerprint
+const TFIDF_MODEL_PREFIX: &str = "tfidf-";
+
+// ============================================================================
+// Embedding Model Types
+// ============================================================================
+
+/// Supported embedding models
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbeddingModel {
+    /// Local Ollama with nomic-embed-text
+    LocalOllama,
+
+    /// OpenAI text-embedding-3-small
+    OpenAI,
+
+    /// TF-IDF fallback (no API needed)
+    #[default]
+    TfIdf,
+}
+
+// ============================================================================
+// TF-IDF Implementation
+// ============================================================================
+
+/// TF-IDF vectorizer for fallback embeddings
+#[derive(Debug, Clone, Default)]
+pub struct TfIdfVectorizer {
+    /// Document frequency for each term
+    document_freq: HashMap<String, usize>,
+
+    /// Total number of documents
+    num_docs: usize,
+
+    /// Vocabulary size (for dimension)
+    vocab_size: usize,
+
+    /// Term to index mapping
+    term_to_idx: HashMap<String, usize>,
+}
+
+impl TfIdfVectorizer {
+    /// Create a new TF-IDF vectorizer
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fit the vectorizer on a corpus
+    pub fn fit(&mut self, documents: &[&str]) {
+        self.num_docs = documents.len();
+        self.term_to_idx.clear();
+        self.document_freq.clear();
+
+        // Build vocabulary and document frequencies
+        for doc in documents {
+            let terms = self.tokenize(doc);
+            let unique_terms: HashSet<_> = terms.into_iter().collect();
+
+            for term in unique_terms {
+                *self.document_freq.entry(term.clone()).or_insert(0) += 1;
+                if !self.term_to_idx.contains_key(&term) {
+                    self.term_to_idx.insert(term, self.vocab_size);
+                    self.vocab_size += 1;
+                }
+            }
+        }
+
+        debug!("TF-IDF vocabulary size: {}", self.vocab_size);
+    }
+
+    /// Transform a document to TF-IDF vector
+    pub fn transform(&self, document: &str) -> Vec<f32> {
+        if self.vocab_size == 0 || self.num_docs == 0 {
+            return vec![];
+        }
+
+        let mut vector = vec![0.0f32; self.vocab_size];
+        let terms = self.tokenize(document);
+        let term_freq = self.compute_term_freq(&terms);
+
+        for (term, tf) in term_freq {
+            if let Some(&idx) = self.term_to_idx.get(&term) {
+                let df = self.document_freq.get(&term).copied().unwrap_or(1);
+                let idf = ((self.num_docs as f32 + 1.0) / (df as f32 + 1.0)).ln() + 1.0;
+                vector[idx] = tf * idf;
+            }
+        }
+
+        // L2 normalize
+        let norm: f32 = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            for val in &mut vector {
+                *val /= norm;
+            }
+        }
+
+        vector
+    }
+
+    /// Fit and transform in one step
+    pub fn fit_transform(&mut self, documents: &[&str]) -> Vec<Vec<f32>> {
+        self.fit(documents);
+        documents.iter().map(|doc| self.transform(doc)).collect()
+    }
+
+    /// Identifies the fitted vocabulary and its document frequencies.
+    /// Vectors are only comparable if made under the same fit.
+    pub fn fingerprint(&self) -> String {
+        let mut terms: Vec<(usize, &str)> =
+            self.term_to_idx.iter().map(|(term, idx)| (*idx, term.as_str())).collect();
+        terms.sort_unstable();
+
+        let mut hasher = Sha256::new();
+        hasher.update((self.num_docs as u64).to_le_bytes());
+        for (_, term) in terms {
+            let df = self.document_freq.get(term).copied().unwrap_or(0);
+            hasher.update(term.as_bytes());
+            hasher.update([0]);
+            hasher.update((df as u64).to_le_bytes());
+        }
+        hex::encode(&hasher.finalize()[..6])
+    }
+
+    /// Compute cosine similarity between two vectors
+    pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+        if a.len() != b.len() || a.is_empty() {
+            return 0.0;
+        }
+
+        let dot: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
+        let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+        if norm_a > 0.0 && norm_b > 0.0 {
+            dot / (norm_a * norm_b)
+        } else {
+            0.0
+        }
+    }
+
+    /// Tokenize text into terms
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        text.to_lowercase()
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .filter(|s| s.len() > 1)
+            .map(|s| s.to_string())
+            .collect()
+    }
+
+    /// Compute term frequencies
+    fn compute_term_freq(&self, terms: &[String]) -> HashMap<String, f32> {
+        let mut freq = HashMap::new();
+        let total = terms.len() as f32;
+
+        for term in terms {
+            *freq.entry(term.clone()).or_insert(0.0) += 1.0;
+        }
+
+        if total > 0.0 {
+            for count in freq.values_mut() {
+                *count /= total;
+            }
+        }
+
+        freq
+    }
+}
+
+// ============================================================================
+// Embedding Generator
+// ============================================================================
+
+/// Embedding generator that supports multiple backends
+pub struct EmbeddingGenerator {
+    model: EmbeddingModel,
+    tfidf: Arc<RwLock<TfIdfVectorizer>>,
+    ollama_url: Option<String>,
+    openai_key: Option<String>,
+}
+
+impl EmbeddingGenerator {
+    /// Create a new embedding generator
+    pub fn new(model: EmbeddingModel) -> Self {
+        Self {
+            model,
+            tfidf: Arc::new(RwLock::new(TfIdfVectorizer::new())),
+            ollama_url: None,
+            openai_key: None,
+        }
+    }
+
+    /// Set Ollama URL
+    pub fn with_ollama_url(mut self, url: String) -> Self {
+        self.ollama_url = Some(url);
+        self
+    }
+
+    /// Set OpenAI API key
+    pub fn with_openai_key(mut self, key: String) -> Self {
+        self.openai_key = Some(key);
+        self
+    }
+
+    /// Fit the TF-IDF model on a corpus (only needed for TfIdf backend)
+    pub fn fit_tfidf(&self, documents: &[&str]) {
+        if matches!(self.model, EmbeddingModel::TfIdf) {
+            let mut tfidf = self.tfidf.write().unwrap();
+            tfidf.fit(documents);
+        }
+    }
+
+    /// Generate embedding for text
+    pub async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        match &self.model {
+            EmbeddingModel::TfIdf => {
+                let vector = self.tfidf.read().unwrap().transform(text);
+                if vector.is_empty() {
+                    anyhow::bail!("TF-IDF vocabulary is empty, fit it on a corpus first");
+                }
+                Ok(vector)
+            }
+            EmbeddingModel::LocalOllama => {
+                self.embed_ollama(text).await
+            }
+            EmbeddingModel::OpenAI => {
+                self.embed_openai(text).await
+            }
+        }
+    }
+
+    /// Generate embeddings for multiple texts
+    pub async fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        // For now, process sequentially. Could be parallelized later.
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for text in texts {
+            embeddings.push(self.embed(text).await?);
+        }
+        Ok(embeddings)
+    }
+
+    /// Embed using Ollama
+    async fn embed_ollama(&self, text: &str) -> Result<Vec<f32>> {
+        let url = self.ollama_url.as_deref()
+            .unwrap_or("http://localhost:11434");
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(format!("{}/api/embeddings", url))
+            .json(&serde_json::json!({
+                "model": OLLAMA_MODEL,
+                "prompt": text
+            }))
+            .send()
+            .await
+            .context("Failed to call Ollama API")?;
+
+        if !response.status().is_success() {
+            anyhow::bail!("Ollama API error: {}", response.status());
+        }
+
+        let json: serde_json::Value = response.json().await?;
+        let embedding = json["embedding"]
+            .as_array()
+            .context("Invalid Ollama response")?
+            .iter()
+            .map(|v| v.as_f64().unwrap_or(0.0) as f32)
+            .collect();
+
+        Ok(embedding)
+    }
+
+    /// Embed using OpenAI
+    async fn embed_openai(&self, text: &str) -> Result<Vec<f32>> {
+        let api_key = self.openai_key.as_ref()
+            .context("OpenAI API key not set")?;
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post("https://api.openai.com/v1/embeddings")
+            .header("Authorization", format!("Bearer {}", api_key))
+            .json(&serde_json::json!({
+                "model": OPENAI_MODEL,
+                "input": text
+            }))
+            .send()
+            .await
+            .context("Failed to call OpenAI API")?;
+
+        if !response.status().is_success() {
+            anyhow::bail!("OpenAI API error: {}", response.status());
+        }
+
+        let json: serde_json::Value = response.json().await?;
+        let embedding = json["data"][0]["embedding"]
+            .as_array()
+            .context("Invalid OpenAI response")?
+            .iter()
+            .map(|v| v.as_f64().unwrap_or(0.0) as f32)
+            .collect();
+
+        Ok(embedding)
+    }
+
+    /// The backend in use
+    pub fn model(&self) -> &EmbeddingModel {
+        &self.model
+    }
+
+    /// Name recorded with each vector this generator makes. TF-IDF names
+    /// carry the vocabulary fingerprint, since a refit changes what every
+    /// dimension means.
+    pub fn model_name(&self) -> String {
+        match &self.model {
+            EmbeddingModel::TfIdf => format!("{}{}", TFIDF_MODEL_PREFIX, self.tfidf.read().unwrap().fingerprint()),
+            EmbeddingModel::LocalOllama => OLLAMA_MODEL.to_string(),
+            EmbeddingModel::OpenAI => OPENAI_MODEL.to_string(),
+        }
+    }
+
+    /// Get the embedding dimension
+    pub fn dimension(&self) -> usize {
+        match &self.model {
+            EmbeddingModel::TfIdf => {
+                self.tfidf.read().unwrap().vocab_size
+            }
+            EmbeddingModel::LocalOllama => 768,  // nomic-embed-text
+            EmbeddingModel::OpenAI => 1536,      // text-embedding-3-small
+        }
+    }
+}
+
+// ============================================================================
+// Shared Memory
+// ============================================================================
+
+/// Set up the embedder for shared memories from `[memory-embeddings]`.
+///
+/// TF-IDF is fitted on the memories stored so far. Memories without an
+/// embedding, or embedded under an earlier TF-IDF fit, are then embedded in
+/// the background.
+pub fn start_memory_embedder(
+    config: &MemoryEmbeddingsConfig,
+    ollama_endpoint: Option<&str>,
+    openai_key: Option<String>,
+    memory: Arc<SharedMemory>,
+) -> Arc<EmbeddingGenerator> {
+    let mut embedder = EmbeddingGenerator::new(config.model.clone());
+    if let Some(url) = config.ollama_url.as_deref().or(ollama_endpoint) {
+        embedder = embedder.with_ollama_url(url.trim_end_matches('/').to_string());
+    }
+    if let Some(key) = openai_key {
+        embedder = embedder.with_openai_key(key);
+    }
+
+    if config.model == EmbeddingModel::TfIdf {
+        match memory.list_all(None, None) {
+            Ok(memories) => {
+                let corpus: Vec<&str> = memories.iter().map(|m| m.content.as_str()).collect();
+                embedder.fit_tfidf(&corpus);
+            }
+            Err(e) => tracing::warn!("[embeddings] Failed to load memories to fit TF-IDF: {}", e),
+        }
+    }
+
+    let embedder = Arc::new(embedder);
+    tracing::info!("[embeddings] Embedding shared memories with {}", embedder.model_name());
+    let background = Arc::clone(&embedder);
+    tokio::spawn(async move {
+        match backfill(&memory, &background).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("[embeddings] Embedded {} stored memories", n),
+            Err(e) => tracing::warn!("[embeddings] Stopped embedding stored memories: {}", e),
+        }
+    });
+    embedder
+}
+
+/// Embed the memories `embedder` should cover but doesn't yet. Stops at the
+/// first failure, e.g. the embedding server being down; the rest are picked
+/// up on the next start.
+async fn backfill(memory: &SharedMemory, embedder: &EmbeddingGenerator) -> Result<usize> {
+    let model = embedder.model_name();
+    let stale_prefix = (*embedder.model() == EmbeddingModel::TfIdf).then_some(TFIDF_MODEL_PREFIX);
+    let pending = memory.memories_to_embed(&model, stale_prefix)?;
+
+    let mut embedded = 0;
+    for (id, content) in pending {
+        let vector = embedder.embed(&content).await?;
+        memory.set_embedding(id, &vector, &model)?;
+        embedded += 1;
+    }
+    Ok(embedded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared_memory::{NewMemory, SharedMemoryConfig};
+
+    #[test]
+    fn test_tfidf_model_name_tracks_the_fit() {
+        let embedder = EmbeddingGenerator::new(EmbeddingModel::TfIdf);
+        embedder.fit_tfidf(&["deploy the staging cluster", "rotate the api keys"]);
+        let name = embedder.model_name();
+        assert!(name.starts_with(TFIDF_MODEL_PREFIX));
+        assert_eq!(name, embedder.model_name());
+
+        embedder.fit_tfidf(&["deploy the staging cluster", "rotate the api keys", "new words"]);
+        assert_ne!(name, embedder.model_name());
+
+        let ollama = EmbeddingGenerator::new(EmbeddingModel::LocalOllama);
+        assert_eq!(ollama.model_name(), OLLAMA_MODEL);
+        assert_eq!(ollama.dimension(), 768);
+    }
+
+    #[tokio::test]
+    async fn test_backfill_embeds_missing_and_stale_tfidf_vectors() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = SharedMemory::with_config(SharedMemoryConfig {
+            database_path: dir.path().join("memory.db"),
+            ..Default::default()
+        })
+        .unwrap();
+        let store = |content: &str, embedding: Option<Vec<f32>>, model: Option<&str>| {
+            memory
+                .store_memory(
+                    None,
+                    &NewMemory {
+                        org: None,
+                        agent_id: "agent-1".to_string(),
+                        content: content.to_string(),
+                        embedding,
+                        embedding_model: model.map(str::to_string),
+                        expires_at: None,
+                        metadata: None,
+                    },
+                )
+                .unwrap()
+        };
+        let plain = store("deploy the staging cluster", None, None);
+        let stale = store("rotate the api keys", Some(vec![1.0]), Some("tfidf-000000000000"));
+        let foreign = store("agent supplied vector", Some(vec![0.5, 0.5]), Some("agent-model"));
+
+        let embedder = EmbeddingGenerator::new(EmbeddingModel::TfIdf);
+        embedder.fit_tfidf(&["deploy the staging cluster", "rotate the api keys", "agent supplied vector"]);
+        assert_eq!(backfill(&memory, &embedder).await.unwrap(), 2);
+
+        let model = embedder.model_name();
+        for id in [plain, stale] {
+            let m = memory.get_memory(id).unwrap().unwrap();
+            assert_eq!(m.embedding_model.as_deref(), Some(model.as_str()));
+            assert_eq!(m.embedding_dim, Some(embedder.dimension()));
+        }
+        let m = memory.get_memory(foreign).unwrap().unwrap();
+        assert_eq!(m.embedding_model.as_deref(), Some("agent-model"));
+        assert_eq!(backfill(&memory, &embedder).await.unwrap(), 0);
+
+        // Queries only meet vectors from their own model
+        let query = embedder.embed("staging deploy").await.unwrap();
+        let results = memory.search_memories(crate::shared_memory::ORG_ALL, &query, Some(&model), 10).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].memory.id, plain);
+        assert!(memory.search_memories(crate::shared_memory::ORG_ALL, &query, None, 10).unwrap().is_empty());
+    }
+}
@@ -2,31 +2,123 @@
 
 use anyhow::Result;
 use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, oneshot, watch, RwLock};
 use chrono::Utc;
-use futures_util::future;
 
+use crate::container::{ContainerRuntime, RuntimeClient};
+use crate::step_condition;
+use crate::step_template::TemplateScope;
 use crate::types::AgentContainer;
 use crate::workflow::{
-    Workflow, WorkflowExecutionRequest, ExecutionStatus, StepResult,
-    StepTask, ExecutionStrategy, WorkflowRegistry,
+    ApprovalDecision, ExecutionEvent, PendingApproval, Workflow, WorkflowExecution, WorkflowExecutionRequest,
+    ExecutionStatus, StepResult, StepTask, ExecutionStrategy, OutputFormat, WorkflowRegistry,
+    WorkflowStep,
 };
 
+/// Used when a step leaves `timeout` at 0
+const DEFAULT_STEP_TIMEOUT_SECS: u64 = 300;
+
+/// Events buffered per stream subscriber before it starts lagging
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// What a live execution has been asked to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunSignal {
+    Run,
+    /// Finish in-flight steps but don't start new ones
+    Pause,
+    /// Abort in-flight agent calls and retry sleeps, then stop
+    Cancel,
+}
+
+/// Returned up the call chain when an execution stops because it was cancelled
+#[derive(Debug, thiserror::Error)]
+#[error("Execution cancelled")]
+struct ExecutionCancelled;
+
+/// Handles a running step needs to reach agents, containers and providers
+#[derive(Clone)]
+struct StepEnv {
+    /// Available agents
+    agents: Arc<RwLock<Vec<AgentContainer>>>,
+    /// Primary container runtime (for ExecuteCode steps)
+    runtime: RuntimeClient,
+    /// Exo runtime for agents created with `runtime = "exo"`
+    exo_runtime: RuntimeClient,
+    /// Stored provider keys, shared with the API (for direct-runtime agents)
+    api_keys: Arc<RwLock<HashMap<String, String>>>,
+    /// Shared HTTP client for HttpRequest steps and direct LLM calls
+    http: reqwest::Client,
+    /// Execution state, for steps that park the run (approvals)
+    registry: Arc<RwLock<WorkflowRegistry>>,
+    /// Approval steps currently waiting on a decision
+    approvals: Arc<Mutex<HashMap<(String, String), WaitingApproval>>>,
+    /// Progress events for live stream subscribers (all executions)
+    events: broadcast::Sender<ExecutionEvent>,
+    /// Token accounting for direct LLM calls, when wired up
+    usage: Option<Arc<crate::usage::UsageLedger>>,
+}
+
+impl StepEnv {
+    /// Publish a progress event; fine if nobody is listening
+    fn emit(&self, event: ExecutionEvent) {
+        let _ = self.events.send(event);
+    }
+}
+
+/// A parked `Approval` step and the channel that wakes it
+struct WaitingApproval {
+    request: PendingApproval,
+    decide: oneshot::Sender<ApprovalDecision>,
+}
+
+/// How a step's task ended
+enum Outcome {
+    Completed(serde_json::Value),
+    Failed(String),
+    Cancelled,
+}
+
 /// Executes workflows by coordinating agent tasks
 pub struct WorkflowExecutor {
     /// Workflow registry
     registry: Arc<RwLock<WorkflowRegistry>>,
-    /// Available agents
-    agents: Arc<RwLock<Vec<AgentContainer>>>,
+    /// Everything steps need to talk to agents
+    env: StepEnv,
+    /// Control channels for executions with a live run task, by execution ID
+    runs: Arc<Mutex<HashMap<String, watch::Sender<RunSignal>>>>,
 }
 
 impl WorkflowExecutor {
     pub fn new(
         registry: Arc<RwLock<WorkflowRegistry>>,
         agents: Arc<RwLock<Vec<AgentContainer>>>,
+        runtime: RuntimeClient,
+        exo_runtime: RuntimeClient,
+        api_keys: Arc<RwLock<HashMap<String, String>>>,
     ) -> Self {
-        Self { registry, agents }
+        Self {
+            env: StepEnv {
+                agents,
+                runtime,
+                exo_runtime,
+                api_keys,
+                http: reqwest::Client::new(),
+                registry: Arc::clone(&registry),
+                approvals: Arc::new(Mutex::new(HashMap::new())),
+                events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+                usage: None,
+            },
+            registry,
+            runs: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Record the token usage of `LlmRequest` steps in `usage`
+    pub fn with_usage(mut self, usage: Arc<crate::usage::UsageLedger>) -> Self {
+        self.env.usage = Some(usage);
+        self
     }
 
     /// Execute a workflow
@@ -42,29 +134,230 @@ impl WorkflowExecutor {
         // Drop the write lock before executing
         drop(registry);
 
-        // Clone for the spawn block
+        self.spawn_run(workflow, execution_id.clone());
+        Ok(execution_id)
+    }
+
+    /// Cancel an execution.
+    ///
+    /// A live run aborts its in-flight steps and is marked `Cancelled` once
+    /// it has unwound; an execution without a run (interrupted by a restart)
+    /// is cancelled in place.
+    pub async fn cancel_execution(&self, execution_id: &str) -> Result<()> {
+        if self.signal(execution_id, RunSignal::Cancel) {
+            tracing::info!("Cancelling workflow execution {}", execution_id);
+            return Ok(());
+        }
+
+        let mut registry = self.registry.write().await;
+        let execution = registry
+            .get_execution_mut(execution_id)
+            .ok_or_else(|| anyhow::anyhow!("Execution {} not found", execution_id))?;
+        if !matches!(
+            execution.status,
+            ExecutionStatus::Pending | ExecutionStatus::Paused | ExecutionStatus::Interrupted
+        ) {
+            return Err(anyhow::anyhow!(
+                "Execution {} is {:?} and can no longer be cancelled",
+                execution_id,
+                execution.status
+            ));
+        }
+        execution.status = ExecutionStatus::Cancelled;
+        execution.completed_at = Some(Utc::now());
+        registry.persist_execution(execution_id);
+        Ok(())
+    }
+
+    /// Pause a running execution: steps already in flight finish, nothing new starts
+    pub async fn pause_execution(&self, execution_id: &str) -> Result<()> {
+        let mut registry = self.registry.write().await;
+        let execution = registry
+            .get_execution_mut(execution_id)
+            .ok_or_else(|| anyhow::anyhow!("Execution {} not found", execution_id))?;
+        if !matches!(
+            execution.status,
+            ExecutionStatus::Pending | ExecutionStatus::Running | ExecutionStatus::AwaitingApproval
+        ) || !self.signal(execution_id, RunSignal::Pause)
+        {
+            return Err(anyhow::anyhow!(
+                "Execution {} is {:?}, only running executions can be paused",
+                execution_id,
+                execution.status
+            ));
+        }
+        execution.status = ExecutionStatus::Paused;
+        registry.persist_execution(execution_id);
+
+        tracing::info!("Paused workflow execution {}", execution_id);
+        Ok(())
+    }
+
+    /// Resume a paused or interrupted execution.
+    ///
+    /// A paused run simply carries on. An interrupted one is restarted from
+    /// its last completed step: steps that already completed keep their
+    /// results and outputs, every other step runs again.
+    pub async fn resume_execution(&self, execution_id: &str) -> Result<()> {
+        let mut registry = self.registry.write().await;
+
+        let execution = registry
+            .get_execution_mut(execution_id)
+            .ok_or_else(|| anyhow::anyhow!("Execution {} not found", execution_id))?;
+        if matches!(execution.status, ExecutionStatus::Paused)
+            && self.signal(execution_id, RunSignal::Run)
+        {
+            execution.status = ExecutionStatus::Running;
+            registry.persist_execution(execution_id);
+            tracing::info!("Resumed paused workflow execution {}", execution_id);
+            return Ok(());
+        }
+        if !matches!(execution.status, ExecutionStatus::Paused | ExecutionStatus::Interrupted) {
+            return Err(anyhow::anyhow!(
+                "Execution {} is {:?}, only paused or interrupted executions can be resumed",
+                execution_id,
+                execution.status
+            ));
+        }
+        let workflow_id = execution.workflow_id.clone();
+
+        let workflow = registry
+            .get_workflow(&workflow_id)
+            .ok_or_else(|| anyhow::anyhow!("Workflow {} no longer exists", workflow_id))?
+            .clone();
+
+        if let Some(execution) = registry.get_execution_mut(execution_id) {
+            execution.status = ExecutionStatus::Pending;
+            execution.error = None;
+            execution.completed_at = None;
+        }
+        registry.persist_execution(execution_id);
+        drop(registry);
+
+        tracing::info!("Resuming workflow execution {}", execution_id);
+        self.spawn_run(workflow, execution_id.to_string());
+        Ok(())
+    }
+
+    /// Receive progress events for every execution from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<ExecutionEvent> {
+        self.env.events.subscribe()
+    }
+
+    /// Approval steps currently waiting on a decision, oldest first
+    pub fn pending_approvals(&self) -> Vec<PendingApproval> {
+        let approvals = self.env.approvals.lock().unwrap();
+        let mut pending: Vec<PendingApproval> =
+            approvals.values().map(|w| w.request.clone()).collect();
+        pending.sort_by_key(|p| p.requested_at);
+        pending
+    }
+
+    /// Approve or reject a waiting `Approval` step
+    pub fn decide_approval(
+        &self,
+        execution_id: &str,
+        step_id: &str,
+        decision: ApprovalDecision,
+    ) -> Result<()> {
+        let waiting = self
+            .env
+            .approvals
+            .lock()
+            .unwrap()
+            .remove(&(execution_id.to_string(), step_id.to_string()))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Step {} of execution {} is not waiting for approval",
+                    step_id,
+                    execution_id
+                )
+            })?;
+
+        tracing::info!(
+            "Step {} of execution {} {} by {}",
+            step_id,
+            execution_id,
+            if decision.approved { "approved" } else { "rejected" },
+            decision.decided_by
+        );
+        waiting
+            .decide
+            .send(decision)
+            .map_err(|_| anyhow::anyhow!("Execution {} stopped waiting for approval", execution_id))
+    }
+
+    /// Send a signal to an execution's live run. Returns false if it has none.
+    fn signal(&self, execution_id: &str, signal: RunSignal) -> bool {
+        match self.runs.lock().unwrap().get(execution_id) {
+            Some(sender) => {
+                sender.send_replace(signal);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Run an execution in the background, recording failure on the execution
+    fn spawn_run(&self, workflow: Workflow, execution_id: String) {
         let registry = Arc::clone(&self.registry);
-        let agents = Arc::clone(&self.agents);
-        let registry_for_handler = Arc::clone(&self.registry);
-        let execution_id_for_handler = execution_id.clone();
-        let execution_id_for_return = execution_id.clone();
+        let env = self.env.clone();
+        let runs = Arc::clone(&self.runs);
+
+        // Registered before spawning so the task can't finish (and deregister)
+        // before it was ever tracked
+        let (sender, signal) = watch::channel(RunSignal::Run);
+        runs.lock().unwrap().insert(execution_id.clone(), sender);
 
-        // Spawn execution in background
         tokio::spawn(async move {
-            if let Err(e) = run_workflow(&workflow, execution_id_for_handler.clone(), registry, agents).await {
-                tracing::error!("Workflow execution failed: {}", e);
-
-                // Update execution status to failed
-                let mut registry = registry_for_handler.write().await;
-                if let Some(execution) = registry.get_execution_mut(&execution_id_for_handler) {
-                    execution.status = ExecutionStatus::Failed;
-                    execution.error = Some(e.to_string());
-                    execution.completed_at = Some(Utc::now());
+            let outcome = run_workflow(&workflow, execution_id.clone(), registry.clone(), env.clone(), signal).await;
+
+            let (status, error) = match outcome {
+                Ok(()) => (ExecutionStatus::Completed, None),
+                Err(e) if e.is::<ExecutionCancelled>() => {
+                    tracing::info!("Workflow execution {} cancelled", execution_id);
+                    update_execution(&registry, &execution_id, |execution| {
+                        execution.status = ExecutionStatus::Cancelled;
+                        execution.completed_at = Some(Utc::now());
+                    })
+                    .await;
+                    (ExecutionStatus::Cancelled, None)
                 }
-            }
+                Err(e) => {
+                    tracing::error!("Workflow execution failed: {}", e);
+
+                    // Update execution status to failed
+                    update_execution(&registry, &execution_id, |execution| {
+                        execution.status = ExecutionStatus::Failed;
+                        execution.error = Some(e.to_string());
+                        execution.completed_at = Some(Utc::now());
+                    })
+                    .await;
+                    (ExecutionStatus::Failed, Some(e.to_string()))
+                }
+            };
+
+            runs.lock().unwrap().remove(&execution_id);
+            env.emit(ExecutionEvent::ExecutionFinished {
+                execution_id,
+                status,
+                error,
+                timestamp: Utc::now(),
+            });
         });
+    }
+}
 
-        Ok(execution_id_for_return)
+/// Apply a change to an execution and write it through to the store
+async fn update_execution(
+    registry: &RwLock<WorkflowRegistry>,
+    execution_id: &str,
+    change: impl FnOnce(&mut WorkflowExecution),
+) {
+    let mut registry = registry.write().await;
+    if let Some(execution) = registry.get_execution_mut(execution_id) {
+        change(execution);
+        registry.persist_execution(execution_id);
     }
 }
 
@@ -73,37 +366,42 @@ async fn run_workflow(
     workflow: &Workflow,
     execution_id: String,
     registry: Arc<RwLock<WorkflowRegistry>>,
-    agents: Arc<RwLock<Vec<AgentContainer>>>,
+    env: StepEnv,
+    signal: watch::Receiver<RunSignal>,
 ) -> Result<()> {
-    // Update execution status to running
-    {
-        let mut registry = registry.write().await;
-        if let Some(execution) = registry.get_execution_mut(&execution_id) {
-            execution.status = ExecutionStatus::Running;
-        }
-    }
+    // Update execution status to running, picking up any steps that already
+    // completed before an interruption
+    let mut completed: HashMap<String, Option<serde_json::Value>> = HashMap::new();
+    let mut params = serde_json::Map::new();
+    update_execution(&registry, &execution_id, |execution| {
+        execution.status = ExecutionStatus::Running;
+        params = execution.parameters.clone().into_iter().collect();
+        completed = execution
+            .step_results
+            .iter()
+            .filter(|(_, r)| matches!(r.status, ExecutionStatus::Completed))
+            .map(|(id, r)| (id.clone(), r.output.clone()))
+            .collect();
+    })
+    .await;
+    let params = serde_json::Value::Object(params);
 
     // Execute based on strategy
     match &workflow.strategy {
         ExecutionStrategy::Sequential => {
-            execute_sequential(workflow, &execution_id, registry.clone(), agents.clone()).await?;
+            execute_sequential(workflow, &execution_id, &params, &completed, registry.clone(), env.clone(), signal).await?;
         }
-        ExecutionStrategy::Parallel => {
-            execute_parallel(workflow, &execution_id, registry.clone(), agents.clone()).await?;
-        }
-        ExecutionStrategy::Dag => {
-            execute_dag(workflow, &execution_id, registry.clone(), agents.clone()).await?;
+        ExecutionStrategy::Parallel | ExecutionStrategy::Dag => {
+            execute_dag(workflow, &execution_id, &params, &completed, registry.clone(), env.clone(), signal).await?;
         }
     }
 
     // Mark execution as completed
-    {
-        let mut registry = registry.write().await;
-        if let Some(execution) = registry.get_execution_mut(&execution_id) {
-            execution.status = ExecutionStatus::Completed;
-            execution.completed_at = Some(Utc::now());
-        }
-    }
+    update_execution(&registry, &execution_id, |execution| {
+        execution.status = ExecutionStatus::Completed;
+        execution.completed_at = Some(Utc::now());
+    })
+    .await;
 
     Ok(())
 }
@@ -112,35 +410,52 @@ async fn run_workflow(
 async fn execute_sequential(
     workflow: &Workflow,
     execution_id: &str,
+    params: &serde_json::Value,
+    completed: &HashMap<String, Option<serde_json::Value>>,
     registry: Arc<RwLock<WorkflowRegistry>>,
-    agents: Arc<RwLock<Vec<AgentContainer>>>,
+    env: StepEnv,
+    mut signal: watch::Receiver<RunSignal>,
 ) -> Result<()> {
-    let mut step_outputs: HashMap<String, serde_json::Value> = HashMap::new();
+    let mut step_outputs: HashMap<String, serde_json::Value> = completed
+        .iter()
+        .filter_map(|(id, output)| Some((id.clone(), output.clone()?)))
+        .collect();
+    let mut branches = Branches::resumed(workflow, completed);
 
-    for step in &workflow.steps {
+    for step in topological_order(workflow)? {
+        if completed.contains_key(&step.id) {
+            continue;
+        }
+        if branches.should_skip(step) {
+            record_skipped(&registry, &env, execution_id, step, &mut branches).await;
+            continue;
+        }
+        wait_while_paused(&mut signal).await?;
         tracing::info!("Executing step: {}", step.name);
+        update_execution(&registry, execution_id, |execution| {
+            execution.current_step = Some(step.id.clone());
+        })
+        .await;
 
-        let result = execute_step(step, &step_outputs, execution_id, agents.clone()).await?;
+        let result = execute_step(step, &step_outputs, params, execution_id, &env, &mut signal).await;
 
         // Store output for next steps
         if let Some(output) = result.output.clone() {
+            branches.decide(step, &output);
             step_outputs.insert(step.id.clone(), output);
         }
 
+        // Check if step failed or was cut short
+        let step_failed = matches!(result.status, ExecutionStatus::Failed);
+        let step_cancelled = matches!(result.status, ExecutionStatus::Cancelled);
+
         // Update execution with step result
-        {
-            let mut registry = registry.write().await;
-            if let Some(execution) = registry.get_execution_mut(execution_id) {
-                execution.step_results.insert(step.id.clone(), result);
-                execution.current_step = Some(step.id.clone());
-            }
-        }
+        record_result(&registry, &env, execution_id, result).await;
 
-        // Check if step failed
-        let registry = registry.read().await;
-        let execution = registry.get_execution(execution_id).unwrap();
-        let step_result = execution.step_results.get(&step.id).unwrap();
-        if matches!(step_result.status, ExecutionStatus::Failed) {
+        if step_cancelled {
+            return Err(ExecutionCancelled.into());
+        }
+        if step_failed {
             return Err(anyhow::anyhow!("Step {} failed", step.name));
         }
     }
@@ -148,121 +463,439 @@ async fn execute_sequential(
     Ok(())
 }
 
-/// Execute workflow steps in parallel (where possible)
-async fn execute_parallel(
+/// Execute workflow steps as a DAG.
+///
+/// Each step starts as soon as all of its `depends_on` have completed, so
+/// independent branches never wait on each other. After a failure no new
+/// steps are started; steps already in flight are allowed to finish. The
+/// same holds while paused, except the run then waits to be resumed.
+async fn execute_dag(
     workflow: &Workflow,
     execution_id: &str,
+    params: &serde_json::Value,
+    already_completed: &HashMap<String, Option<serde_json::Value>>,
     registry: Arc<RwLock<WorkflowRegistry>>,
-    agents: Arc<RwLock<Vec<AgentContainer>>>,
+    env: StepEnv,
+    mut signal: watch::Receiver<RunSignal>,
 ) -> Result<()> {
-    // Group steps by dependency level
-    let levels = get_dependency_levels(workflow);
-
-    let mut step_outputs: HashMap<String, serde_json::Value> = HashMap::new();
-
-    // Execute each level sequentially, but steps within level in parallel
-    for level_steps in levels {
-        let mut tasks = Vec::new();
-
-        for step_id in level_steps {
-            let step = workflow.steps.iter().find(|s| s.id == step_id).unwrap();
-            let exec_id = execution_id.to_string();
-            let outputs = step_outputs.clone();
-            let step_clone = step.clone();
-            let agents_clone = agents.clone();
-            let registry_clone = registry.clone();
-
-            tasks.push(tokio::spawn(async move {
-                let result = execute_step(&step_clone, &outputs, &exec_id, agents_clone).await?;
-
-                // Update execution with step result
-                {
-                    let mut registry = registry_clone.write().await;
-                    if let Some(execution) = registry.get_execution_mut(&exec_id) {
-                        execution.step_results.insert(step_clone.id.clone(), result.clone());
-                        execution.current_step = Some(step_clone.id.clone());
-                    }
+    // Count unmet dependencies per step and index who depends on whom.
+    // Steps completed before an interruption count as already satisfied.
+    let mut pending_deps: HashMap<&str, usize> = HashMap::new();
+    let mut dependents: HashMap<&str, Vec<&WorkflowStep>> = HashMap::new();
+    for step in &workflow.steps {
+        let unmet = step
+            .depends_on
+            .iter()
+            .filter(|dep| !already_completed.contains_key(*dep))
+            .count();
+        pending_deps.insert(step.id.as_str(), unmet);
+        for dep in &step.depends_on {
+            dependents.entry(dep.as_str()).or_default().push(step);
+        }
+    }
+
+    let mut ready: Vec<&WorkflowStep> = workflow
+        .steps
+        .iter()
+        .filter(|s| !already_completed.contains_key(&s.id) && pending_deps[s.id.as_str()] == 0)
+        .collect();
+    let mut step_outputs: HashMap<String, serde_json::Value> = already_completed
+        .iter()
+        .filter_map(|(id, output)| Some((id.clone(), output.clone()?)))
+        .collect();
+    let mut running = tokio::task::JoinSet::new();
+    let mut failed: Vec<String> = Vec::new();
+    let mut completed = already_completed.len();
+    let mut cancelled = false;
+    let mut branches = Branches::resumed(workflow, already_completed);
+    let steps_by_id: HashMap<&str, &WorkflowStep> =
+        workflow.steps.iter().map(|s| (s.id.as_str(), s)).collect();
+
+    loop {
+        // Launch everything that became ready, unless we're winding down
+        if failed.is_empty() && !cancelled && !ready.is_empty() {
+            // With nothing in flight there's nothing to collect, so block here
+            // for as long as the execution is paused
+            if running.is_empty() {
+                wait_while_paused(&mut signal).await?;
+            }
+            let launching: Vec<&WorkflowStep> = if *signal.borrow() == RunSignal::Run {
+                std::mem::take(&mut ready)
+            } else {
+                Vec::new()
+            };
+            for step in launching {
+                // A skipped step finishes immediately and may unblock others
+                if branches.should_skip(step) {
+                    record_skipped(&registry, &env, execution_id, step, &mut branches).await;
+                    completed += 1;
+                    release_dependents(&step.id, &dependents, &mut pending_deps, &mut ready);
+                    continue;
                 }
 
-                Ok::<(String, StepResult), anyhow::Error>((step_clone.id.clone(), result))
-            }));
+                tracing::info!("Executing step: {}", step.name);
+                update_execution(&registry, execution_id, |execution| {
+                    execution.current_step = Some(step.id.clone());
+                })
+                .await;
+
+                let step = step.clone();
+                let outputs = step_outputs.clone();
+                let params = params.clone();
+                let exec_id = execution_id.to_string();
+                let env = env.clone();
+                let mut signal = signal.clone();
+                running.spawn(async move {
+                    let result = execute_step(&step, &outputs, &params, &exec_id, &env, &mut signal).await;
+                    (step.id, result)
+                });
+            }
         }
 
-        // Wait for all tasks in this level to complete
-        let results = future::join_all(tasks).await;
+        // Skips may have made more steps ready without anything running
+        if running.is_empty() && !ready.is_empty() && failed.is_empty() && !cancelled {
+            continue;
+        }
 
-        for result in results {
-            let (step_id, step_result) = result??;
-            if let Some(output) = step_result.output {
-                step_outputs.insert(step_id.clone(), output);
-            }
+        let Some(joined) = running.join_next().await else {
+            break;
+        };
+        let (step_id, result) = joined?;
 
-            if matches!(step_result.status, ExecutionStatus::Failed) {
-                return Err(anyhow::anyhow!("Step {} failed", step_id));
+        let step_failed = matches!(result.status, ExecutionStatus::Failed);
+        let step_cancelled = matches!(result.status, ExecutionStatus::Cancelled);
+        if let Some(output) = result.output.clone() {
+            if let Some(step) = steps_by_id.get(step_id.as_str()) {
+                branches.decide(step, &output);
             }
+            step_outputs.insert(step_id.clone(), output);
         }
+
+        // Update execution with step result
+        record_result(&registry, &env, execution_id, result).await;
+
+        if step_cancelled {
+            cancelled = true;
+            continue;
+        }
+        if step_failed {
+            failed.push(step_id);
+            continue;
+        }
+
+        completed += 1;
+        release_dependents(&step_id, &dependents, &mut pending_deps, &mut ready);
+    }
+
+    if cancelled || *signal.borrow() == RunSignal::Cancel {
+        return Err(ExecutionCancelled.into());
+    }
+    if !failed.is_empty() {
+        return Err(anyhow::anyhow!("Step(s) failed: {}", failed.join(", ")));
+    }
+
+    // Validation rejects cycles, so this only guards against bad definitions
+    // that slipped past it
+    if completed < workflow.steps.len() {
+        let stuck: Vec<&str> = pending_deps
+            .iter()
+            .filter(|(_, count)| **count > 0)
+            .map(|(id, _)| *id)
+            .collect();
+        return Err(anyhow::anyhow!(
+            "Steps never became ready (unsatisfiable dependencies): {}",
+            stuck.join(", ")
+        ));
     }
 
     Ok(())
 }
 
-/// Execute workflow steps as a DAG (respecting dependencies)
-async fn execute_dag(
-    workflow: &Workflow,
+/// Mark a finished step's dependency as met for everything waiting on it
+fn release_dependents<'w>(
+    step_id: &str,
+    dependents: &HashMap<&str, Vec<&'w WorkflowStep>>,
+    pending_deps: &mut HashMap<&str, usize>,
+    ready: &mut Vec<&'w WorkflowStep>,
+) {
+    for dependent in dependents.get(step_id).into_iter().flatten() {
+        if let Some(count) = pending_deps.get_mut(dependent.id.as_str()) {
+            *count -= 1;
+            if *count == 0 {
+                ready.push(dependent);
+            }
+        }
+    }
+}
+
+/// Which steps `Condition`s have ruled out so far.
+///
+/// A step is skipped when it sits on a branch its condition didn't take, or
+/// when every one of its dependencies was skipped. A step joining a taken
+/// and a skipped branch therefore still runs.
+#[derive(Default)]
+struct Branches {
+    /// Steps on a branch that wasn't taken
+    not_taken: HashSet<String>,
+    /// Steps recorded as skipped
+    skipped: HashSet<String>,
+}
+
+impl Branches {
+    /// Rebuild branch decisions from conditions that completed before a resume
+    fn resumed(workflow: &Workflow, completed: &HashMap<String, Option<serde_json::Value>>) -> Self {
+        let mut branches = Self::default();
+        for step in &workflow.steps {
+            if let Some(Some(output)) = completed.get(&step.id) {
+                branches.decide(step, output);
+            }
+        }
+        branches
+    }
+
+    /// Record the decision of a completed step, if it is a condition
+    fn decide(&mut self, step: &WorkflowStep, output: &serde_json::Value) {
+        if let StepTask::Condition { if_true, if_false, .. } = &step.task {
+            let taken = output.get("result").and_then(|r| r.as_bool()).unwrap_or(false);
+            let not_taken = if taken { if_false } else { if_true };
+            self.not_taken.extend(not_taken.iter().cloned());
+        }
+    }
+
+    fn should_skip(&self, step: &WorkflowStep) -> bool {
+        self.not_taken.contains(&step.id)
+            || (!step.depends_on.is_empty()
+                && step.depends_on.iter().all(|d| self.skipped.contains(d)))
+    }
+}
+
+/// Store a finished step's result and announce it to stream subscribers
+async fn record_result(
+    registry: &RwLock<WorkflowRegistry>,
+    env: &StepEnv,
     execution_id: &str,
-    registry: Arc<RwLock<WorkflowRegistry>>,
-    agents: Arc<RwLock<Vec<AgentContainer>>>,
-) -> Result<()> {
-    // DAG execution is similar to parallel, but we use the depends_on field directly
-    execute_parallel(workflow, execution_id, registry, agents).await
+    result: StepResult,
+) {
+    let event = ExecutionEvent::step_finished(execution_id, &result);
+    update_execution(registry, execution_id, |execution| {
+        execution.step_results.insert(result.step_id.clone(), result);
+    })
+    .await;
+    env.emit(event);
+}
+
+/// Record a step as skipped without running it
+async fn record_skipped(
+    registry: &RwLock<WorkflowRegistry>,
+    env: &StepEnv,
+    execution_id: &str,
+    step: &WorkflowStep,
+    branches: &mut Branches,
+) {
+    tracing::info!("Skipping step: {}", step.name);
+    branches.skipped.insert(step.id.clone());
+
+    let result = StepResult {
+        step_id: step.id.clone(),
+        status: ExecutionStatus::Skipped,
+        output: None,
+        timestamp: Utc::now(),
+        duration_ms: 0,
+        error: None,
+        agent_id: None,
+        attempts: 0,
+    };
+    record_result(registry, env, execution_id, result).await;
+}
+
+/// Order steps so each comes after its dependencies, otherwise keeping the
+/// order they were listed in
+fn topological_order(workflow: &Workflow) -> Result<Vec<&WorkflowStep>> {
+    let mut ordered = Vec::with_capacity(workflow.steps.len());
+    let mut placed: HashSet<&str> = HashSet::new();
+
+    while ordered.len() < workflow.steps.len() {
+        let next = workflow.steps.iter().find(|s| {
+            !placed.contains(s.id.as_str())
+                && s.depends_on.iter().all(|d| placed.contains(d.as_str()))
+        });
+
+        match next {
+            Some(step) => {
+                placed.insert(step.id.as_str());
+                ordered.push(step);
+            }
+            None => {
+                return Err(anyhow::anyhow!(
+                    "Workflow {} has unsatisfiable dependencies",
+                    workflow.id
+                ));
+            }
+        }
+    }
+
+    Ok(ordered)
 }
 
 /// Execute a single workflow step
 async fn execute_step(
-    step: &crate::workflow::WorkflowStep,
+    step: &WorkflowStep,
     step_outputs: &HashMap<String, serde_json::Value>,
-    _execution_id: &str,
-    agents: Arc<RwLock<Vec<AgentContainer>>>,
-) -> Result<StepResult> {
+    params: &serde_json::Value,
+    execution_id: &str,
+    env: &StepEnv,
+    signal: &mut watch::Receiver<RunSignal>,
+) -> StepResult {
     let start_time = std::time::Instant::now();
-    let mut attempt = 0;
-    let max_attempts = step.retry_policy.max_attempts;
-
-    // Resolve agent for this step
-    let agent = resolve_agent(step, agents.clone()).await?;
+    env.emit(ExecutionEvent::StepStarted {
+        execution_id: execution_id.to_string(),
+        step_id: step.id.clone(),
+        timestamp: Utc::now(),
+    });
 
     // Prepare task with inputs
-    let _task = prepare_task(step, step_outputs)?;
+    let mut scope = match TemplateScope::new(&step.inputs, step_outputs) {
+        Ok(scope) => scope,
+        Err(e) => {
+            let error = format!("Failed to render inputs for step {}: {}", step.id, e);
+            return finish(step, start_time, Outcome::Failed(error), None, 0);
+        }
+    };
+    scope.bind("params", params.clone());
+
+    // Control-flow steps run in the orchestrator, not on an agent
+    match &step.task {
+        StepTask::Condition { expression, .. } => {
+            let outcome = match step_condition::evaluate(expression, &scope) {
+                Ok(result) => Outcome::Completed(serde_json::json!({ "result": result })),
+                Err(e) => Outcome::Failed(format!("Condition could not be evaluated: {}", e)),
+            };
+            return finish(step, start_time, outcome, None, 1);
+        }
+        StepTask::Approval { message } => {
+            let outcome = match scope.render_str(message) {
+                Ok(message) => await_approval(step, message, execution_id, env, signal).await,
+                Err(e) => Outcome::Failed(format!("Failed to render approval message: {}", e)),
+            };
+            return finish(step, start_time, outcome, None, 1);
+        }
+        StepTask::ForEach { items, task, max_concurrency } => {
+            let tasks = match for_each_tasks(&mut scope, items, task) {
+                Ok(tasks) => tasks,
+                Err(e) => {
+                    let error = format!("Failed to expand ForEach step {}: {}", step.id, e);
+                    return finish(step, start_time, Outcome::Failed(error), None, 0);
+                }
+            };
+            let agent = match resolve_step_agent(step, task, env).await {
+                Ok(agent) => agent,
+                Err(e) => return finish(step, start_time, Outcome::Failed(e.to_string()), None, 0),
+            };
+            let agent_id = agent.as_ref().map(|a| a.id.clone());
+
+            // Items run in batches of `max_concurrency`; results keep item order
+            let mut results: Vec<(Outcome, u32)> = Vec::with_capacity(tasks.len());
+            for batch in tasks.chunks(max_concurrency.unwrap_or(1).max(1)) {
+                let mut running = Vec::with_capacity(batch.len());
+                for task in batch {
+                    let mut signal = signal.clone();
+                    let agent = agent.as_ref();
+                    running.push(async move {
+                        run_with_retries(step, agent, task, execution_id, env, &mut signal).await
+                    });
+                }
+                results.extend(futures::future::join_all(running).await);
+            }
+
+            let attempts = results.iter().map(|(_, attempts)| attempts).sum();
+            let mut outputs = Vec::with_capacity(results.len());
+            let mut outcome = None;
+            for (index, (item_outcome, _)) in results.into_iter().enumerate() {
+                match item_outcome {
+                    Outcome::Completed(output) => outputs.push(output),
+                    Outcome::Failed(e) => {
+                        outcome.get_or_insert(Outcome::Failed(format!("Item {}: {}", index, e)));
+                    }
+                    Outcome::Cancelled => outcome = Some(Outcome::Cancelled),
+                }
+            }
+            let outcome = outcome.unwrap_or(Outcome::Completed(serde_json::Value::Array(outputs)));
+            return finish(step, start_time, outcome, agent_id, attempts);
+        }
+        _ => {}
+    }
+
+    let task = match scope.render_task(&step.task) {
+        Ok(task) => task,
+        Err(e) => {
+            let error = format!("Failed to render inputs for step {}: {}", step.id, e);
+            return finish(step, start_time, Outcome::Failed(error), None, 0);
+        }
+    };
+
+    let agent = match resolve_step_agent(step, &task, env).await {
+        Ok(agent) => agent,
+        Err(e) => return finish(step, start_time, Outcome::Failed(e.to_string()), None, 0),
+    };
+    let agent_id = agent.as_ref().map(|a| a.id.clone());
+
+    let (outcome, attempts) =
+        run_with_retries(step, agent.as_ref(), &task, execution_id, env, signal).await;
+    finish(step, start_time, outcome, agent_id, attempts)
+}
+
+/// Resolve the agent for a step (HTTP requests run from the orchestrator itself)
+async fn resolve_step_agent(
+    step: &WorkflowStep,
+    task: &StepTask,
+    env: &StepEnv,
+) -> Result<Option<AgentContainer>> {
+    Ok(match task {
+        StepTask::HttpRequest { .. } => None,
+        _ => Some(resolve_agent(step, env.agents.clone()).await?),
+    })
+}
+
+/// Run one rendered task under the step's timeout and retry policy.
+///
+/// Returns how it ended and how many attempts were made.
+async fn run_with_retries(
+    step: &WorkflowStep,
+    agent: Option<&AgentContainer>,
+    task: &StepTask,
+    execution_id: &str,
+    env: &StepEnv,
+    signal: &mut watch::Receiver<RunSignal>,
+) -> (Outcome, u32) {
+    let mut attempt = 0;
+    let max_attempts = step.retry_policy.max_attempts;
 
     loop {
         attempt += 1;
 
-        // Execute the task
-        let execution_result = execute_task_on_agent(&agent, step).await;
+        // Execute the task; `timeout` bounds each attempt and cancelling the
+        // execution drops the in-flight call
+        let attempt_result = tokio::select! {
+            result = tokio::time::timeout(
+                std::time::Duration::from_secs(step_timeout_secs(step)),
+                execute_task_on_agent(agent, step, task, env),
+            ) => result,
+            _ = cancelled(signal) => return (Outcome::Cancelled, attempt),
+        };
+        let execution_result = match attempt_result {
+            Ok(result) => result,
+            Err(_) => Err(anyhow::anyhow!(
+                "Step timed out after {}s",
+                step_timeout_secs(step)
+            )),
+        };
 
         match execution_result {
-            Ok(output) => {
-                let duration = start_time.elapsed().as_millis() as u64;
-                return Ok(StepResult {
-                    step_id: step.id.clone(),
-                    status: ExecutionStatus::Completed,
-                    output: Some(output),
-                    timestamp: Utc::now(),
-                    duration_ms: duration,
-                    error: None,
-                });
-            }
+            Ok(output) => return (Outcome::Completed(output), attempt),
             Err(e) => {
                 if attempt >= max_attempts {
-                    let duration = start_time.elapsed().as_millis() as u64;
-                    return Ok(StepResult {
-                        step_id: step.id.clone(),
-                        status: ExecutionStatus::Failed,
-                        output: None,
-                        timestamp: Utc::now(),
-                        duration_ms: duration,
-                        error: Some(format!("Failed after {} attempts: {}", attempt, e)),
-                    });
+                    let error = format!("Failed after {} attempts: {}", attempt, e);
+                    return (Outcome::Failed(error), attempt);
                 }
 
                 // Exponential backoff
@@ -279,15 +912,158 @@ async fn execute_step(
                     delay,
                     e
                 );
-                tokio::time::sleep(tokio::time::Duration::from_millis(delay)).await;
+                env.emit(ExecutionEvent::StepRetrying {
+                    execution_id: execution_id.to_string(),
+                    step_id: step.id.clone(),
+                    attempt,
+                    max_attempts,
+                    delay_ms: delay,
+                    error: e.to_string(),
+                    timestamp: Utc::now(),
+                });
+                tokio::select! {
+                    _ = tokio::time::sleep(tokio::time::Duration::from_millis(delay)) => {}
+                    _ = cancelled(signal) => return (Outcome::Cancelled, attempt),
+                }
             }
         }
     }
 }
 
+/// Render a ForEach body once per item of the referenced array
+fn for_each_tasks(scope: &mut TemplateScope, items: &str, task: &StepTask) -> Result<Vec<StepTask>> {
+    let items = match scope.get(items)? {
+        serde_json::Value::Array(items) => items,
+        other => return Err(anyhow::anyhow!("'{}' is not an array: {}", items, other)),
+    };
+
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            scope.bind("item", item);
+            scope.bind("index", serde_json::json!(index));
+            scope.render_task(task)
+        })
+        .collect()
+}
+
+/// Park an `Approval` step until someone decides or the execution is cancelled
+async fn await_approval(
+    step: &WorkflowStep,
+    message: String,
+    execution_id: &str,
+    env: &StepEnv,
+    signal: &mut watch::Receiver<RunSignal>,
+) -> Outcome {
+    let key = (execution_id.to_string(), step.id.clone());
+    let (decide, decision) = oneshot::channel();
+    env.approvals.lock().unwrap().insert(
+        key.clone(),
+        WaitingApproval {
+            request: PendingApproval {
+                execution_id: execution_id.to_string(),
+                step_id: step.id.clone(),
+                message,
+                requested_at: Utc::now(),
+            },
+            decide,
+        },
+    );
+
+    tracing::info!("Step {} of execution {} is waiting for approval", step.name, execution_id);
+    update_execution(&env.registry, execution_id, |execution| {
+        if matches!(execution.status, ExecutionStatus::Running) {
+            execution.status = ExecutionStatus::AwaitingApproval;
+        }
+    })
+    .await;
+
+    let outcome = tokio::select! {
+        decision = decision => match decision {
+            Ok(decision) if decision.approved => Outcome::Completed(serde_json::json!({
+                "approved": true,
+                "decided_by": decision.decided_by,
+                "comment": decision.comment,
+            })),
+            Ok(decision) => Outcome::Failed(match decision.comment {
+                Some(comment) => format!("Rejected by {}: {}", decision.decided_by, comment),
+                None => format!("Rejected by {}", decision.decided_by),
+            }),
+            Err(_) => Outcome::Failed("Approval request was dropped".to_string()),
+        },
+        _ = cancelled(signal) => Outcome::Cancelled,
+    };
+
+    // Back to running unless another approval in this execution is still open
+    let still_waiting = {
+        let mut approvals = env.approvals.lock().unwrap();
+        approvals.remove(&key);
+        approvals.keys().any(|(id, _)| id == execution_id)
+    };
+    if !still_waiting {
+        update_execution(&env.registry, execution_id, |execution| {
+            if matches!(execution.status, ExecutionStatus::AwaitingApproval) {
+                execution.status = ExecutionStatus::Running;
+            }
+        })
+        .await;
+    }
+
+    outcome
+}
+
+/// Turn an outcome into the result recorded on the execution
+fn finish(
+    step: &WorkflowStep,
+    start_time: std::time::Instant,
+    outcome: Outcome,
+    agent_id: Option<String>,
+    attempts: u32,
+) -> StepResult {
+    let (status, output, error) = match outcome {
+        Outcome::Completed(output) => (ExecutionStatus::Completed, Some(output), None),
+        Outcome::Failed(error) => (ExecutionStatus::Failed, None, Some(error)),
+        Outcome::Cancelled => (ExecutionStatus::Cancelled, None, Some("Cancelled".to_string())),
+    };
+
+    StepResult {
+        step_id: step.id.clone(),
+        status,
+        output,
+        timestamp: Utc::now(),
+        duration_ms: start_time.elapsed().as_millis() as u64,
+        error,
+        agent_id,
+        attempts,
+    }
+}
+
+/// Resolves once the execution is cancelled
+async fn cancelled(signal: &mut watch::Receiver<RunSignal>) {
+    if signal.wait_for(|s| *s == RunSignal::Cancel).await.is_err() {
+        // The executor dropped the run's control channel; nothing can cancel it now
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Block at a step boundary while the execution is paused.
+///
+/// Fails with `ExecutionCancelled` if it is cancelled instead of resumed.
+async fn wait_while_paused(signal: &mut watch::Receiver<RunSignal>) -> Result<()> {
+    let current = match signal.wait_for(|s| *s != RunSignal::Pause).await {
+        Ok(current) => *current,
+        Err(_) => RunSignal::Run,
+    };
+    if current == RunSignal::Cancel {
+        return Err(ExecutionCancelled.into());
+    }
+    Ok(())
+}
+
 /// Resolve which agent should execute a step
 async fn resolve_agent(
-    step: &crate::workflow::WorkflowStep,
+    step: &WorkflowStep,
     agents: Arc<RwLock<Vec<AgentContainer>>>,
 ) -> Result<AgentContainer> {
     let agents = agents.read().await;
@@ -320,64 +1096,407 @@ async fn resolve_agent(
         .ok_or_else(|| anyhow::anyhow!("No running agents available")).cloned()
 }
 
-/// Prepare task with inputs from previous steps
-fn prepare_task(
-    _step: &crate::workflow::WorkflowStep,
-    _step_outputs: &HashMap<String, serde_json::Value>,
-) -> Result<StepTask> {
-    // For now, just return a placeholder
-    // TODO: Implement template substitution for step inputs
-    Err(anyhow::anyhow!("Task preparation not yet implemented"))
-}
-
 /// Execute a task on an agent
 async fn execute_task_on_agent(
-    _agent: &AgentContainer,
-    _step: &crate::workflow::WorkflowStep,
+    agent: Option<&AgentContainer>,
+    step: &WorkflowStep,
+    task: &StepTask,
+    env: &StepEnv,
 ) -> Result<serde_json::Value> {
-    // TODO: Implement actual agent communication
-    // For now, return a success response
-    Ok(serde_json::json!({
-        "status": "success",
-        "message": "Task executed (placeholder)"
-    }))
-}
+    let timeout_secs = step_timeout_secs(step);
 
-/// Get dependency levels for parallel execution
-fn get_dependency_levels(workflow: &Workflow) -> Vec<Vec<String>> {
-    let mut levels: Vec<Vec<String>> = Vec::new();
-    let mut placed: HashSet<String> = HashSet::new();
+    let raw = match task {
+        StepTask::Chat {
+            prompt,
+            system_message,
+            temperature,
+            max_tokens,
+        } => {
+            let agent = require_running(agent)?;
 
-    while placed.len() < workflow.steps.len() {
-        let mut current_level = Vec::new();
+            if agent.runtime.as_deref() == Some("direct") {
+                let (text, usage) = crate::direct_llm::complete(
+                    &env.http,
+                    &env.api_keys,
+                    agent,
+                    system_message.as_deref(),
+                    prompt,
+                    *temperature,
+                    *max_tokens,
+                )
+                .await?;
+                if let (Some(ledger), Some(usage)) = (&env.usage, &usage) {
+                    ledger.record(agent, None, usage);
+                }
+                text.into_bytes()
+            } else {
+                // The gateway has no separate system channel, so prepend it
+                let message = match system_message {
+                    Some(system) => format!("{}\n\n{}", system, prompt),
+                    None => prompt.clone(),
+                };
+                let gateway_token = agent.config.env_vars.get("GATEWAY_TOKEN")
+                    .or_else(|| agent.config.env_vars.get("OPENCLAW_GATEWAY_TOKEN"))
+                    .cloned();
 
-        for step in &workflow.steps {
-            if placed.contains(&step.id) {
-                continue;
+                crate::agent_comms::send_message_to_agent(
+                    agent.gateway_port,
+                    gateway_token.as_deref(),
+                    &message,
+                    timeout_secs,
+                )
+                .await?
+                .into_bytes()
             }
+        }
+        StepTask::ExecuteCode { code, language } => {
+            let agent = require_running(agent)?;
+            if agent.runtime.as_deref() == Some("direct") {
+                return Err(anyhow::anyhow!(
+                    "Agent {} uses the direct runtime and has no container to run code in",
+                    agent.name
+                ));
+            }
+
+            let cmd = code_command(language, code)?;
+            let runtime: &dyn ContainerRuntime = if agent.runtime.as_deref() == Some("exo") {
+                &env.exo_runtime
+            } else {
+                &env.runtime
+            };
+
+            runtime.exec_container(&agent.id, cmd).await?.into_bytes()
+        }
+        StepTask::HttpRequest {
+            url,
+            method,
+            headers,
+            body,
+        } => {
+            let method = reqwest::Method::from_bytes(method.to_uppercase().as_bytes())
+                .map_err(|_| anyhow::anyhow!("Invalid HTTP method: {}", method))?;
 
-            // Check if all dependencies are placed
-            let deps_satisfied = step
-                .depends_on
-                .iter()
-                .all(|dep| placed.contains(dep));
+            let mut request = env
+                .http
+                .request(method, url)
+                .timeout(std::time::Duration::from_secs(timeout_secs));
+            for (name, value) in headers.iter().flatten() {
+                request = request.header(name, value);
+            }
+            request = match body {
+                // Strings are sent verbatim; anything else as a JSON document
+                Some(serde_json::Value::String(raw)) => request.body(raw.clone()),
+                Some(json) => request.json(json),
+                None => request,
+            };
 
-            if deps_satisfied {
-                current_level.push(step.id.clone());
+            let response = request.send().await?;
+            let status = response.status();
+            let bytes = response.bytes().await?;
+            if !status.is_success() {
+                let text = String::from_utf8_lossy(&bytes);
+                return Err(anyhow::anyhow!(
+                    "HTTP {} from {}: {}",
+                    status,
+                    url,
+                    text.chars().take(500).collect::<String>()
+                ));
             }
+            bytes.to_vec()
+        }
+        StepTask::Custom { task_type, .. } => {
+            return Err(anyhow::anyhow!("Unsupported custom task type: {}", task_type));
         }
+        StepTask::Condition { .. } | StepTask::ForEach { .. } | StepTask::Approval { .. } => {
+            return Err(anyhow::anyhow!("Control-flow steps don't run on an agent"));
+        }
+    };
 
-        if current_level.is_empty() {
-            // Circular dependency or error
-            break;
+    let output = format_output(raw, &step.outputs.format)?;
+    Ok(select_outputs(output, &step.outputs.store))
+}
+
+/// Per-attempt timeout for a step, in seconds
+fn step_timeout_secs(step: &WorkflowStep) -> u64 {
+    if step.timeout == 0 {
+        DEFAULT_STEP_TIMEOUT_SECS
+    } else {
+        step.timeout
+    }
+}
+
+/// Require that a step resolved to an agent that is currently running
+fn require_running(agent: Option<&AgentContainer>) -> Result<&AgentContainer> {
+    let agent = agent.ok_or_else(|| anyhow::anyhow!("No agent resolved for step"))?;
+    if agent.status != crate::types::AgentStatus::Running {
+        return Err(anyhow::anyhow!("Agent {} is not running", agent.name));
+    }
+    Ok(agent)
+}
+
+/// Build the in-container command that runs a code snippet
+fn code_command(language: &str, code: &str) -> Result<Vec<String>> {
+    let (interpreter, flag) = match language.to_lowercase().as_str() {
+        "python" | "python3" | "py" => ("python3", "-c"),
+        "javascript" | "js" | "node" => ("node", "-e"),
+        "bash" => ("bash", "-c"),
+        "sh" | "shell" => ("sh", "-c"),
+        other => return Err(anyhow::anyhow!("Unsupported language: {}", other)),
+    };
+    Ok(vec![interpreter.to_string(), flag.to_string(), code.to_string()])
+}
+
+/// Convert raw step output into the format requested by `StepOutputs`
+fn format_output(raw: Vec<u8>, format: &OutputFormat) -> Result<serde_json::Value> {
+    match format {
+        OutputFormat::Json => {
+            let text = String::from_utf8_lossy(&raw);
+            // LLMs like to wrap JSON answers in a markdown code fence
+            let trimmed = text.trim();
+            let unfenced = trimmed
+                .strip_prefix("```json")
+                .or_else(|| trimmed.strip_prefix("```"))
+                .and_then(|rest| rest.strip_suffix("```"))
+                .unwrap_or(trimmed);
+            serde_json::from_str(unfenced.trim())
+                .map_err(|e| anyhow::anyhow!("Step output is not valid JSON: {}", e))
+        }
+        OutputFormat::Text => Ok(serde_json::Value::String(
+            String::from_utf8_lossy(&raw).into_owned(),
+        )),
+        OutputFormat::Binary => {
+            use base64::Engine;
+            Ok(serde_json::Value::String(
+                base64::engine::general_purpose::STANDARD.encode(raw),
+            ))
         }
+    }
+}
 
-        for step_id in &current_level {
-            placed.insert(step_id.clone());
+/// Keep only the keys listed in `StepOutputs.store` (empty = keep everything)
+fn select_outputs(output: serde_json::Value, store: &[String]) -> serde_json::Value {
+    match output {
+        serde_json::Value::Object(map) if !store.is_empty() => serde_json::Value::Object(
+            map.into_iter()
+                .filter(|(key, _)| store.contains(key))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflow::{RetryPolicy, StepInputs, StepOutputs, WorkflowMetadata};
+
+    fn step(id: &str, depends_on: &[&str]) -> WorkflowStep {
+        WorkflowStep {
+            id: id.to_string(),
+            name: id.to_string(),
+            agent_id: None,
+            agent_pattern: None,
+            task: StepTask::Custom {
+                task_type: "noop".to_string(),
+                parameters: HashMap::new(),
+            },
+            depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+            inputs: StepInputs {
+                static_values: HashMap::new(),
+                from_steps: HashMap::new(),
+            },
+            outputs: StepOutputs {
+                store: vec![],
+                format: OutputFormat::Json,
+            },
+            retry_policy: RetryPolicy::default(),
+            timeout: 60,
         }
+    }
+
+    #[test]
+    fn test_topological_order_respects_dependencies() {
+        let workflow = Workflow {
+            id: "wf".to_string(),
+            name: "wf".to_string(),
+            description: String::new(),
+            steps: vec![step("publish", &["review"]), step("draft", &[]), step("review", &["draft"])],
+            strategy: ExecutionStrategy::Sequential,
+            metadata: WorkflowMetadata::default(),
+        };
+
+        let order: Vec<&str> = topological_order(&workflow)
+            .unwrap()
+            .iter()
+            .map(|s| s.id.as_str())
+            .collect();
+        assert_eq!(order, vec!["draft", "review", "publish"]);
+    }
 
-        levels.push(current_level);
+    #[test]
+    fn test_branches_skip_untaken_and_orphaned_steps() {
+        let mut check = step("check", &[]);
+        check.task = StepTask::Condition {
+            expression: "{{draft.score}} >= 7".to_string(),
+            if_true: vec!["publish".to_string()],
+            if_false: vec!["revise".to_string()],
+        };
+        let workflow = Workflow {
+            id: "wf".to_string(),
+            name: "wf".to_string(),
+            description: String::new(),
+            steps: vec![
+                check,
+                step("publish", &["check"]),
+                step("revise", &["check"]),
+                step("resubmit", &["revise"]),
+                step("notify", &["publish", "resubmit"]),
+            ],
+            strategy: ExecutionStrategy::Dag,
+            metadata: WorkflowMetadata::default(),
+        };
+
+        let mut completed = HashMap::new();
+        completed.insert("check".to_string(), Some(serde_json::json!({ "result": true })));
+        let mut branches = Branches::resumed(&workflow, &completed);
+
+        let [_, publish, revise, resubmit, notify] = &workflow.steps[..] else {
+            unreachable!()
+        };
+        assert!(!branches.should_skip(publish));
+        assert!(branches.should_skip(revise));
+        branches.skipped.insert(revise.id.clone());
+        assert!(branches.should_skip(resubmit));
+        branches.skipped.insert(resubmit.id.clone());
+
+        // Joins a taken and a skipped branch, so it still runs
+        assert!(!branches.should_skip(notify));
     }
 
-    levels
+    #[test]
+    fn test_for_each_binds_item_and_index() {
+        let mut outputs = HashMap::new();
+        outputs.insert("research".to_string(), serde_json::json!({ "topics": ["caching", "sharding"] }));
+        let inputs = StepInputs {
+            static_values: HashMap::new(),
+            from_steps: HashMap::new(),
+        };
+        let mut scope = TemplateScope::new(&inputs, &outputs).unwrap();
+        let body = StepTask::Chat {
+            prompt: "{{index}}: summarize {{item}}".to_string(),
+            system_message: None,
+            temperature: None,
+            max_tokens: None,
+        };
+
+        let prompts: Vec<String> = for_each_tasks(&mut scope, "research.topics", &body)
+            .unwrap()
+            .into_iter()
+            .map(|task| match task {
+                StepTask::Chat { prompt, .. } => prompt,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(prompts, vec!["0: summarize caching", "1: summarize sharding"]);
+
+        assert!(for_each_tasks(&mut scope, "research", &body).is_err());
+    }
+
+    #[test]
+    fn test_format_output_strips_code_fence() {
+        let raw = b"```json\n{\"summary\": \"ok\"}\n```".to_vec();
+        let output = format_output(raw, &OutputFormat::Json).unwrap();
+        assert_eq!(output["summary"], "ok");
+
+        assert!(format_output(b"not json".to_vec(), &OutputFormat::Json).is_err());
+        assert_eq!(
+            format_output(b"plain".to_vec(), &OutputFormat::Text).unwrap(),
+            serde_json::json!("plain")
+        );
+    }
+
+    #[test]
+    fn test_select_outputs_filters_keys() {
+        let output = serde_json::json!({"a": 1, "b": 2});
+        let selected = select_outputs(output.clone(), &["a".to_string()]);
+        assert_eq!(selected, serde_json::json!({"a": 1}));
+        assert_eq!(select_outputs(output.clone(), &[]), output);
+    }
+
+    #[tokio::test]
+    async fn test_unresolvable_agent_fails_only_its_step() {
+        let mut draft = step("draft", &[]);
+        draft.agent_id = Some("missing".to_string());
+        let mut check = step("check", &[]);
+        check.task = StepTask::Condition {
+            expression: "true".to_string(),
+            if_true: vec![],
+            if_false: vec![],
+        };
+        let registry = Arc::new(RwLock::new(WorkflowRegistry::new()));
+        registry
+            .write()
+            .await
+            .register_workflow(Workflow {
+                id: "wf".to_string(),
+                name: "wf".to_string(),
+                description: String::new(),
+                steps: vec![draft, check],
+                strategy: ExecutionStrategy::Dag,
+                metadata: WorkflowMetadata::default(),
+            })
+            .unwrap();
+        let runtime = RuntimeClient::unconnected();
+        let executor = WorkflowExecutor::new(
+            Arc::clone(&registry),
+            Arc::new(RwLock::new(Vec::new())),
+            runtime.clone(),
+            runtime,
+            Arc::new(RwLock::new(HashMap::new())),
+        );
+
+        let execution_id = executor
+            .execute_workflow(WorkflowExecutionRequest {
+                workflow_id: "wf".to_string(),
+                parameters: HashMap::new(),
+                strategy: None,
+            })
+            .await
+            .unwrap();
+        let mut execution = None;
+        for _ in 0..100 {
+            let current = registry.read().await.get_execution(&execution_id).cloned().unwrap();
+            if matches!(current.status, ExecutionStatus::Failed) {
+                execution = Some(current);
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let execution = execution.expect("execution should fail");
+
+        let draft = &execution.step_results["draft"];
+        assert!(matches!(draft.status, ExecutionStatus::Failed));
+        assert!(draft.error.as_deref().unwrap().contains("Agent missing not found"));
+        assert!(matches!(execution.step_results["check"].status, ExecutionStatus::Completed));
+    }
+
+    #[tokio::test]
+    async fn test_paused_run_waits_until_resumed_or_cancelled() {
+        let (sender, mut signal) = watch::channel(RunSignal::Pause);
+        let waiter = tokio::spawn(async move {
+            let outcome = wait_while_paused(&mut signal).await;
+            (outcome, signal)
+        });
+
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        sender.send_replace(RunSignal::Run);
+        let (outcome, mut signal) = waiter.await.unwrap();
+        assert!(outcome.is_ok());
+
+        sender.send_replace(RunSignal::Cancel);
+        let err = wait_while_paused(&mut signal).await.unwrap_err();
+        assert!(err.is::<ExecutionCancelled>());
+    }
 }
@@ -0,0 +1,274 @@
+//! Scheduled health checks
+//!
+//! Every running agent with a `health_check` config is probed at its
+//! `interval`: the configured `command` runs in the container via
+//! `exec_container`, or, without one, a WebSocket handshake is made against
+//! the agent's gateway. An agent turns unhealthy after `retries` failed probes
+//! in a row and healthy again on the next success. Each probe is kept in a
+//! short rolling history per agent; `health_status` is persisted when it
+//! flips. An agent that turns unhealthy is stopped and handed to the
+//! restart supervisor if its restart policy would bring it back.
+
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::types::{AgentStatus, ExitReason, HealthCheck, HealthStatus};
+use crate::AppState;
+
+/// Probes kept per agent
+const HISTORY_LIMIT: usize = 100;
+
+/// One probe result
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthSample {
+    pub at: String, // ISO timestamp
+    pub ok: bool,
+    pub duration_ms: u64,
+    pub message: Option<String>,
+}
+
+#[derive(Default)]
+struct Tracker {
+    next_due: Option<Instant>,
+    /// Failed probes in a row
+    failures: u32,
+    history: VecDeque<HealthSample>,
+}
+
+impl Tracker {
+    /// Fold a probe result in, returning the agent's resulting health
+    fn record(&mut self, sample: HealthSample, retries: u32) -> HealthStatus {
+        let retries = retries.max(1);
+        self.failures = if sample.ok { 0 } else { self.failures + 1 };
+
+        let message = match (&sample.message, self.failures) {
+            (_, 0) => "OK".to_string(),
+            (error, n) if n < retries => {
+                format!("Probe failed ({}/{}): {}", n, retries, error.as_deref().unwrap_or("unknown"))
+            }
+            (error, n) => format!(
+                "Unhealthy after {} failed probes: {}",
+                n,
+                error.as_deref().unwrap_or("unknown")
+            ),
+        };
+        let status = HealthStatus {
+            healthy: self.failures < retries,
+            last_check: sample.at.clone(),
+            message: Some(message),
+        };
+
+        self.history.push_back(sample);
+        while self.history.len() > HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+        status
+    }
+}
+
+/// A probe to run, captured so the agents lock isn't held while it runs
+struct DueProbe {
+    id: String,
+    name: String,
+    runtime: Option<String>,
+    gateway_port: u16,
+    check: HealthCheck,
+}
+
+pub struct HealthMonitor {
+    agents: Mutex<HashMap<String, Tracker>>,
+}
+
+impl HealthMonitor {
+    pub fn new() -> Self {
+        Self {
+            agents: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Recent probe results for an agent, oldest first
+    pub fn history(&self, agent_id: &str) -> Vec<HealthSample> {
+        self.agents
+            .lock()
+            .unwrap()
+            .get(agent_id)
+            .map(|t| t.history.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn spawn(state: Arc<AppState>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                run_due_probes(&state).await;
+            }
+        });
+    }
+}
+
+impl Default for HealthMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn run_due_probes(state: &Arc<AppState>) {
+    let now = Instant::now();
+    let due: Vec<DueProbe> = {
+        let containers = state.containers.read().await;
+        let mut trackers = state.health.agents.lock().unwrap();
+        // Forget agents that were removed
+        trackers.retain(|id, _| containers.iter().any(|a| &a.id == id));
+
+        containers
+            .iter()
+            .filter(|a| a.status == AgentStatus::Running && a.runtime.as_deref() != Some("direct"))
+            .filter_map(|a| {
+                let check = a.config.health_check.clone()?;
+                let tracker = trackers.entry(a.id.clone()).or_default();
+                if tracker.next_due.is_some_and(|at| at > now) {
+                    return None;
+                }
+                tracker.next_due = Some(now + Duration::from_secs(check.interval.max(1) as u64));
+                Some(DueProbe {
+                    id: a.id.clone(),
+                    name: a.name.clone(),
+                    runtime: a.runtime.clone(),
+                    gateway_port: a.gateway_port,
+                    check,
+                })
+            })
+            .collect()
+    };
+    if due.is_empty() {
+        return;
+    }
+
+    let samples = futures::future::join_all(due.iter().map(|probe_target| probe(state, probe_target))).await;
+
+    let mut to_stop = Vec::new();
+    {
+        let mut containers = state.containers.write().await;
+        let mut trackers = state.health.agents.lock().unwrap();
+        for (target, sample) in due.iter().zip(samples) {
+            let (Some(agent), Some(tracker)) = (
+                containers.iter_mut().find(|a| a.id == target.id),
+                trackers.get_mut(&target.id),
+            ) else {
+                continue;
+            };
+
+            state.metrics.record_health_probe(&agent.name, sample.ok);
+            let status = tracker.record(sample, target.check.retries);
+            let previous = agent.health_status.as_ref().map(|s| s.healthy);
+            agent.health_status = Some(status.clone());
+
+            if previous != Some(status.healthy) {
+                match (previous, status.healthy) {
+                    (Some(false), true) => tracing::info!("Agent {} is healthy again", agent.name),
+                    (_, false) => tracing::warn!(
+                        "Agent {} is unhealthy: {}",
+                        agent.name,
+                        status.message.as_deref().unwrap_or_default()
+                    ),
+                    _ => {}
+                }
+                if let Err(e) = crate::storage::upsert_agent(&crate::storage::to_stored_agent(agent)) {
+                    tracing::warn!("Failed to persist health status for {}: {}", agent.name, e);
+                }
+            }
+            if previous != Some(false)
+                && !status.healthy
+                && state.supervisor.would_restart(agent, ExitReason::Unhealthy)
+            {
+                to_stop.push((target, status.message.clone()));
+            }
+        }
+    }
+
+    // Stop outside the lock, then let the supervisor restart it
+    for (target, message) in to_stop {
+        if let Err(e) = state.runtime_for(target.runtime.as_deref()).stop_container(&target.name).await {
+            tracing::warn!("Failed to stop unhealthy agent {}: {}", target.name, e);
+        }
+        let mut containers = state.containers.write().await;
+        if let Some(agent) = containers.iter_mut().find(|a| a.id == target.id) {
+            agent.status = AgentStatus::Error;
+            state.supervisor.record_exit(agent, ExitReason::Unhealthy, message);
+        }
+    }
+}
+
+/// Run one probe under the check's timeout
+async fn probe(state: &AppState, target: &DueProbe) -> HealthSample {
+    let started = Instant::now();
+    let timeout = Duration::from_secs(target.check.timeout.max(1) as u64);
+
+    let result = tokio::time::timeout(timeout, async {
+        match &target.check.command {
+            Some(command) => state.runtime_for(target.runtime.as_deref())
+                .exec_container(&target.name, command.clone())
+                .await
+                .map(|_| ()),
+            None => {
+                let url = format!("ws://127.0.0.1:{}", target.gateway_port);
+                let (mut socket, _) = tokio_tungstenite::connect_async(&url).await?;
+                let _ = socket.close(None).await;
+                Ok::<(), anyhow::Error>(())
+            }
+        }
+    })
+    .await
+    .unwrap_or_else(|_| Err(anyhow::anyhow!("timed out after {}s", timeout.as_secs())));
+
+    HealthSample {
+        at: Utc::now().to_rfc3339(),
+        ok: result.is_ok(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        message: result.err().map(|e| e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(ok: bool) -> HealthSample {
+        HealthSample {
+            at: Utc::now().to_rfc3339(),
+            ok,
+            duration_ms: 5,
+            message: (!ok).then(|| "connection refused".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_retries_before_unhealthy() {
+        let mut tracker = Tracker::default();
+
+        assert!(tracker.record(sample(false), 3).healthy);
+        let status = tracker.record(sample(false), 3);
+        assert!(status.healthy);
+        assert_eq!(status.message.as_deref(), Some("Probe failed (2/3): connection refused"));
+
+        let status = tracker.record(sample(false), 3);
+        assert!(!status.healthy);
+
+        assert!(tracker.record(sample(true), 3).healthy);
+        assert_eq!(tracker.failures, 0);
+        assert_eq!(tracker.history.len(), 4);
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut tracker = Tracker::default();
+        for _ in 0..HISTORY_LIMIT + 10 {
+            tracker.record(sample(true), 1);
+        }
+        assert_eq!(tracker.history.len(), HISTORY_LIMIT);
+    }
+}
@@ -1,32 +1,49 @@
 use std::collections::HashMap;
 mod agent_comms;
 mod andor;
+mod ann_index;
 mod chat_db;
+mod circuit_breaker;
 mod direct_llm;
 mod api;
 mod auth;
+mod budgets;
 mod config;
 mod container;
 mod containment;
+mod context_window;
+mod embeddings;
+mod health_monitor;
 mod inference;
+mod memory_lifecycle;
+mod metrics;
+mod metrics_history;
 mod network;
 mod rpc;
 mod secret_manager;
 mod shared_memory;
 mod snapshots;
+mod step_condition;
+mod step_template;
 mod storage;
+mod supervisor;
 mod teams;
 mod templates;
 mod types;
+mod tools;
+mod usage;
 mod validation;
 mod volume_attachment;
 mod workflow;
+mod workflow_files;
+mod workflow_store;
+mod workflow_triggers;
 mod executor;`nmod briefing_engine;`nmod orchestration_engine;`nmod session_manager;`nmod briefing_engine;`nmod orchestration_engine;`nmod session_manager;
 // mod code_index;  // TODO: fix dependencies
 
 use axum::http::{header, HeaderValue, Method};
 use axum::{
-    routing::{delete, get, post},
+    routing::{delete, get, post, put},
     Router,
 };
 use container::ContainerRuntime;
@@ -50,7 +67,9 @@ pub struct AppState {
     pub secrets: SecretsManager,
     pub snapshots: SnapshotManager,
     pub teams: teams::TeamRegistry,
-    pub api_keys: RwLock<HashMap<String, String>>,
+    /// LLM intent classification for team routers (cached across requests)
+    pub router_llm: Arc<teams::LlmClassifier>,
+    pub api_keys: Arc<RwLock<HashMap<String, String>>>,
     pub data_dir: std::path::PathBuf,
     pub auth: RwLock<AuthManager>,
     /// Named volumes that can be attached to agents
@@ -63,13 +82,45 @@ pub struct AppState {
     pub rpc_client: rpc::RpcClient,
     /// Workflow registry for managing workflow definitions
     pub workflows: std::sync::Arc<tokio::sync::RwLock<workflow::WorkflowRegistry>>,
+    /// Counters, histograms and sampled container stats for `/metrics`
+    pub metrics: Arc<metrics::Metrics>,
+    /// Token usage and cost ledger for LLM calls
+    pub usage: Arc<usage::UsageLedger>,
+    /// Per-agent, per-user and per-class chat budgets and rate limits
+    pub budgets: budgets::Budgets,
+    /// Health of upstream LLM providers, shared by all direct-runtime agents
+    pub providers: circuit_breaker::CircuitBreaker,
+    /// Downsampled per-agent resource history
+    pub metrics_history: Arc<metrics_history::MetricsHistory>,
+    /// Scheduled health checks and their recent history
+    pub health: Arc<health_monitor::HealthMonitor>,
+    /// Restarts crashed agents according to their restart policy
+    pub supervisor: Arc<supervisor::Supervisor>,
     /// Workflow executor for running workflows
     pub executor: std::sync::Arc<executor::WorkflowExecutor>,
+    /// Starts workflows from schedules, events and webhooks
+    pub triggers: std::sync::Arc<workflow_triggers::WorkflowTriggers>,
     /// Native inference service manager (optional)
     pub inference: Option<inference::InferenceManager>,
     /// Chat database — users, classes, conversations, LTI mapping.
     /// Message transcripts stay in JSONL; this holds metadata + indexes.
     pub chat_db: std::sync::Arc<chat_db::ChatDb>,
+    /// Cross-agent memory and task queue, if its database opened
+    pub shared_memory: Option<std::sync::Arc<shared_memory::SharedMemory>>,
+    /// Embeds shared memories and search queries, if `[memory-embeddings]`
+    /// is configured
+    pub memory_embedder: Option<Arc<embeddings::EmbeddingGenerator>>,
+}
+
+impl AppState {
+    /// The runtime an agent's container lives in
+    pub fn runtime_for(&self, runtime: Option<&str>) -> &dyn ContainerRuntime {
+        if runtime == Some("exo") {
+            &self.exo_runtime
+        } else {
+            &self.runtime
+        }
+    }
 }
 
 fn load_api_keys(data_dir: &std::path::Path) -> HashMap<String, String> {
@@ -127,6 +178,7 @@ async fn sync_container_states(state: &AppState) -> anyhow::Result<()> {
 
     let mut containers = state.containers.write().await;
     let mut has_changes = false;
+    let mut errored = Vec::new();
 
     for agent in containers.iter_mut() {
         let actual_status = runtime_map_by_name.get(&agent.name);
@@ -165,6 +217,17 @@ async fn sync_container_states(state: &AppState) -> anyhow::Result<()> {
                         );
                         agent.status = actual_status.map(|s| s.clone()).unwrap_or(AgentStatus::Error);
                         has_changes = true;
+                        let reason = match agent.status {
+                            AgentStatus::Error => types::ExitReason::Crashed,
+                            _ => types::ExitReason::Exited,
+                        };
+                        state.supervisor.record_exit(agent, reason, None);
+                        if agent.status == AgentStatus::Error {
+                            errored.push(workflow_triggers::TriggerEvent::AgentError {
+                                agent_id: agent.id.clone(),
+                                agent_name: agent.name.clone(),
+                            });
+                        }
                     }
                 }
             }
@@ -200,6 +263,7 @@ async fn sync_container_states(state: &AppState) -> anyhow::Result<()> {
                     );
                     agent.status = AgentStatus::Stopped;
                     has_changes = true;
+                    state.supervisor.record_exit(agent, types::ExitReason::Disappeared, None);
                 }
             }
         }
@@ -225,6 +289,12 @@ async fn sync_container_states(state: &AppState) -> anyhow::Result<()> {
             }
         }
     }
+    drop(containers);
+
+    // Let workflows react to agents that just failed
+    for event in errored {
+        state.triggers.dispatch(event).await;
+    }
 
     Ok(())
 }
@@ -364,7 +434,8 @@ async fn main() -> anyhow::Result<()> {
             project: None,
             tags: vec![],
             restart_policy: Default::default(),
-            health_status: None,
+            health_status: stored.health_status,
+            restarts: Default::default(),
             runtime: stored.runtime,
             gateway_port: stored
                 .gateway_port
@@ -405,26 +476,105 @@ async fn main() -> anyhow::Result<()> {
     let volumes = load_volumes(&data_dir);
     tracing::info!("Loaded {} volumes", volumes.len());
 
-    // Initialize workflow registry
-    let workflows = std::sync::Arc::new(tokio::sync::RwLock::new(workflow::WorkflowRegistry::new()));
-    tracing::info!("Workflow registry initialized");
+    // Initialize workflow registry, reloading definitions + history from disk
+    let workflow_store = workflow_store::WorkflowStore::open(&data_dir.join("workflows.db"))?;
+    let workflow_registry = workflow::WorkflowRegistry::with_store(workflow_store)?;
+    tracing::info!(
+        "Workflow registry initialized ({} workflows, {} executions)",
+        workflow_registry.list_workflows().len(),
+        workflow_registry.executions.len()
+    );
+    let workflows = std::sync::Arc::new(tokio::sync::RwLock::new(workflow_registry));
+
+    // Workflows defined in workflows/*.yaml, kept in sync with the files
+    let mut workflow_files =
+        workflow_files::WorkflowFiles::new("workflows", std::sync::Arc::clone(&workflows));
+    let workflow_files_count = workflow_files.reload().await;
+    tracing::info!("Loaded {} workflow files", workflow_files_count);
+    workflow_files.spawn();
+
+    // Open chat DB — stored alongside the existing data files.
+    let chat_db_path = data_dir.join("chat.db");
+    let chat_db = std::sync::Arc::new(
+        chat_db::ChatDb::open(&chat_db_path)
+            .expect("failed to open chat database"),
+    );
+    tracing::info!("Chat database initialized at {:?}", chat_db_path);
+
+    // Token usage is recorded in the chat DB and counted in /metrics
+    let metrics = Arc::new(metrics::Metrics::new());
+    let usage = Arc::new(usage::UsageLedger::new(
+        Arc::clone(&chat_db),
+        config.prices.clone(),
+        Arc::clone(&metrics),
+    ));
 
     // Initialize workflow executor
     let rpc_client = rpc::create_rpc_client();
     let containers_arc = std::sync::Arc::new(RwLock::new(merged_agents));
+    let api_keys = Arc::new(RwLock::new(load_api_keys(&data_dir)));
     let executor = std::sync::Arc::new(executor::WorkflowExecutor::new(
         std::sync::Arc::clone(&workflows),
         std::sync::Arc::clone(&containers_arc),
-    ));
+        runtime.clone_runtime_client(),
+        exo_runtime.clone_runtime_client(),
+        Arc::clone(&api_keys),
+    )
+    .with_usage(Arc::clone(&usage)));
     tracing::info!("Workflow executor initialized");
 
-    // Open chat DB — stored alongside the existing data files.
-    let chat_db_path = data_dir.join("chat.db");
-    let chat_db = std::sync::Arc::new(
-        chat_db::ChatDb::open(&chat_db_path)
-            .expect("failed to open chat database"),
+    let router_llm = Arc::new(
+        teams::LlmClassifier::new(Arc::clone(&containers_arc), Arc::clone(&api_keys))
+            .with_usage(Arc::clone(&usage)),
     );
-    tracing::info!("Chat database initialized at {:?}", chat_db_path);
+
+    // Workflow triggers. The task-queue trigger watches the shared-memory DB,
+    // so it is only available if that opens.
+    let triggers = std::sync::Arc::new(workflow_triggers::WorkflowTriggers::new(
+        std::sync::Arc::clone(&workflows),
+        std::sync::Arc::clone(&executor),
+    ));
+    let shared_memory = shared_memory::SharedMemory::with_config(shared_memory::SharedMemoryConfig {
+        database_path: data_dir.join("shared").join("memory.db"),
+        dedup_threshold: config.memory_lifecycle.dedup_threshold,
+        ..Default::default()
+    })
+    .map(std::sync::Arc::new)
+    .map_err(|e| tracing::warn!("Shared memory unavailable, memory and task endpoints, tools and triggers disabled: {}", e))
+    .ok();
+    triggers.spawn(shared_memory.clone());
+    tracing::info!("Workflow triggers started");
+
+    let memory_embedder = match (&config.memory_embeddings, &shared_memory) {
+        (Some(embeddings), Some(memory)) => {
+            let openai_key = api_keys
+                .read()
+                .await
+                .get("openai")
+                .cloned()
+                .or_else(|| std::env::var("OPENAI_API_KEY").ok());
+            let ollama = config.model_servers.ollama.as_ref().map(|s| s.endpoint.as_str());
+            Some(embeddings::start_memory_embedder(
+                embeddings,
+                ollama,
+                openai_key,
+                Arc::clone(memory),
+            ))
+        }
+        _ => None,
+    };
+    if let Some(memory) = &shared_memory {
+        memory_lifecycle::spawn(
+            &config.memory_lifecycle,
+            Arc::clone(memory),
+            memory_embedder.clone(),
+            Arc::clone(&api_keys),
+            Arc::clone(&usage),
+        );
+    }
+
+    let metrics_history =
+        Arc::new(metrics_history::MetricsHistory::open(&data_dir.join("metrics.db"))?);
 
     let state = Arc::new(AppState {
         config,
@@ -436,7 +586,8 @@ async fn main() -> anyhow::Result<()> {
         secrets,
         snapshots,
         teams,
-        api_keys: RwLock::new(load_api_keys(&data_dir)),
+        router_llm,
+        api_keys,
         data_dir,
         auth: RwLock::new(auth_manager),
         volumes: RwLock::new(volumes),
@@ -444,9 +595,19 @@ async fn main() -> anyhow::Result<()> {
         agent_index: RwLock::new(agent_index),
         rpc_client,
         workflows,
+        metrics,
+        usage,
+        budgets: budgets::Budgets::new(Arc::clone(&chat_db)),
+        providers: circuit_breaker::CircuitBreaker::new(),
+        metrics_history,
+        health: Arc::new(health_monitor::HealthMonitor::new()),
+        supervisor: Arc::new(supervisor::Supervisor::new()),
         executor,
+        triggers,
         inference: inference_manager,
         chat_db,
+        shared_memory,
+        memory_embedder,
     });
 
     // Create the protected API routes with auth middleware
@@ -457,7 +618,16 @@ async fn main() -> anyhow::Result<()> {
         .route("/api/agents/:id/logs", get(api::get_logs))
         .route("/api/agents/:id/logs/stream", get(api::logs_websocket))
         .route("/api/agents/:id/metrics", get(api::get_metrics))
+        .route("/api/agents/:id/metrics/history", get(api::get_metrics_history))
+        .route("/api/usage", get(api::get_usage))
+        .route("/api/budgets", get(api::list_budgets))
+        .route("/api/providers/circuits", get(api::provider_circuits))
+        .route(
+            "/api/budgets/:scope/:scope_id",
+            delete(api::delete_budget).put(api::set_budget),
+        )
         .route("/api/agents/:id/health", post(api::run_health_check))
+        .route("/api/agents/:id/health/history", get(api::health_history))
         .route(
             "/api/agents/:id/secrets",
             get(api::list_secrets).post(api::set_secret),
@@ -510,6 +680,17 @@ async fn main() -> anyhow::Result<()> {
         .route("/api/agents/tailscale", get(api::list_agents_with_tailscale))
         .route("/api/discovery/trigger", post(api::trigger_discovery))
         .route("/api/services/registry", get(api::get_service_registry))
+        // Shared memory, task queue and heartbeats (agent token or admin/teacher)
+        .route("/api/memory", get(api::list_memories).post(api::store_memory))
+        .route("/api/memory/search", post(api::search_memories))
+        .route("/api/memory/:id", get(api::get_memory).delete(api::delete_memory))
+        .route("/api/tasks", get(api::list_tasks).post(api::push_task))
+        .route("/api/tasks/pop", post(api::pop_task))
+        .route("/api/tasks/:id", put(api::update_task))
+        .route(
+            "/api/agents/:id/heartbeat",
+            get(api::get_agent_heartbeat).post(api::agent_heartbeat),
+        )
         // Conversation History
         .route("/api/agents/:id/sessions", get(api::list_agent_sessions))
         .route("/api/agents/:id/sessions/:session_id", get(api::get_session_messages))
@@ -529,6 +710,7 @@ async fn main() -> anyhow::Result<()> {
         // Global metrics
         .route("/api/metrics", get(api::get_all_metrics))
         .route("/api/system/stats", get(api::get_system_stats))
+        .route("/metrics", get(api::prometheus_metrics))
         // Templates
         .route("/api/templates", get(api::list_templates))
         // Tags
@@ -565,9 +747,16 @@ async fn main() -> anyhow::Result<()> {
         // Workflows
         .route("/api/workflows", post(api::create_workflow).get(api::list_workflows))
         .route("/api/workflows/:id", get(api::get_workflow))
+        .route("/api/workflows/:id/export", get(api::export_workflow))
         .route("/api/workflows/:id/execute", post(api::execute_workflow))
         .route("/api/workflows/:id/executions", get(api::list_workflow_executions))
         .route("/api/workflows/executions/:id", get(api::get_workflow_execution))
+        .route("/api/workflows/executions/:id/cancel", post(api::cancel_workflow_execution))
+        .route("/api/workflows/executions/:id/pause", post(api::pause_workflow_execution))
+        .route("/api/workflows/executions/:id/resume", post(api::resume_workflow_execution))
+        .route("/api/workflows/executions/:id/approvals/:step_id", post(api::decide_workflow_approval))
+        .route("/api/workflows/approvals", get(api::list_workflow_approvals))
+        .route("/api/workflows/:id/triggers/history", get(api::workflow_trigger_history))
         // Import
         .route("/api/agents/import", post(api::import_agent))
         // Runtime status
@@ -589,9 +778,12 @@ async fn main() -> anyhow::Result<()> {
         .route("/auth/user/register", post(auth::user_register))
         .route("/auth/user/login", post(auth::user_login))
         .route("/api/me", get(auth::me))
+        // Workflow webhooks authenticate with the trigger's own secret
+        .route("/api/workflows/:id/webhook", post(api::workflow_webhook))
         // WebSocket chat routes (handle their own auth via query parameter)
         .route("/api/agents/:id/chat", get(api::chat_websocket))
-        .route("/api/teams/:id/chat", get(api::team_chat_websocket));
+        .route("/api/teams/:id/chat", get(api::team_chat_websocket))
+        .route("/api/workflows/executions/:id/stream", get(api::workflow_execution_stream));
     // Configure CORS to allow requests from Tauri app and development servers
     // Note: When using allow_credentials(true), we cannot use wildcard origin
     let cors = CorsLayer::new()
@@ -641,6 +833,7 @@ async fn main() -> anyhow::Result<()> {
     let app = Router::new()
         .merge(public_routes)
         .merge(protected_routes)
+        .layer(axum::middleware::from_fn_with_state(state.clone(), metrics::track_requests))
         .layer(cors)
         .with_state(state.clone());
 
@@ -666,6 +859,10 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("   GET /auth/status to check auth configuration");
     tracing::info!("   POST /auth/login to authenticate");
 
+    supervisor::Supervisor::spawn(Arc::clone(&state_clone));
+    health_monitor::HealthMonitor::spawn(Arc::clone(&state_clone));
+    metrics::Metrics::spawn(Arc::clone(&state_clone));
+
     // Spawn background task to sync container states every 10 seconds
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(10));
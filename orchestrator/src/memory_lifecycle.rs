@@ -0,0 +1,429 @@
+//! Background upkeep of shared memory
+//!
+//! The sweeper deletes memories whose TTL has run out and, in orgs with a
+//! retention period, those older than it. Consolidation is opt-in: every
+//! `interval-hours` it groups an org's old memories into clusters of
+//! similar embeddings and has an LLM summarize each cluster into a single
+//! memory, which replaces the cluster. Near-duplicates on insert are merged
+//! by `SharedMemory::store_memory` itself.
+
+use anyhow::Result;
+use chrono::{Duration as ChronoDuration, Utc};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+use crate::config::{MemoryConsolidationConfig, MemoryLifecycleConfig};
+use crate::embeddings::{EmbeddingGenerator, TfIdfVectorizer};
+use crate::shared_memory::{Memory, MemoryFilter, MemoryQuery, NewMemory, SharedMemory};
+use crate::types::{AgentConfig, AgentContainer, AgentStatus};
+use crate::usage::UsageLedger;
+
+/// Agent ID consolidated memories are stored under
+pub const CONSOLIDATOR_AGENT: &str = "memory-consolidator";
+
+/// Most old memories read per org and run
+const CONSOLIDATION_BATCH: usize = 1000;
+
+const SUMMARY_PROMPT: &str = "You consolidate the memories of a team of AI agents. \
+You are given several related memories, oldest first. Write one memory that keeps every \
+fact, decision and open question in them, drops repetition, and prefers the newer memory \
+where they disagree. Reply with the consolidated memory only, in plain prose.";
+
+/// Start the sweeper and, if configured, consolidation
+pub fn spawn(
+    config: &MemoryLifecycleConfig,
+    memory: Arc<SharedMemory>,
+    embedder: Option<Arc<EmbeddingGenerator>>,
+    api_keys: Arc<RwLock<HashMap<String, String>>>,
+    usage: Arc<UsageLedger>,
+) {
+    let sweeper_config = config.clone();
+    let sweeper_memory = Arc::clone(&memory);
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(sweeper_config.sweep_interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+            sweep(&sweeper_config, &sweeper_memory);
+        }
+    });
+
+    let Some(consolidation) = config.consolidation.clone() else {
+        return;
+    };
+    tracing::info!(
+        "[memory] Consolidating memories older than {} days every {} hours",
+        consolidation.after_days,
+        consolidation.interval_hours
+    );
+    let consolidator = Consolidator {
+        agent: consolidator_agent(&consolidation),
+        config: consolidation,
+        memory,
+        embedder,
+        api_keys,
+        usage,
+        http: reqwest::Client::new(),
+    };
+    tokio::spawn(async move {
+        let period = Duration::from_secs(consolidator.config.interval_hours.max(1) * 3600);
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            match consolidator.run().await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("[memory] Consolidated {} memories", n),
+                Err(e) => tracing::warn!("[memory] Consolidation failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Delete expired memories and those past their org's retention period
+fn sweep(config: &MemoryLifecycleConfig, memory: &SharedMemory) {
+    match memory.delete_expired() {
+        Ok(0) => {}
+        Ok(n) => tracing::info!("[memory] Deleted {} expired memories", n),
+        Err(e) => tracing::warn!("[memory] Failed to delete expired memories: {}", e),
+    }
+
+    if config.retention_days.is_empty() && config.default_retention_days.is_none() {
+        return;
+    }
+    let orgs = match memory.orgs() {
+        Ok(orgs) => orgs,
+        Err(e) => {
+            tracing::warn!("[memory] Failed to list orgs for retention: {}", e);
+            return;
+        }
+    };
+    for org in orgs {
+        let Some(days) = config.retention_for(&org) else { continue };
+        let cutoff = Utc::now() - ChronoDuration::days(days as i64);
+        match memory.delete_older_than(&org, cutoff) {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("[memory] Deleted {} memories past {} days in org {}", n, days, org),
+            Err(e) => tracing::warn!("[memory] Failed to apply retention in org {}: {}", org, e),
+        }
+    }
+}
+
+struct Consolidator {
+    config: MemoryConsolidationConfig,
+    agent: AgentContainer,
+    memory: Arc<SharedMemory>,
+    embedder: Option<Arc<EmbeddingGenerator>>,
+    api_keys: Arc<RwLock<HashMap<String, String>>>,
+    usage: Arc<UsageLedger>,
+    http: reqwest::Client,
+}
+
+impl Consolidator {
+    /// Consolidate every configured org. Returns how many memories were
+    /// folded into summaries.
+    async fn run(&self) -> Result<usize> {
+        let orgs = if self.config.orgs.is_empty() {
+            self.memory.orgs()?
+        } else {
+            self.config.orgs.clone()
+        };
+
+        let mut consolidated = 0;
+        for org in orgs {
+            consolidated += self.run_org(&org).await?;
+        }
+        Ok(consolidated)
+    }
+
+    async fn run_org(&self, org: &str) -> Result<usize> {
+        let cutoff = Utc::now() - ChronoDuration::days(self.config.after_days as i64);
+        let old: Vec<Memory> = self
+            .memory
+            .search(
+                org,
+                &MemoryQuery {
+                    filter: MemoryFilter {
+                        created_before: Some(cutoff),
+                        ..Default::default()
+                    },
+                    limit: CONSOLIDATION_BATCH,
+                    ..Default::default()
+                },
+            )?
+            .into_iter()
+            .map(|r| r.memory)
+            .collect();
+
+        let mut consolidated = 0;
+        for cluster in clusters(
+            &old,
+            self.config.similarity,
+            self.config.min_cluster.max(2),
+            self.config.max_cluster,
+        ) {
+            let members: Vec<&Memory> = cluster.iter().map(|&i| &old[i]).collect();
+            let summary = self.summarize(&members).await?;
+            let consolidated_memory = self.summary_memory(org, &members, summary).await;
+            let ids: Vec<i64> = members.iter().map(|m| m.id).collect();
+            let id = self.memory.replace_memories(org, &ids, &consolidated_memory)?;
+            tracing::debug!("[memory] Consolidated memories {:?} in org {} into {}", ids, org, id);
+            consolidated += ids.len();
+        }
+        Ok(consolidated)
+    }
+
+    async fn summarize(&self, members: &[&Memory]) -> Result<String> {
+        let (answer, usage) = crate::direct_llm::complete(
+            &self.http,
+            &self.api_keys,
+            &self.agent,
+            Some(SUMMARY_PROMPT),
+            &summary_request(members),
+            Some(0.2),
+            Some(1024),
+        )
+        .await?;
+        if let Some(usage) = &usage {
+            self.usage.record(&self.agent, None, usage);
+        }
+        parse_summary(&answer).ok_or_else(|| anyhow::anyhow!("the model returned an empty summary"))
+    }
+
+    /// The memory replacing a cluster. It is embedded by the built-in
+    /// embedder if that makes the cluster's kind of vector, and otherwise
+    /// placed at the cluster's centroid.
+    async fn summary_memory(&self, org: &str, members: &[&Memory], content: String) -> NewMemory {
+        let model = members[0].embedding_model.clone();
+        let embedder = self
+            .embedder
+            .as_ref()
+            .filter(|e| model.as_deref() == Some(e.model_name().as_str()));
+        let embedding = match embedder {
+            Some(embedder) => match embedder.embed(&content).await {
+                Ok(embedding) => embedding,
+                Err(e) => {
+                    tracing::warn!("[memory] Failed to embed a summary, using the centroid: {}", e);
+                    centroid(members)
+                }
+            },
+            None => centroid(members),
+        };
+
+        let agents: BTreeSet<&str> = members.iter().map(|m| m.agent_id.as_str()).collect();
+        let expires_at = members
+            .iter()
+            .map(|m| m.expires_at)
+            .collect::<Option<Vec<_>>>()
+            .and_then(|times| times.into_iter().max());
+        NewMemory {
+            org: Some(org.to_string()),
+            agent_id: CONSOLIDATOR_AGENT.to_string(),
+            content,
+            embedding: Some(embedding),
+            embedding_model: model,
+            metadata: Some(serde_json::json!({
+                "consolidated_from": members.iter().map(|m| m.id).collect::<Vec<_>>(),
+                "agents": agents,
+            })),
+            expires_at,
+        }
+    }
+}
+
+/// The direct-runtime agent summaries are requested as
+fn consolidator_agent(config: &MemoryConsolidationConfig) -> AgentContainer {
+    let mut agent_config = AgentConfig {
+        llm_provider: config.provider.clone(),
+        llm_model: config.model.clone(),
+        ..Default::default()
+    };
+    if let Some(url) = &config.base_url {
+        agent_config.env_vars.insert("LLM_BASE_URL".to_string(), url.clone());
+    }
+    if let Some(format) = &config.api_format {
+        agent_config.env_vars.insert("LLM_API_FORMAT".to_string(), format.clone());
+    }
+
+    AgentContainer {
+        id: CONSOLIDATOR_AGENT.to_string(),
+        name: CONSOLIDATOR_AGENT.to_string(),
+        status: AgentStatus::Running,
+        config: agent_config,
+        runtime: Some("direct".to_string()),
+        ..Default::default()
+    }
+}
+
+/// Group memories into clusters to summarize, as indices into `memories`.
+///
+/// Oldest first, each memory not yet clustered seeds a cluster of the
+/// memories at least `similarity` to it, with the same kind of vector, up
+/// to `max` in all. Clusters smaller than `min` are dropped and memories
+/// without an embedding are never clustered.
+fn clusters(memories: &[Memory], similarity: f32, min: usize, max: usize) -> Vec<Vec<usize>> {
+    let mut order: Vec<usize> = (0..memories.len())
+        .filter(|&i| memories[i].embedding.as_ref().is_some_and(|e| !e.is_empty()))
+        .collect();
+    order.sort_by_key(|&i| (memories[i].created_at, memories[i].id));
+
+    let mut clustered = vec![false; memories.len()];
+    let mut clusters = Vec::new();
+    for (position, &seed) in order.iter().enumerate() {
+        if clustered[seed] {
+            continue;
+        }
+        let seed_memory = &memories[seed];
+        let seed_embedding = seed_memory.embedding.as_deref().unwrap_or_default();
+
+        let mut cluster = vec![seed];
+        for &other in &order[position + 1..] {
+            if cluster.len() >= max {
+                break;
+            }
+            let candidate = &memories[other];
+            let embedding = candidate.embedding.as_deref().unwrap_or_default();
+            if clustered[other]
+                || candidate.embedding_model != seed_memory.embedding_model
+                || embedding.len() != seed_embedding.len()
+            {
+                continue;
+            }
+            if TfIdfVectorizer::cosine_similarity(seed_embedding, embedding) >= similarity {
+                cluster.push(other);
+            }
+        }
+
+        if cluster.len() >= min {
+            for &i in &cluster {
+                clustered[i] = true;
+            }
+            clusters.push(cluster);
+        }
+    }
+    clusters
+}
+
+/// The user message asking for a cluster's summary
+fn summary_request(members: &[&Memory]) -> String {
+    let mut request = String::from("Memories to consolidate:\n");
+    for (i, memory) in members.iter().enumerate() {
+        request.push_str(&format!(
+            "\n[{}] {} by {}:\n{}\n",
+            i + 1,
+            memory.created_at.format("%Y-%m-%d"),
+            memory.agent_id,
+            memory.content.trim()
+        ));
+    }
+    request
+}
+
+/// The summary in a model's answer, without any code fence around it
+fn parse_summary(answer: &str) -> Option<String> {
+    let mut summary = answer.trim();
+    if let Some(fenced) = summary.strip_prefix("```") {
+        let body = fenced.split_once('\n').map_or("", |(_, body)| body);
+        summary = body.trim_end().strip_suffix("```").unwrap_or(body).trim();
+    }
+    (!summary.is_empty()).then(|| summary.to_string())
+}
+
+/// Normalized mean of the members' embeddings
+fn centroid(members: &[&Memory]) -> Vec<f32> {
+    let dim = members[0].embedding.as_ref().map_or(0, Vec::len);
+    let mut sum = vec![0.0f32; dim];
+    for embedding in members.iter().filter_map(|m| m.embedding.as_ref()) {
+        for (s, x) in sum.iter_mut().zip(embedding) {
+            *s += x;
+        }
+    }
+    let norm = sum.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        sum.iter_mut().for_each(|x| *x /= norm);
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeZone};
+
+    fn memory(id: i64, days_ago: i64, embedding: Option<Vec<f32>>) -> Memory {
+        let created_at: DateTime<Utc> = Utc.with_ymd_and_hms(2026, 1, 31, 0, 0, 0).unwrap()
+            - ChronoDuration::days(days_ago);
+        Memory {
+            id,
+            org: "default".to_string(),
+            agent_id: format!("agent-{}", id % 2),
+            content: format!("memory {}", id),
+            embedding_dim: embedding.as_ref().map(Vec::len),
+            embedding,
+            embedding_model: Some("test".to_string()),
+            metadata: None,
+            created_at,
+            updated_at: created_at,
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn test_similar_memories_are_clustered_oldest_first() {
+        let memories = vec![
+            memory(1, 10, Some(vec![1.0, 0.0, 0.0])),
+            memory(2, 20, Some(vec![0.95, 0.05, 0.0])),
+            memory(3, 5, Some(vec![0.0, 1.0, 0.0])),
+            memory(4, 30, Some(vec![0.9, 0.1, 0.0])),
+            memory(5, 15, None),
+            memory(6, 25, Some(vec![0.0, 0.98, 0.02])),
+            memory(7, 1, Some(vec![0.97, 0.0, 0.03])),
+        ];
+
+        let found = clusters(&memories, 0.9, 2, 20);
+        let ids: Vec<Vec<i64>> = found
+            .iter()
+            .map(|c| c.iter().map(|&i| memories[i].id).collect())
+            .collect();
+        assert_eq!(ids, vec![vec![4, 2, 1, 7], vec![6, 3]]);
+
+        // The cap splits a cluster; the remainder still has to reach `min`
+        let capped = clusters(&memories, 0.9, 2, 3);
+        assert_eq!(capped.len(), 2);
+        assert_eq!(capped[0].len(), 3);
+        assert!(clusters(&memories, 0.9, 5, 20).is_empty());
+
+        // Vectors of another model never join
+        let mut other_model = memories.clone();
+        other_model[1].embedding_model = Some("other".to_string());
+        let found = clusters(&other_model, 0.9, 2, 20);
+        assert!(found.iter().all(|c| !c.contains(&1)));
+    }
+
+    #[test]
+    fn test_summary_request_and_answer() {
+        let a = memory(1, 3, None);
+        let b = memory(2, 2, None);
+        let request = summary_request(&[&a, &b]);
+        assert!(request.contains("[1] 2026-01-28 by agent-1:\nmemory 1"));
+        assert!(request.contains("[2] 2026-01-29 by agent-0:\nmemory 2"));
+
+        assert_eq!(parse_summary("  The team chose Postgres.\n").as_deref(), Some("The team chose Postgres."));
+        assert_eq!(
+            parse_summary("```text\nThe team chose Postgres.\n```").as_deref(),
+            Some("The team chose Postgres.")
+        );
+        assert_eq!(parse_summary("``` \n```"), None);
+        assert_eq!(parse_summary("   "), None);
+    }
+
+    #[test]
+    fn test_centroid_is_normalized() {
+        let a = memory(1, 1, Some(vec![1.0, 0.0]));
+        let b = memory(2, 1, Some(vec![0.0, 1.0]));
+        let c = centroid(&[&a, &b]);
+        assert!((c[0] - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+        assert!((c[1] - c[0]).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,437 @@
+//! Prometheus / OpenMetrics exporter
+//!
+//! `GET /metrics` renders the orchestrator's state in the OpenMetrics text
+//! format. Container stats come from a sampler that polls every running
+//! agent's runtime each `SAMPLE_INTERVAL` and caches the result on the
+//! agent's `resource_usage`, so neither scrapes nor the JSON metrics
+//! endpoints wait on Docker. Counters and histograms are fed by the code
+//! they measure: chat persistence, direct LLM streams, health probes, the
+//! workflow event stream and an HTTP middleware. Each sample is also
+//! folded into the SQLite history in `metrics_history`.
+
+use axum::extract::{MatchedPath, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::types::{AgentContainer, AgentStatus, TokenUsage};
+use crate::workflow::{ExecutionEvent, StepResult};
+use crate::AppState;
+
+/// How often container and system stats are refreshed
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(15);
+
+const HTTP_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+const STEP_BUCKETS: &[f64] = &[0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0];
+
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: &'static [f64],
+    /// Per-bucket counts, not cumulative
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = self.bounds.iter().position(|b| value <= *b) {
+            self.counts[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Host memory and CPU, refreshed by the sampler
+#[derive(Debug, Clone, Copy)]
+pub struct SystemSample {
+    pub total_memory_bytes: u64,
+    pub available_memory_bytes: u64,
+    pub cpu_percent: f32,
+}
+
+#[derive(Default)]
+struct Registry {
+    /// (agent, role)
+    chat_messages: BTreeMap<(String, String), u64>,
+    /// (agent, provider, "input" | "output")
+    llm_tokens: BTreeMap<(String, String, &'static str), u64>,
+    /// (agent, ok)
+    health_probes: BTreeMap<(String, bool), u64>,
+    /// (workflow, status)
+    workflow_steps: BTreeMap<(String, String), Histogram>,
+    /// (method, route, status)
+    http_requests: BTreeMap<(String, String, u16), Histogram>,
+    system: Option<SystemSample>,
+}
+
+pub struct Metrics {
+    registry: Mutex<Registry>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            registry: Mutex::new(Registry::default()),
+        }
+    }
+
+    pub fn record_chat_message(&self, agent: &str, role: &str) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry
+            .chat_messages
+            .entry((agent.to_string(), role.to_string()))
+            .or_default() += 1;
+    }
+
+    pub fn record_tokens(&self, agent: &str, provider: &str, usage: &TokenUsage) {
+        let mut registry = self.registry.lock().unwrap();
+        for (kind, n) in [("input", usage.input_tokens), ("output", usage.output_tokens)] {
+            *registry
+                .llm_tokens
+                .entry((agent.to_string(), provider.to_string(), kind))
+                .or_default() += n;
+        }
+    }
+
+    pub fn record_health_probe(&self, agent: &str, ok: bool) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry.health_probes.entry((agent.to_string(), ok)).or_default() += 1;
+    }
+
+    pub fn record_workflow_step(&self, workflow: &str, result: &StepResult) {
+        let status = format!("{:?}", result.status).to_lowercase();
+        let mut registry = self.registry.lock().unwrap();
+        registry
+            .workflow_steps
+            .entry((workflow.to_string(), status))
+            .or_insert_with(|| Histogram::new(STEP_BUCKETS))
+            .observe(result.duration_ms as f64 / 1000.0);
+    }
+
+    pub fn record_http_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        let mut registry = self.registry.lock().unwrap();
+        registry
+            .http_requests
+            .entry((method.to_string(), route.to_string(), status))
+            .or_insert_with(|| Histogram::new(HTTP_BUCKETS))
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// The latest host sample, if the sampler has run
+    pub fn system(&self) -> Option<SystemSample> {
+        self.registry.lock().unwrap().system
+    }
+
+    /// Start the stats sampler and the workflow step listener
+    pub fn spawn(state: Arc<AppState>) {
+        let sampler_state = Arc::clone(&state);
+        tokio::spawn(async move {
+            let mut sys = sysinfo::System::new();
+            let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+            loop {
+                interval.tick().await;
+                sample_agents(&sampler_state).await;
+
+                sys.refresh_memory();
+                sys.refresh_cpu();
+                sampler_state.metrics.registry.lock().unwrap().system = Some(SystemSample {
+                    total_memory_bytes: sys.total_memory(),
+                    available_memory_bytes: sys.available_memory(),
+                    cpu_percent: sys.global_cpu_info().cpu_usage(),
+                });
+            }
+        });
+
+        tokio::spawn(async move {
+            let mut events = state.executor.subscribe();
+            loop {
+                let (execution_id, result) = match events.recv().await {
+                    Ok(ExecutionEvent::StepCompleted { execution_id, result })
+                    | Ok(ExecutionEvent::StepFailed { execution_id, result }) => (execution_id, result),
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(n)) => {
+                        tracing::warn!("Metrics missed {} workflow events", n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let workflow_id = state
+                    .workflows
+                    .read()
+                    .await
+                    .get_execution(&execution_id)
+                    .map(|e| e.workflow_id.clone())
+                    .unwrap_or_default();
+                state.metrics.record_workflow_step(&workflow_id, &result);
+            }
+        });
+    }
+
+    /// Everything in the OpenMetrics text format
+    pub fn render(&self, agents: &[AgentContainer]) -> String {
+        let mut out = Exposition::default();
+
+        out.family("clawpen_agents", "gauge", "Agents by status");
+        for status in [
+            AgentStatus::Running,
+            AgentStatus::Stopped,
+            AgentStatus::Starting,
+            AgentStatus::Stopping,
+            AgentStatus::Error,
+        ] {
+            let count = agents.iter().filter(|a| a.status == status).count();
+            let label = format!("{:?}", status).to_lowercase();
+            out.sample("clawpen_agents", &[("status", &label)], count);
+        }
+
+        let sampled: Vec<_> = agents
+            .iter()
+            .filter_map(|a| a.resource_usage.as_ref().map(|u| (a.name.as_str(), u)))
+            .collect();
+        out.family("clawpen_agent_cpu_percent", "gauge", "Agent container CPU usage");
+        for (agent, usage) in &sampled {
+            out.sample("clawpen_agent_cpu_percent", &[("agent", agent)], usage.cpu_percent);
+        }
+        out.family("clawpen_agent_memory_bytes", "gauge", "Agent container memory usage");
+        for (agent, usage) in &sampled {
+            let bytes = (usage.memory_mb as f64 * 1024.0 * 1024.0) as u64;
+            out.sample("clawpen_agent_memory_bytes", &[("agent", agent)], bytes);
+        }
+        out.family("clawpen_agent_network_receive_bytes", "counter", "Bytes received by the agent container");
+        for (agent, usage) in &sampled {
+            out.sample("clawpen_agent_network_receive_bytes_total", &[("agent", agent)], usage.network_rx_bytes);
+        }
+        out.family("clawpen_agent_network_transmit_bytes", "counter", "Bytes sent by the agent container");
+        for (agent, usage) in &sampled {
+            out.sample("clawpen_agent_network_transmit_bytes_total", &[("agent", agent)], usage.network_tx_bytes);
+        }
+
+        out.family("clawpen_agent_healthy", "gauge", "Result of the agent's latest health check");
+        for agent in agents {
+            if let Some(health) = &agent.health_status {
+                out.sample("clawpen_agent_healthy", &[("agent", &agent.name)], u8::from(health.healthy));
+            }
+        }
+
+        let registry = self.registry.lock().unwrap();
+
+        out.family("clawpen_health_probes", "counter", "Scheduled health probes");
+        for ((agent, ok), n) in &registry.health_probes {
+            let result = if *ok { "ok" } else { "failed" };
+            out.sample("clawpen_health_probes_total", &[("agent", agent), ("result", result)], n);
+        }
+
+        out.family("clawpen_chat_messages", "counter", "Chat messages persisted");
+        for ((agent, role), n) in &registry.chat_messages {
+            out.sample("clawpen_chat_messages_total", &[("agent", agent), ("role", role)], n);
+        }
+
+        out.family("clawpen_llm_tokens", "counter", "LLM tokens reported by providers");
+        for ((agent, provider, kind), n) in &registry.llm_tokens {
+            out.sample(
+                "clawpen_llm_tokens_total",
+                &[("agent", agent), ("provider", provider), ("kind", kind)],
+                n,
+            );
+        }
+
+        out.family("clawpen_workflow_step_duration_seconds", "histogram", "Workflow step run time");
+        for ((workflow, status), h) in &registry.workflow_steps {
+            out.histogram(
+                "clawpen_workflow_step_duration_seconds",
+                &[("workflow", workflow), ("status", status)],
+                h,
+            );
+        }
+
+        out.family("clawpen_http_request_duration_seconds", "histogram", "HTTP request latency");
+        for ((method, route, status), h) in &registry.http_requests {
+            let status = status.to_string();
+            out.histogram(
+                "clawpen_http_request_duration_seconds",
+                &[("method", method), ("route", route), ("status", &status)],
+                h,
+            );
+        }
+
+        if let Some(system) = registry.system {
+            out.family("clawpen_system_memory_bytes", "gauge", "Host memory");
+            out.sample("clawpen_system_memory_bytes", &[], system.total_memory_bytes);
+            out.family("clawpen_system_memory_available_bytes", "gauge", "Host memory available");
+            out.sample("clawpen_system_memory_available_bytes", &[], system.available_memory_bytes);
+            out.family("clawpen_system_cpu_percent", "gauge", "Host CPU usage");
+            out.sample("clawpen_system_cpu_percent", &[], system.cpu_percent);
+        }
+
+        out.0.push_str("# EOF\n");
+        out.0
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Refresh `resource_usage` on every agent, querying runtimes concurrently
+async fn sample_agents(state: &AppState) {
+    let targets: Vec<(String, Option<String>)> = state
+        .containers
+        .read()
+        .await
+        .iter()
+        .filter(|a| a.status == AgentStatus::Running && a.runtime.as_deref() != Some("direct"))
+        .map(|a| (a.id.clone(), a.runtime.clone()))
+        .collect();
+
+    let stats = futures::future::join_all(
+        targets
+            .iter()
+            .map(|(id, runtime)| state.runtime_for(runtime.as_deref()).get_stats(id)),
+    )
+    .await;
+    let mut sampled: std::collections::HashMap<_, _> = targets
+        .iter()
+        .zip(stats)
+        .filter_map(|((id, _), result)| match result {
+            Ok(usage) => Some((id.as_str(), usage)),
+            Err(e) => {
+                tracing::debug!("Failed to sample stats for {}: {}", id, e);
+                None
+            }
+        })
+        .collect();
+
+    let mut history = Vec::new();
+    for agent in state.containers.write().await.iter_mut() {
+        agent.resource_usage = sampled.remove(agent.id.as_str()).flatten();
+        if let Some(usage) = &agent.resource_usage {
+            history.push((agent.id.clone(), usage.clone()));
+        }
+    }
+
+    if let Err(e) = state.metrics_history.record(chrono::Utc::now(), &history) {
+        tracing::warn!("Failed to record metrics history: {}", e);
+    }
+}
+
+/// Middleware timing every request by its route pattern
+pub async fn track_requests(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let method = request.method().to_string();
+    // The pattern, not the path, to keep label cardinality bounded
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.run(request).await;
+    state
+        .metrics
+        .record_http_request(&method, &route, response.status().as_u16(), started.elapsed());
+    response
+}
+
+#[derive(Default)]
+struct Exposition(String);
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# TYPE {} {}", name, kind);
+        let _ = writeln!(self.0, "# HELP {} {}", name, help);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        let _ = writeln!(self.0, "{}{} {}", name, label_set(labels), value);
+    }
+
+    fn histogram(&mut self, name: &str, labels: &[(&str, &str)], h: &Histogram) {
+        let mut cumulative = 0;
+        for (bound, count) in h.bounds.iter().zip(&h.counts) {
+            cumulative += count;
+            let le = format!("{:?}", bound);
+            let mut with_le = labels.to_vec();
+            with_le.push(("le", &le));
+            self.sample(&format!("{}_bucket", name), &with_le, cumulative);
+        }
+        let mut with_le = labels.to_vec();
+        with_le.push(("le", "+Inf"));
+        self.sample(&format!("{}_bucket", name), &with_le, h.count);
+        self.sample(&format!("{}_count", name), labels, h.count);
+        self.sample(&format!("{}_sum", name), labels, format!("{:?}", h.sum));
+    }
+}
+
+fn label_set(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| {
+            let v = v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{}=\"{}\"", k, v)
+        })
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counters_and_histograms() {
+        let metrics = Metrics::new();
+        metrics.record_chat_message("helper", "user");
+        metrics.record_chat_message("helper", "user");
+        metrics.record_tokens(
+            "helper",
+            "anthropic",
+            &TokenUsage {
+                input_tokens: 120,
+                output_tokens: 30,
+            },
+        );
+        metrics.record_http_request("GET", "/api/agents/:id", 200, Duration::from_millis(30));
+        metrics.record_http_request("GET", "/api/agents/:id", 200, Duration::from_secs(20));
+
+        let text = metrics.render(&[]);
+        assert!(text.contains("clawpen_agents{status=\"running\"} 0\n"));
+        assert!(text.contains("clawpen_chat_messages_total{agent=\"helper\",role=\"user\"} 2\n"));
+        assert!(text.contains(
+            "clawpen_llm_tokens_total{agent=\"helper\",provider=\"anthropic\",kind=\"output\"} 30\n"
+        ));
+        let labels = "method=\"GET\",route=\"/api/agents/:id\",status=\"200\"";
+        assert!(text.contains(&format!("clawpen_http_request_duration_seconds_bucket{{{},le=\"0.025\"}} 0\n", labels)));
+        assert!(text.contains(&format!("clawpen_http_request_duration_seconds_bucket{{{},le=\"0.05\"}} 1\n", labels)));
+        assert!(text.contains(&format!("clawpen_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} 2\n", labels)));
+        assert!(text.contains(&format!("clawpen_http_request_duration_seconds_count{{{}}} 2\n", labels)));
+        assert!(text.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn test_label_values_are_escaped() {
+        assert_eq!(label_set(&[("agent", "a\"b\\c\nd")]), "{agent=\"a\\\"b\\\\c\\nd\"}");
+        assert_eq!(label_set(&[]), "");
+    }
+}
@@ -0,0 +1,329 @@
+//! Per-agent resource history
+//!
+//! Every stats sample taken by the `metrics` sampler is folded into
+//! fixed-size buckets as it arrives: one-minute buckets kept for a day and
+//! one-hour buckets kept for a month. A bucket stores sums and maxima rather
+//! than the raw samples, so a query can regroup it into any coarser step.
+//! Lives in its own SQLite file next to `chat.db`.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::types::ResourceUsage;
+
+/// A bucket width and how long buckets of that width are kept, finest first
+struct Tier {
+    resolution: i64,
+    retention: i64,
+}
+
+const TIERS: &[Tier] = &[
+    Tier { resolution: 60, retention: 24 * 3600 },
+    Tier { resolution: 3600, retention: 30 * 24 * 3600 },
+];
+
+/// Most points a single query may return
+const MAX_POINTS: i64 = 2000;
+
+/// One point of a history query
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryPoint {
+    /// Start of the step this point covers
+    pub timestamp: DateTime<Utc>,
+    pub cpu_percent: f32,
+    pub cpu_percent_max: f32,
+    pub memory_mb: f32,
+    pub memory_mb_max: f32,
+    /// Cumulative counters as of the end of the step
+    pub network_rx_bytes: u64,
+    pub network_tx_bytes: u64,
+    pub samples: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct History {
+    pub agent_id: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Seconds per point
+    pub step: i64,
+    pub points: Vec<HistoryPoint>,
+}
+
+/// Same locking model as `ChatDb`: the Mutex guards the connection, SQLite's
+/// WAL mode handles concurrent readers.
+pub struct MetricsHistory {
+    conn: Mutex<Connection>,
+}
+
+impl MetricsHistory {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path).context("opening metrics.db")?;
+
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.execute_batch(SCHEMA_V1)?;
+
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    /// Fold one round of samples into every tier and drop expired buckets
+    pub fn record(&self, at: DateTime<Utc>, samples: &[(String, ResourceUsage)]) -> Result<()> {
+        let now = at.timestamp();
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut upsert = tx.prepare(
+                "INSERT INTO agent_metrics
+                     (agent_id, resolution, bucket, samples, cpu_sum, cpu_max,
+                      memory_sum, memory_max, rx_bytes, tx_bytes)
+                 VALUES (?1, ?2, ?3, 1, ?4, ?4, ?5, ?5, ?6, ?7)
+                 ON CONFLICT (agent_id, resolution, bucket) DO UPDATE SET
+                     samples = samples + 1,
+                     cpu_sum = cpu_sum + excluded.cpu_sum,
+                     cpu_max = MAX(cpu_max, excluded.cpu_max),
+                     memory_sum = memory_sum + excluded.memory_sum,
+                     memory_max = MAX(memory_max, excluded.memory_max),
+                     rx_bytes = excluded.rx_bytes,
+                     tx_bytes = excluded.tx_bytes",
+            )?;
+            for (agent_id, usage) in samples {
+                for tier in TIERS {
+                    upsert.execute(params![
+                        agent_id,
+                        tier.resolution,
+                        now - now.rem_euclid(tier.resolution),
+                        usage.cpu_percent as f64,
+                        usage.memory_mb as f64,
+                        usage.network_rx_bytes as i64,
+                        usage.network_tx_bytes as i64,
+                    ])?;
+                }
+            }
+            for tier in TIERS {
+                tx.execute(
+                    "DELETE FROM agent_metrics WHERE resolution = ?1 AND bucket < ?2",
+                    params![tier.resolution, now - tier.retention],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn delete_agent(&self, agent_id: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM agent_metrics WHERE agent_id = ?1", params![agent_id])?;
+        Ok(())
+    }
+
+    /// History for `[from, to)`, one point per `step` seconds that has data.
+    /// Reads the finest tier that still covers `from` and is no finer than
+    /// `step`; without a `step`, that tier's resolution is used.
+    pub fn query(
+        &self,
+        agent_id: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        step: Option<i64>,
+        now: DateTime<Utc>,
+    ) -> Result<History, String> {
+        if from >= to {
+            return Err("`from` must be before `to`".to_string());
+        }
+        let age = (now - from).num_seconds();
+        let tier = TIERS
+            .iter()
+            .find(|t| t.retention >= age && step.is_none_or(|s| t.resolution <= s))
+            .or_else(|| TIERS.iter().rev().find(|t| step.is_none_or(|s| t.resolution <= s)))
+            .unwrap_or(&TIERS[0]);
+
+        // Round the step up to a whole number of buckets
+        let step = step.unwrap_or(tier.resolution).max(tier.resolution);
+        let step = step
+            .checked_add(tier.resolution - 1)
+            .map(|s| s / tier.resolution * tier.resolution)
+            .ok_or_else(|| format!("step {}s is too large", step))?;
+        let span = (to - from).num_seconds();
+        if span / step > MAX_POINTS {
+            return Err(format!(
+                "too many points; use a step of at least {}s",
+                span / MAX_POINTS + 1
+            ));
+        }
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT bucket - bucket % ?3 AS t, SUM(samples), SUM(cpu_sum), MAX(cpu_max),
+                        SUM(memory_sum), MAX(memory_max), MAX(rx_bytes), MAX(tx_bytes)
+                 FROM agent_metrics
+                 WHERE agent_id = ?1 AND resolution = ?2 AND bucket >= ?4 AND bucket < ?5
+                 GROUP BY t ORDER BY t",
+            )
+            .map_err(|e| e.to_string())?;
+        let points = stmt
+            .query_map(
+                params![agent_id, tier.resolution, step, from.timestamp(), to.timestamp()],
+                |row| {
+                    let samples: i64 = row.get(1)?;
+                    let n = samples.max(1) as f64;
+                    Ok(HistoryPoint {
+                        timestamp: Utc.timestamp_opt(row.get(0)?, 0).single().unwrap_or_default(),
+                        cpu_percent: (row.get::<_, f64>(2)? / n) as f32,
+                        cpu_percent_max: row.get::<_, f64>(3)? as f32,
+                        memory_mb: (row.get::<_, f64>(4)? / n) as f32,
+                        memory_mb_max: row.get::<_, f64>(5)? as f32,
+                        network_rx_bytes: row.get::<_, i64>(6)? as u64,
+                        network_tx_bytes: row.get::<_, i64>(7)? as u64,
+                        samples: samples as u64,
+                    })
+                },
+            )
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+            .map_err(|e| e.to_string())?;
+
+        Ok(History {
+            agent_id: agent_id.to_string(),
+            from,
+            to,
+            step,
+            points,
+        })
+    }
+}
+
+/// A query timestamp: RFC 3339 or Unix seconds
+pub fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(secs) = value.parse::<i64>() {
+        return Utc
+            .timestamp_opt(secs, 0)
+            .single()
+            .ok_or_else(|| format!("invalid timestamp: {}", value));
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| format!("invalid timestamp: {}", value))
+}
+
+/// A query step: seconds, or a number with an `s`, `m`, `h` or `d` suffix
+pub fn parse_step(value: &str) -> Result<i64, String> {
+    let (number, unit) = match value.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&value[..i], c),
+        _ => (value, 's'),
+    };
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        _ => return Err(format!("invalid step: {}", value)),
+    };
+    match number.parse::<i64>() {
+        Ok(n) if n > 0 => n
+            .checked_mul(multiplier)
+            .ok_or_else(|| format!("step too large: {}", value)),
+        _ => Err(format!("invalid step: {}", value)),
+    }
+}
+
+// ─── Schema ────────────────────────────────────────────────────────────────
+
+const SCHEMA_V1: &str = r#"
+CREATE TABLE IF NOT EXISTS agent_metrics (
+    agent_id    TEXT NOT NULL,
+    resolution  INTEGER NOT NULL,               -- bucket width in seconds
+    bucket      INTEGER NOT NULL,               -- bucket start, Unix seconds
+    samples     INTEGER NOT NULL,
+    cpu_sum     REAL NOT NULL,
+    cpu_max     REAL NOT NULL,
+    memory_sum  REAL NOT NULL,                  -- MB
+    memory_max  REAL NOT NULL,
+    rx_bytes    INTEGER NOT NULL,               -- latest cumulative counter
+    tx_bytes    INTEGER NOT NULL,
+    PRIMARY KEY (agent_id, resolution, bucket)
+);
+CREATE INDEX IF NOT EXISTS idx_agent_metrics_expiry
+    ON agent_metrics(resolution, bucket);
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn usage(cpu: f32, memory: f32, rx: u64) -> ResourceUsage {
+        ResourceUsage {
+            memory_mb: memory,
+            cpu_percent: cpu,
+            network_rx_bytes: rx,
+            network_tx_bytes: 0,
+        }
+    }
+
+    #[test]
+    fn test_samples_are_bucketed_and_regrouped() {
+        let dir = tempdir().unwrap();
+        let history = MetricsHistory::open(&dir.path().join("metrics.db")).unwrap();
+        let start = Utc.with_ymd_and_hms(2026, 3, 2, 10, 0, 0).unwrap();
+        let agent = "agent-1".to_string();
+
+        // Two samples a minute for five minutes
+        for i in 0..10 {
+            let at = start + chrono::Duration::seconds(i * 30);
+            let sample = usage(10.0 * (i % 2 + 1) as f32, 100.0 + i as f32, 1000 * i as u64);
+            history.record(at, &[(agent.clone(), sample)]).unwrap();
+        }
+        let now = start + chrono::Duration::minutes(5);
+
+        let minutes = history.query(&agent, start, now, None, now).unwrap();
+        assert_eq!(minutes.step, 60);
+        assert_eq!(minutes.points.len(), 5);
+        assert_eq!(minutes.points[0].samples, 2);
+        assert_eq!(minutes.points[0].cpu_percent, 15.0);
+        assert_eq!(minutes.points[0].cpu_percent_max, 20.0);
+        assert_eq!(minutes.points[4].network_rx_bytes, 9000);
+
+        // A 90s step rounds up to two-minute groups of minute buckets
+        let grouped = history.query(&agent, start, now, Some(90), now).unwrap();
+        assert_eq!(grouped.step, 120);
+        assert_eq!(grouped.points.len(), 3);
+        assert_eq!(grouped.points[0].samples, 4);
+        assert_eq!(grouped.points[0].memory_mb, 101.5);
+        assert_eq!(grouped.points[0].memory_mb_max, 103.0);
+
+        // Past a day only the hourly tier has it
+        let later = now + chrono::Duration::days(2);
+        history.record(later, &[]).unwrap();
+        let hours = history.query(&agent, start, later, None, later).unwrap();
+        assert_eq!(hours.step, 3600);
+        assert_eq!(hours.points.len(), 1);
+        assert_eq!(hours.points[0].samples, 10);
+        assert!(history.query(&agent, start, now, Some(60), later).unwrap().points.is_empty());
+        assert!(history.query(&agent, start, now, Some(i64::MAX), now).is_err());
+    }
+
+    #[test]
+    fn test_parse_query_values() {
+        assert_eq!(parse_step("90").unwrap(), 90);
+        assert_eq!(parse_step("5m").unwrap(), 300);
+        assert_eq!(parse_step("1d").unwrap(), 86400);
+        assert!(parse_step("0").is_err());
+        assert!(parse_step("5w").is_err());
+        assert!(parse_step("9223372036854775807d").is_err());
+
+        assert_eq!(parse_time("0").unwrap().timestamp(), 0);
+        assert_eq!(
+            parse_time("2026-03-02T10:00:00Z").unwrap(),
+            Utc.with_ymd_and_hms(2026, 3, 2, 10, 0, 0).unwrap()
+        );
+        assert!(parse_time("yesterday").is_err());
+    }
+}
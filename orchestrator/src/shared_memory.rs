@@ -47,6 +47,31 @@
  * Embeddings should be provided as Vec<f32>. The default expected dimension is 1536
  * (OpenAI text-embedding-ada-002), but this can be configured.
  *
+ * Each memory records the model that made its embedding and the dimension.
+ * A search only compares the query with vectors from the same model, and a
+ * model can't store vectors of two different dimensions.
+ *
+ * Without sqlite-vss, searches go through an in-process HNSW index (see
+ * `ann_index`), saved next to the database as `<database>.ann`.
+ *
+ * ### Hybrid Search
+ *
+ * `search` takes a `MemoryQuery`: keywords, a query vector or both, plus
+ * filters on agent, metadata fields and `created_at`. Keywords are matched
+ * by the FTS5 table `memories_fts`, kept in sync with `memories` by
+ * triggers. With both keywords and a vector, the two rankings are merged
+ * by reciprocal-rank fusion.
+ *
+ * ### Lifecycle
+ *
+ * A memory stored with `expires_at` drops out of searches and lists once
+ * that passes and is removed by `delete_expired`, and `delete_older_than`
+ * enforces an org's retention period; the `memory_lifecycle` module runs
+ * both on a timer. With `dedup_threshold` set, a memory whose embedding is
+ * that similar to one already in its org is merged into it instead of
+ * stored again. `replace_memories` swaps a group of memories for one
+ * entry, as consolidation does.
+ *
  * ### Database Schema
  *
  * The module creates the following tables:
@@ -61,7 +86,15 @@
  *     embedding BLOB,
  *     metadata TEXT,  -- JSON
  *     created_at TEXT NOT NULL,
- *     updated_at TEXT NOT NULL
+ *     updated_at TEXT NOT NULL,
+ *     embedding_model TEXT,
+ *     embedding_dim INTEGER,
+ *     expires_at TEXT  -- deleted by the sweeper after this
+ * );
+ *
+ * -- Full-text index over content (external content, synced by triggers)
+ * CREATE VIRTUAL TABLE memories_fts USING fts5(
+ *     content, content='memories', content_rowid='id', tokenize='porter unicode61'
  * );
  *
  * -- Virtual table for vector similarity search
@@ -105,18 +138,41 @@ use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use rusqlite::{params, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use thiserror::Error;
 
+use crate::ann_index::{AnnIndex, IndexedVector, SpaceKey};
+
 // Default embedding dimension (OpenAI ada-002)
 const DEFAULT_EMBEDDING_DIM: usize = 1536;
 
 /// Special org namespace constants
 pub const ORG_COMMON: &str = "common"; // Shared knowledge across orgs
 pub const ORG_ALL: &str = "all"; // Query everything (no org filter)
+
+/// Columns `row_to_memory` reads, in order
+const MEMORY_COLUMNS: &str =
+    "id, org, agent_id, content, embedding, metadata, created_at, updated_at, embedding_model, embedding_dim, expires_at";
+/// `MEMORY_COLUMNS` for a query aliasing memories as `m`
+const MEMORY_COLUMNS_M: &str = "m.id, m.org, m.agent_id, m.content, m.embedding, m.metadata, m.created_at, m.updated_at, m.embedding_model, m.embedding_dim, m.expires_at";
 pub const ORG_DEFAULT: &str = "default"; // Default org when none specified
 
+/// Reciprocal-rank fusion constant (Cormack et al., 2009)
+const RRF_K: f32 = 60.0;
+/// Candidates each ranking brings to a hybrid search: this many per result,
+/// and at least `MIN_HYBRID_CANDIDATES`
+const HYBRID_CANDIDATES_PER_RESULT: usize = 4;
+const MIN_HYBRID_CANDIDATES: usize = 50;
+/// A filtered vector search scores every matching row when there are at
+/// most this many; beyond that it narrows an overfetched index search
+const FILTERED_SCAN_MAX: i64 = 2000;
+/// How many times the requested results a filtered index search fetches
+const FILTERED_OVERFETCH: usize = 10;
+/// Longest TTL a memory may be stored with (ten years)
+const MAX_TTL_SECS: i64 = 10 * 365 * 86400;
+
 /// Errors specific to shared memory operations
 #[derive(Debug, Error)]
 pub enum SharedMemoryError {
@@ -148,6 +204,9 @@ pub struct SharedMemoryConfig {
     pub vss_extension_path: Option<PathBuf>,
     /// Embedding dimension (default: 1536 for OpenAI ada-002)
     pub embedding_dim: usize,
+    /// Cosine similarity at or above which a new memory is merged into the
+    /// most similar one in its org instead of being stored (None: never)
+    pub dedup_threshold: Option<f32>,
 }
 
 impl Default for SharedMemoryConfig {
@@ -156,6 +215,7 @@ impl Default for SharedMemoryConfig {
             database_path: PathBuf::from("/data/claw-pen/shared/memory.db"),
             vss_extension_path: std::env::var("SQLITE_VSS_PATH").ok().map(PathBuf::from),
             embedding_dim: DEFAULT_EMBEDDING_DIM,
+            dedup_threshold: None,
         }
     }
 }
@@ -171,6 +231,15 @@ pub struct Memory {
     pub metadata: Option<serde_json::Value>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Model that made `embedding`, if recorded
+    #[serde(default)]
+    pub embedding_model: Option<String>,
+    /// Length of `embedding`
+    #[serde(default)]
+    pub embedding_dim: Option<usize>,
+    /// When the memory is deleted, if it expires
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
 /// A memory entry without the ID (for insertion)
@@ -180,7 +249,15 @@ pub struct NewMemory {
     pub agent_id: String,
     pub content: String,
     pub embedding: Option<Vec<f32>>,
+    /// Model that made `embedding`. Searches only compare vectors from the
+    /// same model.
+    #[serde(default)]
+    pub embedding_model: Option<String>,
     pub metadata: Option<serde_json::Value>,
+    /// When the memory is deleted; None keeps it (subject to the org's
+    /// retention period)
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
 impl NewMemory {
@@ -188,6 +265,30 @@ impl NewMemory {
     pub fn org_or_default(&self) -> &str {
         self.org.as_deref().unwrap_or(ORG_DEFAULT)
     }
+
+    /// A time to live: seconds, or a number with an `s`, `m`, `h` or `d`
+    /// suffix, of at most ten years
+    pub fn parse_ttl(value: &str) -> Result<chrono::TimeDelta, String> {
+        let value = value.trim();
+        let (number, unit) = match value.char_indices().last() {
+            Some((i, c)) if c.is_ascii_alphabetic() => (&value[..i], c),
+            _ => (value, 's'),
+        };
+        let unit_secs: i64 = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return Err(format!("invalid ttl '{}': use a unit of s, m, h or d", value)),
+        };
+        let secs = match number.parse::<i64>() {
+            Ok(n) if n > 0 => n.checked_mul(unit_secs),
+            _ => return Err(format!("invalid ttl '{}': expected a positive number", value)),
+        };
+        secs.filter(|secs| *secs <= MAX_TTL_SECS)
+            .and_then(chrono::TimeDelta::try_seconds)
+            .ok_or_else(|| format!("ttl '{}' is longer than the maximum of 3650d", value))
+    }
 }
 
 /// Search result with similarity score
@@ -197,9 +298,165 @@ pub struct MemorySearchResult {
     pub similarity: f32,
 }
 
+/// A memory search: keywords, a query vector or both, within a filter
+///
+/// Scores depend on what was asked: cosine similarity for a vector alone,
+/// the negated BM25 rank for keywords alone, and the reciprocal-rank fusion
+/// score when both are given. A search with neither lists the memories
+/// matching the filter, newest first, with a score of 0.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryQuery {
+    /// Keywords, matched against content by FTS5: any of the words, stemmed
+    pub text: Option<String>,
+    pub embedding: Option<Vec<f32>>,
+    /// Model that made `embedding`
+    pub embedding_model: Option<String>,
+    pub filter: MemoryFilter,
+    pub limit: usize,
+}
+
+/// Conditions every search result meets
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MemoryFilter {
+    pub agent_id: Option<String>,
+    /// Metadata fields and the value each must have. A dotted key reaches
+    /// into nested objects; an array field matches if it holds the value.
+    #[serde(default)]
+    pub metadata: serde_json::Map<String, serde_json::Value>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+impl MemoryFilter {
+    pub fn is_empty(&self) -> bool {
+        self.agent_id.is_none()
+            && self.metadata.is_empty()
+            && self.created_after.is_none()
+            && self.created_before.is_none()
+    }
+
+    /// A time bound: an age such as `7d` or `12h` (counted back from now),
+    /// a date, RFC 3339 or Unix seconds
+    pub fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+        let value = value.trim();
+        if value.ends_with(|c: char| c.is_ascii_alphabetic()) {
+            if let Ok(secs) = crate::metrics_history::parse_step(value) {
+                return chrono::TimeDelta::try_seconds(secs)
+                    .and_then(|age| Utc::now().checked_sub_signed(age))
+                    .ok_or_else(|| format!("age '{}' is out of range", value));
+            }
+        }
+        let bound = crate::usage::parse_bound(value)?;
+        DateTime::parse_from_rfc3339(&bound)
+            .map(|t| t.with_timezone(&Utc))
+            .map_err(|e| e.to_string())
+    }
+
+    /// Check the metadata conditions can be turned into SQL
+    pub fn validate(&self) -> Result<()> {
+        self.sql(ORG_ALL, &mut Vec::new()).map(|_| ())
+    }
+
+    /// SQL conditions on `memories m` for this filter within `org`, with
+    /// their values appended to `params`
+    fn sql(&self, org: &str, params: &mut Vec<Box<dyn rusqlite::ToSql>>) -> Result<String> {
+        let mut conditions = vec![unexpired("m.expires_at", params)];
+        if org != ORG_ALL {
+            params.push(Box::new(org.to_string()));
+            conditions.push(format!("m.org = ?{}", params.len()));
+        }
+        if let Some(agent_id) = &self.agent_id {
+            params.push(Box::new(agent_id.clone()));
+            conditions.push(format!("m.agent_id = ?{}", params.len()));
+        }
+        for (key, value) in &self.metadata {
+            let segments: Vec<&str> = key.split('.').collect();
+            if segments.iter().any(|s| s.is_empty() || s.contains('"')) {
+                anyhow::bail!("Invalid metadata key '{}'", key);
+            }
+            let path = format!("$.\"{}\"", segments.join("\".\""));
+            let value: Box<dyn rusqlite::ToSql> = match value {
+                serde_json::Value::String(s) => Box::new(s.clone()),
+                serde_json::Value::Bool(b) => Box::new(*b as i64),
+                serde_json::Value::Number(n) => match n.as_i64() {
+                    Some(i) => Box::new(i),
+                    None => Box::new(n.as_f64().unwrap_or_default()),
+                },
+                _ => anyhow::bail!(
+                    "Metadata filter '{}' must be a string, number or boolean",
+                    key
+                ),
+            };
+            params.push(Box::new(path));
+            params.push(value);
+            // json_each yields a scalar itself, or each element of an array
+            conditions.push(format!(
+                "EXISTS (SELECT 1 FROM json_each(m.metadata, ?{}) WHERE value = ?{})",
+                params.len() - 1,
+                params.len()
+            ));
+        }
+        for (bound, op) in [(&self.created_after, ">="), (&self.created_before, "<")] {
+            if let Some(bound) = bound {
+                params.push(Box::new(bound.to_rfc3339()));
+                conditions.push(format!("m.created_at {} ?{}", op, params.len()));
+            }
+        }
+        Ok(conditions.join(" AND "))
+    }
+}
+
+/// SQL condition on an `expires_at` column keeping the memories that
+/// haven't expired, which the sweeper may not have deleted yet. The current
+/// time is appended to `params`.
+fn unexpired(column: &str, params: &mut Vec<Box<dyn rusqlite::ToSql>>) -> String {
+    params.push(Box::new(Utc::now().to_rfc3339()));
+    format!("({0} IS NULL OR {0} > ?{1})", column, params.len())
+}
+
+/// An FTS5 query matching any of the words in `text`, or None if it has
+/// none. Words are quoted so FTS5 syntax in the text is taken literally.
+fn fts_query(text: &str) -> Option<String> {
+    let mut terms: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| t.len() > 1)
+        .map(|t| format!("\"{}\"", t.to_lowercase()))
+        .collect();
+    terms.sort();
+    terms.dedup();
+    (!terms.is_empty()).then(|| terms.join(" OR "))
+}
+
+/// Merge rankings by reciprocal-rank fusion: a memory scores the sum of
+/// 1 / (RRF_K + rank) over the rankings it appears in
+fn fuse_rankings(rankings: Vec<Vec<MemorySearchResult>>, limit: usize) -> Vec<MemorySearchResult> {
+    let mut fused: HashMap<i64, MemorySearchResult> = HashMap::new();
+    for ranking in rankings {
+        for (rank, result) in ranking.into_iter().enumerate() {
+            let score = 1.0 / (RRF_K + rank as f32 + 1.0);
+            fused
+                .entry(result.memory.id)
+                .and_modify(|r| r.similarity += score)
+                .or_insert(MemorySearchResult {
+                    memory: result.memory,
+                    similarity: score,
+                });
+        }
+    }
+    let mut results: Vec<MemorySearchResult> = fused.into_values().collect();
+    results.sort_by(|a, b| {
+        b.similarity
+            .total_cmp(&a.similarity)
+            .then(b.memory.created_at.cmp(&a.memory.created_at))
+            .then(b.memory.id.cmp(&a.memory.id))
+    });
+    results.truncate(limit);
+    results
+}
+
 /// Task status enum
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "lowercase")]
+#[serde(rename_all = "snake_case")]
 pub enum TaskStatus {
     Pending,
     Claimed,
@@ -279,6 +536,8 @@ pub struct SharedMemory {
     conn: Arc<Mutex<Connection>>,
     config: SharedMemoryConfig,
     vss_enabled: bool,
+    /// Vector index used when sqlite-vss isn't available
+    ann: Option<Mutex<AnnIndex>>,
 }
 
 impl SharedMemory {
@@ -310,15 +569,22 @@ impl SharedMemory {
             })
             .is_ok();
 
-        let shared_memory = Self {
+        let mut shared_memory = Self {
             conn: Arc::new(Mutex::new(conn)),
             config,
             vss_enabled,
+            ann: None,
         };
 
         // Initialize schema
         shared_memory.initialize_schema()?;
 
+        if !shared_memory.vss_enabled {
+            let path = shared_memory.config.database_path.with_extension("ann");
+            let vectors = shared_memory.indexed_vectors()?;
+            shared_memory.ann = Some(Mutex::new(AnnIndex::open(Some(path), vectors)));
+        }
+
         if shared_memory.vss_enabled {
             tracing::info!("SharedMemory initialized with vector search enabled");
         } else {
@@ -330,6 +596,58 @@ impl SharedMemory {
         Ok(shared_memory)
     }
 
+    /// Every stored embedding, to build the ANN index from
+    fn indexed_vectors(&self) -> Result<Vec<IndexedVector>> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+
+        let mut stmt =
+            conn.prepare("SELECT id, org, embedding_model, embedding FROM memories WHERE embedding IS NOT NULL")?;
+        let vectors = stmt
+            .query_map([], |row| {
+                let vector = Self::blob_to_embedding(&row.get::<_, Vec<u8>>(3)?);
+                Ok(IndexedVector {
+                    id: row.get(0)?,
+                    space: SpaceKey {
+                        org: row.get(1)?,
+                        model: row.get(2)?,
+                        dim: vector.len(),
+                    },
+                    vector,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(vectors)
+    }
+
+    /// Add a memory's embedding to the ANN index, if there is one
+    fn index_memory(&self, id: i64, org: &str, model: Option<&str>, embedding: &[f32]) -> Result<()> {
+        if let Some(ann) = &self.ann {
+            let space = SpaceKey {
+                org: org.to_string(),
+                model: model.map(str::to_string),
+                dim: embedding.len(),
+            };
+            ann.lock()
+                .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?
+                .insert(id, space, embedding);
+        }
+        Ok(())
+    }
+
+    /// Drop memories from the ANN index, if there is one
+    fn unindex_memories(&self, ids: &[i64]) -> Result<()> {
+        if let Some(ann) = &self.ann {
+            let mut ann = ann.lock().map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+            for &id in ids {
+                ann.remove(id);
+            }
+        }
+        Ok(())
+    }
+
     /// Attempt to load the sqlite-vss extension
     fn load_vss_extension(_conn: &Connection, extension_path: &Option<PathBuf>) -> Result<()> {
         let path = extension_path
@@ -373,7 +691,10 @@ impl SharedMemory {
                 embedding BLOB,
                 metadata TEXT,
                 created_at TEXT NOT NULL DEFAULT (datetime('now')),
-                updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+                updated_at TEXT NOT NULL DEFAULT (datetime('now')),
+                embedding_model TEXT,
+                embedding_dim INTEGER,
+                expires_at TEXT
             );
 
             CREATE INDEX IF NOT EXISTS idx_memories_org ON memories(org);
@@ -409,6 +730,61 @@ impl SharedMemory {
             "#,
         )?;
 
+        // Databases from before embeddings were labelled: add the columns
+        // and record the dimension of the vectors already stored
+        let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('memories')")?;
+        let columns = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<HashSet<_>, _>>()?;
+        drop(stmt);
+        for (column, sql_type) in [
+            ("embedding_model", "TEXT"),
+            ("embedding_dim", "INTEGER"),
+            ("expires_at", "TEXT"),
+        ] {
+            if !columns.contains(column) {
+                conn.execute(&format!("ALTER TABLE memories ADD COLUMN {} {}", column, sql_type), [])?;
+            }
+        }
+        conn.execute(
+            "UPDATE memories SET embedding_dim = length(embedding) / 4
+             WHERE embedding IS NOT NULL AND embedding_dim IS NULL",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_memories_expires_at ON memories(expires_at)",
+            [],
+        )?;
+
+        // Full-text index over content. A database from before it existed
+        // gets it filled from the memories already stored.
+        let has_fts: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'memories_fts')",
+            [],
+            |row| row.get(0),
+        )?;
+        conn.execute_batch(
+            r#"
+            CREATE VIRTUAL TABLE IF NOT EXISTS memories_fts USING fts5(
+                content, content='memories', content_rowid='id', tokenize='porter unicode61'
+            );
+
+            CREATE TRIGGER IF NOT EXISTS memories_fts_insert AFTER INSERT ON memories BEGIN
+                INSERT INTO memories_fts(rowid, content) VALUES (new.id, new.content);
+            END;
+            CREATE TRIGGER IF NOT EXISTS memories_fts_delete AFTER DELETE ON memories BEGIN
+                INSERT INTO memories_fts(memories_fts, rowid, content) VALUES ('delete', old.id, old.content);
+            END;
+            CREATE TRIGGER IF NOT EXISTS memories_fts_update AFTER UPDATE OF content ON memories BEGIN
+                INSERT INTO memories_fts(memories_fts, rowid, content) VALUES ('delete', old.id, old.content);
+                INSERT INTO memories_fts(rowid, content) VALUES (new.id, new.content);
+            END;
+            "#,
+        )?;
+        if !has_fts {
+            conn.execute("INSERT INTO memories_fts(memories_fts) VALUES ('rebuild')", [])?;
+        }
+
         // Create VSS virtual table if extension is available
         if self.vss_enabled {
             conn.execute_batch(&format!(
@@ -430,16 +806,58 @@ impl SharedMemory {
 
     /// Store a new memory with optional embedding
     ///
+    /// With `dedup_threshold` configured, a memory whose embedding is at
+    /// least that similar to one the same agent already stored in the org is
+    /// merged into it (see `merge_into`) and the existing memory's ID is
+    /// returned.
+    ///
     /// # Arguments
     /// * `org` - Organization namespace (use ORG_DEFAULT if None)
     /// * `memory` - The memory to store
     pub fn store_memory(&self, org: Option<&str>, memory: &NewMemory) -> Result<i64> {
+        let org = org.unwrap_or(memory.org_or_default());
+        if let (Some(threshold), Some(embedding)) = (self.config.dedup_threshold, &memory.embedding) {
+            let nearest = self.search(
+                org,
+                &MemoryQuery {
+                    embedding: Some(embedding.clone()),
+                    embedding_model: memory.embedding_model.clone(),
+                    filter: MemoryFilter {
+                        agent_id: Some(memory.agent_id.clone()),
+                        ..Default::default()
+                    },
+                    limit: 1,
+                    ..Default::default()
+                },
+            )?;
+            if let Some(existing) = nearest.into_iter().find(|r| r.similarity >= threshold) {
+                if let Some(id) = self.merge_into(&existing.memory, memory)? {
+                    return Ok(id);
+                }
+            }
+        }
+
         let conn = self
             .conn
             .lock()
             .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+        let id = self.insert_memory(&conn, org, memory)?;
 
-        let org = org.unwrap_or(memory.org_or_default());
+        tracing::debug!(
+            "Stored memory {} for agent {} in org {}",
+            id,
+            memory.agent_id,
+            org
+        );
+        Ok(id)
+    }
+
+    /// Insert a memory row and add its embedding to VSS or the ANN index
+    fn insert_memory(&self, conn: &Connection, org: &str, memory: &NewMemory) -> Result<i64> {
+        let embedding_dim = memory.embedding.as_ref().map(|e| e.len() as i64);
+        if let (Some(model), Some(dim)) = (&memory.embedding_model, embedding_dim) {
+            Self::check_model_dim(conn, model, dim)?;
+        }
         let now = Utc::now().to_rfc3339();
         let embedding_blob = memory
             .embedding
@@ -453,8 +871,8 @@ impl SharedMemory {
 
         conn.execute(
             r#"
-            INSERT INTO memories (org, agent_id, content, embedding, metadata, created_at, updated_at)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)
+            INSERT INTO memories (org, agent_id, content, embedding, metadata, created_at, updated_at, embedding_model, embedding_dim, expires_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6, ?7, ?8, ?9)
             "#,
             params![
                 org,
@@ -463,6 +881,9 @@ impl SharedMemory {
                 embedding_blob,
                 metadata_json,
                 now,
+                memory.embedding.as_ref().and(memory.embedding_model.as_ref()),
+                embedding_dim,
+                memory.expires_at.map(|t| t.to_rfc3339()),
             ],
         )?;
 
@@ -476,35 +897,233 @@ impl SharedMemory {
                     params![id, Self::embedding_to_blob(embedding)],
                 );
             }
+        } else if let Some(ref embedding) = memory.embedding {
+            self.index_memory(id, org, memory.embedding_model.as_deref(), embedding)?;
+        }
+        Ok(id)
+    }
+
+    /// Fold a near-duplicate into an existing memory of the same agent: the
+    /// newer content and embedding replace the old ones, metadata fields are
+    /// merged (newer values win), and the later of the two expiries is kept,
+    /// a missing one counting as no TTL given. None if the existing memory
+    /// is gone.
+    fn merge_into(&self, existing: &Memory, memory: &NewMemory) -> Result<Option<i64>> {
+        let metadata = match (&existing.metadata, &memory.metadata) {
+            (Some(serde_json::Value::Object(old)), Some(serde_json::Value::Object(new))) => {
+                let mut merged = old.clone();
+                merged.extend(new.clone());
+                Some(serde_json::Value::Object(merged))
+            }
+            (old, new) => new.clone().or_else(|| old.clone()),
+        };
+        let expires_at = existing.expires_at.max(memory.expires_at);
+        let embedding = memory.embedding.as_deref().unwrap_or_default();
+        let blob = Self::embedding_to_blob(embedding);
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+        let rows_affected = conn.execute(
+            "UPDATE memories SET content = ?1, embedding = ?2, metadata = ?3, expires_at = ?4, updated_at = ?5
+             WHERE id = ?6",
+            params![
+                memory.content,
+                blob,
+                metadata.as_ref().map(serde_json::to_string).transpose()?,
+                expires_at.map(|t| t.to_rfc3339()),
+                Utc::now().to_rfc3339(),
+                existing.id,
+            ],
+        )?;
+        if rows_affected == 0 {
+            return Ok(None);
+        }
+        if self.vss_enabled {
+            let _ = conn.execute("DELETE FROM vss_memories WHERE rowid = ?1", params![existing.id]);
+            let _ = conn.execute(
+                "INSERT INTO vss_memories (rowid, embedding) VALUES (?1, ?2)",
+                params![existing.id, blob],
+            );
+        } else {
+            self.index_memory(existing.id, &existing.org, existing.embedding_model.as_deref(), embedding)?;
         }
 
         tracing::debug!(
-            "Stored memory {} for agent {} in org {}",
-            id,
+            "Merged a memory from agent {} into near-duplicate {} in org {}",
             memory.agent_id,
-            org
+            existing.id,
+            existing.org
         );
+        Ok(Some(existing.id))
+    }
+
+    /// Store `memory` in place of the memories `ids`, e.g. a summary of
+    /// them, in one transaction. Not subject to deduplication.
+    pub fn replace_memories(&self, org: &str, ids: &[i64], memory: &NewMemory) -> Result<i64> {
+        let mut conn = self
+            .conn
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+
+        let tx = conn.transaction()?;
+        let id = self.insert_memory(&tx, org, memory)?;
+        for &old in ids {
+            tx.execute("DELETE FROM memories WHERE id = ?1", params![old])?;
+            if self.vss_enabled {
+                let _ = tx.execute("DELETE FROM vss_memories WHERE rowid = ?1", params![old]);
+            }
+        }
+        tx.commit()?;
+        self.unindex_memories(ids)?;
         Ok(id)
     }
 
+    /// Refuse a vector whose dimension differs from those `model` has
+    /// already stored: it was labelled with the wrong model
+    fn check_model_dim(conn: &Connection, model: &str, dim: i64) -> Result<()> {
+        let stored: Option<i64> = conn
+            .query_row(
+                "SELECT embedding_dim FROM memories WHERE embedding_model = ?1 LIMIT 1",
+                params![model],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match stored {
+            Some(stored) if stored != dim => Err(anyhow::anyhow!(
+                "Embedding model '{}' makes {}-dimensional vectors, got {}",
+                model,
+                stored,
+                dim
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Replace a memory's embedding, e.g. when it is (re)embedded by the
+    /// built-in embedder
+    pub fn set_embedding(&self, id: i64, embedding: &[f32], embedding_model: &str) -> Result<bool> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+        Self::check_model_dim(&conn, embedding_model, embedding.len() as i64)?;
+
+        let blob = Self::embedding_to_blob(embedding);
+        let rows_affected = conn.execute(
+            "UPDATE memories SET embedding = ?1, embedding_model = ?2, embedding_dim = ?3, updated_at = ?4 WHERE id = ?5",
+            params![blob, embedding_model, embedding.len() as i64, Utc::now().to_rfc3339(), id],
+        )?;
+        if self.vss_enabled && rows_affected > 0 {
+            let _ = conn.execute("DELETE FROM vss_memories WHERE rowid = ?1", params![id]);
+            let _ = conn.execute(
+                "INSERT INTO vss_memories (rowid, embedding) VALUES (?1, ?2)",
+                params![id, blob],
+            );
+        } else if rows_affected > 0 {
+            let org: String = conn.query_row("SELECT org FROM memories WHERE id = ?1", params![id], |row| row.get(0))?;
+            self.index_memory(id, &org, Some(embedding_model), embedding)?;
+        }
+        Ok(rows_affected > 0)
+    }
+
+    /// IDs and contents of the memories an embedder making `model` vectors
+    /// should (re)embed: those without an embedding, and those embedded by an
+    /// earlier model named with `stale_prefix` (e.g. a previous TF-IDF fit)
+    pub fn memories_to_embed(&self, model: &str, stale_prefix: Option<&str>) -> Result<Vec<(i64, String)>> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, content FROM memories
+             WHERE embedding IS NULL
+                OR (?2 IS NOT NULL AND embedding_model != ?1 AND substr(embedding_model, 1, length(?2)) = ?2)
+             ORDER BY id ASC",
+        )?;
+        let memories = stmt
+            .query_map(params![model, stale_prefix], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(memories)
+    }
+
     /// Search memories by vector similarity
     ///
+    /// Only memories embedded by the same model, with the same dimension,
+    /// are compared with the query; vectors from other models are skipped
+    /// rather than scored against a space they don't share.
+    ///
     /// # Arguments
     /// * `org` - Organization namespace. Use ORG_ALL to search across all orgs,
     ///   ORG_COMMON for shared knowledge, or a specific org name.
     /// * `query_embedding` - The query vector
+    /// * `embedding_model` - Model that made the query vector (None for
+    ///   vectors stored without one)
     /// * `limit` - Maximum number of results
     pub fn search_memories(
         &self,
         org: &str,
         query_embedding: &[f32],
+        embedding_model: Option<&str>,
         limit: usize,
     ) -> Result<Vec<MemorySearchResult>> {
         if self.vss_enabled {
-            self.search_memories_vss(org, query_embedding, limit)
+            self.search_memories_vss(org, query_embedding, embedding_model, limit)
+        } else if let Some(ann) = &self.ann {
+            self.search_memories_ann(ann, org, query_embedding, embedding_model, limit)
         } else {
-            self.search_memories_fallback(org, query_embedding, limit)
+            self.search_memories_fallback(org, query_embedding, embedding_model, limit)
+        }
+    }
+
+    /// Search using the in-process ANN index (when VSS not available)
+    fn search_memories_ann(
+        &self,
+        ann: &Mutex<AnnIndex>,
+        org: &str,
+        query_embedding: &[f32],
+        embedding_model: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<MemorySearchResult>> {
+        let org_filter = (org != ORG_ALL).then_some(org);
+        let hits = ann
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?
+            .search(org_filter, embedding_model, query_embedding, limit);
+        if hits.is_empty() {
+            return Ok(Vec::new());
         }
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> =
+            hits.iter().map(|(id, _)| Box::new(*id) as Box<dyn rusqlite::ToSql>).collect();
+        let placeholders = (1..=hits.len()).map(|i| format!("?{}", i)).collect::<Vec<_>>().join(", ");
+        let query = format!(
+            "SELECT {} FROM memories WHERE id IN ({}) AND {}",
+            MEMORY_COLUMNS,
+            placeholders,
+            unexpired("expires_at", &mut params)
+        );
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+        let mut stmt = conn.prepare(&query)?;
+        let mut memories: HashMap<i64, Memory> = stmt
+            .query_map(params_refs.as_slice(), Self::row_to_memory)?
+            .map(|m| m.map(|m| (m.id, m)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(hits
+            .into_iter()
+            .filter_map(|(id, similarity)| {
+                memories
+                    .remove(&id)
+                    .map(|memory| MemorySearchResult { memory, similarity })
+            })
+            .collect())
     }
 
     /// Search using sqlite-vss (when available)
@@ -512,6 +1131,7 @@ impl SharedMemory {
         &self,
         org: &str,
         query_embedding: &[f32],
+        embedding_model: Option<&str>,
         limit: usize,
     ) -> Result<Vec<MemorySearchResult>> {
         let conn = self
@@ -519,33 +1139,27 @@ impl SharedMemory {
             .lock()
             .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
 
-        let query_blob = Self::embedding_to_blob(query_embedding);
-
-        let (query, params): (String, Vec<Box<dyn rusqlite::ToSql>>) = if org == ORG_ALL {
-            (
-                r#"
-                SELECT m.id, m.org, m.agent_id, m.content, m.embedding, m.metadata, m.created_at, m.updated_at, v.distance
-                FROM memories m
-                JOIN vss_memories v ON m.rowid = v.rowid
-                WHERE vss_search(v.embedding, ?1)
-                ORDER BY v.distance ASC
-                LIMIT ?2
-                "#.to_string(),
-                vec![Box::new(query_blob), Box::new(limit as i32)]
-            )
-        } else {
-            (
-                r#"
-                SELECT m.id, m.org, m.agent_id, m.content, m.embedding, m.metadata, m.created_at, m.updated_at, v.distance
-                FROM memories m
-                JOIN vss_memories v ON m.rowid = v.rowid
-                WHERE vss_search(v.embedding, ?1) AND m.org = ?2
-                ORDER BY v.distance ASC
-                LIMIT ?3
-                "#.to_string(),
-                vec![Box::new(query_blob), Box::new(org.to_string()), Box::new(limit as i32)]
-            )
-        };
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![
+            Box::new(Self::embedding_to_blob(query_embedding)),
+            Box::new(embedding_model.map(str::to_string)),
+            Box::new(query_embedding.len() as i64),
+        ];
+        let mut query = format!(
+            r#"
+            SELECT {}, v.distance
+            FROM memories m
+            JOIN vss_memories v ON m.rowid = v.rowid
+            WHERE vss_search(v.embedding, ?1) AND m.embedding_model IS ?2 AND m.embedding_dim = ?3
+            "#,
+            MEMORY_COLUMNS_M
+        );
+        query.push_str(&format!(" AND {}", unexpired("m.expires_at", &mut params)));
+        if org != ORG_ALL {
+            params.push(Box::new(org.to_string()));
+            query.push_str(&format!(" AND m.org = ?{}", params.len()));
+        }
+        params.push(Box::new(limit as i32));
+        query.push_str(&format!(" ORDER BY v.distance ASC LIMIT ?{}", params.len()));
 
         let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
         let mut stmt = conn.prepare(&query)?;
@@ -553,27 +1167,8 @@ impl SharedMemory {
         let results = stmt
             .query_map(params_refs.as_slice(), |row| {
                 Ok(MemorySearchResult {
-                    memory: Memory {
-                        id: row.get(0)?,
-                        org: row.get(1)?,
-                        agent_id: row.get(2)?,
-                        content: row.get(3)?,
-                        embedding: row
-                            .get::<_, Option<Vec<u8>>>(4)?
-                            .map(|b| Self::blob_to_embedding(&b)),
-                        metadata: row
-                            .get::<_, Option<String>>(5)?
-                            .map(|s| serde_json::from_str(&s))
-                            .transpose()
-                            .unwrap_or(None),
-                        created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(6)?)
-                            .map(|dt| dt.with_timezone(&Utc))
-                            .unwrap_or_else(|_| Utc::now()),
-                        updated_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(7)?)
-                            .map(|dt| dt.with_timezone(&Utc))
-                            .unwrap_or_else(|_| Utc::now()),
-                    },
-                    similarity: 1.0 - row.get::<_, f32>(8)?,
+                    memory: Self::row_to_memory(row)?,
+                    similarity: 1.0 - row.get::<_, f32>(11)?,
                 })
             })?
             .collect::<Result<Vec<_>, _>>()?;
@@ -581,11 +1176,13 @@ impl SharedMemory {
         Ok(results)
     }
 
-    /// Fallback search using cosine similarity in memory (when VSS not available)
+    /// Brute-force search computing cosine similarity with every stored
+    /// vector (when neither VSS nor the ANN index is available)
     fn search_memories_fallback(
         &self,
         org: &str,
         query_embedding: &[f32],
+        embedding_model: Option<&str>,
         limit: usize,
     ) -> Result<Vec<MemorySearchResult>> {
         let conn = self
@@ -593,76 +1190,35 @@ impl SharedMemory {
             .lock()
             .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
 
-        // Get memories with embeddings, filtered by org if not ORG_ALL
-        let query = if org == ORG_ALL {
-            "SELECT id, org, agent_id, content, embedding, metadata, created_at, updated_at FROM memories WHERE embedding IS NOT NULL".to_string()
-        } else {
-            "SELECT id, org, agent_id, content, embedding, metadata, created_at, updated_at FROM memories WHERE embedding IS NOT NULL AND org = ?1".to_string()
-        };
+        // Get memories embedded in the query's space, filtered by org if not ORG_ALL
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![
+            Box::new(embedding_model.map(str::to_string)),
+            Box::new(query_embedding.len() as i64),
+        ];
+        let mut query = format!(
+            "SELECT {} FROM memories WHERE embedding IS NOT NULL AND embedding_model IS ?1 AND embedding_dim = ?2",
+            MEMORY_COLUMNS
+        );
+        query.push_str(&format!(" AND {}", unexpired("expires_at", &mut params)));
+        if org != ORG_ALL {
+            params.push(Box::new(org.to_string()));
+            query.push_str(&format!(" AND org = ?{}", params.len()));
+        }
 
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
         let mut stmt = conn.prepare(&query)?;
-
-        let memories = if org == ORG_ALL {
-            stmt.query_map([], |row| {
-                let embedding_blob: Vec<u8> = row.get(4)?;
-                let embedding = Self::blob_to_embedding(&embedding_blob);
-                Ok((
-                    Memory {
-                        id: row.get(0)?,
-                        org: row.get(1)?,
-                        agent_id: row.get(2)?,
-                        content: row.get(3)?,
-                        embedding: Some(embedding.clone()),
-                        metadata: row
-                            .get::<_, Option<String>>(5)?
-                            .map(|s| serde_json::from_str(&s))
-                            .transpose()
-                            .unwrap_or(None),
-                        created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(6)?)
-                            .map(|dt| dt.with_timezone(&Utc))
-                            .unwrap_or_else(|_| Utc::now()),
-                        updated_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(7)?)
-                            .map(|dt| dt.with_timezone(&Utc))
-                            .unwrap_or_else(|_| Utc::now()),
-                    },
-                    embedding,
-                ))
-            })?
-            .collect::<Result<Vec<_>, _>>()?
-        } else {
-            stmt.query_map(params![org], |row| {
-                let embedding_blob: Vec<u8> = row.get(4)?;
-                let embedding = Self::blob_to_embedding(&embedding_blob);
-                Ok((
-                    Memory {
-                        id: row.get(0)?,
-                        org: row.get(1)?,
-                        agent_id: row.get(2)?,
-                        content: row.get(3)?,
-                        embedding: Some(embedding.clone()),
-                        metadata: row
-                            .get::<_, Option<String>>(5)?
-                            .map(|s| serde_json::from_str(&s))
-                            .transpose()
-                            .unwrap_or(None),
-                        created_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(6)?)
-                            .map(|dt| dt.with_timezone(&Utc))
-                            .unwrap_or_else(|_| Utc::now()),
-                        updated_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(7)?)
-                            .map(|dt| dt.with_timezone(&Utc))
-                            .unwrap_or_else(|_| Utc::now()),
-                    },
-                    embedding,
-                ))
-            })?
-            .collect::<Result<Vec<_>, _>>()?
-        };
+        let memories = stmt
+            .query_map(params_refs.as_slice(), Self::row_to_memory)?
+            .collect::<Result<Vec<_>, _>>()?;
 
         // Calculate similarities
         let mut results: Vec<MemorySearchResult> = memories
             .into_iter()
-            .map(|(memory, embedding)| {
-                let similarity = Self::cosine_similarity(query_embedding, &embedding);
+            .map(|memory| {
+                let similarity = memory
+                    .embedding
+                    .as_deref()
+                    .map_or(0.0, |e| Self::cosine_similarity(query_embedding, e));
                 MemorySearchResult { memory, similarity }
             })
             .collect();
@@ -678,6 +1234,193 @@ impl SharedMemory {
         Ok(results)
     }
 
+    /// Hybrid search: see `MemoryQuery`
+    ///
+    /// # Arguments
+    /// * `org` - Organization namespace. Use ORG_ALL to search across all orgs.
+    /// * `query` - What to look for and the filter results must match
+    pub fn search(&self, org: &str, query: &MemoryQuery) -> Result<Vec<MemorySearchResult>> {
+        let text = query.text.as_deref().map(str::trim).filter(|t| !t.is_empty());
+        let fts = text.and_then(fts_query);
+        let hybrid = fts.is_some() && query.embedding.is_some();
+        let candidates = if hybrid {
+            (query.limit * HYBRID_CANDIDATES_PER_RESULT).max(MIN_HYBRID_CANDIDATES)
+        } else {
+            query.limit
+        };
+
+        let keyword = fts
+            .map(|fts| self.search_fts(org, &fts, &query.filter, candidates))
+            .transpose()?;
+        let vector = query
+            .embedding
+            .as_deref()
+            .map(|e| self.search_vector_filtered(org, e, query.embedding_model.as_deref(), &query.filter, candidates))
+            .transpose()?;
+
+        match (keyword, vector) {
+            (Some(keyword), Some(vector)) => Ok(fuse_rankings(vec![keyword, vector], query.limit)),
+            (Some(results), None) | (None, Some(results)) => Ok(results),
+            // Keywords that were all too short match nothing
+            (None, None) if text.is_some() => Ok(Vec::new()),
+            (None, None) => self.list_filtered(org, &query.filter, query.limit),
+        }
+    }
+
+    /// Keyword search through FTS5, best BM25 rank first
+    fn search_fts(
+        &self,
+        org: &str,
+        fts: &str,
+        filter: &MemoryFilter,
+        limit: usize,
+    ) -> Result<Vec<MemorySearchResult>> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(fts.to_string())];
+        let conditions = filter.sql(org, &mut params)?;
+        params.push(Box::new(limit as i64));
+        let query = format!(
+            "SELECT {}, bm25(memories_fts) AS score
+             FROM memories_fts JOIN memories m ON m.id = memories_fts.rowid
+             WHERE memories_fts MATCH ?1 AND {}
+             ORDER BY score, m.created_at DESC LIMIT ?{}",
+            MEMORY_COLUMNS_M,
+            conditions,
+            params.len()
+        );
+
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+        let mut stmt = conn.prepare(&query)?;
+        let results = stmt
+            .query_map(params_refs.as_slice(), |row| {
+                Ok(MemorySearchResult {
+                    memory: Self::row_to_memory(row)?,
+                    similarity: -row.get::<_, f64>(11)? as f32,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(results)
+    }
+
+    /// Vector search within a filter. Few enough matching rows are scored
+    /// exactly; otherwise the index search is overfetched and narrowed.
+    fn search_vector_filtered(
+        &self,
+        org: &str,
+        query_embedding: &[f32],
+        embedding_model: Option<&str>,
+        filter: &MemoryFilter,
+        limit: usize,
+    ) -> Result<Vec<MemorySearchResult>> {
+        if filter.is_empty() {
+            return self.search_memories(org, query_embedding, embedding_model, limit);
+        }
+
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![
+            Box::new(embedding_model.map(str::to_string)),
+            Box::new(query_embedding.len() as i64),
+        ];
+        let conditions = filter.sql(org, &mut params)?;
+        let in_space = "m.embedding IS NOT NULL AND m.embedding_model IS ?1 AND m.embedding_dim = ?2";
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+        let matching: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM memories m WHERE {} AND {}", in_space, conditions),
+            params_refs.as_slice(),
+            |row| row.get(0),
+        )?;
+
+        if matching <= FILTERED_SCAN_MAX {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM memories m WHERE {} AND {}",
+                MEMORY_COLUMNS_M, in_space, conditions
+            ))?;
+            let mut results: Vec<MemorySearchResult> = stmt
+                .query_map(params_refs.as_slice(), Self::row_to_memory)?
+                .map(|memory| {
+                    memory.map(|memory| {
+                        let similarity = memory
+                            .embedding
+                            .as_deref()
+                            .map_or(0.0, |e| Self::cosine_similarity(query_embedding, e));
+                        MemorySearchResult { memory, similarity }
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            results.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+            results.truncate(limit);
+            return Ok(results);
+        }
+        drop(conn);
+
+        let mut results = self.search_memories(org, query_embedding, embedding_model, limit * FILTERED_OVERFETCH)?;
+        let ids: Vec<i64> = results.iter().map(|r| r.memory.id).collect();
+        let kept = self.ids_matching(org, filter, &ids)?;
+        results.retain(|r| kept.contains(&r.memory.id));
+        results.truncate(limit);
+        Ok(results)
+    }
+
+    /// Which of `ids` match a filter within `org`
+    fn ids_matching(&self, org: &str, filter: &MemoryFilter, ids: &[i64]) -> Result<HashSet<i64>> {
+        if ids.is_empty() {
+            return Ok(HashSet::new());
+        }
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> =
+            ids.iter().map(|&id| Box::new(id) as Box<dyn rusqlite::ToSql>).collect();
+        let placeholders = (1..=ids.len()).map(|i| format!("?{}", i)).collect::<Vec<_>>().join(", ");
+        let conditions = filter.sql(org, &mut params)?;
+        let query = format!("SELECT m.id FROM memories m WHERE m.id IN ({}) AND {}", placeholders, conditions);
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+        let mut stmt = conn.prepare(&query)?;
+        let ids = stmt
+            .query_map(params_refs.as_slice(), |row| row.get(0))?
+            .collect::<Result<HashSet<_>, _>>()?;
+        Ok(ids)
+    }
+
+    /// Memories matching a filter, newest first
+    fn list_filtered(&self, org: &str, filter: &MemoryFilter, limit: usize) -> Result<Vec<MemorySearchResult>> {
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        let conditions = filter.sql(org, &mut params)?;
+        params.push(Box::new(limit as i64));
+        let query = format!(
+            "SELECT {} FROM memories m WHERE {} ORDER BY m.created_at DESC, m.id DESC LIMIT ?{}",
+            MEMORY_COLUMNS_M,
+            conditions,
+            params.len()
+        );
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+        let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+        let mut stmt = conn.prepare(&query)?;
+        let results = stmt
+            .query_map(params_refs.as_slice(), |row| {
+                Ok(MemorySearchResult {
+                    memory: Self::row_to_memory(row)?,
+                    similarity: 0.0,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(results)
+    }
+
     /// List all memories (optionally filtered by org and agent_id)
     ///
     /// # Arguments
@@ -689,34 +1432,22 @@ impl SharedMemory {
             .lock()
             .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
 
-        let (query, params): (String, Vec<Box<dyn rusqlite::ToSql>>) = match (org, agent_id) {
-            // All orgs (None or ORG_ALL)
-            (None, None) | (Some(ORG_ALL), None) => (
-                "SELECT id, org, agent_id, content, embedding, metadata, created_at, updated_at 
-                 FROM memories ORDER BY created_at DESC"
-                    .to_string(),
-                vec![],
-            ),
-            (None, Some(a)) | (Some(ORG_ALL), Some(a)) => (
-                "SELECT id, org, agent_id, content, embedding, metadata, created_at, updated_at 
-                 FROM memories WHERE agent_id = ?1 ORDER BY created_at DESC"
-                    .to_string(),
-                vec![Box::new(a.to_string())],
-            ),
-            // Specific org
-            (Some(o), None) => (
-                "SELECT id, org, agent_id, content, embedding, metadata, created_at, updated_at 
-                 FROM memories WHERE org = ?1 ORDER BY created_at DESC"
-                    .to_string(),
-                vec![Box::new(o.to_string())],
-            ),
-            (Some(o), Some(a)) => (
-                "SELECT id, org, agent_id, content, embedding, metadata, created_at, updated_at 
-                 FROM memories WHERE org = ?1 AND agent_id = ?2 ORDER BY created_at DESC"
-                    .to_string(),
-                vec![Box::new(o.to_string()), Box::new(a.to_string())],
-            ),
-        };
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        let mut query = format!(
+            "SELECT {} FROM memories WHERE {}",
+            MEMORY_COLUMNS,
+            unexpired("expires_at", &mut params)
+        );
+        // ORG_ALL (or None) lists across every org
+        if let Some(o) = org.filter(|o| *o != ORG_ALL) {
+            params.push(Box::new(o.to_string()));
+            query.push_str(&format!(" AND org = ?{}", params.len()));
+        }
+        if let Some(a) = agent_id {
+            params.push(Box::new(a.to_string()));
+            query.push_str(&format!(" AND agent_id = ?{}", params.len()));
+        }
+        query.push_str(" ORDER BY created_at DESC");
 
         let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
         let mut stmt = conn.prepare(&query)?;
@@ -735,7 +1466,7 @@ impl SharedMemory {
             .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
 
         let mut stmt = conn.prepare(
-            "SELECT id, org, agent_id, content, embedding, metadata, created_at, updated_at 
+            "SELECT id, org, agent_id, content, embedding, metadata, created_at, updated_at, embedding_model, embedding_dim, expires_at
              FROM memories WHERE id = ?1",
         )?;
 
@@ -758,6 +1489,9 @@ impl SharedMemory {
         if self.vss_enabled && rows_affected > 0 {
             let _ = conn.execute("DELETE FROM vss_memories WHERE rowid = ?1", params![id]);
         }
+        if rows_affected > 0 {
+            self.unindex_memories(&[id])?;
+        }
 
         Ok(rows_affected > 0)
     }
@@ -768,33 +1502,66 @@ impl SharedMemory {
     /// * `org` - Organization namespace
     /// * `agent_id` - The agent ID to delete memories for
     pub fn delete_agent_memories(&self, org: &str, agent_id: &str) -> Result<usize> {
+        self.delete_where("org = ?1 AND agent_id = ?2", params![org, agent_id])
+            .map(|ids| ids.len())
+    }
+
+    /// Delete the memories whose `expires_at` has passed
+    pub fn delete_expired(&self) -> Result<usize> {
+        self.delete_where(
+            "expires_at IS NOT NULL AND expires_at <= ?1",
+            params![Utc::now().to_rfc3339()],
+        )
+        .map(|ids| ids.len())
+    }
+
+    /// Delete an org's memories created before `cutoff`
+    pub fn delete_older_than(&self, org: &str, cutoff: DateTime<Utc>) -> Result<usize> {
+        self.delete_where("org = ?1 AND created_at < ?2", params![org, cutoff.to_rfc3339()])
+            .map(|ids| ids.len())
+    }
+
+    /// Delete the memories matching an SQL condition, keeping VSS and the
+    /// ANN index in sync. Returns the deleted IDs.
+    fn delete_where(&self, condition: &str, params: &[&dyn rusqlite::ToSql]) -> Result<Vec<i64>> {
         let conn = self
             .conn
             .lock()
             .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
 
-        // Get IDs first for VSS cleanup
-        let ids: Vec<i64> = if self.vss_enabled {
-            conn.prepare("SELECT id FROM memories WHERE org = ?1 AND agent_id = ?2")?
-                .query_map(params![org, agent_id], |row| row.get(0))?
-                .collect::<Result<Vec<_>, _>>()?
-        } else {
-            vec![]
-        };
+        // Get IDs first for VSS and ANN index cleanup
+        let ids: Vec<i64> = conn
+            .prepare(&format!("SELECT id FROM memories WHERE {}", condition))?
+            .query_map(params, |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        if ids.is_empty() {
+            return Ok(ids);
+        }
 
-        let rows_affected = conn.execute(
-            "DELETE FROM memories WHERE org = ?1 AND agent_id = ?2",
-            params![org, agent_id],
-        )?;
+        conn.execute(&format!("DELETE FROM memories WHERE {}", condition), params)?;
 
         // Clean up VSS table
         if self.vss_enabled {
-            for id in ids {
+            for &id in &ids {
                 let _ = conn.execute("DELETE FROM vss_memories WHERE rowid = ?1", params![id]);
             }
         }
+        self.unindex_memories(&ids)?;
+        Ok(ids)
+    }
 
-        Ok(rows_affected)
+    /// Every org that has memories
+    pub fn orgs(&self) -> Result<Vec<String>> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+
+        let mut stmt = conn.prepare("SELECT DISTINCT org FROM memories ORDER BY org")?;
+        let orgs = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(orgs)
     }
 
     // ========================================================================
@@ -924,6 +1691,43 @@ impl SharedMemory {
         Ok(tasks)
     }
 
+    /// Tasks pushed after `after_id`, oldest first.
+    ///
+    /// Task IDs only grow, so callers can poll with the last ID they saw to
+    /// pick up pushes from any process sharing the database.
+    pub fn tasks_after(&self, after_id: i64) -> Result<Vec<Task>> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, from_agent, to_agent, task_type, payload, priority, status, created_at, claimed_at, completed_at FROM tasks WHERE id > ?1 ORDER BY id ASC",
+        )?;
+        let tasks = stmt
+            .query_map(params![after_id], Self::row_to_task)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(tasks)
+    }
+
+    /// Get a specific task by ID
+    pub fn get_task(&self, id: i64) -> Result<Option<Task>> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Lock error: {}", e))?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, from_agent, to_agent, task_type, payload, priority, status, created_at, claimed_at, completed_at FROM tasks WHERE id = ?1",
+        )?;
+
+        stmt.query_row(params![id], Self::row_to_task)
+            .optional()
+            .map_err(SharedMemoryError::from)
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
     /// Update task status
     pub fn update_task_status(&self, task_id: i64, status: TaskStatus) -> Result<()> {
         let conn = self
@@ -1134,6 +1938,12 @@ impl SharedMemory {
             updated_at: DateTime::parse_from_rfc3339(&row.get::<_, String>(7)?)
                 .map(|dt| dt.with_timezone(&Utc))
                 .unwrap_or_else(|_| Utc::now()),
+            embedding_model: row.get(8)?,
+            embedding_dim: row.get::<_, Option<i64>>(9)?.map(|d| d as usize),
+            expires_at: row
+                .get::<_, Option<String>>(10)?
+                .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
+                .map(|dt| dt.with_timezone(&Utc)),
         })
     }
 
@@ -1193,6 +2003,7 @@ mod tests {
             database_path: db_path,
             vss_extension_path: None,
             embedding_dim: 4, // Small for testing
+            dedup_threshold: None,
         })
         .expect("Failed to create test memory")
     }
@@ -1206,6 +2017,8 @@ mod tests {
             agent_id: "agent-1".to_string(),
             content: "Test memory content".to_string(),
             embedding: Some(vec![0.1, 0.2, 0.3, 0.4]),
+            embedding_model: None,
+            expires_at: None,
             metadata: Some(serde_json::json!({"key": "value"})),
         };
 
@@ -1233,6 +2046,8 @@ mod tests {
             agent_id: "agent-1".to_string(),
             content: "Test memory content".to_string(),
             embedding: None,
+            embedding_model: None,
+            expires_at: None,
             metadata: None,
         };
 
@@ -1255,6 +2070,8 @@ mod tests {
                 agent_id: "agent-1".to_string(),
                 content: "First memory".to_string(),
                 embedding: Some(vec![1.0, 0.0, 0.0, 0.0]),
+                embedding_model: None,
+                expires_at: None,
                 metadata: None,
             },
         )
@@ -1267,6 +2084,8 @@ mod tests {
                 agent_id: "agent-2".to_string(),
                 content: "Second memory".to_string(),
                 embedding: Some(vec![0.0, 1.0, 0.0, 0.0]),
+                embedding_model: None,
+                expires_at: None,
                 metadata: None,
             },
         )
@@ -1279,6 +2098,8 @@ mod tests {
                 agent_id: "agent-3".to_string(),
                 content: "Third memory (different org)".to_string(),
                 embedding: Some(vec![1.0, 0.0, 0.0, 0.0]),
+                embedding_model: None,
+                expires_at: None,
                 metadata: None,
             },
         )
@@ -1286,7 +2107,7 @@ mod tests {
 
         // Search with a query similar to first memory, scoped to org-1
         let results = mem
-            .search_memories("org-1", &[0.9, 0.1, 0.0, 0.0], 10)
+            .search_memories("org-1", &[0.9, 0.1, 0.0, 0.0], None, 10)
             .expect("Search failed");
 
         assert_eq!(results.len(), 2);
@@ -1295,17 +2116,51 @@ mod tests {
 
         // Search across all orgs
         let all_results = mem
-            .search_memories(ORG_ALL, &[0.9, 0.1, 0.0, 0.0], 10)
+            .search_memories(ORG_ALL, &[0.9, 0.1, 0.0, 0.0], None, 10)
             .expect("Search failed");
         assert_eq!(all_results.len(), 3);
 
         // Search common org (should be empty)
         let common_results = mem
-            .search_memories(ORG_COMMON, &[0.9, 0.1, 0.0, 0.0], 10)
+            .search_memories(ORG_COMMON, &[0.9, 0.1, 0.0, 0.0], None, 10)
             .expect("Search failed");
         assert_eq!(common_results.len(), 0);
     }
 
+    #[test]
+    fn test_embedding_models_are_not_mixed() {
+        let mem = create_test_memory();
+        let store = |embedding: Vec<f32>, model: Option<&str>| {
+            mem.store_memory(
+                None,
+                &NewMemory {
+                    org: None,
+                    agent_id: "agent-1".to_string(),
+                    content: "memory".to_string(),
+                    embedding: Some(embedding),
+                    embedding_model: model.map(str::to_string),
+                    expires_at: None,
+                    metadata: None,
+                },
+            )
+        };
+
+        let a = store(vec![1.0, 0.0, 0.0], Some("model-a")).unwrap();
+        let b = store(vec![1.0, 0.0, 0.0], Some("model-b")).unwrap();
+        store(vec![1.0, 0.0], None).unwrap();
+        // A model keeps one dimension
+        assert!(store(vec![1.0, 0.0], Some("model-a")).is_err());
+
+        let stored = mem.get_memory(a).unwrap().unwrap();
+        assert_eq!(stored.embedding_model.as_deref(), Some("model-a"));
+        assert_eq!(stored.embedding_dim, Some(3));
+
+        let results = mem.search_memories(ORG_ALL, &[1.0, 0.0, 0.0], Some("model-b"), 10).unwrap();
+        assert_eq!(results.iter().map(|r| r.memory.id).collect::<Vec<_>>(), vec![b]);
+        assert_eq!(mem.search_memories(ORG_ALL, &[1.0, 0.0], None, 10).unwrap().len(), 1);
+        assert!(mem.search_memories(ORG_ALL, &[1.0, 0.0, 0.0], None, 10).unwrap().is_empty());
+    }
+
     #[test]
     fn test_task_queue() {
         let mem = create_test_memory();
@@ -1339,6 +2194,39 @@ mod tests {
         // Pop for specific agent
         let popped2 = mem.pop_task(Some("agent-2")).unwrap().unwrap();
         assert_eq!(popped2.id, task1_id);
+
+        mem.update_task_status(task1_id, TaskStatus::InProgress).unwrap();
+        let task = mem.get_task(task1_id).unwrap().unwrap();
+        assert_eq!(task.status, TaskStatus::InProgress);
+        // Serialized the same way the API accepts it
+        assert_eq!(serde_json::to_value(&task.status).unwrap(), "in_progress");
+        assert!(mem.get_task(task2_id + 1).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_tasks_after() {
+        let mem = create_test_memory();
+        let push = |task_type: &str| {
+            mem.push_task(&NewTask {
+                from_agent: "agent-1".to_string(),
+                to_agent: None,
+                task_type: task_type.to_string(),
+                payload: None,
+                priority: 0,
+            })
+            .unwrap()
+        };
+
+        let first = push("index");
+        let second = push("summarize");
+
+        let all: Vec<i64> = mem.tasks_after(0).unwrap().iter().map(|t| t.id).collect();
+        assert_eq!(all, vec![first, second]);
+
+        let newer = mem.tasks_after(first).unwrap();
+        assert_eq!(newer.len(), 1);
+        assert_eq!(newer[0].task_type, "summarize");
+        assert!(mem.tasks_after(second).unwrap().is_empty());
     }
 
     #[test]
@@ -1369,6 +2257,8 @@ mod tests {
                     agent_id: "agent-1".to_string(),
                     content: "To be deleted".to_string(),
                     embedding: None,
+                    embedding_model: None,
+                    expires_at: None,
                     metadata: None,
                 },
             )
@@ -1391,6 +2281,8 @@ mod tests {
                 agent_id: "agent-1".to_string(),
                 content: "Memory 1".to_string(),
                 embedding: None,
+                embedding_model: None,
+                expires_at: None,
                 metadata: None,
             },
         )
@@ -1403,6 +2295,8 @@ mod tests {
                 agent_id: "agent-1".to_string(),
                 content: "Memory 2".to_string(),
                 embedding: None,
+                embedding_model: None,
+                expires_at: None,
                 metadata: None,
             },
         )
@@ -1416,6 +2310,8 @@ mod tests {
                 agent_id: "agent-1".to_string(),
                 content: "Memory 3".to_string(),
                 embedding: None,
+                embedding_model: None,
+                expires_at: None,
                 metadata: None,
             },
         )
@@ -1434,6 +2330,133 @@ mod tests {
         assert_eq!(org2_memories.len(), 1);
     }
 
+    #[test]
+    fn test_expired_and_old_memories_are_deleted() {
+        let mem = create_test_memory();
+        let store = |org: &str, content: &str, expires_at| {
+            mem.store_memory(
+                Some(org),
+                &NewMemory {
+                    org: None,
+                    agent_id: "agent-1".to_string(),
+                    content: content.to_string(),
+                    embedding: Some(vec![0.1, 0.2, 0.3, 0.4]),
+                    embedding_model: None,
+                    metadata: None,
+                    expires_at,
+                },
+            )
+            .unwrap()
+        };
+        assert_eq!(NewMemory::parse_ttl("90").unwrap().num_seconds(), 90);
+        assert_eq!(NewMemory::parse_ttl("12h").unwrap().num_seconds(), 12 * 3600);
+        assert!(NewMemory::parse_ttl("3650d").is_ok());
+        for bad in ["0", "-5s", "7w", "d", "3651d", "100000000d", "9223372036854775807d"] {
+            assert!(NewMemory::parse_ttl(bad).is_err(), "{}", bad);
+        }
+
+        let expired = store("org-a", "expired", Some(Utc::now() - chrono::Duration::seconds(1)));
+        let pending = store("org-a", "expires later", Some(Utc::now() + chrono::Duration::hours(1)));
+        let old = store("org-a", "old", None);
+        let other_org = store("org-b", "old elsewhere", None);
+
+        // Expired memories are hidden even before they are swept
+        let listed: Vec<i64> = mem.list_all(Some("org-a"), None).unwrap().iter().map(|m| m.id).collect();
+        assert_eq!(listed.len(), 2);
+        assert!(!listed.contains(&expired));
+        let found = mem.search_memories("org-a", &[0.1, 0.2, 0.3, 0.4], None, 10).unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().all(|r| r.memory.id != expired));
+        let query = MemoryQuery {
+            text: Some("expired".to_string()),
+            limit: 10,
+            ..MemoryQuery::default()
+        };
+        let found = mem.search("org-a", &query).unwrap();
+        assert_eq!(found.iter().map(|r| r.memory.id).collect::<Vec<_>>(), vec![pending]);
+
+        assert_eq!(mem.delete_expired().unwrap(), 1);
+        assert!(mem.get_memory(expired).unwrap().is_none());
+        assert!(mem.get_memory(pending).unwrap().unwrap().expires_at.is_some());
+
+        {
+            let conn = mem.conn.lock().unwrap();
+            let long_ago = (Utc::now() - chrono::Duration::days(90)).to_rfc3339();
+            conn.execute(
+                "UPDATE memories SET created_at = ?1 WHERE id IN (?2, ?3)",
+                params![long_ago, old, other_org],
+            )
+            .unwrap();
+        }
+        let cutoff = Utc::now() - chrono::Duration::days(30);
+        assert_eq!(mem.delete_older_than("org-a", cutoff).unwrap(), 1);
+        assert!(mem.get_memory(old).unwrap().is_none());
+        assert!(mem.get_memory(other_org).unwrap().is_some());
+        assert_eq!(mem.orgs().unwrap(), vec!["org-a", "org-b"]);
+        assert_eq!(mem.search_memories("org-a", &[0.1, 0.2, 0.3, 0.4], None, 10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_near_duplicates_are_merged_and_memories_replaced() {
+        let dir = tempdir().unwrap();
+        let mem = SharedMemory::with_config(SharedMemoryConfig {
+            database_path: dir.path().join("test.db"),
+            vss_extension_path: None,
+            embedding_dim: 4,
+            dedup_threshold: Some(0.95),
+        })
+        .unwrap();
+        let new = |content: &str, embedding: Vec<f32>, metadata, expires_at| NewMemory {
+            org: Some("org-a".to_string()),
+            agent_id: "agent-1".to_string(),
+            content: content.to_string(),
+            embedding: Some(embedding),
+            embedding_model: None,
+            metadata: Some(metadata),
+            expires_at,
+        };
+        let later = Utc::now() + chrono::Duration::hours(2);
+
+        let first = new("deploys on Fridays", vec![1.0, 0.0, 0.0, 0.0], serde_json::json!({"a": 1, "b": 1}), None);
+        let first = mem.store_memory(None, &first).unwrap();
+        let update = new("deploys on Fridays at 5pm", vec![0.99, 0.05, 0.0, 0.0], serde_json::json!({"b": 2}), Some(later));
+        let merged = mem.store_memory(None, &update).unwrap();
+        let other = new("uses Postgres", vec![0.0, 1.0, 0.0, 0.0], serde_json::json!({}), None);
+        let distinct = mem.store_memory(None, &other).unwrap();
+        assert_eq!(merged, first);
+        assert_ne!(distinct, first);
+
+        // Another agent's near-duplicate is its own memory
+        let mut elsewhere = new("deploys on Fridays at noon", vec![0.99, 0.0, 0.05, 0.0], serde_json::json!({}), None);
+        elsewhere.agent_id = "agent-2".to_string();
+        let elsewhere = mem.store_memory(None, &elsewhere).unwrap();
+        assert_ne!(elsewhere, first);
+        assert_eq!(mem.get_memory(elsewhere).unwrap().unwrap().agent_id, "agent-2");
+
+        let memory = mem.get_memory(first).unwrap().unwrap();
+        assert_eq!(memory.content, "deploys on Fridays at 5pm");
+        assert_eq!(memory.metadata, Some(serde_json::json!({"a": 1, "b": 2})));
+        assert_eq!(memory.agent_id, "agent-1");
+        assert_eq!(memory.expires_at.map(|t| t.timestamp()), Some(later.timestamp()));
+        assert_eq!(mem.list_all(Some("org-a"), None).unwrap().len(), 3);
+
+        // The later expiry is kept, and a duplicate without one changes nothing
+        let sooner = new("deploys on Fridays", vec![1.0, 0.0, 0.0, 0.0], serde_json::json!({}), Some(Utc::now() + chrono::Duration::hours(1)));
+        assert_eq!(mem.store_memory(None, &sooner).unwrap(), first);
+        let no_ttl = new("deploys on Fridays", vec![1.0, 0.0, 0.0, 0.0], serde_json::json!({}), None);
+        assert_eq!(mem.store_memory(None, &no_ttl).unwrap(), first);
+        let memory = mem.get_memory(first).unwrap().unwrap();
+        assert_eq!(memory.expires_at.map(|t| t.timestamp()), Some(later.timestamp()));
+
+        let summary = new("deploys Fridays at 5pm; uses Postgres", vec![0.7, 0.7, 0.0, 0.0], serde_json::json!({}), None);
+        let summary = mem.replace_memories("org-a", &[first, distinct, elsewhere], &summary).unwrap();
+        let all = mem.list_all(Some("org-a"), None).unwrap();
+        assert_eq!(all.iter().map(|m| m.id).collect::<Vec<_>>(), vec![summary]);
+        let found = mem.search_memories("org-a", &[1.0, 0.0, 0.0, 0.0], None, 10).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].memory.id, summary);
+    }
+
     #[test]
     fn test_list_all_with_org_filter() {
         let mem = create_test_memory();
@@ -1445,6 +2468,8 @@ mod tests {
                 agent_id: "agent-1".to_string(),
                 content: "Memory A1".to_string(),
                 embedding: None,
+                embedding_model: None,
+                expires_at: None,
                 metadata: None,
             },
         )
@@ -1457,6 +2482,8 @@ mod tests {
                 agent_id: "agent-2".to_string(),
                 content: "Memory A2".to_string(),
                 embedding: None,
+                embedding_model: None,
+                expires_at: None,
                 metadata: None,
             },
         )
@@ -1469,6 +2496,8 @@ mod tests {
                 agent_id: "agent-1".to_string(),
                 content: "Memory B1".to_string(),
                 embedding: None,
+                embedding_model: None,
+                expires_at: None,
                 metadata: None,
             },
         )
@@ -1491,4 +2520,282 @@ mod tests {
         assert_eq!(org_a_agent1.len(), 1);
         assert_eq!(org_a_agent1[0].content, "Memory A1");
     }
+
+    #[test]
+    fn test_hybrid_search_with_filters() {
+        let mem = create_test_memory();
+        let store = |content: &str, embedding: [f32; 4], metadata: serde_json::Value| {
+            mem.store_memory(
+                None,
+                &NewMemory {
+                    org: Some("org-a".to_string()),
+                    agent_id: "agent-1".to_string(),
+                    content: content.to_string(),
+                    embedding: Some(embedding.to_vec()),
+                    embedding_model: Some("test".to_string()),
+                    expires_at: None,
+                    metadata: Some(metadata),
+                },
+            )
+            .unwrap()
+        };
+        let cache = store(
+            "Decided to cache rendered pages for an hour",
+            [1.0, 0.0, 0.0, 0.0],
+            serde_json::json!({"type": "decision", "tags": ["project-x", "perf"]}),
+        );
+        let old = store(
+            "Decided against caching sessions",
+            [0.9, 0.1, 0.0, 0.0],
+            serde_json::json!({"type": "decision", "tags": ["project-x"]}),
+        );
+        let other = store(
+            "Caching notes for project Y",
+            [0.0, 1.0, 0.0, 0.0],
+            serde_json::json!({"type": "note", "tags": ["project-y"], "owner": {"team": "web"}}),
+        );
+        let vector_only = store("Latency budget for the front end", [0.95, 0.0, 0.1, 0.0], serde_json::json!({}));
+        {
+            let conn = mem.conn.lock().unwrap();
+            let month_ago = (Utc::now() - chrono::Duration::days(30)).to_rfc3339();
+            conn.execute("UPDATE memories SET created_at = ?1 WHERE id = ?2", params![month_ago, old])
+                .unwrap();
+        }
+        let ids = |results: Vec<MemorySearchResult>| -> Vec<i64> {
+            results.into_iter().map(|r| r.memory.id).collect()
+        };
+        let query = |text: Option<&str>, embedding: Option<[f32; 4]>, filter: MemoryFilter| MemoryQuery {
+            text: text.map(str::to_string),
+            embedding: embedding.map(|e| e.to_vec()),
+            embedding_model: embedding.map(|_| "test".to_string()),
+            filter,
+            limit: 10,
+        };
+
+        // Keywords are stemmed, so "caching" finds "cache"
+        let keyword = ids(mem.search("org-a", &query(Some("caching"), None, MemoryFilter::default())).unwrap());
+        assert_eq!(keyword.len(), 3);
+        assert!(keyword.contains(&cache));
+        assert!(mem.search(ORG_COMMON, &query(Some("caching"), None, MemoryFilter::default())).unwrap().is_empty());
+
+        // Decisions tagged project-x in the last week about caching
+        let filter = MemoryFilter {
+            metadata: serde_json::json!({"type": "decision", "tags": "project-x"})
+                .as_object()
+                .cloned()
+                .unwrap(),
+            created_after: Some(MemoryFilter::parse_time("7d").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(ids(mem.search("org-a", &query(Some("caching"), None, filter.clone())).unwrap()), [cache]);
+        assert_eq!(ids(mem.search("org-a", &query(None, Some([0.9, 0.1, 0.0, 0.0]), filter.clone())).unwrap()), [cache]);
+        assert_eq!(ids(mem.search("org-a", &query(None, None, filter)).unwrap()), [cache]);
+
+        let nested = MemoryFilter {
+            metadata: serde_json::json!({"owner.team": "web"}).as_object().cloned().unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(ids(mem.search(ORG_ALL, &query(None, None, nested)).unwrap()), [other]);
+        let before = MemoryFilter {
+            created_before: Some(MemoryFilter::parse_time("7d").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(ids(mem.search("org-a", &query(None, None, before)).unwrap()), [old]);
+        assert!(MemoryFilter::parse_time("100000000d").is_err());
+
+        // Fusion ranks what both keywords and vector agree on first, and
+        // keeps what only one of them found
+        let fused = ids(mem
+            .search("org-a", &query(Some("rendered pages"), Some([1.0, 0.0, 0.0, 0.0]), MemoryFilter::default()))
+            .unwrap());
+        assert_eq!(fused[0], cache);
+        assert!(fused.contains(&vector_only) && fused.contains(&other));
+
+        // Deletes leave the full-text index too
+        mem.delete(cache).unwrap();
+        assert!(!ids(mem.search("org-a", &query(Some("cache"), None, MemoryFilter::default())).unwrap()).contains(&cache));
+
+        let invalid = MemoryFilter {
+            metadata: serde_json::json!({"tags": ["a", "b"]}).as_object().cloned().unwrap(),
+            ..Default::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_full_text_index_is_filled_for_existing_databases() {
+        let dir = tempdir().unwrap();
+        let config = SharedMemoryConfig {
+            database_path: dir.path().join("memory.db"),
+            vss_extension_path: None,
+            embedding_dim: 4,
+            dedup_threshold: None,
+        };
+        let mem = SharedMemory::with_config(config.clone()).unwrap();
+        mem.store_memory(
+            None,
+            &NewMemory {
+                org: None,
+                agent_id: "agent-1".to_string(),
+                content: "Stored before full-text search".to_string(),
+                embedding: None,
+                embedding_model: None,
+                expires_at: None,
+                metadata: None,
+            },
+        )
+        .unwrap();
+        mem.conn
+            .lock()
+            .unwrap()
+            .execute_batch(
+                "DROP TRIGGER memories_fts_insert; DROP TRIGGER memories_fts_delete;
+                 DROP TRIGGER memories_fts_update; DROP TABLE memories_fts;",
+            )
+            .unwrap();
+        drop(mem);
+
+        let mem = SharedMemory::with_config(config).unwrap();
+        let query = MemoryQuery {
+            text: Some("search".to_string()),
+            limit: 10,
+            ..Default::default()
+        };
+        assert_eq!(mem.search(ORG_ALL, &query).unwrap().len(), 1);
+    }
+
+    fn store_vectors(mem: &SharedMemory, org: &str, vectors: &[Vec<f32>]) -> Vec<i64> {
+        vectors
+            .iter()
+            .enumerate()
+            .map(|(i, embedding)| {
+                mem.store_memory(
+                    None,
+                    &NewMemory {
+                        org: Some(org.to_string()),
+                        agent_id: format!("agent-{}", i % 3),
+                        content: format!("Memory {}", i),
+                        embedding: Some(embedding.clone()),
+                        embedding_model: Some("test".to_string()),
+                        expires_at: None,
+                        metadata: None,
+                    },
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ann_index_follows_the_table() {
+        use crate::ann_index::tests::clustered_vectors;
+
+        let dir = tempdir().unwrap();
+        let config = SharedMemoryConfig {
+            database_path: dir.path().join("memory.db"),
+            vss_extension_path: None,
+            embedding_dim: 8,
+            dedup_threshold: None,
+        };
+        let mem = SharedMemory::with_config(config.clone()).unwrap();
+        let mut vectors = clustered_vectors(405, 8, 7);
+        let queries = vectors.split_off(400);
+        let ids = store_vectors(&mem, "org-a", &vectors);
+        store_vectors(&mem, "org-b", &clustered_vectors(10, 8, 8));
+
+        let top = |mem: &SharedMemory, query: &[f32]| -> Vec<i64> {
+            let results = mem.search_memories("org-a", query, Some("test"), 5).unwrap();
+            assert!(results.iter().all(|r| r.memory.org == "org-a"));
+            results.iter().map(|r| r.memory.id).collect()
+        };
+        let exact = |mem: &SharedMemory, query: &[f32]| -> Vec<i64> {
+            let results = mem.search_memories_fallback("org-a", query, Some("test"), 5).unwrap();
+            results.iter().map(|r| r.memory.id).collect()
+        };
+        for query in &queries {
+            assert_eq!(top(&mem, query)[0], exact(&mem, query)[0]);
+        }
+
+        // Deletes and re-embeds reach the index
+        let nearest = top(&mem, &queries[0])[0];
+        mem.delete(nearest).unwrap();
+        assert!(!top(&mem, &queries[0]).contains(&nearest));
+        assert_eq!(mem.delete_agent_memories("org-a", "agent-0").unwrap(), 134);
+        assert!(mem
+            .search_memories("org-a", &queries[1], Some("test"), 20)
+            .unwrap()
+            .iter()
+            .all(|r| r.memory.agent_id != "agent-0"));
+        let moved = *ids.iter().rev().find(|&&id| id != nearest && (id - ids[0]) % 3 != 0).unwrap();
+        mem.set_embedding(moved, &queries[2], "test").unwrap();
+        assert_eq!(top(&mem, &queries[2])[0], moved);
+
+        // The index is saved and picked up again, including later changes
+        drop(mem);
+        assert!(dir.path().join("memory.ann").exists());
+        let mem = SharedMemory::with_config(config).unwrap();
+        assert_eq!(top(&mem, &queries[2])[0], moved);
+        for query in &queries {
+            assert_eq!(top(&mem, query)[0], exact(&mem, query)[0]);
+        }
+    }
+
+    /// Recall and latency of the ANN index against the brute-force scan.
+    /// `ANN_BENCH_SIZE` and `ANN_BENCH_DIM` set the data set.
+    #[test]
+    #[ignore]
+    fn ann_benchmark() {
+        use crate::ann_index::tests::clustered_vectors;
+        use std::time::{Duration, Instant};
+
+        let env = |name: &str, default: usize| -> usize {
+            std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+        };
+        let (size, dim, k) = (env("ANN_BENCH_SIZE", 20_000), env("ANN_BENCH_DIM", 384), 10);
+
+        let dir = tempdir().unwrap();
+        let config = SharedMemoryConfig {
+            database_path: dir.path().join("memory.db"),
+            vss_extension_path: None,
+            embedding_dim: dim,
+            dedup_threshold: None,
+        };
+        let mem = SharedMemory::with_config(config.clone()).unwrap();
+        {
+            let conn = mem.conn.lock().unwrap();
+            conn.execute_batch("PRAGMA synchronous = OFF").unwrap();
+        }
+        let started = Instant::now();
+        let mut vectors = clustered_vectors(size + 200, dim, 1);
+        let queries = vectors.split_off(size);
+        store_vectors(&mem, "bench", &vectors);
+        println!("stored and indexed {} {}-d vectors in {:?}", size, dim, started.elapsed());
+        drop(mem);
+
+        let started = Instant::now();
+        let mem = SharedMemory::with_config(config).unwrap();
+        println!("reopened with the saved index in {:?}", started.elapsed());
+
+        let (mut ann_time, mut scan_time) = (Duration::ZERO, Duration::ZERO);
+        let mut found = 0;
+        for query in &queries {
+            let started = Instant::now();
+            let approximate = mem.search_memories("bench", query, Some("test"), k).unwrap();
+            ann_time += started.elapsed();
+
+            let started = Instant::now();
+            let exact = mem.search_memories_fallback("bench", query, Some("test"), k).unwrap();
+            scan_time += started.elapsed();
+
+            found += approximate
+                .iter()
+                .filter(|a| exact.iter().any(|e| e.memory.id == a.memory.id))
+                .count();
+        }
+        let recall = found as f64 / (queries.len() * k) as f64;
+        println!("recall@{}: {:.3}", k, recall);
+        println!("ANN search:  {:?} per query", ann_time / queries.len() as u32);
+        println!("brute force: {:?} per query", scan_time / queries.len() as u32);
+        assert!(recall >= 0.9);
+    }
 }
@@ -0,0 +1,279 @@
+//! Expressions for workflow `Condition` steps
+//!
+//! A condition compares values drawn from earlier step outputs:
+//!
+//! ```text
+//! {{review.score}} >= 7 && {{review.verdict}} != "reject"
+//! {{research.findings}} contains "caching" || !{{draft.empty}}
+//! ```
+//!
+//! Operands are `{{placeholders}}` (resolved through the step's
+//! `TemplateScope`), quoted strings, or bare JSON literals (`7`, `true`,
+//! `null`); any other bare word is taken as a string. Supported operators,
+//! loosest-binding first: `||`, `&&`, then `==`, `!=`, `>`, `>=`, `<`, `<=`,
+//! `contains`, and prefix `!`. There are no parentheses. A lone operand is
+//! tested for truthiness: `false`, `null`, `0`, `""`, `[]` and `{}` are false.
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+use crate::step_template::TemplateScope;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Value(Value),
+    Placeholder(String),
+    Op(&'static str),
+}
+
+/// Evaluate a condition expression against a step's template scope
+pub fn evaluate(expression: &str, scope: &TemplateScope) -> Result<bool> {
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Err(anyhow!("Condition expression is empty"));
+    }
+
+    let mut parser = Parser { tokens: &tokens, pos: 0, scope };
+    let result = parser.or()?;
+    if parser.pos != tokens.len() {
+        return Err(anyhow!(
+            "Unexpected trailing input in condition '{}'",
+            expression
+        ));
+    }
+    Ok(result)
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>> {
+    const OPS: [&str; 10] = ["||", "&&", "==", "!=", ">=", "<=", ">", "<", "!", "contains"];
+
+    let mut tokens = Vec::new();
+    let mut rest = expression.trim_start();
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("{{") {
+            let end = after
+                .find("}}")
+                .ok_or_else(|| anyhow!("Unterminated placeholder in condition '{}'", expression))?;
+            tokens.push(Token::Placeholder(after[..end].trim().to_string()));
+            rest = &after[end + 2..];
+        } else if let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') {
+            let body = &rest[1..];
+            let end = body
+                .find(quote)
+                .ok_or_else(|| anyhow!("Unterminated string in condition '{}'", expression))?;
+            tokens.push(Token::Value(Value::String(body[..end].to_string())));
+            rest = &body[end + 1..];
+        } else if let Some(op) = OPS.iter().find(|op| starts_with_op(rest, op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || "=!<>&|".contains(c))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            if word.is_empty() {
+                let c = rest.chars().next().unwrap_or_default();
+                return Err(anyhow!("Unexpected character '{}' in condition '{}'", c, expression));
+            }
+            tokens.push(Token::Value(
+                serde_json::from_str(word).unwrap_or_else(|_| Value::String(word.to_string())),
+            ));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+/// `contains` is only an operator when it stands alone as a word
+fn starts_with_op(input: &str, op: &str) -> bool {
+    if !input.starts_with(op) {
+        return false;
+    }
+    if op.chars().all(|c| c.is_alphabetic()) {
+        return input[op.len()..]
+            .chars()
+            .next()
+            .is_none_or(|c| !c.is_alphanumeric() && c != '_');
+    }
+    true
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    scope: &'a TemplateScope<'a>,
+}
+
+impl Parser<'_> {
+    fn eat(&mut self, op: &'static str) -> bool {
+        if self.tokens.get(self.pos) == Some(&Token::Op(op)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<bool> {
+        let mut result = self.and()?;
+        while self.eat("||") {
+            // Evaluate both sides so a bad reference is always reported
+            let rhs = self.and()?;
+            result = result || rhs;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool> {
+        let mut result = self.comparison()?;
+        while self.eat("&&") {
+            let rhs = self.comparison()?;
+            result = result && rhs;
+        }
+        Ok(result)
+    }
+
+    fn comparison(&mut self) -> Result<bool> {
+        if self.eat("!") {
+            return Ok(!self.comparison()?);
+        }
+
+        let lhs = self.operand()?;
+        let op = match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) if !matches!(*op, "||" | "&&" | "!") => *op,
+            _ => return Ok(truthy(&lhs)),
+        };
+        self.pos += 1;
+        let rhs = self.operand()?;
+
+        Ok(match op {
+            "==" => loosely_equal(&lhs, &rhs),
+            "!=" => !loosely_equal(&lhs, &rhs),
+            "contains" => contains(&lhs, &rhs),
+            _ => {
+                let ordering = compare(&lhs, &rhs).ok_or_else(|| {
+                    anyhow!("Cannot compare {} {} {}", lhs, op, rhs)
+                })?;
+                match op {
+                    ">" => ordering.is_gt(),
+                    ">=" => ordering.is_ge(),
+                    "<" => ordering.is_lt(),
+                    _ => ordering.is_le(),
+                }
+            }
+        })
+    }
+
+    fn operand(&mut self) -> Result<Value> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .ok_or_else(|| anyhow!("Condition ends where a value was expected"))?;
+        self.pos += 1;
+        match token {
+            Token::Value(value) => Ok(value.clone()),
+            Token::Placeholder(name) => self.scope.get(name),
+            Token::Op(op) => Err(anyhow!("Expected a value, found '{}'", op)),
+        }
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+/// Numbers and numeric strings compare as numbers
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn loosely_equal(lhs: &Value, rhs: &Value) -> bool {
+    if let (Some(a), Some(b)) = (as_number(lhs), as_number(rhs)) {
+        return a == b;
+    }
+    lhs == rhs
+}
+
+fn compare(lhs: &Value, rhs: &Value) -> Option<std::cmp::Ordering> {
+    if let (Some(a), Some(b)) = (as_number(lhs), as_number(rhs)) {
+        return a.partial_cmp(&b);
+    }
+    match (lhs, rhs) {
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+fn contains(haystack: &Value, needle: &Value) -> bool {
+    match (haystack, needle) {
+        (Value::String(s), Value::String(sub)) => s.contains(sub.as_str()),
+        (Value::Array(items), needle) => items.iter().any(|item| loosely_equal(item, needle)),
+        (Value::Object(map), Value::String(key)) => map.contains_key(key),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflow::StepInputs;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_evaluate_comparisons_and_logic() {
+        let mut outputs = HashMap::new();
+        outputs.insert(
+            "review".to_string(),
+            json!({"score": 8, "verdict": "accept", "tags": ["perf", "caching"]}),
+        );
+        let inputs = StepInputs {
+            static_values: HashMap::new(),
+            from_steps: HashMap::new(),
+        };
+        let scope = TemplateScope::new(&inputs, &outputs).unwrap();
+
+        let eval = |expr: &str| evaluate(expr, &scope).unwrap();
+        assert!(eval("{{review.score}} >= 7"));
+        assert!(!eval("{{review.score}} < 7"));
+        assert!(eval("{{review.verdict}} == \"accept\" && {{review.score}} != 3"));
+        assert!(eval("{{review.verdict}} == reject || {{review.tags}} contains caching"));
+        assert!(eval("{{review.tags}}"));
+        assert!(!eval("!{{review.score}}"));
+        assert!(eval("{{review.score}} == \"8\""));
+    }
+
+    #[test]
+    fn test_evaluate_rejects_bad_expressions() {
+        let outputs = HashMap::new();
+        let inputs = StepInputs {
+            static_values: HashMap::new(),
+            from_steps: HashMap::new(),
+        };
+        let scope = TemplateScope::new(&inputs, &outputs).unwrap();
+
+        assert!(evaluate("", &scope).is_err());
+        assert!(evaluate("{{missing.value}} > 1", &scope).is_err());
+        assert!(evaluate("1 >", &scope).is_err());
+        assert!(evaluate("true false", &scope).is_err());
+        assert!(evaluate("[1] > 2", &scope).is_err());
+        for typo in ["{{x}} = 1", "a & b", "a | b", "=", "1 =! 2 &"] {
+            let err = tokenize(typo).unwrap_err().to_string();
+            assert!(err.contains("Unexpected character"), "{}: {}", typo, err);
+        }
+    }
+}
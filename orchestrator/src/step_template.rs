@@ -0,0 +1,279 @@
+//! Template substitution for workflow step inputs
+//!
+//! Renders `{{var}}` placeholders in a step's task from:
+//! - `StepInputs.static_values` (by name)
+//! - `StepInputs.from_steps`, which maps `"step_id.json.path"` to a local name
+//! - direct `{{step_id.json.path}}` lookups into earlier step outputs
+//! - paths into bound variables, such as the execution's `{{params.field}}`
+//!
+//! Paths are dot-separated and may index arrays with `.0` or `[0]`.
+//! Any placeholder that can't be resolved is an error, so a step never
+//! sends a half-rendered prompt.
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::workflow::{StepInputs, StepTask};
+
+/// Variables visible to a single step while it is being rendered
+pub struct TemplateScope<'a> {
+    /// Named variables (static inputs + `from_steps` bindings)
+    vars: HashMap<String, Value>,
+    /// Outputs of steps that have already completed, keyed by step ID
+    step_outputs: &'a HashMap<String, Value>,
+}
+
+impl<'a> TemplateScope<'a> {
+    /// Build the scope for a step, resolving its `from_steps` bindings
+    pub fn new(inputs: &StepInputs, step_outputs: &'a HashMap<String, Value>) -> Result<Self> {
+        let mut vars = inputs.static_values.clone();
+
+        for (reference, local_name) in &inputs.from_steps {
+            let value = lookup_step_output(step_outputs, reference)?;
+            vars.insert(local_name.clone(), value.clone());
+        }
+
+        Ok(Self { vars, step_outputs })
+    }
+
+    /// Bind an extra named variable (e.g. the current `ForEach` item)
+    pub fn bind(&mut self, name: &str, value: Value) {
+        self.vars.insert(name.to_string(), value);
+    }
+
+    /// Resolve a placeholder name to a value
+    pub fn get(&self, name: &str) -> Result<Value> {
+        if let Some(value) = self.vars.get(name) {
+            return Ok(value.clone());
+        }
+        if let Some((var, path)) = name.split_once('.') {
+            if let Some(value) = self.vars.get(var) {
+                return lookup_path(value, path)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Template variable '{}' has no value at '{}'", var, path));
+            }
+        }
+        if name.contains('.') || self.step_outputs.contains_key(name) {
+            return lookup_step_output(self.step_outputs, name).cloned();
+        }
+        Err(anyhow!("Unresolved template variable '{}'", name))
+    }
+
+    /// Render every templated field of a step task
+    pub fn render_task(&self, task: &StepTask) -> Result<StepTask> {
+        Ok(match task {
+            StepTask::Chat {
+                prompt,
+                system_message,
+                temperature,
+                max_tokens,
+            } => StepTask::Chat {
+                prompt: self.render_str(prompt)?,
+                system_message: system_message
+                    .as_deref()
+                    .map(|s| self.render_str(s))
+                    .transpose()?,
+                temperature: *temperature,
+                max_tokens: *max_tokens,
+            },
+            StepTask::ExecuteCode { code, language } => StepTask::ExecuteCode {
+                code: self.render_str(code)?,
+                language: language.clone(),
+            },
+            StepTask::HttpRequest {
+                url,
+                method,
+                headers,
+                body,
+            } => StepTask::HttpRequest {
+                url: self.render_str(url)?,
+                method: method.clone(),
+                headers: headers
+                    .as_ref()
+                    .map(|h| {
+                        h.iter()
+                            .map(|(k, v)| Ok((k.clone(), self.render_str(v)?)))
+                            .collect::<Result<HashMap<_, _>>>()
+                    })
+                    .transpose()?,
+                body: body.as_ref().map(|b| self.render_value(b)).transpose()?,
+            },
+            StepTask::Approval { message } => StepTask::Approval {
+                message: self.render_str(message)?,
+            },
+            // Conditions are evaluated, not rendered; ForEach bodies are
+            // rendered once per item with `item`/`index` bound
+            StepTask::Condition { .. } | StepTask::ForEach { .. } | StepTask::Custom { .. } => {
+                task.clone()
+            }
+        })
+    }
+
+    /// Replace every `{{name}}` in a string; non-string values are inserted as JSON
+    pub fn render_str(&self, template: &str) -> Result<String> {
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template;
+
+        while let Some(start) = rest.find("{{") {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            let end = after
+                .find("}}")
+                .ok_or_else(|| anyhow!("Unterminated placeholder in template: {}", template))?;
+            let name = after[..end].trim();
+            if name.is_empty() {
+                return Err(anyhow!("Empty placeholder in template: {}", template));
+            }
+
+            match self.get(name)? {
+                Value::String(s) => out.push_str(&s),
+                other => out.push_str(&other.to_string()),
+            }
+            rest = &after[end + 2..];
+        }
+
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    /// Render strings inside a JSON document.
+    ///
+    /// A string that is exactly one placeholder is replaced by the referenced
+    /// value itself, so `"{{count}}"` can become a number or an object.
+    pub fn render_value(&self, value: &Value) -> Result<Value> {
+        Ok(match value {
+            Value::String(s) => match single_placeholder(s) {
+                Some(name) => self.get(name)?,
+                None => Value::String(self.render_str(s)?),
+            },
+            Value::Array(items) => Value::Array(
+                items
+                    .iter()
+                    .map(|v| self.render_value(v))
+                    .collect::<Result<_>>()?,
+            ),
+            Value::Object(map) => Value::Object(
+                map.iter()
+                    .map(|(k, v)| Ok((k.clone(), self.render_value(v)?)))
+                    .collect::<Result<_>>()?,
+            ),
+            other => other.clone(),
+        })
+    }
+}
+
+/// If `s` is exactly `{{name}}`, return `name`
+fn single_placeholder(s: &str) -> Option<&str> {
+    let inner = s.trim().strip_prefix("{{")?.strip_suffix("}}")?;
+    if inner.contains("{{") || inner.contains("}}") {
+        return None;
+    }
+    Some(inner.trim())
+}
+
+/// Look up `"step_id.path.to.value"` in the completed step outputs
+fn lookup_step_output<'v>(
+    step_outputs: &'v HashMap<String, Value>,
+    reference: &str,
+) -> Result<&'v Value> {
+    let (step_id, path) = match reference.split_once('.') {
+        Some((step_id, path)) => (step_id, path),
+        None => (reference, ""),
+    };
+
+    let output = step_outputs.get(step_id).ok_or_else(|| {
+        anyhow!(
+            "Template reference '{}' points at step '{}', which has no output yet",
+            reference,
+            step_id
+        )
+    })?;
+
+    lookup_path(output, path).ok_or_else(|| {
+        anyhow!(
+            "Template reference '{}': output of step '{}' has no value at '{}'",
+            reference,
+            step_id,
+            path
+        )
+    })
+}
+
+/// Follow a dot/bracket path (`a.b[0].c` or `a.b.0.c`) into a JSON value
+pub fn lookup_path<'v>(value: &'v Value, path: &str) -> Option<&'v Value> {
+    let normalized = path.replace('[', ".").replace(']', "");
+    let mut current = value;
+
+    for segment in normalized.split('.').filter(|s| !s.is_empty()) {
+        current = match current {
+            Value::Object(map) => map.get(segment)?,
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+
+    Some(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn inputs(
+        static_values: &[(&str, Value)],
+        from_steps: &[(&str, &str)],
+    ) -> StepInputs {
+        StepInputs {
+            static_values: static_values
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect(),
+            from_steps: from_steps
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_render_static_and_step_values() {
+        let mut outputs = HashMap::new();
+        outputs.insert(
+            "research".to_string(),
+            json!({"findings": [{"title": "Caching"}], "count": 3}),
+        );
+
+        let inputs = inputs(
+            &[("audience", json!("students"))],
+            &[("research.findings[0].title", "topic")],
+        );
+        let scope = TemplateScope::new(&inputs, &outputs).unwrap();
+
+        assert_eq!(
+            scope
+                .render_str("Explain {{ topic }} to {{audience}} ({{research.count}} sources)")
+                .unwrap(),
+            "Explain Caching to students (3 sources)"
+        );
+
+        let body = scope
+            .render_value(&json!({"n": "{{research.count}}", "label": "n={{research.count}}"}))
+            .unwrap();
+        assert_eq!(body, json!({"n": 3, "label": "n=3"}));
+    }
+
+    #[test]
+    fn test_missing_references_fail() {
+        let outputs = HashMap::new();
+
+        // from_steps pointing at a step that hasn't produced output
+        assert!(TemplateScope::new(&inputs(&[], &[("draft.text", "draft")]), &outputs).is_err());
+
+        let scope = TemplateScope::new(&inputs(&[], &[]), &outputs).unwrap();
+        let err = scope.render_str("Hello {{name}}").unwrap_err();
+        assert!(err.to_string().contains("name"));
+        assert!(scope.render_str("Hello {{name").is_err());
+    }
+}
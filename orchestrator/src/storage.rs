@@ -32,6 +32,9 @@ pub struct StoredAgent {
     /// Tailscale IP address (for agent-to-agent communication)
     #[serde(default)]
     pub tailscale_ip: Option<String>,
+    /// Last health check result
+    #[serde(default)]
+    pub health_status: Option<crate::types::HealthStatus>,
 }
 
 /// Load all persisted agents from disk
@@ -104,7 +107,8 @@ impl From<StoredAgent> for crate::types::AgentContainer {
             project: None,
             tags: vec![],
             restart_policy: Default::default(),
-            health_status: None,
+            health_status: stored.health_status,
+            restarts: Default::default(),
             runtime: stored.runtime,
             gateway_port: stored
                 .gateway_port
@@ -126,6 +130,7 @@ pub fn to_stored_agent(container: &crate::types::AgentContainer) -> StoredAgent
         runtime: container.runtime.clone(),
         gateway_port: Some(container.gateway_port),
         tailscale_ip: container.tailscale_ip.clone(),
+        health_status: container.health_status.clone(),
     }
 }
 
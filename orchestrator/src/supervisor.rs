@@ -0,0 +1,309 @@
+//! Restart supervisor
+//!
+//! `sync_container_states` reports agents whose containers stopped behind
+//! the orchestrator's back. The supervisor decides from the agent's
+//! `RestartPolicy` whether to bring them back, waits out an exponential
+//! backoff and restarts them through the same path as
+//! `POST /api/agents/:id/start`. An agent that exits more than
+//! `CRASH_LOOP_LIMIT` times within `CRASH_LOOP_WINDOW` is left stopped and
+//! flagged until someone starts it by hand. Stops made through the API are
+//! never undone.
+//!
+//! `Always` differs from `UnlessStopped` only at orchestrator startup:
+//! `Always` agents that are not running are started then.
+
+use chrono::Utc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::types::{AgentContainer, AgentExit, AgentStatus, ExitReason, RestartPolicy};
+use crate::AppState;
+
+/// First restart delay; doubles with every consecutive failure
+const BACKOFF_BASE: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(300);
+/// Exits allowed within `CRASH_LOOP_WINDOW` before giving up
+const CRASH_LOOP_LIMIT: usize = 5;
+const CRASH_LOOP_WINDOW: Duration = Duration::from_secs(600);
+/// Running this long after a restart resets the backoff
+const STABLE_AFTER: Duration = Duration::from_secs(300);
+
+#[derive(Default)]
+struct Supervised {
+    /// Exits inside the crash-loop window, oldest first
+    recent_exits: VecDeque<Instant>,
+    /// Failures since the agent last stayed up for `STABLE_AFTER`
+    consecutive: u32,
+    restarted_at: Option<Instant>,
+    next_attempt: Option<Instant>,
+}
+
+pub struct Supervisor {
+    agents: Mutex<HashMap<String, Supervised>>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self {
+            agents: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Note an exit seen by the reconciler and schedule a restart if the
+    /// agent's policy asks for one
+    pub fn record_exit(&self, agent: &mut AgentContainer, reason: ExitReason, message: Option<String>) {
+        let now = Instant::now();
+        let mut agents = self.agents.lock().unwrap();
+        let entry = agents.entry(agent.id.clone()).or_default();
+
+        if entry.restarted_at.is_some_and(|at| now.duration_since(at) >= STABLE_AFTER) {
+            entry.consecutive = 0;
+        }
+        entry.recent_exits.push_back(now);
+        while entry
+            .recent_exits
+            .front()
+            .is_some_and(|at| now.duration_since(*at) > CRASH_LOOP_WINDOW)
+        {
+            entry.recent_exits.pop_front();
+        }
+
+        agent.restarts.last_exit = Some(AgentExit {
+            reason,
+            at: Utc::now().to_rfc3339(),
+            message,
+        });
+        entry.next_attempt = None;
+        agent.restarts.next_restart_at = None;
+
+        let policy = effective_policy(agent);
+        if !restarts_after(policy, reason) {
+            return;
+        }
+        if entry.recent_exits.len() > CRASH_LOOP_LIMIT {
+            tracing::error!(
+                "Agent {} exited {} times in {}s, not restarting it again",
+                agent.name,
+                entry.recent_exits.len(),
+                CRASH_LOOP_WINDOW.as_secs()
+            );
+            agent.restarts.crash_looping = true;
+            return;
+        }
+
+        entry.consecutive += 1;
+        let delay = backoff(entry.consecutive);
+        tracing::info!(
+            "Agent {} exited ({:?}), restarting in {}s ({:?})",
+            agent.name,
+            reason,
+            delay.as_secs(),
+            policy
+        );
+        schedule(entry, agent, delay);
+    }
+
+    /// Whether an exit for `reason` would be followed by a restart
+    pub fn would_restart(&self, agent: &AgentContainer, reason: ExitReason) -> bool {
+        !agent.restarts.crash_looping && restarts_after(effective_policy(agent), reason)
+    }
+
+    /// A stop through the API: cancel any pending restart
+    pub fn record_user_stop(&self, agent: &mut AgentContainer) {
+        if let Some(entry) = self.agents.lock().unwrap().get_mut(&agent.id) {
+            entry.next_attempt = None;
+        }
+        agent.restarts.next_restart_at = None;
+        agent.restarts.last_exit = Some(AgentExit {
+            reason: ExitReason::UserStop,
+            at: Utc::now().to_rfc3339(),
+            message: None,
+        });
+    }
+
+    /// A start through the API: forget past crashes
+    pub fn record_manual_start(&self, agent: &mut AgentContainer) {
+        self.agents.lock().unwrap().remove(&agent.id);
+        agent.restarts.next_restart_at = None;
+        agent.restarts.crash_looping = false;
+    }
+
+    /// Agents whose restart is due, clearing their schedule
+    fn take_due(&self, now: Instant) -> Vec<String> {
+        let mut agents = self.agents.lock().unwrap();
+        agents
+            .iter_mut()
+            .filter(|(_, entry)| entry.next_attempt.is_some_and(|at| at <= now))
+            .map(|(id, entry)| {
+                entry.next_attempt = None;
+                id.clone()
+            })
+            .collect()
+    }
+
+    fn record_restarted(&self, agent: &mut AgentContainer) {
+        if let Some(entry) = self.agents.lock().unwrap().get_mut(&agent.id) {
+            entry.restarted_at = Some(Instant::now());
+        }
+        agent.restarts.restart_count += 1;
+        agent.restarts.next_restart_at = None;
+    }
+
+    /// Start `Always` agents that are down, then restart agents as they come due
+    pub fn spawn(state: Arc<AppState>) {
+        tokio::spawn(async move {
+            {
+                let mut containers = state.containers.write().await;
+                let mut agents = state.supervisor.agents.lock().unwrap();
+                for agent in containers.iter_mut() {
+                    if effective_policy(agent) == RestartPolicy::Always
+                        && agent.status != AgentStatus::Running
+                        && agent.runtime.as_deref() != Some("direct")
+                    {
+                        schedule(agents.entry(agent.id.clone()).or_default(), agent, Duration::ZERO);
+                    }
+                }
+            }
+
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                for id in state.supervisor.take_due(Instant::now()) {
+                    restart(&state, &id).await;
+                }
+            }
+        });
+    }
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn restart(state: &Arc<AppState>, id: &str) {
+    // Stopped by a user or started by hand since this was scheduled
+    let wanted = state.containers.read().await.iter().any(|a| {
+        a.id == id && a.status != AgentStatus::Running && a.restarts.next_restart_at.is_some()
+    });
+    if !wanted {
+        return;
+    }
+
+    let result = crate::api::launch_agent(state, id).await;
+
+    let mut containers = state.containers.write().await;
+    let Some(agent) = containers.iter_mut().find(|a| a.id == id) else {
+        return;
+    };
+    match result {
+        Ok(_) => {
+            tracing::info!("Restarted agent {}", agent.name);
+            state.supervisor.record_restarted(agent);
+        }
+        Err((_, e)) => {
+            tracing::warn!("Failed to restart agent {}: {}", agent.name, e);
+            agent.status = AgentStatus::Error;
+            state.supervisor.record_exit(agent, ExitReason::RestartFailed, Some(e));
+        }
+    }
+}
+
+fn schedule(entry: &mut Supervised, agent: &mut AgentContainer, delay: Duration) {
+    entry.next_attempt = Some(Instant::now() + delay);
+    let at = Utc::now() + chrono::Duration::from_std(delay).unwrap_or_default();
+    agent.restarts.next_restart_at = Some(at.to_rfc3339());
+}
+
+/// The container-level policy wins when set; otherwise the agent config's
+fn effective_policy(agent: &AgentContainer) -> RestartPolicy {
+    match agent.restart_policy {
+        RestartPolicy::Never => agent.config.restart_policy,
+        policy => policy,
+    }
+}
+
+fn restarts_after(policy: RestartPolicy, reason: ExitReason) -> bool {
+    !matches!(
+        (policy, reason),
+        (_, ExitReason::UserStop)
+            | (RestartPolicy::Never, _)
+            | (RestartPolicy::OnFailure, ExitReason::Exited)
+    )
+}
+
+fn backoff(consecutive: u32) -> Duration {
+    BACKOFF_BASE
+        .saturating_mul(2u32.saturating_pow(consecutive.saturating_sub(1)))
+        .min(BACKOFF_MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::AgentConfig;
+
+    fn agent(policy: RestartPolicy) -> AgentContainer {
+        AgentContainer {
+            id: "agent-1".to_string(),
+            name: "worker".to_string(),
+            status: AgentStatus::Error,
+            config: AgentConfig {
+                restart_policy: policy,
+                ..Default::default()
+            },
+            runtime: Some("docker".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_policy_and_backoff() {
+        assert!(!restarts_after(RestartPolicy::Never, ExitReason::Crashed));
+        assert!(!restarts_after(RestartPolicy::OnFailure, ExitReason::Exited));
+        assert!(restarts_after(RestartPolicy::OnFailure, ExitReason::Disappeared));
+        assert!(restarts_after(RestartPolicy::UnlessStopped, ExitReason::Exited));
+        assert!(!restarts_after(RestartPolicy::Always, ExitReason::UserStop));
+
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(4), Duration::from_secs(8));
+        assert_eq!(backoff(40), BACKOFF_MAX);
+    }
+
+    #[test]
+    fn test_crash_loop_stops_restarts() {
+        let supervisor = Supervisor::new();
+        let mut agent = agent(RestartPolicy::OnFailure);
+
+        for _ in 0..CRASH_LOOP_LIMIT {
+            supervisor.record_exit(&mut agent, ExitReason::Crashed, None);
+            assert!(agent.restarts.next_restart_at.is_some());
+            assert!(!agent.restarts.crash_looping);
+        }
+        supervisor.record_exit(&mut agent, ExitReason::Crashed, None);
+        assert!(agent.restarts.crash_looping);
+        assert!(agent.restarts.next_restart_at.is_none());
+
+        // Starting it by hand clears the hold
+        supervisor.record_manual_start(&mut agent);
+        assert!(!agent.restarts.crash_looping);
+        supervisor.record_exit(&mut agent, ExitReason::Crashed, None);
+        assert!(agent.restarts.next_restart_at.is_some());
+    }
+
+    #[test]
+    fn test_user_stop_cancels_pending_restart() {
+        let supervisor = Supervisor::new();
+        let mut agent = agent(RestartPolicy::Always);
+
+        supervisor.record_exit(&mut agent, ExitReason::Exited, None);
+        assert!(agent.restarts.next_restart_at.is_some());
+
+        supervisor.record_user_stop(&mut agent);
+        assert!(agent.restarts.next_restart_at.is_none());
+        assert_eq!(agent.restarts.last_exit.as_ref().unwrap().reason, ExitReason::UserStop);
+        assert!(supervisor.take_due(Instant::now() + BACKOFF_MAX).is_empty());
+    }
+}
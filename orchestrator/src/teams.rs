@@ -8,6 +8,8 @@ use chrono::Utc;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
 use crate::types::*;
@@ -54,6 +56,8 @@ struct RouterConfigRaw {
     confidence_threshold: f32,
     #[serde(default = "default_true")]
     clarify_on_low_confidence: bool,
+    #[serde(default)]
+    llm: Option<RouterLlmConfig>,
 }
 
 /// Registry of all teams
@@ -142,6 +146,7 @@ impl TeamRegistry {
                 mode: config.router.mode,
                 confidence_threshold: config.router.confidence_threshold,
                 clarify_on_low_confidence: config.router.clarify_on_low_confidence,
+                llm: config.router.llm,
             },
             agents: config.agents,
             routing: config.routing,
@@ -278,18 +283,25 @@ impl TeamRegistry {
 /// Router classifies messages and determines target agent
 pub struct Router {
     team: Team,
+    llm: Option<Arc<LlmClassifier>>,
 }
 
 impl Router {
     pub fn new(team: Team) -> Self {
-        Self { team }
+        Self { team, llm: None }
+    }
+
+    /// Classify with `llm` in `llm` and `hybrid` modes
+    pub fn with_llm(mut self, llm: Arc<LlmClassifier>) -> Self {
+        self.llm = Some(llm);
+        self
     }
 
     /// Classify a message to determine routing
-    pub fn classify(&self, message: &str) -> ClassificationResult {
+    pub async fn classify(&self, message: &str) -> ClassificationResult {
         match self.team.router.mode {
             RouterMode::Keyword => self.classify_by_keywords(message),
-            RouterMode::Llm => self.classify_by_llm(message),
+            RouterMode::Llm => self.classify_by_llm(message).await,
             RouterMode::Hybrid => {
                 // Try keywords first
                 let keyword_result = self.classify_by_keywords(message);
@@ -299,7 +311,7 @@ impl Router {
                     keyword_result
                 } else {
                     // Fall back to LLM classification
-                    let llm_result = self.classify_by_llm(message);
+                    let llm_result = self.classify_by_llm(message).await;
 
                     // Use whichever has higher confidence
                     if llm_result.confidence > keyword_result.confidence {
@@ -359,16 +371,24 @@ impl Router {
         }
     }
 
-    fn classify_by_llm(&self, _message: &str) -> ClassificationResult {
-        // TODO: Implement LLM-based classification
-        // For now, return low-confidence unknown
-        tracing::warn!("LLM routing not yet implemented, falling back to keyword");
-
-        ClassificationResult {
+    async fn classify_by_llm(&self, message: &str) -> ClassificationResult {
+        let unknown = ClassificationResult {
             intent: "unknown".to_string(),
             confidence: 0.0,
             matched_keywords: vec![],
             needs_clarification: true,
+        };
+
+        let Some(llm) = &self.llm else {
+            tracing::warn!("No LLM classifier for team {}, falling back to keyword", self.team.id);
+            return unknown;
+        };
+        match llm.classify(&self.team, message).await {
+            Ok(result) => result,
+            Err(e) => {
+                tracing::warn!("LLM routing failed for team {}: {}", self.team.id, e);
+                unknown
+            }
         }
     }
 
@@ -412,6 +432,186 @@ impl Router {
     }
 }
 
+// ============================================================================
+// LLM CLASSIFICATION
+// ============================================================================
+
+/// How long an LLM classification is reused for the same message
+const CLASSIFICATION_TTL: Duration = Duration::from_secs(600);
+/// Cached classifications kept across all teams
+const CLASSIFICATION_CACHE_LIMIT: usize = 1000;
+
+/// Classifies messages with an LLM for routers in `llm` / `hybrid` mode.
+///
+/// Calls the team's `[router.llm]` provider if set, otherwise the provider
+/// and model configured on the router agent (`router.name`), with the same
+/// one-shot completion the workflow executor uses. The call goes straight to
+/// the provider: the router agent's own system prompt, tools and chat
+/// history play no part. Answers are cached per team and message for
+/// `CLASSIFICATION_TTL`.
+pub struct LlmClassifier {
+    http: reqwest::Client,
+    agents: Arc<RwLock<Vec<AgentContainer>>>,
+    api_keys: Arc<RwLock<HashMap<String, String>>>,
+    cache: std::sync::Mutex<HashMap<(String, String), (Instant, ClassificationResult)>>,
+    usage: Option<Arc<crate::usage::UsageLedger>>,
+}
+
+/// What the model is asked to reply with
+#[derive(Debug, Deserialize)]
+struct LlmClassification {
+    intent: String,
+    #[serde(default)]
+    confidence: f32,
+}
+
+impl LlmClassifier {
+    pub fn new(
+        agents: Arc<RwLock<Vec<AgentContainer>>>,
+        api_keys: Arc<RwLock<HashMap<String, String>>>,
+    ) -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()
+                .unwrap_or_default(),
+            agents,
+            api_keys,
+            cache: std::sync::Mutex::new(HashMap::new()),
+            usage: None,
+        }
+    }
+
+    /// Record the token usage of classification calls in `usage`
+    pub fn with_usage(mut self, usage: Arc<crate::usage::UsageLedger>) -> Self {
+        self.usage = Some(usage);
+        self
+    }
+
+    pub async fn classify(&self, team: &Team, message: &str) -> Result<ClassificationResult> {
+        // Case and spacing don't change the answer
+        let normalized = message.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+        let key = (team.id.clone(), normalized);
+        if let Some((at, result)) = self.cache.lock().unwrap().get(&key) {
+            if at.elapsed() < CLASSIFICATION_TTL {
+                return Ok(result.clone());
+            }
+        }
+
+        let agent = self.router_model(team).await?;
+        let (answer, usage) = crate::direct_llm::complete(
+            &self.http,
+            &self.api_keys,
+            &agent,
+            Some(&classification_prompt(team)),
+            message,
+            Some(0.0),
+            Some(200),
+        )
+        .await?;
+        if let (Some(ledger), Some(usage)) = (&self.usage, &usage) {
+            ledger.record(&agent, None, usage);
+        }
+        let result = parse_classification(team, &answer)?;
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, (at, _)| at.elapsed() < CLASSIFICATION_TTL);
+        if cache.len() >= CLASSIFICATION_CACHE_LIMIT {
+            if let Some(oldest) = cache.iter().min_by_key(|(_, (at, _))| *at).map(|(k, _)| k.clone()) {
+                cache.remove(&oldest);
+            }
+        }
+        cache.insert(key, (Instant::now(), result.clone()));
+        Ok(result)
+    }
+
+    /// The model settings the classification call uses, as an agent for
+    /// `direct_llm::complete`: the router agent itself, or one standing in
+    /// for `[router.llm]`
+    async fn router_model(&self, team: &Team) -> Result<AgentContainer> {
+        let router = &team.router;
+        let Some(llm) = &router.llm else {
+            let agents = self.agents.read().await;
+            return agents
+                .iter()
+                .find(|a| a.name == router.name || a.id == router.name)
+                .cloned()
+                .ok_or_else(|| {
+                    anyhow::anyhow!("router agent {} not found and no [router.llm] configured", router.name)
+                });
+        };
+
+        let mut config = AgentConfig {
+            llm_provider: llm.provider.clone(),
+            llm_model: llm.model.clone(),
+            ..Default::default()
+        };
+        if let Some(url) = &llm.base_url {
+            config.env_vars.insert("LLM_BASE_URL".to_string(), url.clone());
+        }
+        if let Some(format) = &llm.api_format {
+            config.env_vars.insert("LLM_API_FORMAT".to_string(), format.clone());
+        }
+
+        Ok(AgentContainer {
+            id: format!("{}-router", team.id),
+            name: router.name.clone(),
+            status: AgentStatus::Running,
+            config,
+            runtime: Some("direct".to_string()),
+            ..Default::default()
+        })
+    }
+}
+
+/// System prompt listing the team's intents
+fn classification_prompt(team: &Team) -> String {
+    let mut intents: Vec<_> = team.agents.iter().collect();
+    intents.sort_by_key(|(intent, _)| intent.as_str());
+    let intents: Vec<String> = intents
+        .into_iter()
+        .map(|(intent, agent)| format!("- {}: {}", intent, agent.description))
+        .collect();
+
+    format!(
+        "You route messages for the \"{}\" team. Pick the intent whose description best \
+         fits the user's message.\n\nIntents:\n{}\n\nReply with only JSON: \
+         {{\"intent\": \"<one of the intents above, or unknown>\", \"confidence\": <0.0-1.0>}}",
+        team.name,
+        intents.join("\n")
+    )
+}
+
+/// Read the model's answer; an intent the team doesn't have counts as unknown
+fn parse_classification(team: &Team, answer: &str) -> Result<ClassificationResult> {
+    // Models like to wrap JSON in prose or code fences
+    let json = match (answer.find('{'), answer.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &answer[start..=end],
+        _ => return Err(anyhow::anyhow!("no JSON in classifier answer: {}", answer)),
+    };
+    let parsed: LlmClassification = serde_json::from_str(json)
+        .with_context(|| format!("unreadable classifier answer: {}", json))?;
+
+    let intent = parsed.intent.trim();
+    if !team.agents.contains_key(intent) {
+        return Ok(ClassificationResult {
+            intent: "unknown".to_string(),
+            confidence: 0.0,
+            matched_keywords: vec![],
+            needs_clarification: true,
+        });
+    }
+
+    let confidence = parsed.confidence.clamp(0.0, 1.0);
+    Ok(ClassificationResult {
+        intent: intent.to_string(),
+        confidence,
+        matched_keywords: vec![],
+        needs_clarification: confidence < team.router.confidence_threshold
+            && team.router.clarify_on_low_confidence,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -459,6 +659,7 @@ mod tests {
                 mode: RouterMode::Keyword,
                 confidence_threshold: 0.7,
                 clarify_on_low_confidence: true,
+                llm: None,
             },
             agents,
             routing,
@@ -469,22 +670,22 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_keyword_routing() {
+    #[tokio::test]
+    async fn test_keyword_routing() {
         let team = create_test_team();
         let router = Router::new(team);
 
-        let result = router.classify("I have a receipt to submit");
+        let result = router.classify("I have a receipt to submit").await;
         assert_eq!(result.intent, "receipts");
         assert!(!result.needs_clarification);
     }
 
-    #[test]
-    fn test_unknown_routing() {
+    #[tokio::test]
+    async fn test_unknown_routing() {
         let team = create_test_team();
         let router = Router::new(team);
 
-        let result = router.classify("Hello there!");
+        let result = router.classify("Hello there!").await;
         assert_eq!(result.intent, "unknown");
         assert!(result.needs_clarification);
     }
@@ -537,52 +738,52 @@ mod tests {
         assert!(northern_envelope.description.contains("cold climates"));
     }
 
-    #[test]
-    fn test_the_closer_routing_simple() {
+    #[tokio::test]
+    async fn test_the_closer_routing_simple() {
         let registry = TeamRegistry::new("../teams");
         let team = registry.load_team_config(Path::new("../teams/design-build-firm.toml")).unwrap();
         let router = Router::new(team);
 
         // Test with a simple message
-        let result = router.classify("generate the punch list");
+        let result = router.classify("generate the punch list").await;
         assert_eq!(result.intent, "the_closer");
 
-        let result = router.classify("complete the work");
+        let result = router.classify("complete the work").await;
         assert_eq!(result.intent, "the_closer");
     }
 
-    #[test]
-    fn test_design_build_firm_routing() {
+    #[tokio::test]
+    async fn test_design_build_firm_routing() {
         let registry = TeamRegistry::new("../teams");
         let team = registry.load_team_config(Path::new("../teams/design-build-firm.toml")).unwrap();
         let router = Router::new(team);
 
         // Test time analyst routing
-        let result = router.classify("How long will this bathroom reno take and what's the cost estimate?");
+        let result = router.classify("How long will this bathroom reno take and what's the cost estimate?").await;
         assert_eq!(result.intent, "time_analyst");
 
         // Test design assistant routing
-        let result = router.classify("Can you help me design and create a 3D Revit model for the new addition?");
+        let result = router.classify("Can you help me design and create a 3D Revit model for the new addition?").await;
         assert_eq!(result.intent, "design_assistant");
 
         // Test northern envelope routing
-        let result = router.classify("Will this wall assembly meet SB-12 thermal compliance requirements?");
+        let result = router.classify("Will this wall assembly meet SB-12 thermal compliance requirements?").await;
         assert_eq!(result.intent, "northern_envelope");
 
         // Test site coordinator routing
-        let result = router.classify("What's happening on the job site today with the electrician and plumber?");
+        let result = router.classify("What's happening on the job site today with the electrician and plumber?").await;
         assert_eq!(result.intent, "site_coordinator");
 
         // Test scheduler routing
-        let result = router.classify("What's the critical path schedule timeline for finishing on time?");
+        let result = router.classify("What's the critical path schedule timeline for finishing on time?").await;
         assert_eq!(result.intent, "scheduler");
 
         // Test client shield routing
-        let result = router.classify("Send the customer a communication about scope changes");
+        let result = router.classify("Send the customer a communication about scope changes").await;
         assert_eq!(result.intent, "client_shield");
 
         // Test the closer routing
-        let result = router.classify("Generate the warranty package and final punch list for handoff");
+        let result = router.classify("Generate the warranty package and final punch list for handoff").await;
         assert_eq!(result.intent, "the_closer");
     }
 
@@ -704,4 +905,77 @@ mod tests {
             assert!(result.is_err());
         });
     }
+
+    /// Local OpenAI-compatible server that always gives `answer`, counting calls
+    async fn stub_provider(answer: &'static str) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let app = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post(move |axum::Json(body): axum::Json<serde_json::Value>| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                // The team's intents are offered to the model
+                let system = body["messages"][0]["content"].as_str().unwrap_or_default();
+                assert!(system.contains("- payables: Handles bills"));
+                async move {
+                    axum::Json(serde_json::json!({
+                        "choices": [{ "message": { "role": "assistant", "content": answer } }]
+                    }))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, calls)
+    }
+
+    #[tokio::test]
+    async fn test_llm_routing_against_stub_provider() {
+        let (url, calls) =
+            stub_provider("```json\n{\"intent\": \"payables\", \"confidence\": 0.92}\n```").await;
+
+        let mut team = create_test_team();
+        team.router.mode = RouterMode::Llm;
+        team.router.llm = Some(RouterLlmConfig {
+            provider: LlmProvider::OpenAI,
+            model: Some("router-model".to_string()),
+            base_url: Some(url),
+            api_format: None,
+        });
+
+        let api_keys = HashMap::from([("openai".to_string(), "test-key".to_string())]);
+        let classifier = Arc::new(LlmClassifier::new(
+            Arc::new(RwLock::new(vec![])),
+            Arc::new(RwLock::new(api_keys)),
+        ));
+        let router = Router::new(team).with_llm(classifier);
+
+        let result = router.classify("The electricity invoice came in").await;
+        assert_eq!(result.intent, "payables");
+        assert!((result.confidence - 0.92).abs() < f32::EPSILON);
+        assert!(!result.needs_clarification);
+
+        // Same message modulo case and spacing comes from the cache
+        let again = router.classify("the electricity   invoice came in").await;
+        assert_eq!(again.intent, "payables");
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_parse_classification() {
+        let team = create_test_team();
+
+        let result = parse_classification(&team, r#"{"intent": "receipts", "confidence": 0.4}"#).unwrap();
+        assert_eq!(result.intent, "receipts");
+        assert!(result.needs_clarification);
+
+        let result = parse_classification(&team, r#"Sure! {"intent": "weather", "confidence": 0.99}"#).unwrap();
+        assert_eq!(result.intent, "unknown");
+        assert_eq!(result.confidence, 0.0);
+
+        assert!(parse_classification(&team, "payables").is_err());
+    }
 }
@@ -0,0 +1,435 @@
+//! Tools for direct-runtime agents
+//!
+//! An agent lists the tools it may use in `config.tools`. `direct_llm`
+//! describes them to the model in the provider's wire format; when the model
+//! calls one, `execute` runs it here and the output goes back to the model
+//! on the next request. Failures are handed to the model as error results
+//! rather than ending the chat.
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use serde_json::{json, Value};
+
+use crate::shared_memory::{MemoryFilter, MemoryQuery, ORG_COMMON, ORG_DEFAULT};
+use crate::types::{AgentContainer, AgentStatus, AgentTool};
+use crate::AppState;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(15);
+/// Fetched bodies are cut off here so one page can't flood the context
+const MAX_FETCH_BYTES: usize = 64 * 1024;
+const MAX_REDIRECTS: usize = 5;
+const MESSAGE_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_SEARCH_LIMIT: u64 = 5;
+const MAX_SEARCH_LIMIT: u64 = 20;
+
+/// A tool as described to the model
+pub struct ToolSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// JSON Schema of the arguments object
+    pub parameters: Value,
+}
+
+/// A tool call made by the model
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    /// Provider-assigned ID the result must refer back to
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+}
+
+/// What a tool call returned, as text for the model
+#[derive(Debug, Clone)]
+pub struct ToolOutput {
+    pub content: String,
+    pub is_error: bool,
+}
+
+impl AgentTool {
+    /// The name the model calls this tool by
+    pub fn name(&self) -> &'static str {
+        match self {
+            AgentTool::MemorySearch { .. } => "memory_search",
+            AgentTool::MessageAgent { .. } => "message_agent",
+            AgentTool::HttpFetch { .. } => "http_fetch",
+        }
+    }
+
+    pub fn spec(&self) -> ToolSpec {
+        let (description, parameters) = match self {
+            AgentTool::MemorySearch { .. } => (
+                "Search the shared memory other agents have stored, by keywords and meaning, \
+                 optionally only entries with given metadata or from a recent period. \
+                 Returns the best matching entries.",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "query": { "type": "string", "description": "Keywords to look for" },
+                        "metadata": {
+                            "type": "object",
+                            "description": "Metadata fields and the value each entry must have, e.g. {\"project\": \"X\"}"
+                        },
+                        "since": {
+                            "type": "string",
+                            "description": "Only entries newer than this: an age such as 7d or 12h, or a date"
+                        },
+                        "limit": { "type": "integer", "minimum": 1, "maximum": MAX_SEARCH_LIMIT }
+                    },
+                    "required": ["query"]
+                }),
+            ),
+            AgentTool::MessageAgent { .. } => (
+                "Send a message to another agent and wait for its reply.",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "agent": { "type": "string", "description": "Name of the agent to message" },
+                        "message": { "type": "string" }
+                    },
+                    "required": ["agent", "message"]
+                }),
+            ),
+            AgentTool::HttpFetch { .. } => (
+                "Fetch a URL with HTTP GET and return the status and body. Only some hosts are allowed.",
+                json!({
+                    "type": "object",
+                    "properties": {
+                        "url": { "type": "string", "description": "http:// or https:// URL" }
+                    },
+                    "required": ["url"]
+                }),
+            ),
+        };
+        ToolSpec {
+            name: self.name(),
+            description,
+            parameters,
+        }
+    }
+}
+
+/// Run one tool call for `agent`. Calls to tools the agent doesn't have are
+/// refused.
+pub async fn execute(state: &AppState, agent: &AgentContainer, call: &ToolCall) -> ToolOutput {
+    let Some(tool) = agent.config.tools.iter().find(|t| t.name() == call.name) else {
+        return ToolOutput {
+            content: format!("unknown tool '{}'", call.name),
+            is_error: true,
+        };
+    };
+    let result = match tool {
+        AgentTool::MemorySearch { org } => {
+            memory_search(state, &search_orgs(agent, org.as_deref()), &call.arguments).await
+        }
+        AgentTool::MessageAgent { allowed_agents } => {
+            message_agent(state, agent, allowed_agents, &call.arguments).await
+        }
+        AgentTool::HttpFetch { allowed_hosts } => http_fetch(allowed_hosts, &call.arguments).await,
+    };
+    tracing::info!(
+        "[tools] {} called {}: {}",
+        agent.name,
+        call.name,
+        if result.is_ok() { "ok" } else { "error" }
+    );
+    match result {
+        Ok(content) => ToolOutput { content, is_error: false },
+        Err(content) => ToolOutput { content, is_error: true },
+    }
+}
+
+fn str_arg<'a>(arguments: &'a Value, name: &str) -> Result<&'a str, String> {
+    arguments
+        .get(name)
+        .and_then(Value::as_str)
+        .filter(|s| !s.trim().is_empty())
+        .ok_or_else(|| format!("missing string argument '{}'", name))
+}
+
+/// The orgs `memory_search` reads: the configured one, or the agent's own
+/// (its project, or `default`) and `common`, as over the REST API. Every org
+/// is only searched when configured as `all`.
+fn search_orgs(agent: &AgentContainer, org: Option<&str>) -> Vec<String> {
+    if let Some(org) = org {
+        return vec![org.to_string()];
+    }
+    let own = agent.project.clone().unwrap_or_else(|| ORG_DEFAULT.to_string());
+    if own == ORG_COMMON {
+        vec![own]
+    } else {
+        vec![own, ORG_COMMON.to_string()]
+    }
+}
+
+async fn memory_search(state: &AppState, orgs: &[String], arguments: &Value) -> Result<String, String> {
+    let query = str_arg(arguments, "query")?;
+    let limit = arguments
+        .get("limit")
+        .and_then(Value::as_u64)
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .clamp(1, MAX_SEARCH_LIMIT) as usize;
+    let memory = state
+        .shared_memory
+        .as_ref()
+        .ok_or_else(|| "shared memory is unavailable".to_string())?;
+
+    let filter = MemoryFilter {
+        metadata: match arguments.get("metadata") {
+            Some(Value::Object(fields)) => fields.clone(),
+            Some(Value::Null) | None => Default::default(),
+            Some(_) => return Err("'metadata' must be an object".to_string()),
+        },
+        created_after: arguments
+            .get("since")
+            .and_then(Value::as_str)
+            .map(MemoryFilter::parse_time)
+            .transpose()?,
+        ..Default::default()
+    };
+    filter.validate().map_err(|e| e.to_string())?;
+    let embedded = crate::api::embed_for_memory(state, query).await;
+    let query = MemoryQuery {
+        text: Some(query.to_string()),
+        embedding_model: embedded.as_ref().map(|(_, model)| model.clone()),
+        embedding: embedded.map(|(vector, _)| vector),
+        filter,
+        limit,
+    };
+
+    let mut results = Vec::new();
+    for org in orgs {
+        results.extend(memory.search(org, &query).map_err(|e| format!("search failed: {}", e))?);
+    }
+    results.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    results.truncate(limit);
+    if results.is_empty() {
+        return Ok("No matching memories.".to_string());
+    }
+    let entries: Vec<Value> = results
+        .iter()
+        .map(|r| {
+            json!({
+                "content": r.memory.content,
+                "agent": r.memory.agent_id,
+                "org": r.memory.org,
+                "metadata": r.memory.metadata,
+                "created_at": r.memory.created_at.to_rfc3339(),
+                "score": r.similarity,
+            })
+        })
+        .collect();
+    serde_json::to_string_pretty(&entries).map_err(|e| e.to_string())
+}
+
+async fn message_agent(
+    state: &AppState,
+    sender: &AgentContainer,
+    allowed_agents: &[String],
+    arguments: &Value,
+) -> Result<String, String> {
+    let target = str_arg(arguments, "agent")?;
+    let message = str_arg(arguments, "message")?;
+
+    let recipient = {
+        let containers = state.containers.read().await;
+        containers
+            .iter()
+            .find(|a| a.name == target || a.id == target)
+            .cloned()
+            .ok_or_else(|| format!("no agent named '{}'", target))?
+    };
+    if recipient.id == sender.id {
+        return Err("an agent can't message itself".to_string());
+    }
+    if !agent_allowed(allowed_agents, &recipient) {
+        return Err(format!("not allowed to message '{}'", recipient.name));
+    }
+    if recipient.status != AgentStatus::Running {
+        return Err(format!("agent '{}' is not running", recipient.name));
+    }
+
+    if recipient.runtime.as_deref() == Some("direct") {
+        let http = reqwest::Client::new();
+        let (reply, usage) =
+            crate::direct_llm::complete(&http, &state.api_keys, &recipient, None, message, None, None)
+                .await
+                .map_err(|e| format!("'{}' failed to answer: {}", recipient.name, e))?;
+        if let Some(usage) = &usage {
+            state.usage.record(&recipient, None, usage);
+        }
+        return Ok(reply);
+    }
+
+    let token = recipient
+        .config
+        .env_vars
+        .get("GATEWAY_TOKEN")
+        .or_else(|| recipient.config.env_vars.get("OPENCLAW_GATEWAY_TOKEN"));
+    crate::agent_comms::send_message_to_agent(
+        recipient.gateway_port,
+        token.map(String::as_str),
+        message,
+        MESSAGE_TIMEOUT_SECS,
+    )
+    .await
+    .map_err(|e| format!("'{}' failed to answer: {}", recipient.name, e))
+}
+
+/// Whether `recipient` is on a `message_agent` allowlist, by name or ID;
+/// `*` allows every agent and an empty list none
+fn agent_allowed(allowed_agents: &[String], recipient: &AgentContainer) -> bool {
+    allowed_agents
+        .iter()
+        .any(|a| a == "*" || *a == recipient.name || *a == recipient.id)
+}
+
+/// Whether `host` matches an allowlist entry: exactly, or under a
+/// `*.domain` entry (which also covers the bare domain)
+fn host_allowed(allowed_hosts: &[String], host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    allowed_hosts.iter().any(|pattern| {
+        let pattern = pattern.trim().to_ascii_lowercase();
+        match pattern.strip_prefix("*.") {
+            Some(domain) => host == domain || host.ends_with(&format!(".{}", domain)),
+            None => host == pattern,
+        }
+    })
+}
+
+fn check_url(allowed_hosts: &[String], url: &reqwest::Url) -> Result<(), String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("unsupported URL scheme '{}'", url.scheme()));
+    }
+    match url.host_str() {
+        Some(host) if host_allowed(allowed_hosts, host) => Ok(()),
+        Some(host) => Err(format!("host '{}' is not on the allowlist", host)),
+        None => Err("URL has no host".to_string()),
+    }
+}
+
+async fn http_fetch(allowed_hosts: &[String], arguments: &Value) -> Result<String, String> {
+    let url = reqwest::Url::parse(str_arg(arguments, "url")?).map_err(|e| format!("invalid URL: {}", e))?;
+    check_url(allowed_hosts, &url)?;
+
+    // Redirects are only followed within the allowlist
+    let redirect_hosts = allowed_hosts.to_vec();
+    let client = reqwest::Client::builder()
+        .timeout(HTTP_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if check_url(&redirect_hosts, attempt.url()).is_ok() {
+                attempt.follow()
+            } else {
+                attempt.stop()
+            }
+        }))
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let resp = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| format!("request failed: {}", e))?;
+    let status = resp.status();
+    let content_type = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+
+    let mut body = Vec::new();
+    let mut truncated = false;
+    let mut stream = resp.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| format!("reading response failed: {}", e))?;
+        let room = MAX_FETCH_BYTES - body.len();
+        if chunk.len() > room {
+            body.extend_from_slice(&chunk[..room]);
+            truncated = true;
+            break;
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let mut text = format!("HTTP {}\nContent-Type: {}\n\n{}", status, content_type, String::from_utf8_lossy(&body));
+    if truncated {
+        text.push_str("\n[truncated]");
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_allowlist() {
+        let allowed = vec!["api.github.com".to_string(), "*.wikipedia.org".to_string()];
+        assert!(host_allowed(&allowed, "api.github.com"));
+        assert!(host_allowed(&allowed, "API.GitHub.com."));
+        assert!(!host_allowed(&allowed, "github.com"));
+        assert!(host_allowed(&allowed, "en.wikipedia.org"));
+        assert!(host_allowed(&allowed, "wikipedia.org"));
+        assert!(!host_allowed(&allowed, "evilwikipedia.org"));
+
+        let url = |s: &str| reqwest::Url::parse(s).unwrap();
+        assert!(check_url(&allowed, &url("https://en.wikipedia.org/wiki/Rust")).is_ok());
+        assert!(check_url(&allowed, &url("file:///etc/passwd")).is_err());
+        assert!(check_url(&allowed, &url("http://169.254.169.254/latest")).is_err());
+    }
+
+    #[test]
+    fn test_agent_allowlist() {
+        let agent: AgentContainer = serde_json::from_value(json!({
+            "id": "a2",
+            "name": "researcher",
+            "status": "running",
+            "config": {},
+        }))
+        .unwrap();
+        let allowed = |list: &[&str]| {
+            agent_allowed(&list.iter().map(|a| a.to_string()).collect::<Vec<_>>(), &agent)
+        };
+        assert!(!allowed(&[]));
+        assert!(allowed(&["researcher"]));
+        assert!(allowed(&["a2"]));
+        assert!(!allowed(&["writer"]));
+        assert!(allowed(&["*"]));
+    }
+
+    #[test]
+    fn test_memory_search_stays_in_the_agents_orgs() {
+        let mut agent: AgentContainer = serde_json::from_value(json!({
+            "id": "a1",
+            "name": "tutor",
+            "status": "running",
+            "config": {},
+        }))
+        .unwrap();
+        assert_eq!(search_orgs(&agent, None), [ORG_DEFAULT, ORG_COMMON]);
+        agent.project = Some("biology-101".to_string());
+        assert_eq!(search_orgs(&agent, None), ["biology-101", ORG_COMMON]);
+        assert_eq!(search_orgs(&agent, Some("all")), ["all"]);
+    }
+
+    #[test]
+    fn test_specs_round_trip_config() {
+        let tools: Vec<AgentTool> = serde_json::from_value(json!([
+            { "name": "memory_search" },
+            { "name": "message_agent", "allowed_agents": ["researcher"] },
+            { "name": "http_fetch", "allowed_hosts": ["api.github.com"] }
+        ]))
+        .unwrap();
+        let names: Vec<_> = tools.iter().map(|t| t.spec().name).collect();
+        assert_eq!(names, ["memory_search", "message_agent", "http_fetch"]);
+        for tool in &tools {
+            assert_eq!(serde_json::to_value(tool).unwrap()["name"], tool.name());
+            assert_eq!(tool.spec().parameters["type"], "object");
+        }
+    }
+}
@@ -21,6 +21,9 @@ pub struct AgentContainer {
     /// Last health check result
     #[serde(default)]
     pub health_status: Option<HealthStatus>,
+    /// What the restart supervisor has done about this agent's exits
+    #[serde(default)]
+    pub restarts: RestartStatus,
     /// Runtime used for this container (docker or exo)
     #[serde(default)]
     pub runtime: Option<String>,
@@ -29,6 +32,28 @@ pub struct AgentContainer {
     pub gateway_port: u16,
 }
 
+impl Default for AgentContainer {
+    /// A stopped agent with the default config, for filling in with
+    /// struct update syntax
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            name: String::new(),
+            status: AgentStatus::Stopped,
+            config: AgentConfig::default(),
+            tailscale_ip: None,
+            resource_usage: None,
+            project: None,
+            tags: Vec::new(),
+            restart_policy: RestartPolicy::default(),
+            health_status: None,
+            restarts: RestartStatus::default(),
+            runtime: None,
+            gateway_port: default_gateway_port(),
+        }
+    }
+}
+
 pub fn default_gateway_port() -> u16 {
     18790
 }
@@ -77,6 +102,83 @@ pub struct AgentConfig {
     /// If not specified, defaults to node:20-alpine
     #[serde(default)]
     pub image: Option<String>,
+    /// Providers a direct-runtime agent fails over to, in order, when its own
+    /// provider is rate limited, erroring or unreachable
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub llm_fallbacks: Vec<LlmFallback>,
+    /// Orchestrator-provided tools a direct-runtime agent may call
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<AgentTool>,
+    /// How direct-runtime chat keeps long conversations within the model's
+    /// context window
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<ContextConfig>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ContextConfig {
+    /// Context window in tokens; overrides the `[[context-windows]]` entry
+    /// for the model
+    #[serde(default)]
+    pub window_tokens: Option<u32>,
+    #[serde(default)]
+    pub strategy: ContextStrategy,
+}
+
+/// What to do with turns that no longer fit the context window. Either way
+/// the transcript on disk keeps every turn.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextStrategy {
+    /// Leave the oldest turns out of the request
+    #[default]
+    SlidingWindow,
+    /// Have the model summarize them, and send the summary instead
+    Summarize,
+}
+
+/// A tool the orchestrator runs on behalf of a direct-runtime agent. The
+/// model sees its name, description and parameters; the settings here
+/// limit what it can reach.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "name", rename_all = "snake_case")]
+pub enum AgentTool {
+    /// Keyword search over the shared memory store
+    MemorySearch {
+        /// Org to search, `all` for every org; defaults to the agent's own
+        /// org and `common`
+        #[serde(default)]
+        org: Option<String>,
+    },
+    /// Send a message to another running agent and wait for its reply
+    MessageAgent {
+        /// Agent names or IDs it may message; `*` = any, empty = none
+        #[serde(default)]
+        allowed_agents: Vec<String>,
+    },
+    /// GET a web page or API on an allowlisted host
+    HttpFetch {
+        /// Host names, or `*.example.com` for a domain and its subdomains
+        allowed_hosts: Vec<String>,
+    },
+}
+
+/// One step of a direct-runtime agent's failover chain. Unset fields resolve
+/// like the agent's own provider: the stored key for `provider`, and for
+/// local servers the `[model-servers]` endpoint and default model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LlmFallback {
+    pub provider: LlmProvider,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Overrides the provider's default endpoint
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// `openai`, `anthropic`, `ollama` or `gemini`
+    #[serde(default)]
+    pub api_format: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
 }
 
 fn default_memory() -> u32 {
@@ -107,6 +209,27 @@ pub enum LlmProvider {
     },
 }
 
+impl LlmProvider {
+    /// Lowercase name, as written in config files
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LlmProvider::OpenAI => "openai",
+            LlmProvider::Anthropic => "anthropic",
+            LlmProvider::Gemini => "gemini",
+            LlmProvider::Kimi => "kimi",
+            LlmProvider::Zai => "zai",
+            LlmProvider::KimiCode => "kimicode",
+            LlmProvider::Access => "access",
+            LlmProvider::Huggingface => "huggingface",
+            LlmProvider::Ollama => "ollama",
+            LlmProvider::LlamaCpp => "llamacpp",
+            LlmProvider::Vllm => "vllm",
+            LlmProvider::Lmstudio => "lmstudio",
+            LlmProvider::Custom { .. } => "custom",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum RestartPolicy {
@@ -155,7 +278,8 @@ pub struct HealthCheck {
     /// Number of retries before marking unhealthy
     #[serde(default = "default_health_retries")]
     pub retries: u32,
-    /// Command to run for health check (default: openclaw --version)
+    /// Command run inside the container; it must exit 0. Unset = open a
+    /// WebSocket to the agent's gateway instead.
     #[serde(default)]
     pub command: Option<Vec<String>>,
 }
@@ -181,6 +305,42 @@ impl Default for HealthCheck {
     }
 }
 
+/// Restart bookkeeping kept by the supervisor (not persisted)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RestartStatus {
+    /// Automatic restarts since the orchestrator started
+    pub restart_count: u32,
+    pub last_exit: Option<AgentExit>,
+    /// When the next automatic restart is due (ISO timestamp)
+    pub next_restart_at: Option<String>,
+    /// Exited too often in a short time; left stopped until started by hand
+    pub crash_looping: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentExit {
+    pub reason: ExitReason,
+    pub at: String, // ISO timestamp
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitReason {
+    /// Stopped through the API
+    UserStop,
+    /// The runtime reported the container stopped on its own
+    Exited,
+    /// The runtime reported an error state
+    Crashed,
+    /// The container is gone from the runtime
+    Disappeared,
+    /// An automatic restart attempt failed
+    RestartFailed,
+    /// Stopped by the health monitor after failing its health checks
+    Unhealthy,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct HealthStatus {
     pub healthy: bool,
@@ -247,6 +407,9 @@ pub struct PartialAgentConfig {
     pub health_check: Option<HealthCheck>,
     pub volumes: Option<Vec<VolumeMount>>,
     pub image: Option<String>,
+    pub llm_fallbacks: Option<Vec<LlmFallback>>,
+    pub tools: Option<Vec<AgentTool>>,
+    pub context: Option<ContextConfig>,
 }
 
 // === Project/Group Management ===
@@ -305,8 +468,9 @@ pub struct SnapshotInfo {
 
 #[allow(unused_imports)]
 pub use crate::shared_memory::{
-    AgentStatusEntry, Memory, MemorySearchResult, NewMemory, NewTask, SharedMemory,
-    SharedMemoryConfig, SharedMemoryError, Task, TaskStatus, ORG_ALL, ORG_COMMON, ORG_DEFAULT,
+    AgentStatusEntry, Memory, MemoryFilter, MemoryQuery, MemorySearchResult, NewMemory, NewTask,
+    SharedMemory, SharedMemoryConfig, SharedMemoryError, Task, TaskStatus, ORG_ALL, ORG_COMMON,
+    ORG_DEFAULT,
 };
 
 impl AgentConfig {
@@ -349,6 +513,15 @@ impl AgentConfig {
         if let Some(ref image) = partial.image {
             self.image = Some(image.clone());
         }
+        if let Some(ref fallbacks) = partial.llm_fallbacks {
+            self.llm_fallbacks = fallbacks.clone();
+        }
+        if let Some(ref tools) = partial.tools {
+            self.tools = tools.clone();
+        }
+        if let Some(ref context) = partial.context {
+            self.context = Some(context.clone());
+        }
     }
 }
 
@@ -392,6 +565,25 @@ pub struct RouterConfig {
     /// Ask for clarification if confidence is low
     #[serde(default = "default_true")]
     pub clarify_on_low_confidence: bool,
+    /// Provider for LLM classification; unset = use the router agent's own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub llm: Option<RouterLlmConfig>,
+}
+
+/// Provider a router classifies with. The API key comes from the stored
+/// keys for `provider`, like a direct-runtime agent's.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouterLlmConfig {
+    #[serde(default)]
+    pub provider: LlmProvider,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Overrides the provider's default endpoint (`LLM_BASE_URL`)
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// `openai` or `anthropic` (`LLM_API_FORMAT`)
+    #[serde(default)]
+    pub api_format: Option<String>,
 }
 
 pub fn default_router_mode() -> RouterMode {
@@ -851,6 +1043,13 @@ pub struct ConversationMessage {
     pub metadata: HashMap<String, serde_json::Value>,
 }
 
+/// Tokens a provider reported for one completion
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
 /// Summary of a conversation session
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConversationSession {
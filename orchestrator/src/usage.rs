@@ -0,0 +1,213 @@
+//! Token usage and cost accounting
+//!
+//! Providers report token counts with each completion. `UsageLedger::record`
+//! prices them from the `prices` table in the config, writes a row to
+//! `chat.db` per completion and bumps the `/metrics` token counters.
+//! `GET /api/usage` sums those rows per day or month. Costs are fixed when
+//! recorded, so changing a price doesn't rewrite past reports.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use std::sync::Arc;
+
+use crate::chat_db::{ChatDb, NewUsage};
+use crate::config::{model_match_rank, ModelPrice};
+use crate::metrics::Metrics;
+use crate::types::{AgentContainer, TokenUsage};
+
+pub struct PriceTable {
+    prices: Vec<ModelPrice>,
+}
+
+impl PriceTable {
+    pub fn new(prices: Vec<ModelPrice>) -> Self {
+        Self { prices }
+    }
+
+    /// The most specific price for a model: an exact match, then the longest
+    /// `*` prefix, then the provider-wide entry
+    fn lookup(&self, provider: &str, model: &str) -> Option<&ModelPrice> {
+        let candidates = self.prices.iter().filter(|p| p.provider.eq_ignore_ascii_case(provider));
+        let mut best: Option<(usize, &ModelPrice)> = None;
+        for price in candidates {
+            let Some(rank) = model_match_rank(price.model.as_deref(), model) else { continue };
+            if best.is_none_or(|(r, _)| rank > r) {
+                best = Some((rank, price));
+            }
+        }
+        best.map(|(_, price)| price)
+    }
+
+    pub fn cost(&self, provider: &str, model: &str, usage: &TokenUsage) -> Option<f64> {
+        self.lookup(provider, model).map(|p| {
+            (usage.input_tokens as f64 * p.input_per_million
+                + usage.output_tokens as f64 * p.output_per_million)
+                / 1_000_000.0
+        })
+    }
+}
+
+pub struct UsageLedger {
+    db: Arc<ChatDb>,
+    prices: PriceTable,
+    metrics: Arc<Metrics>,
+}
+
+impl UsageLedger {
+    pub fn new(db: Arc<ChatDb>, prices: Vec<ModelPrice>, metrics: Arc<Metrics>) -> Self {
+        Self {
+            db,
+            prices: PriceTable::new(prices),
+            metrics,
+        }
+    }
+
+    /// Account one completion made by `agent`, on behalf of `user_id` if a
+    /// chat user started it
+    pub fn record(&self, agent: &AgentContainer, user_id: Option<&str>, usage: &TokenUsage) {
+        let provider = agent.config.llm_provider.as_str();
+        let model = agent.config.llm_model.as_deref().unwrap_or("default");
+        self.record_served(agent, provider, model, user_id, usage);
+    }
+
+    /// Like `record`, for a completion served by another provider than the
+    /// agent's own (a failover)
+    pub fn record_served(
+        &self,
+        agent: &AgentContainer,
+        provider: &str,
+        model: &str,
+        user_id: Option<&str>,
+        usage: &TokenUsage,
+    ) {
+        self.metrics.record_tokens(&agent.name, provider, usage);
+
+        let row = NewUsage {
+            at: Utc::now().to_rfc3339(),
+            agent_id: agent.id.clone(),
+            agent_name: agent.name.clone(),
+            user_id: user_id.map(str::to_string),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            cost_usd: self.prices.cost(provider, model, usage),
+        };
+        if let Err(e) = self.db.record_usage(&row) {
+            tracing::warn!("Failed to record token usage for {}: {}", agent.name, e);
+        }
+    }
+}
+
+/// A report bound: a date (`2026-03-02`, midnight UTC), RFC 3339 or Unix
+/// seconds, normalized to the RFC 3339 form usage rows are stored in
+pub fn parse_bound(value: &str) -> Result<String, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let midnight = date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+        return Ok(midnight.to_rfc3339());
+    }
+    crate::metrics_history::parse_time(value).map(|t: DateTime<Utc>| t.to_rfc3339())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat_db::{UsageGroup, UsagePeriod, UsageQuery};
+    use crate::types::{AgentConfig, AgentStatus, LlmProvider};
+
+    fn price(provider: &str, model: Option<&str>, input: f64, output: f64) -> ModelPrice {
+        ModelPrice {
+            provider: provider.to_string(),
+            model: model.map(str::to_string),
+            input_per_million: input,
+            output_per_million: output,
+        }
+    }
+
+    fn agent(name: &str, provider: LlmProvider, model: &str) -> AgentContainer {
+        AgentContainer {
+            id: format!("{}-id", name),
+            name: name.to_string(),
+            status: AgentStatus::Running,
+            config: AgentConfig {
+                llm_provider: provider,
+                llm_model: Some(model.to_string()),
+                ..Default::default()
+            },
+            runtime: Some("direct".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_most_specific_price_wins() {
+        let table = PriceTable::new(vec![
+            price("anthropic", None, 1.0, 1.0),
+            price("anthropic", Some("claude-*"), 3.0, 15.0),
+            price("anthropic", Some("claude-haiku-*"), 0.8, 4.0),
+            price("anthropic", Some("claude-haiku-4"), 1.0, 5.0),
+        ]);
+        let usage = TokenUsage {
+            input_tokens: 1_000_000,
+            output_tokens: 100_000,
+        };
+
+        let cost = |model| table.cost("anthropic", model, &usage).unwrap();
+        assert!((cost("claude-sonnet-4") - 4.5).abs() < 1e-9);
+        assert!((cost("claude-haiku-3-5") - 1.2).abs() < 1e-9);
+        assert!((cost("claude-haiku-4") - 1.5).abs() < 1e-9);
+        assert!((cost("other") - 1.1).abs() < 1e-9);
+        assert_eq!(table.cost("openai", "gpt-4o", &usage), None);
+    }
+
+    #[test]
+    fn test_usage_is_reported_per_period_and_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Arc::new(ChatDb::open(&dir.path().join("chat.db")).unwrap());
+        let ledger = UsageLedger::new(
+            Arc::clone(&db),
+            vec![price("openai", None, 2.0, 8.0)],
+            Arc::new(Metrics::new()),
+        );
+
+        let tutor = agent("tutor", LlmProvider::OpenAI, "gpt-4o");
+        let local = agent("local", LlmProvider::Ollama, "llama3");
+        let usage = TokenUsage {
+            input_tokens: 500_000,
+            output_tokens: 125_000,
+        };
+        ledger.record(&tutor, Some("student-1"), &usage);
+        ledger.record(&tutor, Some("student-2"), &usage);
+        ledger.record(&local, Some("student-1"), &usage);
+
+        let query = |group_by| UsageQuery {
+            period: UsagePeriod::Month,
+            group_by,
+            from: Some(parse_bound("2000-01-01").unwrap()),
+            to: None,
+            agent_id: None,
+            user_id: None,
+            provider: None,
+        };
+
+        let by_agent = db.usage_report(&query(UsageGroup::Agent)).unwrap();
+        assert_eq!(by_agent.len(), 2);
+        let tutor_row = by_agent.iter().find(|r| r.key == "tutor").unwrap();
+        assert_eq!(tutor_row.requests, 2);
+        assert_eq!(tutor_row.input_tokens, 1_000_000);
+        assert!((tutor_row.cost_usd - 4.0).abs() < 1e-9);
+        let local_row = by_agent.iter().find(|r| r.key == "local").unwrap();
+        assert_eq!(local_row.unpriced_requests, 1);
+        assert_eq!(local_row.cost_usd, 0.0);
+
+        let by_user = db.usage_report(&query(UsageGroup::User)).unwrap();
+        let student = by_user.iter().find(|r| r.key == "student-1").unwrap();
+        assert_eq!(student.requests, 2);
+        assert_eq!(student.period, Utc::now().format("%Y-%m").to_string());
+
+        let mut filtered = query(UsageGroup::Provider);
+        filtered.provider = Some("ollama".to_string());
+        let rows = db.usage_report(&filtered).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].key, "ollama");
+    }
+}
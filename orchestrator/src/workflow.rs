@@ -2,9 +2,19 @@
 // Enables complex task chains and agent collaboration
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
 use chrono::{DateTime, Utc};
 
+use crate::workflow_store::WorkflowStore;
+
+/// How many trigger firings are kept (in memory and on disk)
+const TRIGGER_HISTORY_LIMIT: usize = 1000;
+
+/// Stands in for webhook secrets when workflows are read or exported. A file
+/// carrying it keeps the secret of the workflow it replaces.
+pub const REDACTED_SECRET: &str = "<redacted>";
+
 /// A workflow definition for orchestrating multi-agent tasks
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Workflow {
@@ -13,15 +23,31 @@ pub struct Workflow {
     /// Human-readable workflow name
     pub name: String,
     /// Workflow description
+    #[serde(default)]
     pub description: String,
     /// List of workflow steps
     pub steps: Vec<WorkflowStep>,
     /// Workflow execution strategy
     pub strategy: ExecutionStrategy,
     /// Workflow metadata
+    #[serde(default)]
     pub metadata: WorkflowMetadata,
 }
 
+impl Workflow {
+    /// A copy safe to show or commit, with webhook secrets replaced by
+    /// `REDACTED_SECRET`
+    pub fn redacted(&self) -> Workflow {
+        let mut workflow = self.clone();
+        for trigger in &mut workflow.metadata.triggers {
+            if let WorkflowTrigger::Webhook { secret } = trigger {
+                *secret = REDACTED_SECRET.to_string();
+            }
+        }
+        workflow
+    }
+}
+
 /// How to execute the workflow steps
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ExecutionStrategy {
@@ -41,20 +67,27 @@ pub struct WorkflowStep {
     /// Step name
     pub name: String,
     /// Agent ID to execute this step (null = orchestrator assigns)
+    #[serde(default)]
     pub agent_id: Option<String>,
     /// Agent type/name pattern for auto-assignment
+    #[serde(default)]
     pub agent_pattern: Option<String>,
     /// Step to execute (prompt, command, etc.)
     pub task: StepTask,
     /// Dependencies - step IDs that must complete before this step
+    #[serde(default)]
     pub depends_on: Vec<String>,
     /// Input data mapping (references outputs from previous steps)
+    #[serde(default)]
     pub inputs: StepInputs,
     /// Output mapping (how to store step results)
+    #[serde(default)]
     pub outputs: StepOutputs,
     /// Retry configuration
+    #[serde(default)]
     pub retry_policy: RetryPolicy,
-    /// Timeout for this step (seconds)
+    /// Timeout for this step (seconds, 0 = default)
+    #[serde(default)]
     pub timeout: u64,
 }
 
@@ -64,7 +97,7 @@ pub struct WorkflowStep {
 pub enum StepTask {
     /// Chat completion task
     Chat {
-        /// The prompt/template to send to the agent
+        /// The prompt/template to send to the agent (`{{var}}` placeholders are rendered)
         prompt: String,
         /// Optional system message
         system_message: Option<String>,
@@ -91,6 +124,33 @@ pub enum StepTask {
         /// Request body
         body: Option<serde_json::Value>,
     },
+    /// Evaluate an expression over earlier outputs to choose which
+    /// downstream steps run (see `step_condition` for the syntax)
+    Condition {
+        /// Expression, e.g. `{{review.score}} >= 7`
+        expression: String,
+        /// Steps that only run when the expression is true
+        #[serde(default)]
+        if_true: Vec<String>,
+        /// Steps that only run when the expression is false
+        #[serde(default)]
+        if_false: Vec<String>,
+    },
+    /// Run a task once per element of a JSON array from an earlier step
+    ForEach {
+        /// Reference to the array, e.g. `research.findings`
+        items: String,
+        /// Task run per item, with `{{item}}` and `{{index}}` bound
+        task: Box<StepTask>,
+        /// How many items run at once (default 1)
+        #[serde(default)]
+        max_concurrency: Option<usize>,
+    },
+    /// Park the execution until an admin or teacher approves or rejects it
+    Approval {
+        /// Shown to approvers (`{{var}}` placeholders are rendered)
+        message: String,
+    },
     /// Custom task type
     Custom {
         /// Task type name
@@ -101,28 +161,33 @@ pub enum StepTask {
 }
 
 /// Input data mapping for a step
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct StepInputs {
     /// Static input values
-    #[serde(rename = "static")]
+    #[serde(rename = "static", default)]
     pub static_values: HashMap<String, serde_json::Value>,
     /// Dynamic inputs from previous step outputs
     /// Format: {"step_id.output_key": "local_var_name"}
+    /// The key after the step ID may be a JSON path, e.g. "research.items[0].title"
+    #[serde(default)]
     pub from_steps: HashMap<String, String>,
 }
 
 /// Output mapping for a step
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct StepOutputs {
     /// Which outputs to store
+    #[serde(default)]
     pub store: Vec<String>,
     /// Output format (json, text, binary)
+    #[serde(default)]
     pub format: OutputFormat,
 }
 
 /// Output format for step results
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub enum OutputFormat {
+    #[default]
     Json,
     Text,
     Binary,
@@ -152,8 +217,9 @@ impl Default for RetryPolicy {
     }
 }
 
-/// Workflow metadata
+/// Workflow metadata (omitted fields take their defaults)
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct WorkflowMetadata {
     /// Workflow version
     pub version: String,
@@ -165,6 +231,57 @@ pub struct WorkflowMetadata {
     pub tags: Vec<String>,
     /// Whether workflow is active
     pub enabled: bool,
+    /// What starts this workflow besides a manual execute call
+    pub triggers: Vec<WorkflowTrigger>,
+}
+
+/// An automatic way to start a workflow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum WorkflowTrigger {
+    /// Five-field cron expression evaluated in UTC, e.g. `*/15 * * * *`
+    Schedule { cron: String },
+    /// An agent transitioned to `Error` (any agent if `agent` is unset)
+    AgentError {
+        /// Agent ID or name
+        #[serde(default)]
+        agent: Option<String>,
+    },
+    /// A task was pushed to the shared-memory queue
+    TaskQueued {
+        /// Only fire for this task type
+        #[serde(default)]
+        task_type: Option<String>,
+    },
+    /// `POST /api/workflows/:id/webhook` with a matching `X-Webhook-Secret` header
+    Webhook { secret: String },
+}
+
+impl WorkflowTrigger {
+    /// Short name recorded in trigger history
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WorkflowTrigger::Schedule { .. } => "schedule",
+            WorkflowTrigger::AgentError { .. } => "agent_error",
+            WorkflowTrigger::TaskQueued { .. } => "task_queued",
+            WorkflowTrigger::Webhook { .. } => "webhook",
+        }
+    }
+}
+
+/// One time a trigger fired
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TriggerFiring {
+    pub id: String,
+    pub workflow_id: String,
+    /// `WorkflowTrigger::kind` of the trigger that fired
+    pub trigger: String,
+    pub fired_at: DateTime<Utc>,
+    /// Event details, also passed to the execution as parameters
+    pub payload: serde_json::Value,
+    /// The execution it started, if it could start one
+    pub execution_id: Option<String>,
+    pub error: Option<String>,
 }
 
 impl Default for WorkflowMetadata {
@@ -175,6 +292,7 @@ impl Default for WorkflowMetadata {
             created_at: Utc::now(),
             tags: Vec::new(),
             enabled: true,
+            triggers: Vec::new(),
         }
     }
 }
@@ -198,6 +316,10 @@ pub struct WorkflowExecution {
     pub completed_at: Option<DateTime<Utc>>,
     /// Error message if failed
     pub error: Option<String>,
+    /// Parameters the execution was started with (a trigger's event payload,
+    /// for triggered runs), available to step templates as `{{params.*}}`
+    #[serde(default)]
+    pub parameters: HashMap<String, serde_json::Value>,
 }
 
 /// Workflow execution status
@@ -208,6 +330,15 @@ pub enum ExecutionStatus {
     Completed,
     Failed,
     Cancelled,
+    /// Held between steps until resumed or cancelled
+    Paused,
+    /// Parked on an `Approval` step until someone decides
+    AwaitingApproval,
+    /// Not run because a `Condition` sent the execution down another branch
+    Skipped,
+    /// The orchestrator stopped while the execution was running; it can be
+    /// resumed from the last completed step
+    Interrupted,
 }
 
 /// Result of a workflow step execution
@@ -225,6 +356,12 @@ pub struct StepResult {
     pub duration_ms: u64,
     /// Error message if failed
     pub error: Option<String>,
+    /// Agent that ran the step (None for orchestrator-side steps)
+    #[serde(default)]
+    pub agent_id: Option<String>,
+    /// Number of attempts made, including retries
+    #[serde(default)]
+    pub attempts: u32,
 }
 
 /// Workflow execution request
@@ -238,20 +375,211 @@ pub struct WorkflowExecutionRequest {
     pub strategy: Option<ExecutionStrategy>,
 }
 
+/// Progress of a running execution, as pushed to stream subscribers
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ExecutionEvent {
+    StepStarted {
+        execution_id: String,
+        step_id: String,
+        timestamp: DateTime<Utc>,
+    },
+    /// An attempt failed and the step will run again after `delay_ms`
+    StepRetrying {
+        execution_id: String,
+        step_id: String,
+        attempt: u32,
+        max_attempts: u32,
+        delay_ms: u64,
+        error: String,
+        timestamp: DateTime<Utc>,
+    },
+    StepCompleted {
+        execution_id: String,
+        result: StepResult,
+    },
+    /// Failed or cut short by cancellation (see `result.status`)
+    StepFailed {
+        execution_id: String,
+        result: StepResult,
+    },
+    StepSkipped {
+        execution_id: String,
+        step_id: String,
+    },
+    ExecutionFinished {
+        execution_id: String,
+        status: ExecutionStatus,
+        error: Option<String>,
+        timestamp: DateTime<Utc>,
+    },
+}
+
+impl ExecutionEvent {
+    /// The event announcing a step's final result
+    pub fn step_finished(execution_id: &str, result: &StepResult) -> Self {
+        let execution_id = execution_id.to_string();
+        match result.status {
+            ExecutionStatus::Completed => ExecutionEvent::StepCompleted {
+                execution_id,
+                result: result.clone(),
+            },
+            ExecutionStatus::Skipped => ExecutionEvent::StepSkipped {
+                execution_id,
+                step_id: result.step_id.clone(),
+            },
+            _ => ExecutionEvent::StepFailed {
+                execution_id,
+                result: result.clone(),
+            },
+        }
+    }
+
+    pub fn execution_id(&self) -> &str {
+        match self {
+            ExecutionEvent::StepStarted { execution_id, .. }
+            | ExecutionEvent::StepRetrying { execution_id, .. }
+            | ExecutionEvent::StepCompleted { execution_id, .. }
+            | ExecutionEvent::StepFailed { execution_id, .. }
+            | ExecutionEvent::StepSkipped { execution_id, .. }
+            | ExecutionEvent::ExecutionFinished { execution_id, .. } => execution_id,
+        }
+    }
+}
+
+/// An `Approval` step waiting on a decision
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingApproval {
+    pub execution_id: String,
+    pub step_id: String,
+    /// Rendered message for the approver
+    pub message: String,
+    pub requested_at: DateTime<Utc>,
+}
+
+/// An approver's answer to a `PendingApproval`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApprovalDecision {
+    pub approved: bool,
+    #[serde(default)]
+    pub comment: Option<String>,
+    /// Subject of the approver's token; set by the API, not the caller
+    #[serde(default)]
+    pub decided_by: String,
+}
+
+/// A rejected workflow definition, pointing at the part that is wrong so
+/// file loaders can report a line number
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct InvalidWorkflow {
+    pub at: InvalidAt,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvalidAt {
+    Workflow,
+    /// Step by ID
+    Step(String),
+    /// Entry in `metadata.triggers` by index
+    Trigger(usize),
+}
+
+fn invalid(at: InvalidAt, message: String) -> anyhow::Error {
+    InvalidWorkflow { at, message }.into()
+}
+
 /// Workflow registry for managing workflow definitions
 pub struct WorkflowRegistry {
     workflows: HashMap<String, Workflow>,
+    /// Workflows defined by YAML files, and the file each came from
+    file_sources: HashMap<String, PathBuf>,
     pub executions: HashMap<String, WorkflowExecution>,
+    /// Most recent trigger firings, oldest first
+    trigger_history: VecDeque<TriggerFiring>,
+    /// Durable backing store (None = in-memory only)
+    store: Option<WorkflowStore>,
 }
 
 impl WorkflowRegistry {
     pub fn new() -> Self {
         Self {
             workflows: HashMap::new(),
+            file_sources: HashMap::new(),
             executions: HashMap::new(),
+            trigger_history: VecDeque::new(),
+            store: None,
+        }
+    }
+
+    /// Create a registry backed by `store`, reloading everything it holds.
+    ///
+    /// Executions that were still in progress (pending, running, paused or
+    /// awaiting approval) when the orchestrator stopped are marked
+    /// `Interrupted` so they can be resumed.
+    pub fn with_store(store: WorkflowStore) -> Result<Self, anyhow::Error> {
+        let mut registry = Self::new();
+
+        for workflow in store.load_workflows()? {
+            registry.workflows.insert(workflow.id.clone(), workflow);
+        }
+
+        for mut execution in store.load_executions()? {
+            if matches!(
+                execution.status,
+                ExecutionStatus::Pending
+                    | ExecutionStatus::Running
+                    | ExecutionStatus::Paused
+                    | ExecutionStatus::AwaitingApproval
+            ) {
+                execution.status = ExecutionStatus::Interrupted;
+                execution.error =
+                    Some("Orchestrator stopped while this execution was running".to_string());
+                if let Err(e) = store.save_execution(&execution) {
+                    tracing::warn!("Failed to mark execution {} interrupted: {}", execution.id, e);
+                }
+            }
+            registry.executions.insert(execution.id.clone(), execution);
+        }
+
+        registry.trigger_history = store.load_trigger_history(TRIGGER_HISTORY_LIMIT)?.into();
+
+        registry.store = Some(store);
+        Ok(registry)
+    }
+
+    /// Write an execution's current state through to the store
+    pub fn persist_execution(&self, id: &str) {
+        if let (Some(store), Some(execution)) = (&self.store, self.executions.get(id)) {
+            if let Err(e) = store.save_execution(execution) {
+                tracing::warn!("Failed to persist workflow execution {}: {}", id, e);
+            }
         }
     }
 
+    /// Record a trigger firing, dropping the oldest beyond the history limit
+    pub fn record_trigger(&mut self, firing: TriggerFiring) {
+        if let Some(store) = &self.store {
+            if let Err(e) = store.save_trigger_firing(&firing, TRIGGER_HISTORY_LIMIT) {
+                tracing::warn!("Failed to persist trigger firing for {}: {}", firing.workflow_id, e);
+            }
+        }
+        self.trigger_history.push_back(firing);
+        while self.trigger_history.len() > TRIGGER_HISTORY_LIMIT {
+            self.trigger_history.pop_front();
+        }
+    }
+
+    /// Trigger firings for a workflow, most recent first
+    pub fn trigger_history(&self, workflow_id: &str) -> Vec<&TriggerFiring> {
+        self.trigger_history
+            .iter()
+            .rev()
+            .filter(|f| f.workflow_id == workflow_id)
+            .collect()
+    }
+
     /// Register a workflow definition
     pub fn register_workflow(&mut self, workflow: Workflow) -> Result<(), anyhow::Error> {
         if self.workflows.contains_key(&workflow.id) {
@@ -261,10 +589,93 @@ impl WorkflowRegistry {
         // Validate workflow
         self.validate_workflow(&workflow)?;
 
+        if let Some(store) = &self.store {
+            store.save_workflow(&workflow)?;
+        }
+
         self.workflows.insert(workflow.id.clone(), workflow);
         Ok(())
     }
 
+    /// Add or replace a workflow defined by a YAML file.
+    ///
+    /// The file becomes the source of truth: a workflow with the same ID
+    /// created through the API is replaced and dropped from the store. An ID
+    /// already claimed by a different file is rejected.
+    pub fn load_file_workflow(&mut self, mut workflow: Workflow, path: &Path) -> Result<(), anyhow::Error> {
+        self.restore_secrets(&mut workflow);
+        self.validate_workflow(&workflow)?;
+
+        match self.file_sources.get(&workflow.id) {
+            Some(other) if other != path => {
+                return Err(invalid(
+                    InvalidAt::Workflow,
+                    format!("Workflow {} is already defined in {}", workflow.id, other.display()),
+                ));
+            }
+            Some(_) => {}
+            None if self.workflows.contains_key(&workflow.id) => {
+                tracing::info!("Workflow {} is now defined by {}", workflow.id, path.display());
+                if let Some(store) = &self.store {
+                    store.delete_workflow(&workflow.id)?;
+                }
+            }
+            None => {}
+        }
+
+        self.file_sources.insert(workflow.id.clone(), path.to_path_buf());
+        self.workflows.insert(workflow.id.clone(), workflow);
+        Ok(())
+    }
+
+    /// Put back the webhook secrets an exported workflow had redacted, from
+    /// the loaded workflow of the same ID: the nth webhook trigger takes the
+    /// secret of the loaded nth webhook. Placeholders with nothing to restore
+    /// are left for validation to reject.
+    fn restore_secrets(&self, workflow: &mut Workflow) {
+        let Some(loaded) = self.workflows.get(&workflow.id) else {
+            return;
+        };
+        let secrets: Vec<&String> = loaded
+            .metadata
+            .triggers
+            .iter()
+            .filter_map(|t| match t {
+                WorkflowTrigger::Webhook { secret } => Some(secret),
+                _ => None,
+            })
+            .collect();
+        let webhooks = workflow.metadata.triggers.iter_mut().filter_map(|t| match t {
+            WorkflowTrigger::Webhook { secret } => Some(secret),
+            _ => None,
+        });
+        for (redacted, secret) in webhooks.zip(secrets) {
+            if redacted == REDACTED_SECRET {
+                *redacted = secret.clone();
+            }
+        }
+    }
+
+    /// Drop the workflows defined by `path` (other than `keep`), returning their IDs
+    pub fn unload_file_workflows(&mut self, path: &Path, keep: Option<&str>) -> Vec<String> {
+        let ids: Vec<String> = self
+            .file_sources
+            .iter()
+            .filter(|(id, source)| source.as_path() == path && Some(id.as_str()) != keep)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &ids {
+            self.file_sources.remove(id);
+            self.workflows.remove(id);
+        }
+        ids
+    }
+
+    /// The YAML file a workflow was loaded from, if any
+    pub fn workflow_source(&self, id: &str) -> Option<&Path> {
+        self.file_sources.get(id).map(PathBuf::as_path)
+    }
+
     /// Get a workflow by ID
     pub fn get_workflow(&self, id: &str) -> Option<&Workflow> {
         self.workflows.get(id)
@@ -299,9 +710,11 @@ impl WorkflowRegistry {
             started_at: Utc::now(),
             completed_at: None,
             error: None,
+            parameters: request.parameters,
         };
 
         self.executions.insert(execution_id.clone(), execution);
+        self.persist_execution(&execution_id);
         Ok(execution_id)
     }
 
@@ -317,85 +730,197 @@ impl WorkflowRegistry {
 
     /// Update execution status
     pub fn update_execution(&mut self, execution: WorkflowExecution) {
-        self.executions.insert(execution.id.clone(), execution);
+        let id = execution.id.clone();
+        self.executions.insert(id.clone(), execution);
+        self.persist_execution(&id);
     }
 
-    /// Validate a workflow definition
-    fn validate_workflow(&self, workflow: &Workflow) -> Result<(), anyhow::Error> {
+    /// Validate a workflow definition. Errors are `InvalidWorkflow`.
+    pub fn validate_workflow(&self, workflow: &Workflow) -> Result<(), anyhow::Error> {
         // Check for duplicate step IDs
         let mut step_ids = std::collections::HashSet::new();
         for step in &workflow.steps {
             if !step_ids.insert(&step.id) {
-                return Err(anyhow::anyhow!("Duplicate step ID: {}", step.id));
+                return Err(invalid(
+                    InvalidAt::Step(step.id.clone()),
+                    format!("Duplicate step ID: {}", step.id),
+                ));
             }
+        }
+
+        // Validate dependencies exist (report every dangling edge at once)
+        let dangling: Vec<(&String, String)> = workflow
+            .steps
+            .iter()
+            .flat_map(|step| {
+                step.depends_on
+                    .iter()
+                    .filter(|dep| !step_ids.contains(dep))
+                    .map(move |dep| (&step.id, format!("{} -> {}", step.id, dep)))
+            })
+            .collect();
+        if let Some((first, _)) = dangling.first() {
+            return Err(invalid(
+                InvalidAt::Step(first.to_string()),
+                format!(
+                    "Workflow has dependencies on non-existent steps: {}",
+                    dangling.iter().map(|(_, edge)| edge.as_str()).collect::<Vec<_>>().join(", ")
+                ),
+            ));
+        }
 
-            // Validate dependencies exist
-            for dep in &step.depends_on {
-                if !workflow.steps.iter().any(|s| &s.id == dep) {
-                    return Err(anyhow::anyhow!(
-                        "Step {} depends on non-existent step {}",
-                        step.id, dep
+        for step in &workflow.steps {
+            // Validate input bindings point at real steps
+            for reference in step.inputs.from_steps.keys() {
+                let source = reference.split('.').next().unwrap_or_default();
+                if source == step.id || !workflow.steps.iter().any(|s| s.id == source) {
+                    return Err(invalid(
+                        InvalidAt::Step(step.id.clone()),
+                        format!("Step {} takes input from unknown step {}", step.id, source),
                     ));
                 }
             }
+
+            match &step.task {
+                // Branch targets must hang directly off the condition so they
+                // can't start before it has decided
+                StepTask::Condition { if_true, if_false, .. } => {
+                    for target in if_true.iter().chain(if_false) {
+                        let depends = workflow
+                            .steps
+                            .iter()
+                            .find(|s| &s.id == target)
+                            .map(|s| s.depends_on.contains(&step.id));
+                        match depends {
+                            None => {
+                                return Err(invalid(
+                                    InvalidAt::Step(step.id.clone()),
+                                    format!("Condition {} branches to unknown step {}", step.id, target),
+                                ));
+                            }
+                            Some(false) => {
+                                return Err(invalid(
+                                    InvalidAt::Step(target.clone()),
+                                    format!(
+                                        "Step {} is a branch of condition {} but does not depend on it",
+                                        target, step.id
+                                    ),
+                                ));
+                            }
+                            Some(true) => {}
+                        }
+                    }
+                }
+                StepTask::ForEach { task, max_concurrency, .. } => {
+                    if !matches!(
+                        **task,
+                        StepTask::Chat { .. } | StepTask::ExecuteCode { .. } | StepTask::HttpRequest { .. }
+                    ) {
+                        return Err(invalid(
+                            InvalidAt::Step(step.id.clone()),
+                            format!(
+                                "ForEach step {} can only repeat Chat, ExecuteCode or HttpRequest tasks",
+                                step.id
+                            ),
+                        ));
+                    }
+                    if *max_concurrency == Some(0) {
+                        return Err(invalid(
+                            InvalidAt::Step(step.id.clone()),
+                            format!("ForEach step {} has max_concurrency 0", step.id),
+                        ));
+                    }
+                }
+                _ => {}
+            }
         }
 
         // Check for circular dependencies
-        if self.has_circular_dependencies(workflow) {
-            return Err(anyhow::anyhow!("Workflow has circular dependencies"));
+        if let Some(cycle) = self.find_cycle(workflow) {
+            return Err(invalid(
+                InvalidAt::Step(cycle[0].clone()),
+                format!("Workflow has circular dependencies: {}", cycle.join(" -> ")),
+            ));
+        }
+
+        for (index, trigger) in workflow.metadata.triggers.iter().enumerate() {
+            match trigger {
+                WorkflowTrigger::Schedule { cron } => {
+                    crate::workflow_triggers::CronSchedule::parse(cron).map_err(|e| {
+                        invalid(InvalidAt::Trigger(index), format!("Invalid schedule '{}': {}", cron, e))
+                    })?;
+                }
+                WorkflowTrigger::Webhook { secret } if secret == REDACTED_SECRET => {
+                    return Err(invalid(
+                        InvalidAt::Trigger(index),
+                        "Webhook secret is redacted and no loaded workflow has one to keep; set it".to_string(),
+                    ));
+                }
+                WorkflowTrigger::Webhook { secret } if secret.len() < 16 => {
+                    return Err(invalid(
+                        InvalidAt::Trigger(index),
+                        "Webhook secret must be at least 16 characters".to_string(),
+                    ));
+                }
+                _ => {}
+            }
         }
 
         Ok(())
     }
 
-    /// Check if workflow has circular dependencies
-    fn has_circular_dependencies(&self, workflow: &Workflow) -> bool {
+    /// Find a dependency cycle, returned as the path of step IDs that loops
+    /// back on itself (e.g. `[a, b, a]`)
+    fn find_cycle(&self, workflow: &Workflow) -> Option<Vec<String>> {
         // Build dependency graph using owned Strings
         let mut graph: HashMap<String, Vec<String>> = HashMap::new();
         for step in &workflow.steps {
             graph.insert(step.id.clone(), step.depends_on.to_vec());
         }
 
-        // Detect cycles using DFS
+        // Detect cycles using DFS, walking steps in declaration order so the
+        // reported cycle is deterministic
         let mut visited = std::collections::HashSet::new();
-        let mut rec_stack = std::collections::HashSet::new();
+        let mut path = Vec::new();
 
-        for step_id in graph.keys() {
-            if self.has_cycle_util(&graph, step_id, &mut visited, &mut rec_stack) {
-                return true;
+        for step in &workflow.steps {
+            if let Some(cycle) = self.find_cycle_util(&graph, &step.id, &mut visited, &mut path) {
+                return Some(cycle);
             }
         }
 
-        false
+        None
     }
 
-    fn has_cycle_util(
+    fn find_cycle_util(
         &self,
         graph: &HashMap<String, Vec<String>>,
         node: &str,
         visited: &mut std::collections::HashSet<String>,
-        rec_stack: &mut std::collections::HashSet<String>,
-    ) -> bool {
-        if rec_stack.contains(node) {
-            return true;
+        path: &mut Vec<String>,
+    ) -> Option<Vec<String>> {
+        if let Some(start) = path.iter().position(|n| n == node) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(node.to_string());
+            return Some(cycle);
         }
         if visited.contains(node) {
-            return false;
+            return None;
         }
 
         visited.insert(node.to_string());
-        rec_stack.insert(node.to_string());
+        path.push(node.to_string());
 
         if let Some(neighbors) = graph.get(node) {
             for neighbor in neighbors {
-                if self.has_cycle_util(graph, neighbor, visited, rec_stack) {
-                    return true;
+                if let Some(cycle) = self.find_cycle_util(graph, neighbor, visited, path) {
+                    return Some(cycle);
                 }
             }
         }
 
-        rec_stack.remove(node);
-        false
+        path.pop();
+        None
     }
 }
 
@@ -485,6 +1010,95 @@ mod tests {
             metadata: WorkflowMetadata::default(),
         };
 
-        assert!(registry.has_circular_dependencies(&workflow));
+        assert_eq!(
+            registry.find_cycle(&workflow),
+            Some(vec![
+                "step1".to_string(),
+                "step2".to_string(),
+                "step1".to_string()
+            ])
+        );
+
+        let err = registry.validate_workflow(&workflow).unwrap_err();
+        assert!(err.to_string().contains("step1 -> step2 -> step1"));
+    }
+
+    #[test]
+    fn test_condition_branches_must_depend_on_condition() {
+        let registry = WorkflowRegistry::new();
+
+        let step = |id: &str, task: StepTask, depends_on: &[&str]| WorkflowStep {
+            id: id.to_string(),
+            name: id.to_string(),
+            agent_id: None,
+            agent_pattern: None,
+            task,
+            depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+            inputs: StepInputs {
+                static_values: HashMap::new(),
+                from_steps: HashMap::new(),
+            },
+            outputs: StepOutputs {
+                store: vec![],
+                format: OutputFormat::Json,
+            },
+            retry_policy: RetryPolicy::default(),
+            timeout: 60,
+        };
+        let condition = StepTask::Condition {
+            expression: "true".to_string(),
+            if_true: vec!["yes".to_string()],
+            if_false: vec!["no".to_string()],
+        };
+        let approval = || StepTask::Approval {
+            message: "Ship it?".to_string(),
+        };
+
+        let mut workflow = Workflow {
+            id: "branching".to_string(),
+            name: "Branching".to_string(),
+            description: String::new(),
+            steps: vec![
+                step("check", condition, &[]),
+                step("yes", approval(), &["check"]),
+                step("no", approval(), &[]),
+            ],
+            strategy: ExecutionStrategy::Dag,
+            metadata: WorkflowMetadata::default(),
+        };
+
+        let err = registry.validate_workflow(&workflow).unwrap_err();
+        assert!(err.to_string().contains("no is a branch of condition check"));
+
+        workflow.steps[2].depends_on = vec!["check".to_string()];
+        assert!(registry.validate_workflow(&workflow).is_ok());
+    }
+
+    #[test]
+    fn test_step_finished_event_follows_result_status() {
+        let mut result = StepResult {
+            step_id: "fetch".to_string(),
+            status: ExecutionStatus::Completed,
+            output: Some(serde_json::json!("ok")),
+            timestamp: Utc::now(),
+            duration_ms: 12,
+            error: None,
+            agent_id: None,
+            attempts: 1,
+        };
+
+        let event = serde_json::to_value(ExecutionEvent::step_finished("exec-1", &result)).unwrap();
+        assert_eq!(event["type"], "step-completed");
+        assert_eq!(event["execution_id"], "exec-1");
+        assert_eq!(event["result"]["step_id"], "fetch");
+
+        result.status = ExecutionStatus::Cancelled;
+        let event = ExecutionEvent::step_finished("exec-1", &result);
+        assert!(matches!(event, ExecutionEvent::StepFailed { .. }));
+
+        result.status = ExecutionStatus::Skipped;
+        let event = serde_json::to_value(ExecutionEvent::step_finished("exec-1", &result)).unwrap();
+        assert_eq!(event["type"], "step-skipped");
+        assert_eq!(event["step_id"], "fetch");
     }
 }
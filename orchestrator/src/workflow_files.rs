@@ -0,0 +1,350 @@
+//! Workflow definitions kept as YAML files
+//!
+//! Every `*.yaml` / `*.yml` file in the workflows directory holds one
+//! workflow, in the same shape `POST /api/workflows` accepts as JSON. Files
+//! starting with `_` are skipped, like team templates. The directory is
+//! polled so edits take effect without a restart: a file that fails to parse
+//! or validate is reported with its line number and whatever version was
+//! loaded before stays active. Deleting a file unloads its workflow.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::RwLock;
+
+use crate::workflow::{InvalidAt, InvalidWorkflow, Workflow, WorkflowRegistry};
+
+/// How often the directory is checked for changes
+const RELOAD_INTERVAL_SECS: u64 = 2;
+
+/// A workflow file that could not be loaded
+#[derive(Debug)]
+pub struct FileError {
+    /// 1-based line the problem was found on, when it can be pinned down
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for FileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Watches a directory of workflow files and keeps the registry in sync
+pub struct WorkflowFiles {
+    dir: PathBuf,
+    registry: Arc<RwLock<WorkflowRegistry>>,
+    /// Modification time of each file as of its last load attempt
+    seen: HashMap<PathBuf, SystemTime>,
+}
+
+impl WorkflowFiles {
+    pub fn new(dir: impl Into<PathBuf>, registry: Arc<RwLock<WorkflowRegistry>>) -> Self {
+        Self {
+            dir: dir.into(),
+            registry,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Load new and changed files and unload workflows whose file is gone.
+    /// Returns how many files loaded successfully.
+    pub async fn reload(&mut self) -> usize {
+        let files = match list_files(&self.dir) {
+            Ok(files) => files,
+            Err(e) => {
+                tracing::warn!("Cannot read workflows directory {}: {}", self.dir.display(), e);
+                Vec::new()
+            }
+        };
+
+        let mut registry = self.registry.write().await;
+
+        let gone: Vec<PathBuf> = self
+            .seen
+            .keys()
+            .filter(|path| !files.iter().any(|(file, _)| file == *path))
+            .cloned()
+            .collect();
+        for path in gone {
+            self.seen.remove(&path);
+            for id in registry.unload_file_workflows(&path, None) {
+                tracing::info!("Unloaded workflow {} ({} was removed)", id, path.display());
+            }
+        }
+
+        let mut loaded = 0;
+        for (path, modified) in files {
+            if self.seen.get(&path) == Some(&modified) {
+                continue;
+            }
+            self.seen.insert(path.clone(), modified);
+
+            let result = std::fs::read_to_string(&path)
+                .map_err(|e| FileError { line: None, message: e.to_string() })
+                .and_then(|source| load_file(&mut registry, &path, &source));
+            match result {
+                Ok(id) => {
+                    tracing::info!("Loaded workflow {} from {}", id, path.display());
+                    loaded += 1;
+                }
+                Err(e) => tracing::error!("Invalid workflow file {}: {}", path.display(), e),
+            }
+        }
+
+        loaded
+    }
+
+    /// Poll the directory for changes in the background
+    pub fn spawn(mut self) {
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(std::time::Duration::from_secs(RELOAD_INTERVAL_SECS));
+            loop {
+                interval.tick().await;
+                self.reload().await;
+            }
+        });
+    }
+}
+
+/// Workflow files in `dir` with their modification times (missing dir = none)
+fn list_files(dir: &Path) -> std::io::Result<Vec<(PathBuf, SystemTime)>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_yaml = path
+            .extension()
+            .is_some_and(|e| e == "yaml" || e == "yml");
+        let is_template = path
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with('_'));
+        if is_yaml && !is_template {
+            let modified = std::fs::metadata(&path)?.modified()?;
+            files.push((path, modified));
+        }
+    }
+    Ok(files)
+}
+
+/// Parse, validate and register one file's workflow, returning its ID
+fn load_file(registry: &mut WorkflowRegistry, path: &Path, source: &str) -> Result<String, FileError> {
+    let workflow = parse(source)?;
+    let id = workflow.id.clone();
+
+    registry.load_file_workflow(workflow, path).map_err(|e| {
+        let line = e
+            .downcast_ref::<InvalidWorkflow>()
+            .and_then(|invalid| locate(source, &invalid.at));
+        FileError { line, message: e.to_string() }
+    })?;
+
+    // The file may have been edited to define a different ID
+    registry.unload_file_workflows(path, Some(&id));
+    Ok(id)
+}
+
+/// Parse a workflow from YAML
+pub fn parse(source: &str) -> Result<Workflow, FileError> {
+    serde_yaml::from_str(source).map_err(|e| {
+        let message = e.to_string();
+        // serde_yaml appends the position itself; it goes in `line` instead
+        let message = match message.rfind(" at line ") {
+            Some(at) => message[..at].to_string(),
+            None => message,
+        };
+        FileError {
+            line: e.location().map(|l| l.line()),
+            message,
+        }
+    })
+}
+
+/// Render a workflow as YAML that `parse` reads back, with webhook secrets
+/// redacted. Loaded over the workflow it came from, the file keeps them.
+pub fn to_yaml(workflow: &Workflow) -> Result<String> {
+    Ok(serde_yaml::to_string(&workflow.redacted())?)
+}
+
+/// Find the line a validation error refers to by scanning the source
+fn locate(source: &str, at: &InvalidAt) -> Option<usize> {
+    let indent = |line: &str| line.len() - line.trim_start().len();
+
+    match at {
+        InvalidAt::Workflow => None,
+        InvalidAt::Step(id) => source
+            .lines()
+            .position(|line| {
+                let key = line.trim_start().trim_start_matches("- ").trim_start();
+                // The workflow's own `id:` is the only one at column 0
+                line.starts_with([' ', '-'])
+                    && key
+                        .strip_prefix("id:")
+                        .is_some_and(|value| value.trim().trim_matches(['"', '\'']) == id)
+            })
+            .map(|index| index + 1),
+        InvalidAt::Trigger(index) => {
+            let mut lines = source.lines().enumerate();
+            let (_, header) = lines.find(|(_, line)| line.trim_start().starts_with("triggers:"))?;
+            let base = indent(header);
+
+            let mut items = lines
+                .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+                .take_while(|(_, line)| {
+                    indent(line) > base || (indent(line) == base && line.trim_start().starts_with('-'))
+                })
+                .filter(|(_, line)| line.trim_start().starts_with('-'))
+                .peekable();
+            let item_indent = indent(items.peek()?.1);
+            items
+                .filter(|(_, line)| indent(line) == item_indent)
+                .nth(*index)
+                .map(|(number, _)| number + 1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflow::WorkflowTrigger;
+
+    const PIPELINE: &str = r#"id: review-pipeline
+name: Review pipeline
+strategy: Dag
+metadata:
+  version: "1.0.0"
+  created_at: 2025-01-01T00:00:00Z
+  tags: []
+  enabled: true
+  triggers:
+    - type: Schedule
+      cron: "0 9 * * 1"
+    - type: Webhook
+      secret: too-short
+steps:
+  - id: draft
+    name: Draft
+    task:
+      type: Chat
+      data:
+        prompt: Write a summary
+  - id: review
+    name: Review
+    depends_on: [draft]
+    task:
+      type: Chat
+      data:
+        prompt: "Review {{draft}}"
+"#;
+
+    #[test]
+    fn test_validation_errors_point_at_lines() {
+        let mut registry = WorkflowRegistry::new();
+        let path = Path::new("workflows/review.yaml");
+
+        let err = load_file(&mut registry, path, PIPELINE).unwrap_err();
+        assert_eq!(err.line, Some(12));
+        assert!(err.message.contains("Webhook secret"));
+
+        let source = PIPELINE
+            .replace("secret: too-short", "secret: long-enough-secret-value")
+            .replace("depends_on: [draft]", "depends_on: [drfat]");
+        let err = load_file(&mut registry, path, &source).unwrap_err();
+        assert_eq!(err.line, Some(21));
+
+        let err = parse("id: x\nname: X\nstrategy: Dag\nsteps:\n  - id: a\n    name: A\n").unwrap_err();
+        assert_eq!(err.line, Some(5));
+        assert!(err.message.contains("task"), "{}", err);
+    }
+
+    #[test]
+    fn test_file_replaces_and_unloads() {
+        let mut registry = WorkflowRegistry::new();
+        let path = Path::new("workflows/review.yaml");
+        let source = PIPELINE.replace("secret: too-short", "secret: long-enough-secret-value");
+
+        assert_eq!(load_file(&mut registry, path, &source).unwrap(), "review-pipeline");
+        assert_eq!(registry.workflow_source("review-pipeline"), Some(path));
+
+        // Renaming the workflow in place drops the old ID
+        let renamed = source.replace("id: review-pipeline", "id: review-v2");
+        load_file(&mut registry, path, &renamed).unwrap();
+        assert!(registry.get_workflow("review-pipeline").is_none());
+
+        // Another file may not claim the same ID
+        let err = load_file(&mut registry, Path::new("workflows/copy.yaml"), &renamed).unwrap_err();
+        assert!(err.message.contains("already defined"));
+
+        assert_eq!(registry.unload_file_workflows(path, None), vec!["review-v2".to_string()]);
+        assert!(registry.list_workflows().is_empty());
+    }
+
+    #[test]
+    fn test_export_round_trips() {
+        let workflow = parse(include_str!("../../workflows/_example-workflow.yaml")).unwrap();
+        WorkflowRegistry::new().validate_workflow(&workflow).unwrap();
+
+        let reparsed = parse(&to_yaml(&workflow).unwrap()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&workflow).unwrap(),
+            serde_json::to_value(&reparsed).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_export_redacts_webhook_secrets() {
+        let mut registry = WorkflowRegistry::new();
+        let path = Path::new("workflows/review.yaml");
+        let source = PIPELINE.replace("secret: too-short", "secret: long-enough-secret-value");
+        load_file(&mut registry, path, &source).unwrap();
+
+        let exported = to_yaml(registry.get_workflow("review-pipeline").unwrap()).unwrap();
+        assert!(!exported.contains("long-enough-secret-value"));
+
+        // Reloading the export keeps the secret it replaces
+        load_file(&mut registry, path, &exported).unwrap();
+        let workflow = registry.get_workflow("review-pipeline").unwrap();
+        let secret = match &workflow.metadata.triggers[1] {
+            WorkflowTrigger::Webhook { secret } => secret.clone(),
+            other => panic!("unexpected trigger {:?}", other),
+        };
+        assert_eq!(secret, "long-enough-secret-value");
+
+        // Elsewhere there is nothing to keep
+        let err = load_file(&mut WorkflowRegistry::new(), path, &exported).unwrap_err();
+        assert!(err.message.contains("redacted"), "{}", err);
+
+        // Each webhook gets its own secret back
+        let source = source.replace(
+            "    - type: Webhook\n",
+            "    - type: Webhook\n      secret: second-webhook-secret-value\n    - type: Webhook\n",
+        );
+        load_file(&mut registry, path, &source).unwrap();
+        let exported = to_yaml(registry.get_workflow("review-pipeline").unwrap()).unwrap();
+        assert!(!exported.contains("second-webhook-secret-value"));
+        load_file(&mut registry, path, &exported).unwrap();
+        let secrets: Vec<&str> = registry
+            .get_workflow("review-pipeline")
+            .unwrap()
+            .metadata
+            .triggers
+            .iter()
+            .filter_map(|t| match t {
+                WorkflowTrigger::Webhook { secret } => Some(secret.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(secrets, ["second-webhook-secret-value", "long-enough-secret-value"]);
+    }
+}
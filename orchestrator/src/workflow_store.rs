@@ -0,0 +1,223 @@
+//! Durable storage for workflow definitions and execution history.
+//!
+//! Lives in its own SQLite file next to `chat.db`. Definitions and
+//! executions are stored as JSON documents keyed by ID — the in-memory
+//! `WorkflowRegistry` stays the source of truth while the process runs, and
+//! this store is written through on every change so it can be reloaded at
+//! startup.
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::workflow::{TriggerFiring, Workflow, WorkflowExecution};
+
+/// Same locking model as `ChatDb`: the Mutex guards the connection, SQLite's
+/// WAL mode handles concurrent readers.
+pub struct WorkflowStore {
+    conn: Mutex<Connection>,
+}
+
+impl WorkflowStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path).context("opening workflows.db")?;
+
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.execute_batch(SCHEMA_V1)?;
+
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    // ─── Workflows ──────────────────────────────────────────────────────────
+
+    pub fn save_workflow(&self, workflow: &Workflow) -> Result<()> {
+        let definition = serde_json::to_string(workflow)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO workflows (id, definition, updated_at)
+             VALUES (?1, ?2, CURRENT_TIMESTAMP)",
+            params![workflow.id, definition],
+        )?;
+        Ok(())
+    }
+
+    pub fn load_workflows(&self) -> Result<Vec<Workflow>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, definition FROM workflows ORDER BY id")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(decode_rows(rows, "workflow"))
+    }
+
+    pub fn delete_workflow(&self, id: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM workflows WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    // ─── Executions ─────────────────────────────────────────────────────────
+
+    pub fn save_execution(&self, execution: &WorkflowExecution) -> Result<()> {
+        let record = serde_json::to_string(execution)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO workflow_executions (id, workflow_id, record, started_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                execution.id,
+                execution.workflow_id,
+                record,
+                execution.started_at.to_rfc3339(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn load_executions(&self) -> Result<Vec<WorkflowExecution>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, record FROM workflow_executions ORDER BY started_at",
+        )?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(decode_rows(rows, "execution"))
+    }
+
+    // ─── Trigger history ────────────────────────────────────────────────────
+
+    /// Record a firing and prune everything older than the newest `keep`
+    pub fn save_trigger_firing(&self, firing: &TriggerFiring, keep: usize) -> Result<()> {
+        let record = serde_json::to_string(firing)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO workflow_trigger_history (id, workflow_id, record, fired_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![firing.id, firing.workflow_id, record, firing.fired_at.to_rfc3339()],
+        )?;
+        conn.execute(
+            "DELETE FROM workflow_trigger_history WHERE id NOT IN (
+                 SELECT id FROM workflow_trigger_history ORDER BY fired_at DESC LIMIT ?1
+             )",
+            params![keep as i64],
+        )?;
+        Ok(())
+    }
+
+    /// The newest `limit` firings, oldest first
+    pub fn load_trigger_history(&self, limit: usize) -> Result<Vec<TriggerFiring>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, record FROM (
+                 SELECT id, record, fired_at FROM workflow_trigger_history
+                 ORDER BY fired_at DESC LIMIT ?1
+             ) ORDER BY fired_at",
+        )?;
+        let rows = stmt
+            .query_map(params![limit as i64], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(decode_rows(rows, "trigger firing"))
+    }
+}
+
+/// Deserialize stored JSON documents, skipping (and logging) any that no
+/// longer match the current types rather than refusing to start.
+fn decode_rows<T: serde::de::DeserializeOwned>(rows: Vec<(String, String)>, kind: &str) -> Vec<T> {
+    rows.into_iter()
+        .filter_map(|(id, json)| match serde_json::from_str(&json) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                tracing::warn!("Skipping unreadable stored {} {}: {}", kind, id, e);
+                None
+            }
+        })
+        .collect()
+}
+
+// ─── Schema ────────────────────────────────────────────────────────────────
+
+const SCHEMA_V1: &str = r#"
+CREATE TABLE IF NOT EXISTS workflows (
+    id          TEXT PRIMARY KEY,
+    definition  TEXT NOT NULL,                  -- JSON-encoded Workflow
+    updated_at  DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS workflow_executions (
+    id           TEXT PRIMARY KEY,
+    workflow_id  TEXT NOT NULL,
+    record       TEXT NOT NULL,                 -- JSON-encoded WorkflowExecution
+    started_at   TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_workflow_executions_workflow
+    ON workflow_executions(workflow_id, started_at);
+
+CREATE TABLE IF NOT EXISTS workflow_trigger_history (
+    id           TEXT PRIMARY KEY,
+    workflow_id  TEXT NOT NULL,
+    record       TEXT NOT NULL,                 -- JSON-encoded TriggerFiring
+    fired_at     TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_workflow_trigger_history_fired
+    ON workflow_trigger_history(fired_at);
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflow::{ExecutionStatus, WorkflowRegistry};
+    use chrono::Utc;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    fn execution(id: &str, status: ExecutionStatus) -> WorkflowExecution {
+        WorkflowExecution {
+            id: id.to_string(),
+            workflow_id: "wf".to_string(),
+            status,
+            current_step: None,
+            step_results: HashMap::new(),
+            started_at: Utc::now(),
+            completed_at: None,
+            error: None,
+            parameters: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_reload_marks_running_executions_interrupted() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("workflows.db");
+
+        let store = WorkflowStore::open(&path).unwrap();
+        store.save_execution(&execution("done", ExecutionStatus::Completed)).unwrap();
+        store.save_execution(&execution("live", ExecutionStatus::Running)).unwrap();
+        drop(store);
+
+        let registry = WorkflowRegistry::with_store(WorkflowStore::open(&path).unwrap()).unwrap();
+        assert!(matches!(
+            registry.get_execution("done").unwrap().status,
+            ExecutionStatus::Completed
+        ));
+        assert!(matches!(
+            registry.get_execution("live").unwrap().status,
+            ExecutionStatus::Interrupted
+        ));
+
+        // The interrupted status is written back, not just applied in memory
+        let reloaded = WorkflowStore::open(&path).unwrap().load_executions().unwrap();
+        let live = reloaded.iter().find(|e| e.id == "live").unwrap();
+        assert!(matches!(live.status, ExecutionStatus::Interrupted));
+    }
+}
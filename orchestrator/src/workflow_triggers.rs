@@ -0,0 +1,481 @@
+//! Automatic workflow starts: cron schedules, orchestrator events and webhooks
+//!
+//! Triggers are declared on `WorkflowMetadata.triggers`. Every firing —
+//! whether or not it managed to start an execution — is recorded in the
+//! registry's trigger history. Disabled workflows are never considered.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::auth::constant_time_eq;
+use crate::executor::WorkflowExecutor;
+use crate::shared_memory::{SharedMemory, Task};
+use crate::workflow::{TriggerFiring, Workflow, WorkflowExecutionRequest, WorkflowRegistry, WorkflowTrigger};
+
+/// How often the shared-memory task queue is polled for new tasks
+const TASK_POLL_INTERVAL_SECS: u64 = 5;
+
+/// Something that happened in the orchestrator that workflows can react to
+#[derive(Debug, Clone)]
+pub enum TriggerEvent {
+    /// An agent the orchestrator thought was running is now in `Error`
+    AgentError { agent_id: String, agent_name: String },
+    /// A task was pushed to the shared-memory queue
+    TaskQueued(Task),
+}
+
+impl TriggerEvent {
+    fn matches(&self, trigger: &WorkflowTrigger) -> bool {
+        match (self, trigger) {
+            (
+                TriggerEvent::AgentError { agent_id, agent_name },
+                WorkflowTrigger::AgentError { agent },
+            ) => agent
+                .as_deref()
+                .is_none_or(|a| a == agent_id || a == agent_name),
+            (TriggerEvent::TaskQueued(task), WorkflowTrigger::TaskQueued { task_type }) => {
+                task_type.as_deref().is_none_or(|t| t == task.task_type)
+            }
+            _ => false,
+        }
+    }
+
+    fn payload(&self) -> serde_json::Value {
+        match self {
+            TriggerEvent::AgentError { agent_id, agent_name } => serde_json::json!({
+                "agent_id": agent_id,
+                "agent_name": agent_name,
+            }),
+            TriggerEvent::TaskQueued(task) => serde_json::json!({
+                "task_id": task.id,
+                "task_type": task.task_type,
+                "from_agent": task.from_agent,
+                "to_agent": task.to_agent,
+                "payload": task.payload,
+            }),
+        }
+    }
+}
+
+/// Why a webhook call was refused
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    #[error("Invalid webhook secret")]
+    Unauthorized,
+    #[error("Workflow is disabled")]
+    Disabled,
+}
+
+/// Starts workflows when their triggers fire
+pub struct WorkflowTriggers {
+    registry: Arc<RwLock<WorkflowRegistry>>,
+    executor: Arc<WorkflowExecutor>,
+}
+
+impl WorkflowTriggers {
+    pub fn new(registry: Arc<RwLock<WorkflowRegistry>>, executor: Arc<WorkflowExecutor>) -> Self {
+        Self { registry, executor }
+    }
+
+    /// Start the cron loop and, if shared memory is available, the task-queue watcher
+    pub fn spawn(self: &Arc<Self>, shared_memory: Option<Arc<SharedMemory>>) {
+        let triggers = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                // Wake at the top of each minute
+                let now = Utc::now();
+                let next = DateTime::from_timestamp((now.timestamp() / 60 + 1) * 60, 0).unwrap_or(now);
+                let wait = (next - now).to_std().unwrap_or_default();
+                tokio::time::sleep(wait).await;
+
+                triggers.run_schedules(next).await;
+            }
+        });
+
+        let Some(memory) = shared_memory else {
+            return;
+        };
+        let triggers = Arc::clone(self);
+        tokio::spawn(async move {
+            // Only tasks pushed from now on count
+            let mut last_seen = match memory.tasks_after(0) {
+                Ok(tasks) => tasks.last().map(|t| t.id).unwrap_or(0),
+                Err(e) => {
+                    tracing::warn!("Task-queue triggers disabled, can't read tasks: {}", e);
+                    return;
+                }
+            };
+
+            let mut interval =
+                tokio::time::interval(tokio::time::Duration::from_secs(TASK_POLL_INTERVAL_SECS));
+            loop {
+                interval.tick().await;
+                match memory.tasks_after(last_seen) {
+                    Ok(tasks) => {
+                        for task in tasks {
+                            last_seen = last_seen.max(task.id);
+                            triggers.dispatch(TriggerEvent::TaskQueued(task)).await;
+                        }
+                    }
+                    Err(e) => tracing::warn!("Failed to poll task queue for triggers: {}", e),
+                }
+            }
+        });
+    }
+
+    /// Fire every enabled workflow with a trigger matching `event`
+    pub async fn dispatch(&self, event: TriggerEvent) {
+        let matching: Vec<(String, &'static str)> = {
+            let registry = self.registry.read().await;
+            registry
+                .list_workflows()
+                .into_iter()
+                .filter(|w| w.metadata.enabled)
+                .filter_map(|w| {
+                    let trigger = w.metadata.triggers.iter().find(|t| event.matches(t))?;
+                    Some((w.id.clone(), trigger.kind()))
+                })
+                .collect()
+        };
+
+        for (workflow_id, kind) in matching {
+            self.fire(&workflow_id, kind, event.payload()).await;
+        }
+    }
+
+    /// Fire a workflow's webhook trigger if `secret` matches one of them
+    pub async fn fire_webhook(
+        &self,
+        workflow_id: &str,
+        secret: Option<&str>,
+        payload: serde_json::Value,
+    ) -> Result<TriggerFiring, WebhookError> {
+        {
+            let registry = self.registry.read().await;
+            let authorized = |workflow: &&Workflow| {
+                secret.is_some_and(|secret| {
+                    workflow.metadata.triggers.iter().any(|t| match t {
+                        WorkflowTrigger::Webhook { secret: expected } => {
+                            constant_time_eq(expected.as_bytes(), secret.as_bytes())
+                        }
+                        _ => false,
+                    })
+                })
+            };
+            // An unknown workflow is refused like a wrong secret, so workflow
+            // IDs can't be probed without one
+            let Some(workflow) = registry.get_workflow(workflow_id).filter(authorized) else {
+                return Err(WebhookError::Unauthorized);
+            };
+            if !workflow.metadata.enabled {
+                return Err(WebhookError::Disabled);
+            }
+        }
+
+        Ok(self.fire(workflow_id, "webhook", payload).await)
+    }
+
+    /// Fire schedules due at `at` (truncated to the minute)
+    async fn run_schedules(&self, at: DateTime<Utc>) {
+        let due: Vec<(String, String)> = {
+            let registry = self.registry.read().await;
+            registry
+                .list_workflows()
+                .into_iter()
+                .filter(|w| w.metadata.enabled)
+                .filter_map(|w| {
+                    w.metadata.triggers.iter().find_map(|t| match t {
+                        WorkflowTrigger::Schedule { cron } => CronSchedule::parse(cron)
+                            .ok()
+                            .filter(|schedule| schedule.matches(at))
+                            .map(|_| (w.id.clone(), cron.clone())),
+                        _ => None,
+                    })
+                })
+                .collect()
+        };
+
+        for (workflow_id, cron) in due {
+            let payload = serde_json::json!({ "cron": cron, "scheduled_for": at });
+            self.fire(&workflow_id, "schedule", payload).await;
+        }
+    }
+
+    /// Start an execution and record the firing either way
+    async fn fire(&self, workflow_id: &str, kind: &str, payload: serde_json::Value) -> TriggerFiring {
+        let parameters: HashMap<String, serde_json::Value> = match &payload {
+            serde_json::Value::Object(map) => map.clone().into_iter().collect(),
+            other => HashMap::from([("payload".to_string(), other.clone())]),
+        };
+        let request = WorkflowExecutionRequest {
+            workflow_id: workflow_id.to_string(),
+            parameters,
+            strategy: None,
+        };
+
+        let (execution_id, error) = match self.executor.execute_workflow(request).await {
+            Ok(id) => {
+                tracing::info!("{} trigger started workflow {} ({})", kind, workflow_id, id);
+                (Some(id), None)
+            }
+            Err(e) => {
+                tracing::warn!("{} trigger could not start workflow {}: {}", kind, workflow_id, e);
+                (None, Some(e.to_string()))
+            }
+        };
+
+        let firing = TriggerFiring {
+            id: uuid::Uuid::new_v4().to_string(),
+            workflow_id: workflow_id.to_string(),
+            trigger: kind.to_string(),
+            fired_at: Utc::now(),
+            payload,
+            execution_id,
+            error,
+        };
+        self.registry.write().await.record_trigger(firing.clone());
+        firing
+    }
+}
+
+// ─── Cron ──────────────────────────────────────────────────────────────────
+
+/// A parsed five-field cron expression: minute, hour, day of month, month,
+/// day of week (0-7, both 0 and 7 are Sunday).
+///
+/// Each field accepts `*`, numbers, ranges (`1-5`), steps (`*/15`, `0-30/10`)
+/// and comma-separated lists. As in standard cron, when both day fields are
+/// restricted a time matches if either of them does.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(anyhow!(
+                "expected 5 fields (minute hour day month weekday), found {}",
+                fields.len()
+            ));
+        };
+
+        let mut weekdays = parse_field(weekday, 0, 7)?;
+        // 7 is an alias for Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays & !(1 << 7)) | 1;
+        }
+
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days: parse_field(day, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            weekdays,
+            // As in Vixie cron, a field starting with `*` (such as `*/2`)
+            // doesn't count as restricting the day
+            days_restricted: !day.starts_with('*'),
+            weekdays_restricted: !weekday.starts_with('*'),
+        })
+    }
+
+    /// Whether the schedule fires during the minute containing `at`
+    pub fn matches(&self, at: DateTime<Utc>) -> bool {
+        let bit = |mask: u64, value: u32| mask & (1 << value) != 0;
+
+        let day = bit(self.days, at.day());
+        let weekday = bit(self.weekdays, at.weekday().num_days_from_sunday());
+        let day_matches = match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        };
+
+        bit(self.minutes, at.minute())
+            && bit(self.hours, at.hour())
+            && bit(self.months, at.month())
+            && day_matches
+    }
+}
+
+/// Parse one cron field into a bitmask of allowed values
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut mask = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .map_err(|_| anyhow!("invalid step '{}' in '{}'", step, field))?;
+                if step == 0 {
+                    return Err(anyhow!("step must be positive in '{}'", field));
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+
+        let parse = |value: &str| -> Result<u32> {
+            let n: u32 = value
+                .parse()
+                .map_err(|_| anyhow!("invalid value '{}' in '{}'", value, field))?;
+            if n < min || n > max {
+                return Err(anyhow!("{} is outside {}-{} in '{}'", n, min, max, field));
+            }
+            Ok(n)
+        };
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (parse(a)?, parse(b)?),
+                // `5/15` means "from 5, every 15"
+                None if step > 1 => (parse(range)?, max),
+                None => {
+                    let n = parse(range)?;
+                    (n, n)
+                }
+            },
+        };
+        if start > end {
+            return Err(anyhow!("range {}-{} is backwards in '{}'", start, end, field));
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+
+    Ok(mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_cron_matches() {
+        let at = |d, h, m| Utc.with_ymd_and_hms(2026, 3, d, h, m, 0).unwrap();
+
+        let every_quarter = CronSchedule::parse("*/15 * * * *").unwrap();
+        assert!(every_quarter.matches(at(2, 9, 45)));
+        assert!(!every_quarter.matches(at(2, 9, 46)));
+
+        // 2026-03-02 is a Monday
+        let weekday_mornings = CronSchedule::parse("30 8 * * 1-5").unwrap();
+        assert!(weekday_mornings.matches(at(2, 8, 30)));
+        assert!(!weekday_mornings.matches(at(1, 8, 30)));
+
+        // Both day fields restricted: either one matching is enough
+        let first_or_sunday = CronSchedule::parse("0 0 1 * 7").unwrap();
+        assert!(first_or_sunday.matches(at(1, 0, 0)));
+        assert!(first_or_sunday.matches(at(8, 0, 0)));
+        assert!(!first_or_sunday.matches(at(2, 0, 0)));
+
+        // A stepped `*` day field doesn't count as restricted, so both apply
+        let odd_mondays = CronSchedule::parse("0 0 */2 * 1").unwrap();
+        assert!(odd_mondays.matches(at(9, 0, 0)));
+        assert!(!odd_mondays.matches(at(2, 0, 0)));
+        assert!(!odd_mondays.matches(at(3, 0, 0)));
+    }
+
+    #[test]
+    fn test_cron_rejects_bad_expressions() {
+        for bad in ["", "* * * *", "60 * * * *", "*/0 * * * *", "5-1 * * * *", "a * * * *"] {
+            assert!(CronSchedule::parse(bad).is_err(), "{:?} should not parse", bad);
+        }
+    }
+
+    #[test]
+    fn test_event_matching() {
+        let event = TriggerEvent::AgentError {
+            agent_id: "a1".to_string(),
+            agent_name: "researcher".to_string(),
+        };
+        assert!(event.matches(&WorkflowTrigger::AgentError { agent: None }));
+        assert!(event.matches(&WorkflowTrigger::AgentError {
+            agent: Some("researcher".to_string())
+        }));
+        assert!(!event.matches(&WorkflowTrigger::AgentError {
+            agent: Some("writer".to_string())
+        }));
+        assert!(!event.matches(&WorkflowTrigger::TaskQueued { task_type: None }));
+    }
+
+    #[tokio::test]
+    async fn test_webhook_payload_reaches_step_templates() {
+        let registry = Arc::new(RwLock::new(WorkflowRegistry::new()));
+        let workflow = serde_json::from_value(serde_json::json!({
+            "id": "deploy",
+            "name": "Deploy",
+            "strategy": "Sequential",
+            "metadata": {
+                "version": "1",
+                "created_at": Utc::now(),
+                "triggers": [{ "type": "Webhook", "secret": "long-enough-secret-value" }]
+            },
+            "steps": [{
+                "id": "confirm",
+                "name": "Confirm",
+                "task": { "type": "Approval", "data": { "message": "Deploy {{params.ref}} by {{params.pusher.name}}?" } }
+            }]
+        }))
+        .unwrap();
+        registry.write().await.register_workflow(workflow).unwrap();
+        let runtime = crate::container::RuntimeClient::unconnected();
+        let executor = Arc::new(WorkflowExecutor::new(
+            Arc::clone(&registry),
+            Arc::new(RwLock::new(Vec::new())),
+            runtime.clone(),
+            runtime,
+            Arc::new(RwLock::new(HashMap::new())),
+        ));
+        let triggers = WorkflowTriggers::new(Arc::clone(&registry), Arc::clone(&executor));
+
+        let payload = serde_json::json!({ "ref": "v1.2", "pusher": { "name": "sam" } });
+        let firing = triggers
+            .fire_webhook("deploy", Some("long-enough-secret-value"), payload)
+            .await
+            .unwrap();
+        let execution_id = firing.execution_id.unwrap();
+        // A wrong secret and an unknown workflow are refused alike
+        let refused = [
+            ("deploy", Some("wrong-secret-value")),
+            ("deploy", None),
+            ("unknown", Some("long-enough-secret-value")),
+        ];
+        for (workflow_id, secret) in refused {
+            let err = triggers
+                .fire_webhook(workflow_id, secret, serde_json::Value::Null)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, WebhookError::Unauthorized), "{}: {}", workflow_id, err);
+        }
+        assert_eq!(
+            registry.read().await.get_execution(&execution_id).unwrap().parameters["ref"],
+            "v1.2"
+        );
+
+        let mut pending = Vec::new();
+        for _ in 0..100 {
+            pending = executor.pending_approvals();
+            if !pending.is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].message, "Deploy v1.2 by sam?");
+    }
+}